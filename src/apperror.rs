@@ -26,6 +26,14 @@ pub enum AppError {
     ErrorReason(String),
     ResendError(String),
     AuthError(String),
+    /// Authenticated, but not allowed to access the resource
+    Forbidden(String),
+    /// The record was changed by someone else since it was read
+    Conflict(String),
+    /// Too many attempts, holds the seconds until the caller may retry
+    RateLimited(u64),
+    /// Signed in, but the operation needs a recent passkey verification, see `auth::passkey`
+    ReverificationRequired,
     DatabaseError(String),
     EnvVarError(String),
     NotFound(String),
@@ -42,6 +50,8 @@ pub enum AppError {
     ServerFnError(ServerFnErrorErr),
 
     DiskError(String),
+
+    MigrationError(String),
 }
 
 impl AppError {
@@ -170,6 +180,31 @@ impl axum::response::IntoResponse for AppError {
                     "Authentication required".to_string(),
                 )
             }
+            AppError::Forbidden(msg) => {
+                tracing::warn!(error = %msg, "Forbidden");
+                (
+                    axum::http::StatusCode::FORBIDDEN,
+                    "Forbidden".to_string(),
+                )
+            }
+            AppError::Conflict(msg) => {
+                tracing::warn!(error = %msg, "Conflict");
+                (axum::http::StatusCode::CONFLICT, msg.clone())
+            }
+            AppError::RateLimited(retry_after) => {
+                tracing::warn!(retry_after = %retry_after, "Rate limited");
+                (
+                    axum::http::StatusCode::TOO_MANY_REQUESTS,
+                    format!("Too many requests, retry after {}s", retry_after),
+                )
+            }
+            AppError::ReverificationRequired => {
+                tracing::warn!("Passkey reverification required");
+                (
+                    axum::http::StatusCode::FORBIDDEN,
+                    "Verify with your passkey to continue".to_string(),
+                )
+            }
             AppError::DatabaseError(msg) => {
                 tracing::error!(error = %msg, "Database operation failed");
                 (
@@ -226,13 +261,29 @@ impl axum::response::IntoResponse for AppError {
                     "Disk operation error".to_string(),
                 )
             }
+            AppError::MigrationError(msg) => {
+                tracing::error!(error = %msg, "Schema migration error");
+                (
+                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                    "Database error".to_string(),
+                )
+            }
         };
 
         let body = axum::Json(serde_json::json!({
             "error": error_message,
         }));
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+
+        if let AppError::RateLimited(retry_after) = self {
+            response.headers_mut().insert(
+                axum::http::header::RETRY_AFTER,
+                axum::http::HeaderValue::from(retry_after),
+            );
+        }
+
+        response
     }
 }
 
@@ -262,6 +313,14 @@ impl From<&str> for AppError {
     }
 }
 
+#[cfg(feature = "ssr")]
+impl From<crate::cached_surrealdb::SurrealCacheError> for AppError {
+    fn from(error: crate::cached_surrealdb::SurrealCacheError) -> Self {
+        tracing::error!(error = %error, "Cache error");
+        Self::DatabaseError(error.to_string())
+    }
+}
+
 #[cfg(feature = "ssr")]
 impl From<std::io::Error> for AppError {
     fn from(error: std::io::Error) -> Self {
@@ -291,6 +350,12 @@ impl Display for AppError {
             AppError::ErrorReason(msg) => write!(f, "Error reason: {}", msg),
             AppError::ResendError(msg) => write!(f, "Resend error: {}", msg),
             AppError::AuthError(msg) => write!(f, "Authentication error: {}", msg),
+            AppError::Forbidden(msg) => write!(f, "Forbidden: {}", msg),
+            AppError::Conflict(msg) => write!(f, "Conflict: {}", msg),
+            AppError::RateLimited(retry_after) => {
+                write!(f, "Too many requests, retry after {}s", retry_after)
+            }
+            AppError::ReverificationRequired => write!(f, "Verify with your passkey to continue"),
             AppError::DatabaseError(msg) => write!(f, "Database error: {}", msg),
             AppError::EnvVarError(msg) => write!(f, "Environment variable error: {}", msg),
             AppError::NotFound(msg) => write!(f, "Not found: {}", msg),
@@ -300,6 +365,7 @@ impl Display for AppError {
             AppError::DeserializationError(msg) => write!(f, "Deserialization error: {}", msg),
             AppError::ServerFnError(msg) => write!(f, "Server function error: {}", msg),
             AppError::DiskError(msg) => write!(f, "Disk error: {}", msg),
+            AppError::MigrationError(msg) => write!(f, "Migration error: {}", msg),
         }
     }
 }
@@ -394,4 +460,12 @@ impl From<resend_rs::Error> for AppError {
     }
 }
 
+impl AppError {
+    /// True for `AppError::Conflict`. Server functions that return `AppError` keep the variant
+    /// on the way to the browser, so forms can offer a reload instead of a plain error.
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, AppError::Conflict(_))
+    }
+}
+
 impl serde::ser::StdError for AppError {}
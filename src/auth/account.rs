@@ -1,4 +1,4 @@
-use crate::RecordId;
+use crate::{Datetime, RecordId};
 use partial_struct::Partial;
 use serde::{Deserialize, Serialize};
 
@@ -19,6 +19,14 @@ pub struct AdapterAccount {
     pub scope: String,
     pub token_type: String,
     pub user_id: RecordId,
+    /// Argon2id hash of `AccountType::Credentials` accounts, see `auth::password`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    /// Failed password sign-ins since the last success or lockout.
+    #[serde(default)]
+    pub failed_attempts: u32,
+    #[serde(default)]
+    pub locked_until: Option<Datetime>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -90,11 +90,13 @@ impl SurrealAdapter {
         Ok(session)
     }
 
+    /// The signed in session with `session_token` and its user, sessions waiting for their
+    /// second factor are not found.
     pub async fn get_session_and_user(
         &self,
         session_token: String,
     ) -> Result<Option<(AdapterSession, AdapterUser)>, AppError> {
-        let session = AdapterSession::from_string(session_token).await?;
+        let session = AdapterSession::signed_in(session_token).await?;
         let user = AdapterUser::get_user(session.user_id.clone()).await?;
         Ok(Some((session, user)))
     }
@@ -131,21 +133,19 @@ impl SurrealAdapter {
 
 #[cfg(feature = "ssr")]
 #[tokio::test]
-#[ignore = "temporary skip for cicd test"]
 async fn test_adapter() -> Result<(), AppError> {
-    use crate::{date_utils::parse_surrealdb_datetime_to_chrono, theme::Theme};
-    use chrono::Utc;
+    use crate::db::connection::{db_memory, with_db};
 
-    // use crate::components::theme::Theme;
+    with_db(db_memory().await?, adapter_roundtrip()).await
+}
 
-    let db = crate::db_init().await?;
+#[cfg(all(test, feature = "ssr"))]
+async fn adapter_roundtrip() -> Result<(), AppError> {
+    use crate::{date_utils::parse_surrealdb_datetime_to_chrono, theme::Theme};
+    use chrono::Utc;
 
     let testemail = "example@test.com";
 
-    db.query("DELETE user WHERE email = $email;")
-        .bind(("email", testemail))
-        .await?;
-
     println!("Available in tests or when SSR feature is enabled");
 
     let adapter = SurrealAdapter {};
@@ -0,0 +1,165 @@
+use leptos::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssr")]
+use surrealdb::{Datetime, RecordId};
+
+#[cfg(not(feature = "ssr"))]
+use crate::{Datetime, RecordId};
+
+#[cfg(feature = "ssr")]
+use crate::{
+    AppError, db_init,
+    token_hash::{hash_token, verify_token},
+    user::AdapterUser,
+};
+
+/// Prefix of generated keys, makes them recognisable in logs and secret scanners.
+pub const API_KEY_PREFIX: &str = "tk_";
+
+/// A key for `Authorization: Bearer <key>` requests on behalf of its user. Only the hash of the
+/// key is stored, the plaintext is returned once by `ApiKey::create`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ApiKey {
+    pub id: RecordId,
+    pub name: String,
+    pub user_id: RecordId,
+    #[serde(default, skip_serializing)]
+    pub key_hash: String,
+    /// First characters of the key, to tell keys apart in listings.
+    pub key_start: String,
+    pub created_at: Datetime,
+    pub last_used: Option<Datetime>,
+    pub expires_at: Option<Datetime>,
+}
+
+#[cfg(feature = "ssr")]
+impl ApiKey {
+    /// Creates a key for `user_id`, returns it with the plaintext key.
+    ///
+    /// ### EXAMPLE:
+    /// ```rs
+    ///     let (api_key, secret) = ApiKey::create(user.id, "CI".into(), None).await?;
+    ///     // curl -H "Authorization: Bearer {secret}" ...
+    /// ```
+    pub async fn create(
+        user_id: RecordId,
+        name: String,
+        expires_at: Option<Datetime>,
+    ) -> Result<(ApiKey, String), AppError> {
+        let client = db_init().await?;
+        let secret = format!("{}{}", API_KEY_PREFIX, uuid::Uuid::new_v4().simple());
+
+        let mut result = client
+            .query("CREATE ONLY api_key SET name = $name, user_id = $user_id, key_hash = $key_hash, key_start = $key_start, created_at = time::now(), last_used = NONE, expires_at = $expires_at;")
+            .bind(("name", name))
+            .bind(("user_id", user_id))
+            .bind(("key_hash", hash_token(&secret)?))
+            .bind(("key_start", secret.chars().take(8).collect::<String>()))
+            .bind(("expires_at", expires_at))
+            .await?;
+
+        let api_key: Option<ApiKey> = result.take(0)?;
+        let api_key =
+            api_key.ok_or_else(|| AppError::DatabaseError("Could not create API key".into()))?;
+
+        Ok((api_key, secret))
+    }
+
+    pub async fn get_by_user(user_id: RecordId) -> Result<Vec<ApiKey>, AppError> {
+        let client = db_init().await?;
+
+        let mut result = client
+            .query("SELECT * FROM api_key WHERE user_id = $user_id ORDER BY created_at DESC;")
+            .bind(("user_id", user_id))
+            .await?;
+
+        let keys: Vec<ApiKey> = result.take(0)?;
+        Ok(keys)
+    }
+
+    /// Deletes the key `id` if it belongs to `user_id`, returns whether it existed.
+    pub async fn revoke(user_id: RecordId, id: RecordId) -> Result<bool, AppError> {
+        let client = db_init().await?;
+
+        let mut result = client
+            .query("DELETE api_key WHERE id = $id AND user_id = $user_id RETURN BEFORE;")
+            .bind(("id", id))
+            .bind(("user_id", user_id))
+            .await?;
+
+        let removed: Vec<ApiKey> = result.take(0)?;
+        Ok(!removed.is_empty())
+    }
+
+    /// Deletes every key of `user_id`, returns how many. Only for account recovery, account
+    /// deletion and the user asking for it, keys outlive sessions and privilege changes.
+    pub async fn revoke_all(user_id: RecordId) -> Result<u64, AppError> {
+        let client = db_init().await?;
+
+        let mut result = client
+            .query("RETURN count((DELETE api_key WHERE user_id = $user_id RETURN BEFORE));")
+            .bind(("user_id", user_id))
+            .await?;
+
+        let removed: Option<u64> = result.take(0)?;
+        Ok(removed.unwrap_or(0))
+    }
+
+    /// The user of an unexpired key, marking the key as used.
+    pub async fn authenticate(secret: &str) -> Result<AdapterUser, AppError> {
+        use crate::db_seperate_connection;
+
+        let client = db_seperate_connection().await?;
+
+        let mut result = client
+            .query(
+                "UPDATE api_key SET last_used = time::now() WHERE key_hash = $key_hash AND (expires_at = NONE OR expires_at > time::now()) RETURN NONE;
+                SELECT * FROM ONLY api_key WHERE key_hash = $key_hash AND (expires_at = NONE OR expires_at > time::now()) LIMIT 1;
+                (SELECT user_id FROM ONLY api_key WHERE key_hash = $key_hash AND (expires_at = NONE OR expires_at > time::now()) LIMIT 1 FETCH user_id).user_id;",
+            )
+            .bind(("key_hash", hash_token(secret)?))
+            .await?;
+
+        let api_key: Option<ApiKey> = result.take(1)?;
+        let user: Option<AdapterUser> = result.take(2)?;
+
+        match (api_key, user) {
+            (Some(api_key), Some(user)) if verify_token(secret, &api_key.key_hash) => Ok(user),
+            _ => Err(AppError::AuthError("Invalid API key".into())),
+        }
+    }
+}
+
+/// Creates an API key for the signed in user, the returned key is only shown once. Like listing
+/// and revoking keys, this needs a session, a key can't mint more keys.
+#[server]
+pub async fn create_api_key(name: String) -> Result<String, ServerFnError> {
+    let user = crate::auth::extract::session_user().await?;
+    let (_, secret) = ApiKey::create(user.id, name, None).await?;
+    Ok(secret)
+}
+
+#[server]
+pub async fn list_api_keys() -> Result<Vec<ApiKey>, ServerFnError> {
+    let user = crate::auth::extract::session_user().await?;
+    Ok(ApiKey::get_by_user(user.id).await?)
+}
+
+#[server]
+pub async fn revoke_api_key(id: RecordId) -> Result<(), ServerFnError> {
+    let user = crate::auth::extract::session_user().await?;
+
+    if !ApiKey::revoke(user.id, id).await? {
+        return Err(AppError::NotFound("API key not found".into()).into());
+    }
+
+    Ok(())
+}
+
+/// Revokes every API key of the signed in user, returns how many.
+#[server]
+pub async fn revoke_all_api_keys() -> Result<u64, ServerFnError> {
+    let user = crate::auth::extract::session_user().await?;
+    Ok(ApiKey::revoke_all(user.id).await?)
+}
@@ -4,6 +4,15 @@ use leptos::{prelude::*, reactive::spawn_local};
 use leptos_router::hooks::use_query_map;
 use urlencoding::decode;
 
+/// Where the magic link continues to, the decoded `callbackUrl` if it stays on this site,
+/// the home page otherwise.
+fn callback_redirect(callback_url: &str) -> String {
+    decode(callback_url)
+        .ok()
+        .and_then(|url| crate::auth::protected::safe_callback_url(&url))
+        .unwrap_or_else(|| "/".to_string())
+}
+
 #[derive(Clone, Debug)]
 enum AuthStatus {
     Loading,
@@ -49,15 +58,14 @@ pub fn AuthCallback() -> impl IntoView {
 
                         // Redirect or update UI as needed
                         if let Some(url) = callback_url {
-                            let url = match decode(url.as_str()) {
-                                Ok(decoded) => decoded.to_string(),
-                                Err(_e) => {
-                                    #[cfg(feature = "ssr")]
-                                    tracing::error!("Failed to decode callback URL: {}", _e);
-
-                                    return;
-                                }
+                            let url = callback_redirect(&url);
+
+                            // users with two-factor enter their code before continuing
+                            let url = match crate::two_factor::two_factor_pending().await {
+                                Ok(true) => crate::two_factor::two_factor_challenge_path(&url),
+                                _ => url,
                             };
+
                             // Perform redirect to callback URL
                             window().location().set_href(&url).unwrap();
                         }
@@ -493,11 +501,10 @@ pub fn OAuthCallback() -> impl IntoView {
 
 #[server]
 pub async fn handle_oauth_callback(code: String, state: String) -> Result<String, ServerFnError> {
-    use crate::auth::oauth::{OAuthConfig, OAuthUserInfo};
+    use crate::auth::oauth::OAuthConfig;
     use crate::auth::session::{delete_oauth_state, get_oauth_state};
     use http::header::HeaderValue;
     use leptos_axum::ResponseOptions;
-    use oauth2::{AuthorizationCode, PkceCodeVerifier, TokenResponse};
 
     // Retrieve and validate OAuth state
     let oauth_state = get_oauth_state(state.clone()).await?;
@@ -505,26 +512,37 @@ pub async fn handle_oauth_callback(code: String, state: String) -> Result<String
     // Delete the state to prevent reuse
     delete_oauth_state(state).await?;
 
-    // Get the provider config
-    let config = match oauth_state.provider {
-        OAuthProvider::Github => OAuthConfig::github(),
-        OAuthProvider::Google => OAuthConfig::google(),
-        OAuthProvider::Discord => OAuthConfig::discord(),
-    }
-    .map_err(|e| ServerFnError::new(e))?;
-
-    let client = config.build_client().map_err(|e| ServerFnError::new(e))?;
-
-    // Exchange the code for a token using async HTTP client
-    let token_result = client
-        .exchange_code(AuthorizationCode::new(code))
-        .set_pkce_verifier(PkceCodeVerifier::new(oauth_state.pkce_verifier))
-        .request_async(oauth2::reqwest::async_http_client)
-        .await
-        .map_err(|e| ServerFnError::new(format!("Failed to exchange code for token: {}", e)))?;
-
-    // Fetch user info from the provider
-    let user_info = fetch_user_info(&config, token_result.access_token().secret()).await?;
+    let pkce_verifier = &oauth_state.pkce_verifier;
+    let user_info = match &oauth_state.provider {
+        OAuthProvider::Github => {
+            fetch_user_info(OAuthConfig::github(), code, pkce_verifier, github_user_info).await?
+        }
+        OAuthProvider::Google => {
+            fetch_user_info(OAuthConfig::google(), code, pkce_verifier, google_user_info).await?
+        }
+        OAuthProvider::Discord => {
+            fetch_user_info(
+                OAuthConfig::discord(),
+                code,
+                pkce_verifier,
+                discord_user_info,
+            )
+            .await?
+        }
+        // OpenID Connect providers validate their id_token instead of calling a user endpoint
+        OAuthProvider::Oidc(name) => {
+            let nonce = oauth_state
+                .nonce
+                .as_deref()
+                .ok_or_else(|| ServerFnError::new("OAuth state has no nonce"))?;
+
+            crate::auth::oidc::oidc_provider(name)
+                .map_err(ServerFnError::new)?
+                .complete_signin(&code, pkce_verifier, nonce)
+                .await
+                .map_err(ServerFnError::new)?
+        }
+    };
 
     // Create or get user
     let user = get_or_create_user_from_oauth(&user_info, &oauth_state.provider).await?;
@@ -541,20 +559,43 @@ pub async fn handle_oauth_callback(code: String, state: String) -> Result<String
         );
     }
 
+    if session.two_factor_pending {
+        return Ok(crate::two_factor::two_factor_challenge_path(
+            &oauth_state.callback_url,
+        ));
+    }
+
     Ok(oauth_state.callback_url)
 }
 
+/// Exchanges the code for a token and reads the user from the provider's user endpoint.
 #[cfg(feature = "ssr")]
 async fn fetch_user_info(
-    config: &crate::auth::oauth::OAuthConfig,
-    access_token: &str,
+    config: Result<crate::auth::oauth::OAuthConfig, String>,
+    code: String,
+    pkce_verifier: &str,
+    parse: fn(serde_json::Value) -> crate::auth::oauth::OAuthUserInfo,
 ) -> Result<crate::auth::oauth::OAuthUserInfo, ServerFnError> {
-    use crate::auth::oauth::{OAuthProvider, OAuthUserInfo};
+    use oauth2::{AuthorizationCode, PkceCodeVerifier, TokenResponse};
+
+    let config = config.map_err(ServerFnError::new)?;
+    let client = config.build_client().map_err(ServerFnError::new)?;
+
+    // Exchange the code for a token using async HTTP client
+    let token_result = client
+        .exchange_code(AuthorizationCode::new(code))
+        .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier.to_string()))
+        .request_async(oauth2::reqwest::async_http_client)
+        .await
+        .map_err(|e| ServerFnError::new(format!("Failed to exchange code for token: {}", e)))?;
 
     let http_client = reqwest::Client::new();
     let response = http_client
         .get(&config.user_info_url)
-        .header("Authorization", format!("Bearer {}", access_token))
+        .header(
+            "Authorization",
+            format!("Bearer {}", token_result.access_token().secret()),
+        )
         .header("User-Agent", "Tinkr-OAuth-Client")
         .send()
         .await
@@ -565,35 +606,47 @@ async fn fetch_user_info(
         .await
         .map_err(|e| ServerFnError::new(format!("Failed to parse user info: {}", e)))?;
 
-    // Parse based on provider
-    let user_info = match config.provider {
-        OAuthProvider::Github => OAuthUserInfo {
-            id: json["id"].as_i64().unwrap_or(0).to_string(),
-            email: json["email"].as_str().map(|s| s.to_string()),
-            name: json["login"].as_str().map(|s| s.to_string()),
-            avatar: json["avatar_url"].as_str().map(|s| s.to_string()),
-        },
-        OAuthProvider::Google => OAuthUserInfo {
-            id: json["id"].as_str().unwrap_or("").to_string(),
-            email: json["email"].as_str().map(|s| s.to_string()),
-            name: json["name"].as_str().map(|s| s.to_string()),
-            avatar: json["picture"].as_str().map(|s| s.to_string()),
-        },
-        OAuthProvider::Discord => OAuthUserInfo {
-            id: json["id"].as_str().unwrap_or("").to_string(),
-            email: json["email"].as_str().map(|s| s.to_string()),
-            name: json["username"].as_str().map(|s| s.to_string()),
-            avatar: json["avatar"].as_str().map(|avatar| {
-                format!(
-                    "https://cdn.discordapp.com/avatars/{}/{}.png",
-                    json["id"].as_str().unwrap_or(""),
-                    avatar
-                )
-            }),
-        },
-    };
+    Ok(parse(json))
+}
+
+/// GitHub only shows verified emails on the public profile.
+#[cfg(feature = "ssr")]
+fn github_user_info(json: serde_json::Value) -> crate::auth::oauth::OAuthUserInfo {
+    crate::auth::oauth::OAuthUserInfo {
+        id: json["id"].as_i64().unwrap_or(0).to_string(),
+        email: json["email"].as_str().map(|s| s.to_string()),
+        email_verified: true,
+        name: json["login"].as_str().map(|s| s.to_string()),
+        avatar: json["avatar_url"].as_str().map(|s| s.to_string()),
+    }
+}
+
+#[cfg(feature = "ssr")]
+fn google_user_info(json: serde_json::Value) -> crate::auth::oauth::OAuthUserInfo {
+    crate::auth::oauth::OAuthUserInfo {
+        id: json["id"].as_str().unwrap_or("").to_string(),
+        email: json["email"].as_str().map(|s| s.to_string()),
+        email_verified: json["verified_email"].as_bool().unwrap_or(false),
+        name: json["name"].as_str().map(|s| s.to_string()),
+        avatar: json["picture"].as_str().map(|s| s.to_string()),
+    }
+}
 
-    Ok(user_info)
+#[cfg(feature = "ssr")]
+fn discord_user_info(json: serde_json::Value) -> crate::auth::oauth::OAuthUserInfo {
+    crate::auth::oauth::OAuthUserInfo {
+        id: json["id"].as_str().unwrap_or("").to_string(),
+        email: json["email"].as_str().map(|s| s.to_string()),
+        email_verified: json["verified"].as_bool().unwrap_or(false),
+        name: json["username"].as_str().map(|s| s.to_string()),
+        avatar: json["avatar"].as_str().map(|avatar| {
+            format!(
+                "https://cdn.discordapp.com/avatars/{}/{}.png",
+                json["id"].as_str().unwrap_or(""),
+                avatar
+            )
+        }),
+    }
 }
 
 #[cfg(feature = "ssr")]
@@ -611,8 +664,10 @@ async fn get_or_create_user_from_oauth(
         return Ok(user);
     }
 
-    // If user has email, try to find by email
-    if let Some(ref email) = user_info.email {
+    // Only a verified email links the OAuth account to an existing user
+    if let Some(ref email) = user_info.email
+        && user_info.email_verified
+    {
         let email_addr = crate::EmailAddress(email.clone());
         if let Ok(user) = AdapterUser::get_user_by_email(email_addr).await {
             // Link OAuth account to existing user
@@ -625,11 +680,13 @@ async fn get_or_create_user_from_oauth(
     let username = user_info
         .name
         .clone()
-        .unwrap_or_else(|| format!("user_{}", &user_info.id[..8]));
+        .unwrap_or_else(|| format!("user_{}", user_info.id.chars().take(8).collect::<String>()));
 
     let user = AdapterUser::create_user(CreateUserData {
         email: crate::EmailAddress(user_info.email.clone().unwrap_or_default()),
-        email_verified: Some(surrealdb::Datetime::from(chrono::Utc::now())), // OAuth providers verify emails
+        email_verified: user_info
+            .email_verified
+            .then(|| surrealdb::Datetime::from(chrono::Utc::now())),
         image: user_info.avatar.clone(),
         name: username,
         theme: Theme::System,
@@ -649,3 +706,20 @@ async fn get_or_create_user_from_oauth(
 
     Ok(user)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_callback_redirect() {
+        assert_eq!(
+            callback_redirect("%2Fsettings%3Ftab%3D1"),
+            "/settings?tab=1"
+        );
+        assert_eq!(callback_redirect("%2F%2Fevil.example"), "/");
+        assert_eq!(callback_redirect("https%3A%2F%2Fevil.example"), "/");
+        assert_eq!(callback_redirect("javascript:alert(1)"), "/");
+        assert_eq!(callback_redirect(""), "/");
+    }
+}
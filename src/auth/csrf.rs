@@ -0,0 +1,133 @@
+//! CSRF tokens checked by `middleware::csrf::CsrfLayer` on every state-changing request.
+//!
+//! The token is sent to the browser in the `csrf_token` cookie. Signed in users get
+//! `hash_token("csrf:" + session_token)`, so a token only works with the session it was issued
+//! for. Signed out visitors get a random token. `CsrfScript` copies the cookie into the
+//! `x-csrf-token` header of same-origin `fetch` calls (which covers server functions and
+//! `ActionForm`) and into a `csrf_token` field of posted forms. `XmlHttpRequest` uploads set the
+//! header from `browser_csrf_token()`.
+
+use leptos::prelude::*;
+
+#[cfg(feature = "ssr")]
+use crate::{AppError, token_hash::hash_token};
+
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+pub const CSRF_FORM_FIELD: &str = "csrf_token";
+
+/// The token issued for a request, put in the request extensions by `CsrfLayer`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsrfToken(pub String);
+
+/// The token bound to `session_token`.
+#[cfg(feature = "ssr")]
+pub fn csrf_token_for_session(session_token: &str) -> Result<String, AppError> {
+    hash_token(&format!("csrf:{session_token}"))
+}
+
+/// The token of the request being rendered or handled, `None` without `CsrfLayer`.
+#[cfg(feature = "ssr")]
+pub fn request_csrf_token() -> Option<String> {
+    use_context::<http::request::Parts>()
+        .and_then(|parts| parts.extensions.get::<CsrfToken>().cloned())
+        .map(|CsrfToken(token)| token)
+}
+
+/// The token in the `csrf_token` cookie of a `Cookie` header or `document.cookie`.
+pub fn csrf_token_from_cookies(cookies: &str) -> Option<String> {
+    cookies
+        .split(';')
+        .find_map(|cookie| cookie.trim().strip_prefix(&format!("{CSRF_COOKIE}=")))
+        .map(|token| {
+            urlencoding::decode(token)
+                .map(|token| token.into_owned())
+                .unwrap_or_else(|_| token.to_string())
+        })
+        .filter(|token| !token.is_empty())
+}
+
+/// The token of the page, for requests `CsrfScript` doesn't cover. Set it as the `CSRF_HEADER`
+/// of `XmlHttpRequest` uploads.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     xhr.open("POST", "/api/upload-avatar")?;
+///     if let Some(token) = browser_csrf_token() {
+///         xhr.set_request_header(CSRF_HEADER, &token)?;
+///     }
+/// ```
+pub fn browser_csrf_token() -> Option<String> {
+    use wasm_bindgen::JsCast;
+
+    let document = web_sys::window()?
+        .document()?
+        .dyn_into::<web_sys::HtmlDocument>()
+        .ok()?;
+
+    csrf_token_from_cookies(&document.cookie().ok()?)
+}
+
+const CSRF_SCRIPT: &str = r#"(function () {
+  if (window.__tinkrCsrf) return;
+  window.__tinkrCsrf = true;
+  function token() {
+    var m = document.cookie.match(/(?:^|;\s*)csrf_token=([^;]*)/);
+    return m ? decodeURIComponent(m[1]) : "";
+  }
+  function sameOrigin(url) {
+    try { return new URL(url, location.href).origin === location.origin; } catch (e) { return false; }
+  }
+  var fetch = window.fetch;
+  window.fetch = function (input, init) {
+    init = init || {};
+    var request = input instanceof Request ? input : null;
+    var method = (init.method || (request && request.method) || "GET").toUpperCase();
+    if (method !== "GET" && method !== "HEAD" && sameOrigin(request ? request.url : String(input))) {
+      var headers = new Headers(init.headers || (request ? request.headers : undefined));
+      headers.set("x-csrf-token", token());
+      init.headers = headers;
+    }
+    return fetch.call(this, input, init);
+  };
+  document.addEventListener("submit", function (e) {
+    var form = e.target;
+    if (!(form instanceof HTMLFormElement) || form.method.toLowerCase() !== "post" || !sameOrigin(form.action)) return;
+    var field = form.querySelector('input[name="csrf_token"]');
+    if (!field) {
+      field = document.createElement("input");
+      field.type = "hidden";
+      field.name = "csrf_token";
+      form.appendChild(field);
+    }
+    field.value = token();
+  }, true);
+})();"#;
+
+/// Adds the CSRF token to same-origin `fetch` calls and form posts. Render it once in the
+/// shell, before the hydration scripts.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     <head>
+///         <CsrfScript />
+///         <HydrationScripts options />
+///     </head>
+/// ```
+#[component]
+pub fn CsrfScript() -> impl IntoView {
+    view! { <script inner_html=CSRF_SCRIPT></script> }
+}
+
+/// Hidden `csrf_token` input for forms that have to work before the page has hydrated.
+#[component]
+pub fn CsrfField() -> impl IntoView {
+    #[cfg(feature = "ssr")]
+    let token = request_csrf_token().unwrap_or_default();
+
+    // filled in by CsrfScript when the form is submitted
+    #[cfg(not(feature = "ssr"))]
+    let token = String::new();
+
+    view! { <input type="hidden" name=CSRF_FORM_FIELD value=token /> }
+}
@@ -0,0 +1,241 @@
+//! Resolving the user of a request from its `session_token` cookie or an
+//! `Authorization: Bearer <api key>` header. The axum extractors and the server-fn helpers
+//! (`get_user`, `get_user_option`, `logout`, ...) share this code path, and the user is resolved
+//! at most once per request.
+
+use axum::extract::FromRequestParts;
+use axum_extra::extract::CookieJar;
+use http::{HeaderMap, header::AUTHORIZATION, request::Parts};
+use leptos::prelude::*;
+
+use crate::{AppError, api_key::ApiKey, user::AdapterUser};
+
+/// Name of the session cookie.
+pub const SESSION_COOKIE: &str = "session_token";
+
+/// The session token in the cookies of `headers`.
+pub fn session_token_from_headers(headers: &HeaderMap) -> Option<String> {
+    CookieJar::from_headers(headers)
+        .iter()
+        .find(|cookie| cookie.name() == SESSION_COOKIE)
+        .map(|cookie| cookie.value().to_string())
+        .filter(|token| !token.is_empty())
+}
+
+/// The bearer token of the `Authorization` header.
+pub fn bearer_token_from_headers(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+
+    (scheme.eq_ignore_ascii_case("bearer") && !token.trim().is_empty())
+        .then(|| token.trim().to_string())
+}
+
+/// How the user of a request authenticated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// The `session_token` cookie.
+    Session,
+    /// An `Authorization: Bearer <api key>` header. Keys can't manage keys, sessions or second
+    /// factors, nor reach admin routes, see `session_user`.
+    ApiKey,
+}
+
+/// The user resolved for a request, kept in the request extensions (and the leptos context in
+/// server functions) so later lookups in the same request are free.
+#[derive(Debug, Clone)]
+struct ResolvedUser(Option<(AdapterUser, AuthMethod)>);
+
+async fn resolve_user(headers: &HeaderMap) -> Result<Option<(AdapterUser, AuthMethod)>, AppError> {
+    if let Some(token) = session_token_from_headers(headers) {
+        match AdapterUser::get_user_from_session(token).await {
+            Ok(user) => return Ok(Some((user, AuthMethod::Session))),
+            // an expired or unknown session counts as signed out
+            Err(AppError::AuthError(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    if let Some(token) = bearer_token_from_headers(headers) {
+        return ApiKey::authenticate(&token)
+            .await
+            .map(|user| Some((user, AuthMethod::ApiKey)));
+    }
+
+    Ok(None)
+}
+
+async fn user_from_parts(parts: &mut Parts) -> Result<Option<(AdapterUser, AuthMethod)>, AppError> {
+    if let Some(ResolvedUser(user)) = parts.extensions.get::<ResolvedUser>() {
+        return Ok(user.clone());
+    }
+
+    let user = resolve_user(&parts.headers).await?;
+    parts.extensions.insert(ResolvedUser(user.clone()));
+    Ok(user)
+}
+
+/// The user of the request being handled by a server function, `None` when signed out.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     #[server]
+///     pub async fn my_orders() -> Result<Vec<Order>, ServerFnError> {
+///         let user = request_user().await?.ok_or(ServerFnError::new("Not logged in."))?;
+///         ...
+///     }
+/// ```
+pub async fn request_user() -> Result<Option<AdapterUser>, AppError> {
+    Ok(request_auth().await?.map(|(user, _)| user))
+}
+
+/// The user of the request being handled by a server function and how they authenticated,
+/// `None` when signed out.
+pub async fn request_auth() -> Result<Option<(AdapterUser, AuthMethod)>, AppError> {
+    if let Some(ResolvedUser(user)) = use_context::<ResolvedUser>() {
+        return Ok(user);
+    }
+
+    let mut parts = use_context::<Parts>()
+        .ok_or_else(|| AppError::AuthError("No request to read the user from".into()))?;
+    let user = user_from_parts(&mut parts).await?;
+
+    provide_context(ResolvedUser(user.clone()));
+    Ok(user)
+}
+
+/// The user signed in with a session cookie. For server functions that manage credentials (API
+/// keys, passwords, second factors), which an API key must not be able to reach.
+pub async fn session_user() -> Result<AdapterUser, AppError> {
+    require_session(request_auth().await?)
+}
+
+fn require_session(auth: Option<(AdapterUser, AuthMethod)>) -> Result<AdapterUser, AppError> {
+    match auth {
+        Some((user, AuthMethod::Session)) => Ok(user),
+        Some((_, AuthMethod::ApiKey)) => Err(AppError::Forbidden(
+            "Not available with an API key, sign in instead".into(),
+        )),
+        None => Err(AppError::AuthError("Not logged in.".into())),
+    }
+}
+
+/// The session token of the request being handled by a server function.
+pub fn request_session_token() -> Option<String> {
+    use_context::<Parts>().and_then(|parts| session_token_from_headers(&parts.headers))
+}
+
+/// The signed in user, rejects with 401 otherwise.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     async fn handler(CurrentUser(user): CurrentUser) -> Result<Json<User>, AppError> {
+///         Ok(Json(user))
+///     }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CurrentUser(pub AdapterUser);
+
+/// The signed in user if there is one.
+#[derive(Debug, Clone)]
+pub struct OptionalUser(pub Option<AdapterUser>);
+
+/// A signed in admin (or super admin), rejects with 401 or 403 otherwise. Also rejects admins
+/// without two-factor when `TINKR_REQUIRE_ADMIN_2FA` is set, and requests made with an API key.
+#[derive(Debug, Clone)]
+pub struct AdminUser(pub AdapterUser);
+
+/// A signed in super admin, rejects with 401 or 403 otherwise. Like `AdminUser`, API keys are
+/// rejected.
+#[derive(Debug, Clone)]
+pub struct SuperAdmin(pub AdapterUser);
+
+impl<S: Send + Sync> FromRequestParts<S> for OptionalUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(OptionalUser(
+            user_from_parts(parts).await?.map(|(user, _)| user),
+        ))
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for CurrentUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        user_from_parts(parts)
+            .await?
+            .map(|(user, _)| CurrentUser(user))
+            .ok_or_else(|| AppError::AuthError("Not logged in.".into()))
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for AdminUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user = require_session(user_from_parts(parts).await?)?;
+
+        if user.is_admin != Some(true) && user.superadmin != Some(true) {
+            return Err(AppError::Forbidden("Admin access required".into()));
+        }
+
+        crate::two_factor::check_admin_two_factor(&user).await?;
+        Ok(AdminUser(user))
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for SuperAdmin {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user = require_session(user_from_parts(parts).await?)?;
+
+        if user.superadmin != Some(true) {
+            return Err(AppError::Forbidden("Super admin access required".into()));
+        }
+
+        crate::two_factor::check_admin_two_factor(&user).await?;
+        Ok(SuperAdmin(user))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    #[test]
+    fn test_tokens_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(session_token_from_headers(&headers), None);
+        assert_eq!(bearer_token_from_headers(&headers), None);
+
+        headers.insert(
+            http::header::COOKIE,
+            HeaderValue::from_static("theme=dark; session_token=abc"),
+        );
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer tk_123"));
+        assert_eq!(session_token_from_headers(&headers), Some("abc".into()));
+        assert_eq!(bearer_token_from_headers(&headers), Some("tk_123".into()));
+
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_static("Basic dXNlcjpwYXNz"),
+        );
+        assert_eq!(bearer_token_from_headers(&headers), None);
+
+        // only the exact cookie name counts
+        headers.insert(
+            http::header::COOKIE,
+            HeaderValue::from_static("old_session_token=xyz; session_token=abc"),
+        );
+        assert_eq!(session_token_from_headers(&headers), Some("abc".into()));
+        headers.insert(
+            http::header::COOKIE,
+            HeaderValue::from_static("old_session_token=xyz"),
+        );
+        assert_eq!(session_token_from_headers(&headers), None);
+    }
+}
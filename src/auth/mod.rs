@@ -5,12 +5,27 @@ pub mod account_details;
 #[cfg(feature = "ssr")]
 pub mod adapter_rs_surreal;
 
+pub mod api_key;
+
+#[cfg(feature = "ssr")]
+pub mod extract;
+
 pub mod callback;
+pub mod csrf;
+pub mod oidc;
+pub mod passkey;
+pub mod password;
 pub mod session;
 
+#[cfg(feature = "ssr")]
+pub mod rate_limit;
+
 #[cfg(feature = "ssr")]
 pub mod token;
 
+#[cfg(feature = "ssr")]
+pub mod token_hash;
+
 pub mod ui_auth;
 pub mod user;
 
@@ -18,4 +33,8 @@ pub mod guest;
 
 pub mod authcheck;
 
+pub mod protected;
+
+pub mod two_factor;
+
 pub mod oauth;
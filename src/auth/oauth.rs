@@ -3,12 +3,14 @@ use oauth2::basic::BasicClient;
 #[cfg(feature = "ssr")]
 use oauth2::{AuthUrl, ClientId, ClientSecret, RedirectUrl, TokenUrl};
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-#[serde(rename_all = "lowercase")]
+/// Stored and sent as its name, `Oidc` providers are named when registered, see `auth::oidc`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(from = "String", into = "String")]
 pub enum OAuthProvider {
     Github,
     Google,
     Discord,
+    Oidc(String),
 }
 
 impl OAuthProvider {
@@ -17,10 +19,28 @@ impl OAuthProvider {
             OAuthProvider::Github => "github",
             OAuthProvider::Google => "google",
             OAuthProvider::Discord => "discord",
+            OAuthProvider::Oidc(name) => name,
+        }
+    }
+}
+
+impl From<String> for OAuthProvider {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "github" => OAuthProvider::Github,
+            "google" => OAuthProvider::Google,
+            "discord" => OAuthProvider::Discord,
+            _ => OAuthProvider::Oidc(name),
         }
     }
 }
 
+impl From<OAuthProvider> for String {
+    fn from(provider: OAuthProvider) -> Self {
+        provider.as_str().to_string()
+    }
+}
+
 #[cfg(feature = "ssr")]
 pub struct OAuthConfig {
     pub provider: OAuthProvider,
@@ -105,6 +125,8 @@ impl OAuthConfig {
 pub struct OAuthUserInfo {
     pub id: String,
     pub email: Option<String>,
+    /// Whether the provider verified `email`, only verified emails link to existing accounts.
+    pub email_verified: bool,
     pub name: Option<String>,
     pub avatar: Option<String>,
 }
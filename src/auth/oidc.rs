@@ -0,0 +1,903 @@
+//! Generic OpenID Connect providers (Keycloak, Microsoft Entra ID, Auth0, ...) configured by
+//! issuer URL.
+//!
+//! The provider metadata is discovered from `{issuer}/.well-known/openid-configuration` and its
+//! signing keys (JWKS) are cached, refetched when a token names an unknown key. The `id_token` of
+//! the code exchange is checked for its signature, `iss`, `aud`, `exp` and the `nonce` sent with
+//! the authorization request before its claims become an `OAuthUserInfo`.
+//!
+//! Providers are named, the name is their `OAuthProvider::Oidc` value and the last segment of
+//! their callback URL `/api/auth/callback/{name}`. They are read from the environment:
+//!
+//! ```sh
+//! TINKR_OIDC_PROVIDERS=keycloak,entra
+//! KEYCLOAK_ISSUER=https://sso.example.com/realms/main
+//! KEYCLOAK_CLIENT_ID=tinkr
+//! KEYCLOAK_CLIENT_SECRET=...
+//! ENTRA_ISSUER=https://login.microsoftonline.com/{tenant}/v2.0
+//! ENTRA_DISPLAY_NAME=Microsoft
+//! ```
+//!
+//! or registered at startup:
+//!
+//! ```rs
+//!     register_oidc_provider(
+//!         OidcProviderConfig::new("keycloak", issuer, client_id, client_secret)
+//!             .with_display_name("Company SSO"),
+//!     )?;
+//! ```
+
+use leptos::{prelude::*, reactive::spawn_local};
+use serde::{Deserialize, Serialize};
+
+use crate::AppError;
+
+#[cfg(feature = "ssr")]
+use std::collections::HashMap;
+#[cfg(feature = "ssr")]
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+#[cfg(feature = "ssr")]
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "ssr")]
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, jwk::Jwk};
+
+#[cfg(feature = "ssr")]
+use crate::auth::oauth::{OAuthConfig, OAuthProvider, OAuthUserInfo};
+
+/// Comma separated names of the providers to read from the environment.
+pub const OIDC_PROVIDERS_ENV: &str = "TINKR_OIDC_PROVIDERS";
+
+/// Discovery documents are fetched again after this long.
+#[cfg(feature = "ssr")]
+const METADATA_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Signing keys are fetched again after this long, or when a token names an unknown key.
+#[cfg(feature = "ssr")]
+const JWKS_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Unknown key ids don't refetch the keys more often than this.
+#[cfg(feature = "ssr")]
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+
+/// Asymmetric algorithms accepted for `id_token` signatures.
+#[cfg(feature = "ssr")]
+const ALLOWED_ALGORITHMS: [Algorithm; 9] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// A configured provider as shown on the sign-in form.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OidcProviderInfo {
+    pub name: String,
+    pub display_name: String,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct OidcProviderConfig {
+    /// Lowercase letters, digits, `-` and `_`, not the name of a built-in provider.
+    pub name: String,
+    pub display_name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Requested with `openid`.
+    pub scopes: Vec<String>,
+    /// Use the `email` claim even without `email_verified`. Such an email is only set on newly
+    /// created users, it never links the sign-in to an existing account.
+    pub trust_email: bool,
+}
+
+#[cfg(feature = "ssr")]
+impl OidcProviderConfig {
+    pub fn new(
+        name: impl Into<String>,
+        issuer: impl Into<String>,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Self {
+        let name = name.into();
+
+        Self {
+            display_name: name.clone(),
+            name,
+            issuer: issuer.into(),
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            scopes: vec!["email".to_string(), "profile".to_string()],
+            trust_email: false,
+        }
+    }
+
+    /// From `{NAME}_ISSUER`, `{NAME}_CLIENT_ID`, `{NAME}_CLIENT_SECRET` and optionally
+    /// `{NAME}_DISPLAY_NAME`, `{NAME}_SCOPES` (space separated) and `{NAME}_TRUST_EMAIL`.
+    pub fn from_env(name: &str) -> Result<Self, AppError> {
+        let prefix = name.to_uppercase().replace('-', "_");
+        let var = |key: &str| std::env::var(format!("{}_{}", prefix, key));
+        let required = |key: &str| {
+            var(key).map_err(|_| {
+                AppError::EnvVarError(format!("Missing {}_{} environment variable", prefix, key))
+            })
+        };
+
+        let mut config = Self::new(
+            name,
+            required("ISSUER")?,
+            required("CLIENT_ID")?,
+            required("CLIENT_SECRET")?,
+        );
+
+        if let Ok(display_name) = var("DISPLAY_NAME") {
+            config = config.with_display_name(display_name);
+        }
+        if let Ok(scopes) = var("SCOPES") {
+            config = config.with_scopes(scopes.split_whitespace().map(String::from).collect());
+        }
+        if let Ok(trust) = var("TRUST_EMAIL") {
+            config = config.with_trusted_email(trust == "true" || trust == "1");
+        }
+
+        Ok(config)
+    }
+
+    pub fn with_display_name(mut self, display_name: impl Into<String>) -> Self {
+        self.display_name = display_name.into();
+        self
+    }
+
+    pub fn with_scopes(mut self, scopes: Vec<String>) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    pub fn with_trusted_email(mut self, trust_email: bool) -> Self {
+        self.trust_email = trust_email;
+        self
+    }
+
+    fn validate(&self) -> Result<(), AppError> {
+        let valid_name = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+
+        if !valid_name
+            || !matches!(
+                OAuthProvider::from(self.name.clone()),
+                OAuthProvider::Oidc(_)
+            )
+        {
+            return Err(AppError::Config(format!(
+                "Invalid OIDC provider name: {}",
+                self.name
+            )));
+        }
+
+        if !self.issuer.starts_with("https://") && !self.issuer.starts_with("http://") {
+            return Err(AppError::Config(format!(
+                "Invalid issuer URL for {}: {}",
+                self.name, self.issuer
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// The parts of the discovery document that are used.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    #[serde(default)]
+    pub userinfo_endpoint: Option<String>,
+    pub jwks_uri: String,
+    #[serde(default)]
+    pub token_endpoint_auth_methods_supported: Option<Vec<String>>,
+}
+
+/// Claims of a validated `id_token`.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    #[serde(default)]
+    pub aud: serde_json::Value,
+    #[serde(default)]
+    pub azp: Option<String>,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: Option<bool>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub preferred_username: Option<String>,
+    #[serde(default)]
+    pub picture: Option<String>,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    id_token: Option<String>,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Default)]
+struct OidcCache {
+    metadata: Option<(OidcMetadata, Instant)>,
+    jwks: Option<(Vec<Jwk>, Instant)>,
+}
+
+/// A registered provider with its cached metadata and signing keys.
+#[cfg(feature = "ssr")]
+pub struct OidcProvider {
+    pub config: OidcProviderConfig,
+    cache: Mutex<OidcCache>,
+}
+
+#[cfg(feature = "ssr")]
+fn oidc_error(message: impl std::fmt::Display) -> AppError {
+    AppError::AuthError(format!("OIDC: {}", message))
+}
+
+#[cfg(feature = "ssr")]
+async fn fetch_json<T: serde::de::DeserializeOwned>(url: &str) -> Result<T, AppError> {
+    reqwest::Client::new()
+        .get(url)
+        .header("User-Agent", "Tinkr-OAuth-Client")
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| oidc_error(format!("could not fetch {}: {}", url, e)))?
+        .json()
+        .await
+        .map_err(|e| oidc_error(format!("could not parse {}: {}", url, e)))
+}
+
+#[cfg(feature = "ssr")]
+impl OidcProvider {
+    pub fn new(config: OidcProviderConfig) -> Result<Self, AppError> {
+        config.validate()?;
+
+        Ok(Self {
+            config,
+            cache: Mutex::new(OidcCache::default()),
+        })
+    }
+
+    pub fn info(&self) -> OidcProviderInfo {
+        OidcProviderInfo {
+            name: self.config.name.clone(),
+            display_name: self.config.display_name.clone(),
+        }
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, OidcCache> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The discovery document, cached for `METADATA_TTL`.
+    pub async fn metadata(&self) -> Result<OidcMetadata, AppError> {
+        if let Some((metadata, fetched)) = &self.cache().metadata
+            && fetched.elapsed() < METADATA_TTL
+        {
+            return Ok(metadata.clone());
+        }
+
+        let issuer = self.config.issuer.trim_end_matches('/');
+        let metadata: OidcMetadata =
+            fetch_json(&format!("{}/.well-known/openid-configuration", issuer)).await?;
+
+        if metadata.issuer.trim_end_matches('/') != issuer {
+            return Err(oidc_error(format!(
+                "discovery document of {} names issuer {}",
+                issuer, metadata.issuer
+            )));
+        }
+
+        self.cache().metadata = Some((metadata.clone(), Instant::now()));
+        Ok(metadata)
+    }
+
+    /// The signing key `kid`, fetching the keys again if it is unknown (key rotation).
+    async fn signing_key(
+        &self,
+        metadata: &OidcMetadata,
+        kid: Option<&str>,
+    ) -> Result<Jwk, AppError> {
+        let find = |keys: &[Jwk]| {
+            keys.iter()
+                .find(|key| kid.is_none() || key.common.key_id.as_deref() == kid)
+                .cloned()
+        };
+
+        let refetch = match &self.cache().jwks {
+            Some((keys, fetched)) => match find(keys) {
+                Some(key) if fetched.elapsed() < JWKS_TTL => return Ok(key),
+                Some(_) => true,
+                None => fetched.elapsed() >= JWKS_MIN_REFRESH,
+            },
+            None => true,
+        };
+
+        if refetch {
+            let jwks: serde_json::Value = fetch_json(&metadata.jwks_uri).await?;
+
+            // skip keys this library can't read, and encryption keys
+            let keys: Vec<Jwk> = jwks["keys"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|key| key["use"].as_str() != Some("enc"))
+                .filter_map(|key| serde_json::from_value(key.clone()).ok())
+                .collect();
+
+            self.cache().jwks = Some((keys, Instant::now()));
+        }
+
+        self.cache()
+            .jwks
+            .as_ref()
+            .and_then(|(keys, _)| find(keys))
+            .ok_or_else(|| oidc_error("unknown signing key"))
+    }
+
+    /// Checks the signature, `iss`, `aud`, `azp`, `exp` and `nonce` of an `id_token`.
+    pub async fn validate_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, AppError> {
+        let metadata = self.metadata().await?;
+
+        let header = jsonwebtoken::decode_header(id_token).map_err(oidc_error)?;
+        if !ALLOWED_ALGORITHMS.contains(&header.alg) {
+            return Err(oidc_error(format!(
+                "unsupported algorithm {:?}",
+                header.alg
+            )));
+        }
+
+        let jwk = self.signing_key(&metadata, header.kid.as_deref()).await?;
+        let key = DecodingKey::from_jwk(&jwk).map_err(oidc_error)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(oidc_error)?
+            .claims;
+
+        // with several audiences, the token must have been issued to us
+        let audiences = claims.aud.as_array().map_or(1, |aud| aud.len());
+        if audiences > 1 && claims.azp.as_deref() != Some(self.config.client_id.as_str()) {
+            return Err(oidc_error("token was issued to another party"));
+        }
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(oidc_error("nonce mismatch"));
+        }
+
+        Ok(claims)
+    }
+
+    /// Exchanges an authorization code, returns the access token and the `id_token`.
+    async fn exchange_code(
+        &self,
+        metadata: &OidcMetadata,
+        code: &str,
+        pkce_verifier: &str,
+    ) -> Result<(String, String), AppError> {
+        let redirect_url = format!(
+            "{}/api/auth/callback/{}",
+            OAuthConfig::get_redirect_url(),
+            self.config.name
+        );
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &redirect_url),
+            ("code_verifier", pkce_verifier),
+        ];
+
+        // client_secret_basic is the default when the provider doesn't say
+        let basic = metadata
+            .token_endpoint_auth_methods_supported
+            .as_ref()
+            .is_none_or(|methods| methods.iter().any(|m| m == "client_secret_basic"));
+
+        let mut request = reqwest::Client::new().post(&metadata.token_endpoint);
+        if basic {
+            request = request.basic_auth(
+                urlencoding::encode(&self.config.client_id),
+                Some(urlencoding::encode(&self.config.client_secret)),
+            );
+        } else {
+            form.push(("client_id", &self.config.client_id));
+            form.push(("client_secret", &self.config.client_secret));
+        }
+
+        let tokens: TokenResponse = request
+            .form(&form)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| oidc_error(format!("failed to exchange code for token: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| oidc_error(format!("could not parse token response: {}", e)))?;
+
+        let id_token = tokens
+            .id_token
+            .ok_or_else(|| oidc_error("token response has no id_token"))?;
+
+        Ok((tokens.access_token, id_token))
+    }
+
+    /// Maps validated claims to the user, only using verified or trusted email addresses.
+    pub fn user_info_from_claims(&self, claims: IdTokenClaims) -> OAuthUserInfo {
+        let email_verified = claims.email_verified == Some(true);
+        let email_trusted = self.config.trust_email || email_verified;
+
+        OAuthUserInfo {
+            id: claims.sub,
+            email: claims.email.filter(|_| email_trusted),
+            email_verified,
+            name: claims.name.or(claims.preferred_username),
+            avatar: claims.picture,
+        }
+    }
+
+    /// Completes a sign-in: exchanges the code, validates the `id_token` and reads the user from
+    /// its claims, asking the userinfo endpoint when they have no email.
+    pub async fn complete_signin(
+        &self,
+        code: &str,
+        pkce_verifier: &str,
+        nonce: &str,
+    ) -> Result<OAuthUserInfo, AppError> {
+        let metadata = self.metadata().await?;
+        let (access_token, id_token) = self.exchange_code(&metadata, code, pkce_verifier).await?;
+        let mut claims = self.validate_id_token(&id_token, nonce).await?;
+
+        if claims.email.is_none()
+            && let Some(userinfo_endpoint) = &metadata.userinfo_endpoint
+        {
+            let userinfo: serde_json::Value = reqwest::Client::new()
+                .get(userinfo_endpoint)
+                .bearer_auth(&access_token)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| oidc_error(format!("could not fetch user info: {}", e)))?
+                .json()
+                .await
+                .map_err(|e| oidc_error(format!("could not parse user info: {}", e)))?;
+
+            // the userinfo response must be about the same user
+            if userinfo["sub"].as_str() == Some(claims.sub.as_str()) {
+                claims.email = userinfo["email"].as_str().map(String::from);
+                claims.email_verified = userinfo["email_verified"].as_bool();
+                claims.name = claims.name.or(userinfo["name"].as_str().map(String::from));
+            }
+        }
+
+        Ok(self.user_info_from_claims(claims))
+    }
+
+    /// The `OAuthConfig` of the discovered endpoints, for building the authorization URL.
+    pub async fn oauth_config(&self) -> Result<OAuthConfig, AppError> {
+        let metadata = self.metadata().await?;
+
+        Ok(OAuthConfig {
+            provider: OAuthProvider::Oidc(self.config.name.clone()),
+            client_id: self.config.client_id.clone(),
+            client_secret: self.config.client_secret.clone(),
+            auth_url: metadata.authorization_endpoint,
+            token_url: metadata.token_endpoint,
+            user_info_url: metadata.userinfo_endpoint.unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(feature = "ssr")]
+static OIDC_PROVIDERS: LazyLock<RwLock<HashMap<String, Arc<OidcProvider>>>> = LazyLock::new(|| {
+    let mut providers = HashMap::new();
+
+    let names = std::env::var(OIDC_PROVIDERS_ENV).unwrap_or_default();
+    for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        match OidcProviderConfig::from_env(name).and_then(OidcProvider::new) {
+            Ok(provider) => {
+                providers.insert(name.to_string(), Arc::new(provider));
+            }
+            Err(e) => tracing::error!(provider = name, error = %e, "Skipping OIDC provider"),
+        }
+    }
+
+    RwLock::new(providers)
+});
+
+/// Adds or replaces a provider, next to the ones from `OIDC_PROVIDERS_ENV`.
+#[cfg(feature = "ssr")]
+pub fn register_oidc_provider(config: OidcProviderConfig) -> Result<(), AppError> {
+    let provider = OidcProvider::new(config)?;
+
+    OIDC_PROVIDERS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(provider.config.name.clone(), Arc::new(provider));
+
+    Ok(())
+}
+
+#[cfg(feature = "ssr")]
+pub fn oidc_provider(name: &str) -> Result<Arc<OidcProvider>, AppError> {
+    OIDC_PROVIDERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned()
+        .ok_or_else(|| AppError::Config(format!("Unknown OIDC provider: {}", name)))
+}
+
+/// The registered providers, sorted by name.
+#[server]
+pub async fn list_oidc_providers() -> Result<Vec<OidcProviderInfo>, AppError> {
+    let mut providers: Vec<OidcProviderInfo> = OIDC_PROVIDERS
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .values()
+        .map(|provider| provider.info())
+        .collect();
+
+    providers.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(providers)
+}
+
+/// A "Continue with ..." button for each registered provider.
+#[component]
+pub fn OidcSignInButtons(#[prop(into)] callback_url: Signal<String>) -> impl IntoView {
+    use crate::auth::oauth::OAuthProvider;
+    use crate::auth::ui_auth::oauth_signin;
+
+    let providers = Resource::new(|| (), |_| list_oidc_providers());
+    let (error_message, set_error_message) = signal(Option::<String>::None);
+
+    let on_signin = move |name: String| {
+        set_error_message.set(None);
+        spawn_local(async move {
+            match oauth_signin(
+                OAuthProvider::Oidc(name),
+                Some(callback_url.get_untracked()),
+            )
+            .await
+            {
+                Ok(url) => {
+                    window().location().set_href(&url).unwrap();
+                }
+                Err(_e) => {
+                    #[cfg(feature = "ssr")]
+                    tracing::error!("OIDC error: {:?}", _e);
+
+                    set_error_message.set(Some(_e.to_string()));
+                }
+            }
+        });
+    };
+
+    view! {
+        <Suspense fallback=|| ()>
+            {move || {
+                providers
+                    .get()
+                    .and_then(Result::ok)
+                    .map(|providers| {
+                        providers
+                            .into_iter()
+                            .map(|provider| {
+                                let name = provider.name.clone();
+                                view! {
+                                    <button
+                                        type="button"
+                                        on:click=move |_| on_signin(name.clone())
+                                        class="w-full flex items-center justify-center gap-3 bg-white hover:bg-neutral-50 dark:bg-neutral-800 dark:hover:bg-neutral-700 text-neutral-800 dark:text-white border border-neutral-300 dark:border-neutral-600 px-4 py-3 rounded-md font-semibold duration-150"
+                                    >
+                                        {format!("Continue with {}", provider.display_name)}
+                                    </button>
+                                }
+                            })
+                            .collect_view()
+                    })
+            }}
+        </Suspense>
+
+        {move || {
+            error_message
+                .get()
+                .map(|error| {
+                    view! { <div class="text-center text-red-600 dark:text-red-400">{error}</div> }
+                })
+        }}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, routing::get, routing::post};
+    use jsonwebtoken::{EncodingKey, Header};
+    use ring::rand::SystemRandom;
+    use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+
+    const CLIENT_ID: &str = "tinkr-test";
+
+    fn base64url(bytes: &[u8]) -> String {
+        use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// A local issuer serving discovery, an ES256 key set and a token endpoint that returns
+    /// `id_token`.
+    async fn mock_issuer(public_point: Vec<u8>, id_token: Arc<Mutex<String>>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let metadata = serde_json::json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}/authorize"),
+            "token_endpoint": format!("{issuer}/token"),
+            "jwks_uri": format!("{issuer}/jwks"),
+            "token_endpoint_auth_methods_supported": ["client_secret_post"],
+        });
+        let jwks = serde_json::json!({ "keys": [
+            { "kty": "RSA", "use": "enc", "kid": "enc", "alg": "RSA-OAEP", "n": "AQAB", "e": "AQAB" },
+            {
+                "kty": "EC",
+                "use": "sig",
+                "kid": "key-1",
+                "alg": "ES256",
+                "crv": "P-256",
+                "x": base64url(&public_point[1..33]),
+                "y": base64url(&public_point[33..]),
+            },
+        ]});
+
+        let app = Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get(move || async move { Json(metadata) }),
+            )
+            .route("/jwks", get(move || async move { Json(jwks) }))
+            .route(
+                "/token",
+                post(move || async move {
+                    let id_token = id_token.lock().unwrap().clone();
+                    Json(serde_json::json!({
+                        "access_token": "access",
+                        "token_type": "Bearer",
+                        "id_token": id_token,
+                    }))
+                }),
+            );
+
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        issuer
+    }
+
+    fn sign(pkcs8: &[u8], kid: &str, claims: serde_json::Value) -> String {
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(kid.to_string());
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_ec_der(pkcs8)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_oidc_mock_issuer() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+            .unwrap()
+            .as_ref()
+            .to_vec();
+        let public_point = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng)
+            .unwrap()
+            .public_key()
+            .as_ref()
+            .to_vec();
+
+        let id_token = Arc::new(Mutex::new(String::new()));
+        let issuer = mock_issuer(public_point, id_token.clone()).await;
+
+        let provider = OidcProvider::new(OidcProviderConfig::new(
+            "mock",
+            issuer.clone(),
+            CLIENT_ID,
+            "secret",
+        ))
+        .unwrap();
+
+        let claims = |aud: serde_json::Value, nonce: &str, exp: u64| {
+            serde_json::json!({
+                "iss": issuer,
+                "sub": "user-1",
+                "aud": aud,
+                "exp": exp,
+                "iat": now(),
+                "nonce": nonce,
+                "email": "ada@example.com",
+                "email_verified": true,
+                "preferred_username": "ada",
+            })
+        };
+
+        let valid = sign(
+            &pkcs8,
+            "key-1",
+            claims(CLIENT_ID.into(), "n-1", now() + 300),
+        );
+        *id_token.lock().unwrap() = valid.clone();
+
+        let user = provider
+            .complete_signin("code", "verifier", "n-1")
+            .await
+            .unwrap();
+        assert_eq!(user.id, "user-1");
+        assert_eq!(user.email.as_deref(), Some("ada@example.com"));
+        assert!(user.email_verified);
+        assert_eq!(user.name.as_deref(), Some("ada"));
+
+        assert!(provider.validate_id_token(&valid, "n-2").await.is_err());
+
+        let expired = sign(
+            &pkcs8,
+            "key-1",
+            claims(CLIENT_ID.into(), "n-1", now() - 600),
+        );
+        assert!(provider.validate_id_token(&expired, "n-1").await.is_err());
+
+        let other_audience = sign(
+            &pkcs8,
+            "key-1",
+            claims("someone-else".into(), "n-1", now() + 300),
+        );
+        assert!(
+            provider
+                .validate_id_token(&other_audience, "n-1")
+                .await
+                .is_err()
+        );
+
+        let shared_audience = sign(
+            &pkcs8,
+            "key-1",
+            claims(
+                serde_json::json!([CLIENT_ID, "someone-else"]),
+                "n-1",
+                now() + 300,
+            ),
+        );
+        assert!(
+            provider
+                .validate_id_token(&shared_audience, "n-1")
+                .await
+                .is_err()
+        );
+
+        let unknown_key = sign(
+            &pkcs8,
+            "key-2",
+            claims(CLIENT_ID.into(), "n-1", now() + 300),
+        );
+        assert!(
+            provider
+                .validate_id_token(&unknown_key, "n-1")
+                .await
+                .is_err()
+        );
+
+        // signed by another key under the known kid
+        let other_pkcs8 =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let forged = sign(
+            other_pkcs8.as_ref(),
+            "key-1",
+            claims(CLIENT_ID.into(), "n-1", now() + 300),
+        );
+        assert!(provider.validate_id_token(&forged, "n-1").await.is_err());
+
+        let wrong_issuer = OidcProvider::new(OidcProviderConfig::new(
+            "wrong",
+            format!("{}/realms/other", issuer),
+            CLIENT_ID,
+            "secret",
+        ))
+        .unwrap();
+        assert!(wrong_issuer.metadata().await.is_err());
+    }
+
+    #[test]
+    fn test_oidc_provider_config() {
+        assert!(
+            OidcProvider::new(OidcProviderConfig::new("google", "https://a", "id", "s")).is_err()
+        );
+        assert!(
+            OidcProvider::new(OidcProviderConfig::new("Key Cloak", "https://a", "id", "s"))
+                .is_err()
+        );
+        assert!(
+            OidcProvider::new(OidcProviderConfig::new("keycloak", "sso.local", "id", "s")).is_err()
+        );
+
+        let provider = OidcProvider::new(OidcProviderConfig::new(
+            "keycloak",
+            "https://sso.local",
+            "id",
+            "s",
+        ))
+        .unwrap();
+
+        let unverified = IdTokenClaims {
+            sub: "1".into(),
+            aud: serde_json::Value::Null,
+            azp: None,
+            nonce: None,
+            email: Some("ada@example.com".into()),
+            email_verified: None,
+            name: Some("Ada".into()),
+            preferred_username: Some("ada".into()),
+            picture: None,
+        };
+        assert_eq!(
+            provider.user_info_from_claims(unverified.clone()).email,
+            None
+        );
+
+        let trusted = OidcProvider::new(
+            OidcProviderConfig::new("corp", "https://sso.local", "id", "s")
+                .with_trusted_email(true),
+        )
+        .unwrap();
+        let user = trusted.user_info_from_claims(unverified.clone());
+        assert_eq!(user.email.as_deref(), Some("ada@example.com"));
+        assert!(!user.email_verified);
+        assert_eq!(user.name.as_deref(), Some("Ada"));
+
+        assert_eq!(
+            OAuthProvider::from("keycloak".to_string()),
+            OAuthProvider::Oidc("keycloak".into())
+        );
+        assert_eq!(
+            OAuthProvider::from("github".to_string()),
+            OAuthProvider::Github
+        );
+        assert_eq!(
+            serde_json::to_string(&OAuthProvider::Oidc("entra".into())).unwrap(),
+            "\"entra\""
+        );
+    }
+}
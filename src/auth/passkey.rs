@@ -0,0 +1,1353 @@
+//! Passkey (WebAuthn) sign-in and step-up verification.
+//!
+//! Signed in users register passkeys from `PasskeySettings`, afterwards `PasskeySignInButton`
+//! signs them in without an email or password. Sensitive operations call
+//! `require_recent_reverification` on the server and `reverify_with_passkey` in the browser, so
+//! a stolen session cookie alone can't reveal a wallet private key.
+//!
+//! Attestation is "none", authenticators are not verified, only their ES256 (P-256) and EdDSA
+//! (Ed25519) public keys are stored in the `passkey` table. Challenges are single use and kept in
+//! `webauthn_challenge` for `CHALLENGE_TIMEOUT_SECONDS`. The ceremonies are checked by
+//! `RelyingParty::verify_registration` and `RelyingParty::verify_authentication`, which need no
+//! database and can be driven by a software authenticator in tests.
+
+use leptos::{prelude::*, reactive::spawn_local};
+use leptos_router::hooks::use_query_map;
+use serde::{Deserialize, Serialize};
+
+use crate::AppError;
+
+#[cfg(feature = "ssr")]
+use surrealdb::{Datetime, RecordId};
+
+#[cfg(not(feature = "ssr"))]
+use crate::{Datetime, RecordId};
+
+#[cfg(feature = "ssr")]
+use crate::{db_init, session::AdapterSession, token_hash::hash_token, user::AdapterUser};
+
+/// Display name of the relying party shown by authenticators, `Tinkr` when unset.
+pub const PASSKEY_RP_NAME_ENV: &str = "TINKR_PASSKEY_RP_NAME";
+
+pub const CHALLENGE_TIMEOUT_SECONDS: u64 = 300;
+
+/// How long a passkey verification satisfies `require_recent_reverification`.
+pub const REVERIFY_WINDOW_MINUTES: i64 = 5;
+
+/// COSE algorithm identifiers of the supported public keys.
+pub const COSE_ES256: i64 = -7;
+pub const COSE_EDDSA: i64 = -8;
+
+const PURPOSE_REGISTRATION: &str = "registration";
+const PURPOSE_SIGNIN: &str = "signin";
+const PURPOSE_REVERIFY: &str = "reverify";
+
+// authenticator data flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// A registered passkey as shown to its user.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasskeyInfo {
+    pub id: RecordId,
+    pub name: String,
+    pub created_at: Option<Datetime>,
+    pub last_used: Option<Datetime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelyingPartyEntity {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    /// Base64url user handle, returned by the authenticator on sign-in.
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CredentialParameter {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub alg: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Base64url credential id.
+    pub id: String,
+}
+
+impl CredentialDescriptor {
+    pub fn public_key(id: String) -> Self {
+        Self {
+            kind: "public-key".to_string(),
+            id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+/// `publicKey` options of `navigator.credentials.create`, binary fields base64url encoded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialCreationOptions {
+    pub rp: RelyingPartyEntity,
+    pub user: UserEntity,
+    pub challenge: String,
+    pub pub_key_cred_params: Vec<CredentialParameter>,
+    pub timeout: u64,
+    pub attestation: String,
+    pub authenticator_selection: AuthenticatorSelection,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+}
+
+/// `publicKey` options of `navigator.credentials.get`, binary fields base64url encoded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialRequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    pub timeout: u64,
+    pub user_verification: String,
+    /// Empty to let the user pick any discoverable passkey for the site.
+    pub allow_credentials: Vec<CredentialDescriptor>,
+}
+
+/// The new credential from `navigator.credentials.create`, binary fields base64url encoded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationResponse {
+    pub id: String,
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// The assertion from `navigator.credentials.get`, binary fields base64url encoded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationResponse {
+    pub id: String,
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(default)]
+    pub user_handle: Option<String>,
+}
+
+/// A credential accepted by `RelyingParty::verify_registration`.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedCredential {
+    /// Base64url credential id.
+    pub credential_id: String,
+    /// Uncompressed P-256 point for ES256, the raw key for EdDSA.
+    pub public_key: Vec<u8>,
+    pub algorithm: i64,
+    pub sign_count: u32,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+    #[serde(default, rename = "crossOrigin")]
+    cross_origin: bool,
+}
+
+#[cfg(feature = "ssr")]
+struct AuthenticatorData {
+    rp_id_hash: Vec<u8>,
+    flags: u8,
+    sign_count: u32,
+    /// Credential id, COSE public key and its algorithm, present on registration.
+    attested: Option<(Vec<u8>, Vec<u8>, i64)>,
+}
+
+#[cfg(feature = "ssr")]
+fn invalid(reason: &str) -> AppError {
+    AppError::AuthError(format!("Invalid passkey response: {}", reason))
+}
+
+#[cfg(feature = "ssr")]
+pub fn base64url_encode(bytes: &[u8]) -> String {
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[cfg(feature = "ssr")]
+pub fn base64url_decode(value: &str) -> Result<Vec<u8>, AppError> {
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| invalid("bad base64url"))
+}
+
+/// The base64url challenge in `clientDataJSON`, used to find the stored challenge.
+#[cfg(feature = "ssr")]
+pub fn response_challenge(client_data_json: &str) -> Result<String, AppError> {
+    let client_data: ClientData = serde_json::from_slice(&base64url_decode(client_data_json)?)
+        .map_err(|_| invalid("bad client data"))?;
+    Ok(client_data.challenge)
+}
+
+#[cfg(feature = "ssr")]
+fn cose_field(map: &[(ciborium::Value, ciborium::Value)], key: i64) -> Option<&ciborium::Value> {
+    map.iter()
+        .find(|(k, _)| k.as_integer().is_some_and(|k| i128::from(k) == key as i128))
+        .map(|(_, v)| v)
+}
+
+/// The public key and algorithm of a COSE_Key.
+#[cfg(feature = "ssr")]
+fn parse_cose_key(bytes: &[u8]) -> Result<(Vec<u8>, i64), AppError> {
+    let value: ciborium::Value =
+        ciborium::from_reader(bytes).map_err(|_| invalid("bad public key"))?;
+    let map = value.as_map().ok_or_else(|| invalid("bad public key"))?;
+
+    let int = |key| {
+        cose_field(map, key)
+            .and_then(|v| v.as_integer())
+            .map(i128::from)
+    };
+    let bytes = |key| {
+        cose_field(map, key)
+            .and_then(|v| v.as_bytes())
+            .filter(|b| b.len() == 32)
+            .cloned()
+    };
+
+    // kty 1, alg 3, crv -1, x -2, y -3
+    match (int(1), int(3), int(-1)) {
+        (Some(2), Some(alg), Some(1)) if alg == COSE_ES256 as i128 => {
+            let (x, y) = bytes(-2)
+                .zip(bytes(-3))
+                .ok_or_else(|| invalid("bad P-256 key"))?;
+            let mut point = vec![0x04];
+            point.extend(x);
+            point.extend(y);
+            Ok((point, COSE_ES256))
+        }
+        (Some(1), Some(alg), Some(6)) if alg == COSE_EDDSA as i128 => {
+            let x = bytes(-2).ok_or_else(|| invalid("bad Ed25519 key"))?;
+            Ok((x, COSE_EDDSA))
+        }
+        _ => Err(invalid("unsupported public key algorithm")),
+    }
+}
+
+#[cfg(feature = "ssr")]
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData, AppError> {
+    if data.len() < 37 {
+        return Err(invalid("authenticator data too short"));
+    }
+
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    let attested = if flags & FLAG_ATTESTED_CREDENTIAL != 0 {
+        // 16 byte AAGUID, 2 byte length, credential id, COSE key
+        let rest = data.get(37 + 16..).ok_or_else(|| invalid("truncated"))?;
+        let (len, rest) = rest
+            .split_at_checked(2)
+            .ok_or_else(|| invalid("truncated"))?;
+        let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+        let (credential_id, key) = rest
+            .split_at_checked(len)
+            .ok_or_else(|| invalid("truncated"))?;
+        let (public_key, algorithm) = parse_cose_key(key)?;
+        Some((credential_id.to_vec(), public_key, algorithm))
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        rp_id_hash: data[..32].to_vec(),
+        flags,
+        sign_count,
+        attested,
+    })
+}
+
+/// The site passkeys are registered for, its id is the host of its origin.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelyingParty {
+    pub id: String,
+    pub origin: String,
+    pub name: String,
+}
+
+#[cfg(feature = "ssr")]
+impl RelyingParty {
+    pub fn new(origin: &str, name: &str) -> Self {
+        let origin = origin.trim_end_matches('/');
+        let (scheme, rest) = origin.split_once("://").unwrap_or(("https", origin));
+        let authority = rest.split('/').next().unwrap_or(rest);
+        let host = authority
+            .rsplit_once(':')
+            .map_or(authority, |(host, _)| host);
+
+        Self {
+            id: host.to_string(),
+            origin: format!("{}://{}", scheme, authority),
+            name: name.to_string(),
+        }
+    }
+
+    /// From `TINKR_AUTH_URL` and `PASSKEY_RP_NAME_ENV`.
+    pub fn from_env() -> Self {
+        let origin =
+            std::env::var("TINKR_AUTH_URL").unwrap_or_else(|_| "http://localhost:3000".to_string());
+        let name = std::env::var(PASSKEY_RP_NAME_ENV).unwrap_or_else(|_| "Tinkr".to_string());
+        Self::new(&origin, &name)
+    }
+
+    pub fn creation_options(
+        &self,
+        challenge: String,
+        user: &AdapterUser,
+        exclude: Vec<String>,
+    ) -> CredentialCreationOptions {
+        CredentialCreationOptions {
+            rp: RelyingPartyEntity {
+                id: self.id.clone(),
+                name: self.name.clone(),
+            },
+            user: UserEntity {
+                id: base64url_encode(user.id.to_string().as_bytes()),
+                name: user.email.0.clone(),
+                display_name: user.name.clone(),
+            },
+            challenge,
+            pub_key_cred_params: [COSE_ES256, COSE_EDDSA]
+                .into_iter()
+                .map(|alg| CredentialParameter {
+                    kind: "public-key".to_string(),
+                    alg,
+                })
+                .collect(),
+            timeout: CHALLENGE_TIMEOUT_SECONDS * 1000,
+            attestation: "none".to_string(),
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "required".to_string(),
+                user_verification: "required".to_string(),
+            },
+            exclude_credentials: exclude
+                .into_iter()
+                .map(CredentialDescriptor::public_key)
+                .collect(),
+        }
+    }
+
+    pub fn request_options(
+        &self,
+        challenge: String,
+        allow: Vec<String>,
+    ) -> CredentialRequestOptions {
+        CredentialRequestOptions {
+            challenge,
+            rp_id: self.id.clone(),
+            timeout: CHALLENGE_TIMEOUT_SECONDS * 1000,
+            user_verification: "required".to_string(),
+            allow_credentials: allow
+                .into_iter()
+                .map(CredentialDescriptor::public_key)
+                .collect(),
+        }
+    }
+
+    /// Checks the client data of a ceremony, returns its raw bytes for the signature.
+    fn verify_client_data(
+        &self,
+        client_data_json: &str,
+        kind: &str,
+        challenge: &str,
+    ) -> Result<Vec<u8>, AppError> {
+        let raw = base64url_decode(client_data_json)?;
+        let client_data: ClientData =
+            serde_json::from_slice(&raw).map_err(|_| invalid("bad client data"))?;
+
+        if client_data.kind != kind {
+            return Err(invalid("wrong ceremony"));
+        }
+        if client_data.challenge != challenge {
+            return Err(invalid("wrong challenge"));
+        }
+        if client_data.origin != self.origin || client_data.cross_origin {
+            return Err(invalid("wrong origin"));
+        }
+
+        Ok(raw)
+    }
+
+    fn verify_authenticator_data(&self, data: &AuthenticatorData) -> Result<(), AppError> {
+        use sha2::{Digest, Sha256};
+
+        if data.rp_id_hash[..] != Sha256::digest(self.id.as_bytes())[..] {
+            return Err(invalid("wrong relying party"));
+        }
+        if data.flags & FLAG_USER_PRESENT == 0 || data.flags & FLAG_USER_VERIFIED == 0 {
+            return Err(invalid("user not verified"));
+        }
+
+        Ok(())
+    }
+
+    /// Verifies a `navigator.credentials.create` response to `challenge`.
+    pub fn verify_registration(
+        &self,
+        response: &RegistrationResponse,
+        challenge: &str,
+    ) -> Result<VerifiedCredential, AppError> {
+        self.verify_client_data(&response.client_data_json, "webauthn.create", challenge)?;
+
+        let attestation: ciborium::Value =
+            ciborium::from_reader(base64url_decode(&response.attestation_object)?.as_slice())
+                .map_err(|_| invalid("bad attestation object"))?;
+
+        // attestation "none", the format and statement are not checked
+        let auth_data = attestation
+            .as_map()
+            .and_then(|map| {
+                map.iter()
+                    .find(|(k, _)| k.as_text() == Some("authData"))
+                    .and_then(|(_, v)| v.as_bytes())
+            })
+            .ok_or_else(|| invalid("missing authenticator data"))?;
+
+        let data = parse_authenticator_data(auth_data)?;
+        self.verify_authenticator_data(&data)?;
+
+        let (credential_id, public_key, algorithm) =
+            data.attested.ok_or_else(|| invalid("missing credential"))?;
+
+        let credential_id = base64url_encode(&credential_id);
+        if credential_id != response.id.trim_end_matches('=') {
+            return Err(invalid("credential id mismatch"));
+        }
+
+        Ok(VerifiedCredential {
+            credential_id,
+            public_key,
+            algorithm,
+            sign_count: data.sign_count,
+        })
+    }
+
+    /// Verifies a `navigator.credentials.get` response to `challenge` against a stored public
+    /// key, returns the new signature counter.
+    pub fn verify_authentication(
+        &self,
+        response: &AuthenticationResponse,
+        challenge: &str,
+        public_key: &[u8],
+        algorithm: i64,
+        stored_sign_count: u32,
+    ) -> Result<u32, AppError> {
+        use ring::signature::{
+            ECDSA_P256_SHA256_ASN1, ED25519, UnparsedPublicKey, VerificationAlgorithm,
+        };
+        use sha2::{Digest, Sha256};
+
+        let client_data =
+            self.verify_client_data(&response.client_data_json, "webauthn.get", challenge)?;
+
+        let auth_data = base64url_decode(&response.authenticator_data)?;
+        let data = parse_authenticator_data(&auth_data)?;
+        self.verify_authenticator_data(&data)?;
+
+        let verification: &'static dyn VerificationAlgorithm = match algorithm {
+            COSE_ES256 => &ECDSA_P256_SHA256_ASN1,
+            COSE_EDDSA => &ED25519,
+            _ => return Err(invalid("unsupported public key algorithm")),
+        };
+
+        let mut signed = auth_data;
+        signed.extend_from_slice(&Sha256::digest(&client_data));
+
+        UnparsedPublicKey::new(verification, public_key)
+            .verify(&signed, &base64url_decode(&response.signature)?)
+            .map_err(|_| invalid("bad signature"))?;
+
+        // authenticators without a counter always send 0, otherwise it must grow
+        if (data.sign_count != 0 || stored_sign_count != 0) && data.sign_count <= stored_sign_count
+        {
+            tracing::warn!(
+                credential = %response.id,
+                "Passkey signature counter went backwards, possibly cloned"
+            );
+            return Err(invalid("signature counter went backwards"));
+        }
+
+        Ok(data.sign_count)
+    }
+}
+
+/// A registered passkey.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct Passkey {
+    pub id: RecordId,
+    pub user_id: RecordId,
+    pub credential_id: String,
+    /// Base64url public key, see `VerifiedCredential::public_key`.
+    public_key: String,
+    pub algorithm: i64,
+    #[serde(default)]
+    pub sign_count: u32,
+    pub name: String,
+    #[serde(default)]
+    pub created_at: Option<Datetime>,
+    #[serde(default)]
+    pub last_used: Option<Datetime>,
+}
+
+#[cfg(feature = "ssr")]
+impl Passkey {
+    pub async fn get_by_credential_id(credential_id: &str) -> Result<Option<Passkey>, AppError> {
+        let client = db_init().await?;
+
+        let mut result = client
+            .query("SELECT * FROM ONLY passkey WHERE credential_id = $credential_id LIMIT 1;")
+            .bind((
+                "credential_id",
+                credential_id.trim_end_matches('=').to_string(),
+            ))
+            .await?;
+
+        let passkey: Option<Passkey> = result.take(0)?;
+        Ok(passkey)
+    }
+
+    pub async fn get_by_user(user_id: RecordId) -> Result<Vec<Passkey>, AppError> {
+        let client = db_init().await?;
+
+        let mut result = client
+            .query("SELECT * FROM passkey WHERE user_id = $user_id ORDER BY created_at;")
+            .bind(("user_id", user_id))
+            .await?;
+
+        let passkeys: Vec<Passkey> = result.take(0)?;
+        Ok(passkeys)
+    }
+
+    pub async fn create(
+        user_id: RecordId,
+        credential: VerifiedCredential,
+        name: String,
+    ) -> Result<Passkey, AppError> {
+        let client = db_init().await?;
+
+        let mut result = client
+            .query(
+                "CREATE ONLY passkey SET user_id = $user_id, credential_id = $credential_id, public_key = $public_key, algorithm = $algorithm, sign_count = $sign_count, name = $name, created_at = time::now(), last_used = NONE;",
+            )
+            .bind(("user_id", user_id))
+            .bind(("credential_id", credential.credential_id))
+            .bind(("public_key", base64url_encode(&credential.public_key)))
+            .bind(("algorithm", credential.algorithm))
+            .bind(("sign_count", credential.sign_count))
+            .bind(("name", name))
+            .await?;
+
+        let passkey: Option<Passkey> = result.take(0)?;
+        passkey.ok_or_else(|| AppError::Conflict("This passkey is already registered".into()))
+    }
+
+    /// Deletes a passkey of `user_id`, returns whether there was one.
+    pub async fn delete(user_id: RecordId, id: RecordId) -> Result<bool, AppError> {
+        let client = db_init().await?;
+
+        let mut result = client
+            .query("DELETE passkey WHERE id = $id AND user_id = $user_id RETURN BEFORE;")
+            .bind(("id", id))
+            .bind(("user_id", user_id))
+            .await?;
+
+        let deleted: Vec<Passkey> = result.take(0)?;
+        Ok(!deleted.is_empty())
+    }
+
+    pub fn to_info(&self) -> PasskeyInfo {
+        PasskeyInfo {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            created_at: self.created_at.clone(),
+            last_used: self.last_used.clone(),
+        }
+    }
+
+    /// Verifies an assertion made with this passkey and stores its new counter.
+    pub async fn authenticate(
+        &self,
+        rp: &RelyingParty,
+        response: &AuthenticationResponse,
+        challenge: &str,
+    ) -> Result<(), AppError> {
+        let sign_count = rp.verify_authentication(
+            response,
+            challenge,
+            &base64url_decode(&self.public_key)?,
+            self.algorithm,
+            self.sign_count,
+        )?;
+
+        if let Some(handle) = &response.user_handle
+            && base64url_decode(handle)? != self.user_id.to_string().as_bytes()
+        {
+            return Err(invalid("user handle mismatch"));
+        }
+
+        let client = db_init().await?;
+
+        client
+            .query("UPDATE $id SET sign_count = $sign_count, last_used = time::now() RETURN NONE;")
+            .bind(("id", self.id.clone()))
+            .bind(("sign_count", sign_count))
+            .await?
+            .check()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug, Deserialize)]
+struct StoredChallenge {
+    #[serde(default)]
+    user_id: Option<RecordId>,
+}
+
+/// Stores a new single use challenge, returns it base64url encoded.
+#[cfg(feature = "ssr")]
+async fn new_challenge(purpose: &str, user_id: Option<RecordId>) -> Result<String, AppError> {
+    use rand::Rng;
+
+    let challenge = base64url_encode(&rand::rng().random::<[u8; 32]>());
+    let client = db_init().await?;
+
+    client
+        .query(format!(
+            "CREATE webauthn_challenge SET challenge = $challenge, purpose = $purpose, user_id = $user_id, expires = time::now() + {}s RETURN NONE;",
+            CHALLENGE_TIMEOUT_SECONDS
+        ))
+        .bind(("challenge", hash_token(&challenge)?))
+        .bind(("purpose", purpose.to_string()))
+        .bind(("user_id", user_id))
+        .await?
+        .check()?;
+
+    Ok(challenge)
+}
+
+/// Uses up the challenge a response was made for, returns the user it was issued to.
+#[cfg(feature = "ssr")]
+async fn take_challenge(challenge: &str, purpose: &str) -> Result<Option<RecordId>, AppError> {
+    let client = db_init().await?;
+
+    let mut result = client
+        .query("DELETE webauthn_challenge WHERE challenge = $challenge AND purpose = $purpose AND expires > time::now() RETURN BEFORE;")
+        .bind(("challenge", hash_token(challenge)?))
+        .bind(("purpose", purpose.to_string()))
+        .await?;
+
+    let taken: Vec<StoredChallenge> = result.take(0)?;
+    taken
+        .into_iter()
+        .next()
+        .map(|stored| stored.user_id)
+        .ok_or_else(|| AppError::AuthError("The passkey request expired, try again".into()))
+}
+
+#[cfg(feature = "ssr")]
+fn parse_response<T: serde::de::DeserializeOwned>(response: &str) -> Result<T, AppError> {
+    serde_json::from_str(response).map_err(|_| invalid("bad response"))
+}
+
+/// The signed in session of the current request, with its user.
+#[cfg(feature = "ssr")]
+async fn signed_in_session() -> Result<(AdapterSession, AdapterUser), AppError> {
+    let not_logged_in = || AppError::AuthError("Not logged in.".into());
+
+    let token = crate::auth::extract::request_session_token().ok_or_else(not_logged_in)?;
+    let session = AdapterSession::signed_in(token)
+        .await
+        .map_err(|_| not_logged_in())?;
+    let user = AdapterUser::get_user(session.user_id.clone()).await?;
+
+    Ok((session, user))
+}
+
+/// Rejects the request with `AppError::ReverificationRequired` unless its session was verified
+/// with a passkey in the last `REVERIFY_WINDOW_MINUTES`.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     #[server]
+///     pub async fn delete_account() -> Result<(), AppError> {
+///         require_recent_reverification().await?;
+///         ...
+///     }
+/// ```
+#[cfg(feature = "ssr")]
+pub async fn require_recent_reverification() -> Result<(), AppError> {
+    let (session, _) = signed_in_session().await?;
+
+    if !session.reverified_within(REVERIFY_WINDOW_MINUTES) {
+        return Err(AppError::ReverificationRequired);
+    }
+
+    Ok(())
+}
+
+/// Rejects adding or removing passkeys on the strength of the session cookie alone. Users with a
+/// passkey have to reverify with it, others need a current two-factor `code` or a session signed
+/// in within `REVERIFY_WINDOW_MINUTES`.
+#[cfg(feature = "ssr")]
+async fn require_passkey_change(
+    session: &AdapterSession,
+    user: &AdapterUser,
+    code: Option<String>,
+) -> Result<(), AppError> {
+    if !Passkey::get_by_user(user.id.clone()).await?.is_empty() {
+        if !session.reverified_within(REVERIFY_WINDOW_MINUTES) {
+            return Err(AppError::ReverificationRequired);
+        }
+        return Ok(());
+    }
+
+    if let Some(code) = code.filter(|code| !code.trim().is_empty()) {
+        crate::two_factor::verify_signed_in_code(user, &code).await?;
+        return Ok(());
+    }
+
+    if !session.signed_in_within(REVERIFY_WINDOW_MINUTES) {
+        return Err(AppError::Forbidden(
+            "Sign in again or enter your two-factor code to add a passkey".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Starts registering a passkey for the signed in user, see `require_passkey_change` for when
+/// `code` is needed. The challenge is only issued once that passes, so finishing needs no check.
+#[server]
+pub async fn start_passkey_registration(
+    code: Option<String>,
+) -> Result<CredentialCreationOptions, AppError> {
+    let (session, user) = signed_in_session().await?;
+    require_passkey_change(&session, &user, code).await?;
+
+    let exclude = Passkey::get_by_user(user.id.clone())
+        .await?
+        .into_iter()
+        .map(|passkey| passkey.credential_id)
+        .collect();
+
+    let challenge = new_challenge(PURPOSE_REGISTRATION, Some(user.id.clone())).await?;
+    Ok(RelyingParty::from_env().creation_options(challenge, &user, exclude))
+}
+
+/// Stores the passkey created for `start_passkey_registration`, `response` is the JSON of a
+/// `RegistrationResponse`.
+#[server]
+pub async fn finish_passkey_registration(
+    response: String,
+    name: String,
+) -> Result<PasskeyInfo, AppError> {
+    let (_, user) = signed_in_session().await?;
+    let response: RegistrationResponse = parse_response(&response)?;
+
+    let challenge = response_challenge(&response.client_data_json)?;
+    if take_challenge(&challenge, PURPOSE_REGISTRATION).await? != Some(user.id.clone()) {
+        return Err(invalid("challenge was issued to someone else"));
+    }
+
+    let credential = RelyingParty::from_env().verify_registration(&response, &challenge)?;
+
+    let name = match name.trim() {
+        "" => "Passkey".to_string(),
+        name => name.chars().take(64).collect(),
+    };
+
+    Ok(Passkey::create(user.id, credential, name).await?.to_info())
+}
+
+#[server]
+pub async fn list_passkeys() -> Result<Vec<PasskeyInfo>, AppError> {
+    let (_, user) = signed_in_session().await?;
+
+    Ok(Passkey::get_by_user(user.id)
+        .await?
+        .iter()
+        .map(Passkey::to_info)
+        .collect())
+}
+
+/// Removes a passkey of the signed in user, after a reverification with one of their passkeys.
+#[server]
+pub async fn delete_passkey(id: RecordId) -> Result<(), AppError> {
+    let (session, user) = signed_in_session().await?;
+    require_passkey_change(&session, &user, None).await?;
+
+    if !Passkey::delete(user.id, id).await? {
+        return Err(AppError::NotFound("Passkey not found".into()));
+    }
+
+    Ok(())
+}
+
+/// Starts a passkey sign-in, any passkey registered for the site can answer it.
+#[server]
+pub async fn start_passkey_signin() -> Result<CredentialRequestOptions, AppError> {
+    use crate::rate_limit::{PASSKEY_PER_IP, check_rate_limits};
+
+    let ip = crate::session::SessionClient::from_context().ip;
+    check_rate_limits(&[(&PASSKEY_PER_IP, ip.as_deref())]).await?;
+
+    let challenge = new_challenge(PURPOSE_SIGNIN, None).await?;
+    Ok(RelyingParty::from_env().request_options(challenge, Vec::new()))
+}
+
+/// Signs in with the passkey response to `start_passkey_signin`, returns the URL to continue to.
+#[server]
+pub async fn finish_passkey_signin(
+    response: String,
+    callback_url: Option<String>,
+) -> Result<String, AppError> {
+    use crate::rate_limit::{PASSKEY_PER_IP, check_rate_limits};
+
+    let ip = crate::session::SessionClient::from_context().ip;
+    check_rate_limits(&[(&PASSKEY_PER_IP, ip.as_deref())]).await?;
+
+    let response: AuthenticationResponse = parse_response(&response)?;
+    let challenge = response_challenge(&response.client_data_json)?;
+    take_challenge(&challenge, PURPOSE_SIGNIN).await?;
+
+    let passkey = Passkey::get_by_credential_id(&response.id)
+        .await?
+        .ok_or_else(|| AppError::AuthError("Unknown passkey".into()))?;
+
+    passkey
+        .authenticate(&RelyingParty::from_env(), &response, &challenge)
+        .await?;
+
+    let user = AdapterUser::get_user(passkey.user_id.clone()).await?;
+    let session = user.new_session().await?;
+    session.set_cookie();
+
+    let callback_url = callback_url
+        .as_deref()
+        .and_then(crate::auth::protected::safe_callback_url)
+        .unwrap_or_else(|| "/".to_string());
+
+    if session.two_factor_pending {
+        return Ok(crate::two_factor::two_factor_challenge_path(&callback_url));
+    }
+
+    // signing in with a passkey is a verification of its own
+    session.mark_reverified().await?;
+
+    Ok(callback_url)
+}
+
+/// Starts a step-up verification with one of the signed in user's passkeys.
+#[server]
+pub async fn start_passkey_reverification() -> Result<CredentialRequestOptions, AppError> {
+    let (_, user) = signed_in_session().await?;
+
+    let allow: Vec<String> = Passkey::get_by_user(user.id.clone())
+        .await?
+        .into_iter()
+        .map(|passkey| passkey.credential_id)
+        .collect();
+
+    if allow.is_empty() {
+        return Err(AppError::NotFound(
+            "Add a passkey in your security settings first".into(),
+        ));
+    }
+
+    let challenge = new_challenge(PURPOSE_REVERIFY, Some(user.id)).await?;
+    Ok(RelyingParty::from_env().request_options(challenge, allow))
+}
+
+/// Marks the session as recently verified, see `require_recent_reverification`.
+#[server]
+pub async fn finish_passkey_reverification(response: String) -> Result<(), AppError> {
+    let (session, user) = signed_in_session().await?;
+    let response: AuthenticationResponse = parse_response(&response)?;
+
+    let challenge = response_challenge(&response.client_data_json)?;
+    if take_challenge(&challenge, PURPOSE_REVERIFY).await? != Some(user.id.clone()) {
+        return Err(invalid("challenge was issued to someone else"));
+    }
+
+    let passkey = Passkey::get_by_credential_id(&response.id)
+        .await?
+        .filter(|passkey| passkey.user_id == user.id)
+        .ok_or_else(|| AppError::AuthError("Unknown passkey".into()))?;
+
+    passkey
+        .authenticate(&RelyingParty::from_env(), &response, &challenge)
+        .await?;
+
+    session.mark_reverified().await
+}
+
+/// Converts between the JSON options and responses and the `ArrayBuffer`s of the WebAuthn API.
+#[cfg(not(feature = "ssr"))]
+const WEBAUTHN_HELPERS: &str = r#"
+var decode = function (s) {
+  s = s.replace(/-/g, "+").replace(/_/g, "/");
+  while (s.length % 4) s += "=";
+  return Uint8Array.from(atob(s), function (c) { return c.charCodeAt(0); });
+};
+var encode = function (b) {
+  var s = "";
+  new Uint8Array(b).forEach(function (c) { s += String.fromCharCode(c); });
+  return btoa(s).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+};
+var o = JSON.parse(options);
+o.challenge = decode(o.challenge);
+"#;
+
+#[cfg(not(feature = "ssr"))]
+const WEBAUTHN_CREATE: &str = r#"
+o.user.id = decode(o.user.id);
+o.excludeCredentials.forEach(function (c) { c.id = decode(c.id); });
+return navigator.credentials.create({ publicKey: o }).then(function (c) {
+  return JSON.stringify({
+    id: c.id,
+    clientDataJson: encode(c.response.clientDataJSON),
+    attestationObject: encode(c.response.attestationObject)
+  });
+});
+"#;
+
+#[cfg(not(feature = "ssr"))]
+const WEBAUTHN_GET: &str = r#"
+o.allowCredentials.forEach(function (c) { c.id = decode(c.id); });
+return navigator.credentials.get({ publicKey: o }).then(function (c) {
+  return JSON.stringify({
+    id: c.id,
+    clientDataJson: encode(c.response.clientDataJSON),
+    authenticatorData: encode(c.response.authenticatorData),
+    signature: encode(c.response.signature),
+    userHandle: c.response.userHandle ? encode(c.response.userHandle) : null
+  });
+});
+"#;
+
+/// Runs a WebAuthn ceremony in the browser with JSON `options`, returns the JSON response.
+#[cfg(not(feature = "ssr"))]
+async fn run_ceremony(ceremony: &str, options: String) -> Result<String, String> {
+    use wasm_bindgen::{JsCast, JsValue};
+
+    let js_error = |e: JsValue| {
+        e.dyn_ref::<js_sys::Error>()
+            .map(|e| String::from(e.message()))
+            .unwrap_or_else(|| "Passkey request failed".to_string())
+    };
+
+    let function =
+        js_sys::Function::new_with_args("options", &format!("{}{}", WEBAUTHN_HELPERS, ceremony));
+    let promise: js_sys::Promise = function
+        .call1(&JsValue::NULL, &JsValue::from_str(&options))
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+
+    wasm_bindgen_futures::JsFuture::from(promise)
+        .await
+        .map_err(js_error)?
+        .as_string()
+        .ok_or_else(|| "Passkey request failed".to_string())
+}
+
+#[cfg(not(feature = "ssr"))]
+async fn create_credential(options: &CredentialCreationOptions) -> Result<String, String> {
+    let options = serde_json::to_string(options).map_err(|e| e.to_string())?;
+    run_ceremony(WEBAUTHN_CREATE, options).await
+}
+
+#[cfg(not(feature = "ssr"))]
+async fn get_credential(options: &CredentialRequestOptions) -> Result<String, String> {
+    let options = serde_json::to_string(options).map_err(|e| e.to_string())?;
+    run_ceremony(WEBAUTHN_GET, options).await
+}
+
+#[cfg(feature = "ssr")]
+async fn create_credential(_options: &CredentialCreationOptions) -> Result<String, String> {
+    Err("Passkeys need a browser".to_string())
+}
+
+#[cfg(feature = "ssr")]
+async fn get_credential(_options: &CredentialRequestOptions) -> Result<String, String> {
+    Err("Passkeys need a browser".to_string())
+}
+
+/// Registers a passkey of this device for the signed in user, asking for an existing passkey
+/// first when they have one. `code` is a two-factor code, for users without passkeys.
+pub async fn register_passkey(name: String, code: Option<String>) -> Result<PasskeyInfo, String> {
+    let options = match start_passkey_registration(code.clone()).await {
+        Err(AppError::ReverificationRequired) => {
+            reverify_with_passkey().await?;
+            start_passkey_registration(code).await
+        }
+        other => other,
+    }
+    .map_err(|e| e.to_string())?;
+    let response = create_credential(&options).await?;
+
+    finish_passkey_registration(response, name)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Removes a passkey of the signed in user, asking for one of their passkeys first.
+pub async fn remove_passkey(id: RecordId) -> Result<(), String> {
+    match delete_passkey(id.clone()).await {
+        Err(AppError::ReverificationRequired) => {
+            reverify_with_passkey().await?;
+            delete_passkey(id).await
+        }
+        other => other,
+    }
+    .map_err(|e| e.to_string())
+}
+
+/// Asks for a passkey so the session passes `require_recent_reverification`.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     let key = match reveal_wallet_private_key(id.clone()).await {
+///         Err(AppError::ReverificationRequired) => {
+///             reverify_with_passkey().await?;
+///             reveal_wallet_private_key(id).await
+///         }
+///         other => other,
+///     };
+/// ```
+pub async fn reverify_with_passkey() -> Result<(), String> {
+    let options = start_passkey_reverification()
+        .await
+        .map_err(|e| e.to_string())?;
+    let response = get_credential(&options).await?;
+
+    finish_passkey_reverification(response)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+async fn sign_in_with_passkey(callback_url: String) -> Result<String, String> {
+    let options = start_passkey_signin().await.map_err(|e| e.to_string())?;
+    let response = get_credential(&options).await?;
+
+    finish_passkey_signin(response, Some(callback_url))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Signs in with a passkey, then continues to `callbackUrl`.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     <PasskeySignInButton />
+/// ```
+#[component]
+pub fn PasskeySignInButton() -> impl IntoView {
+    let query = use_query_map();
+    let (is_loading, set_is_loading) = signal(false);
+    let (error_message, set_error_message) = signal(Option::<String>::None);
+
+    let on_click = move |_| {
+        let callback = query
+            .read_untracked()
+            .get("callbackUrl")
+            .and_then(|url| crate::auth::protected::safe_callback_url(&url))
+            .unwrap_or_else(|| "/".to_string());
+
+        set_is_loading.set(true);
+        set_error_message.set(None);
+
+        spawn_local(async move {
+            match sign_in_with_passkey(callback).await {
+                Ok(url) => {
+                    window().location().set_href(&url).unwrap();
+                }
+                Err(e) => {
+                    set_error_message.set(Some(e));
+                    set_is_loading.set(false);
+                }
+            }
+        });
+    };
+
+    view! {
+        <button
+            type="button"
+            on:click=on_click
+            class="w-full flex items-center justify-center gap-3 bg-emerald-600 hover:bg-emerald-700 dark:bg-emerald-600 dark:hover:bg-emerald-500 text-white px-4 py-3 rounded-md font-semibold duration-150 disabled:opacity-50 disabled:cursor-not-allowed"
+            disabled=move || is_loading.get()
+        >
+            "Sign in with a passkey"
+        </button>
+        {move || {
+            error_message
+                .get()
+                .map(|error| {
+                    view! {
+                        <div class="text-center text-sm text-red-600 dark:text-red-400">{error}</div>
+                    }
+                })
+        }}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ciborium::Value;
+    use ring::rand::SystemRandom;
+    use ring::signature::{ECDSA_P256_SHA256_ASN1_SIGNING, EcdsaKeyPair, KeyPair};
+    use sha2::{Digest, Sha256};
+
+    /// A P-256 authenticator in software, following the WebAuthn wire formats.
+    struct SoftwareAuthenticator {
+        key_pair: EcdsaKeyPair,
+        credential_id: Vec<u8>,
+        sign_count: u32,
+        rng: SystemRandom,
+    }
+
+    impl SoftwareAuthenticator {
+        fn new() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+            let key_pair =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng)
+                    .unwrap();
+
+            Self {
+                key_pair,
+                credential_id: b"software-credential".to_vec(),
+                sign_count: 0,
+                rng,
+            }
+        }
+
+        fn client_data(kind: &str, challenge: &str, origin: &str) -> Vec<u8> {
+            serde_json::json!({ "type": kind, "challenge": challenge, "origin": origin })
+                .to_string()
+                .into_bytes()
+        }
+
+        fn authenticator_data(&self, rp_id: &str, attested: bool) -> Vec<u8> {
+            let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+            let flags = FLAG_USER_PRESENT
+                | FLAG_USER_VERIFIED
+                | if attested {
+                    FLAG_ATTESTED_CREDENTIAL
+                } else {
+                    0
+                };
+            data.push(flags);
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+
+            if attested {
+                let point = self.key_pair.public_key().as_ref();
+                let cose_key = Value::Map(vec![
+                    (Value::from(1), Value::from(2)),
+                    (Value::from(3), Value::from(COSE_ES256)),
+                    (Value::from(-1), Value::from(1)),
+                    (Value::from(-2), Value::Bytes(point[1..33].to_vec())),
+                    (Value::from(-3), Value::Bytes(point[33..].to_vec())),
+                ]);
+
+                data.extend_from_slice(&[0; 16]);
+                data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+                data.extend_from_slice(&self.credential_id);
+                ciborium::into_writer(&cose_key, &mut data).unwrap();
+            }
+
+            data
+        }
+
+        fn register(&self, rp: &RelyingParty, challenge: &str) -> RegistrationResponse {
+            let attestation = Value::Map(vec![
+                (Value::from("fmt"), Value::from("none")),
+                (Value::from("attStmt"), Value::Map(vec![])),
+                (
+                    Value::from("authData"),
+                    Value::Bytes(self.authenticator_data(&rp.id, true)),
+                ),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+
+            RegistrationResponse {
+                id: base64url_encode(&self.credential_id),
+                client_data_json: base64url_encode(&Self::client_data(
+                    "webauthn.create",
+                    challenge,
+                    &rp.origin,
+                )),
+                attestation_object: base64url_encode(&attestation_object),
+            }
+        }
+
+        fn authenticate(
+            &mut self,
+            rp: &RelyingParty,
+            challenge: &str,
+            origin: &str,
+        ) -> AuthenticationResponse {
+            self.sign_count += 1;
+
+            let client_data = Self::client_data("webauthn.get", challenge, origin);
+            let auth_data = self.authenticator_data(&rp.id, false);
+
+            let mut signed = auth_data.clone();
+            signed.extend_from_slice(&Sha256::digest(&client_data));
+            let signature = self.key_pair.sign(&self.rng, &signed).unwrap();
+
+            AuthenticationResponse {
+                id: base64url_encode(&self.credential_id),
+                client_data_json: base64url_encode(&client_data),
+                authenticator_data: base64url_encode(&auth_data),
+                signature: base64url_encode(signature.as_ref()),
+                user_handle: None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_passkey_ceremonies() {
+        let rp = RelyingParty::new("https://app.example.com:8443/", "Example");
+        assert_eq!(rp.id, "app.example.com");
+        assert_eq!(rp.origin, "https://app.example.com:8443");
+
+        let mut authenticator = SoftwareAuthenticator::new();
+
+        let registration = authenticator.register(&rp, "register-challenge");
+        assert!(
+            rp.verify_registration(&registration, "other-challenge")
+                .is_err()
+        );
+
+        let credential = rp
+            .verify_registration(&registration, "register-challenge")
+            .unwrap();
+        assert_eq!(credential.algorithm, COSE_ES256);
+        assert_eq!(
+            credential.public_key,
+            authenticator.key_pair.public_key().as_ref()
+        );
+        assert_eq!(
+            response_challenge(&registration.client_data_json).unwrap(),
+            "register-challenge"
+        );
+
+        let assertion = authenticator.authenticate(&rp, "signin-challenge", &rp.origin);
+        let verify = |response: &AuthenticationResponse, stored_count| {
+            rp.verify_authentication(
+                response,
+                "signin-challenge",
+                &credential.public_key,
+                credential.algorithm,
+                stored_count,
+            )
+        };
+
+        assert_eq!(verify(&assertion, 0).unwrap(), 1);
+        // replayed, the counter didn't grow
+        assert!(verify(&assertion, 1).is_err());
+
+        let phished = authenticator.authenticate(&rp, "signin-challenge", "https://evil.example");
+        assert!(verify(&phished, 1).is_err());
+
+        let mut tampered = authenticator.authenticate(&rp, "signin-challenge", &rp.origin);
+        tampered.signature = base64url_encode(b"not a signature");
+        assert!(verify(&tampered, 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_plain_session_cannot_add_passkey() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+        use leptos::reactive::computed::ScopedFuture;
+
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            let session = user.new_session().await?;
+            let (parts, _) = http::Request::builder()
+                .header(
+                    http::header::COOKIE,
+                    format!(
+                        "{}={}",
+                        crate::auth::extract::SESSION_COOKIE,
+                        session.session_token
+                    ),
+                )
+                .body(())
+                .unwrap()
+                .into_parts();
+            let owner = Owner::new();
+            owner.with(|| provide_context(parts));
+            let start = || owner.with(|| ScopedFuture::new(start_passkey_registration(None)));
+
+            // a fresh sign-in may add the first passkey
+            assert!(start().await.is_ok());
+
+            let rp = RelyingParty::from_env();
+            let registration = SoftwareAuthenticator::new().register(&rp, "challenge");
+            let credential = rp.verify_registration(&registration, "challenge")?;
+            Passkey::create(user.id.clone(), credential, "First".into()).await?;
+
+            // a second one needs a reverification with the first
+            assert!(matches!(
+                start().await,
+                Err(AppError::ReverificationRequired)
+            ));
+            session.mark_reverified().await?;
+            assert!(start().await.is_ok());
+
+            // without passkeys, an older session needs a two-factor code
+            Passkey::delete(
+                user.id.clone(),
+                Passkey::get_by_user(user.id.clone()).await?[0].id.clone(),
+            )
+            .await?;
+            db_init()
+                .await?
+                .query("UPDATE $id SET created_at = time::now() - 1h;")
+                .bind(("id", session.id.clone()))
+                .await?
+                .check()?;
+            assert!(matches!(start().await, Err(AppError::Forbidden(_))));
+
+            Ok(())
+        })
+        .await
+    }
+}
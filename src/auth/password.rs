@@ -0,0 +1,476 @@
+//! Optional password sign-in next to magic links. The password is stored as an Argon2id hash in
+//! an `account` row of type `credentials`, one per user. Passwords are set or reset through an
+//! emailed `VerificationToken`, so only the owner of the address can add one. Signed in users can
+//! also add one right after verifying with a passkey.
+
+use leptos::prelude::*;
+
+use crate::AppError;
+
+#[cfg(feature = "ssr")]
+use crate::{account::AdapterAccount, db_init, user::AdapterUser};
+
+#[cfg(feature = "ssr")]
+use surrealdb::RecordId;
+
+/// `provider` of credentials accounts.
+pub const CREDENTIALS_PROVIDER: &str = "credentials";
+
+pub const PASSWORD_MIN_LENGTH: usize = 12;
+
+/// Longer passwords are refused so hashing stays cheap.
+pub const PASSWORD_MAX_LENGTH: usize = 128;
+
+/// Failed sign-ins before the account is locked.
+pub const MAX_FAILED_ATTEMPTS: u32 = 5;
+
+pub const LOCKOUT_MINUTES: i64 = 15;
+
+/// Where the emailed reset link points, render `ResetPasswordForm` on this route.
+pub const RESET_PASSWORD_PATH: &str = "/reset-password";
+
+/// Checks the length policy, the breach check runs on the server when the password is set.
+pub fn check_password_policy(password: &str) -> Result<(), AppError> {
+    let length = password.chars().count();
+
+    if length < PASSWORD_MIN_LENGTH {
+        return Err(AppError::ErrorReason(format!(
+            "Password must be at least {} characters",
+            PASSWORD_MIN_LENGTH
+        )));
+    }
+
+    if length > PASSWORD_MAX_LENGTH {
+        return Err(AppError::ErrorReason(format!(
+            "Password must be at most {} characters",
+            PASSWORD_MAX_LENGTH
+        )));
+    }
+
+    Ok(())
+}
+
+/// Argon2id hash of `password` in PHC string format.
+#[cfg(feature = "ssr")]
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    use argon2::{Argon2, PasswordHasher, password_hash::SaltString};
+    use rand::Rng;
+
+    let salt_bytes: [u8; 16] = rand::rng().random();
+    let salt = SaltString::encode_b64(&salt_bytes)
+        .map_err(|e| AppError::GenericError(format!("Could not encode salt: {}", e)))?;
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AppError::GenericError(format!("Could not hash password: {}", e)))
+}
+
+#[cfg(feature = "ssr")]
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    use argon2::{Argon2, PasswordHash, PasswordVerifier};
+
+    PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// Whether `password` appears in the Have I Been Pwned breach corpus. Only the first five
+/// characters of its SHA-1 leave the server. The check fails open when the service is down.
+#[cfg(feature = "ssr")]
+pub async fn is_breached_password(password: &str) -> bool {
+    use sha1::{Digest, Sha1};
+
+    let digest = hex::encode_upper(Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = digest.split_at(5);
+
+    let response = reqwest::Client::new()
+        .get(format!("https://api.pwnedpasswords.com/range/{}", prefix))
+        .header("Add-Padding", "true")
+        .timeout(std::time::Duration::from_secs(5))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+
+    let body = match response {
+        Ok(response) => response.text().await,
+        Err(e) => Err(e),
+    };
+
+    match body {
+        Ok(body) => body.lines().any(|line| {
+            line.split_once(':')
+                .is_some_and(|(hash, count)| hash == suffix && count.trim() != "0")
+        }),
+        Err(e) => {
+            tracing::warn!("Breached password check failed: {}", e);
+            false
+        }
+    }
+}
+
+/// The length policy and the breach check.
+#[cfg(feature = "ssr")]
+pub async fn validate_new_password(password: &str) -> Result<(), AppError> {
+    check_password_policy(password)?;
+
+    if is_breached_password(password).await {
+        return Err(AppError::ErrorReason(
+            "This password has appeared in a data breach, please choose another".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// The credentials account of `user_id`.
+#[cfg(feature = "ssr")]
+pub async fn get_credentials(user_id: RecordId) -> Result<Option<AdapterAccount>, AppError> {
+    let client = db_init().await?;
+
+    let mut result = client
+        .query("SELECT * FROM ONLY account WHERE user_id = $user_id AND account_type = 'credentials' LIMIT 1;")
+        .bind(("user_id", user_id))
+        .await?;
+
+    let account: Option<AdapterAccount> = result.take(0)?;
+    Ok(account)
+}
+
+/// Sets the password of `user_id`, creating the credentials account if needed, and clears any
+/// lockout. The password must already have passed `validate_new_password`.
+#[cfg(feature = "ssr")]
+pub async fn set_user_password(user_id: RecordId, password: &str) -> Result<(), AppError> {
+    let client = db_init().await?;
+    let password_hash = hash_password(password)?;
+
+    let query = match get_credentials(user_id.clone()).await? {
+        Some(_) => {
+            "UPDATE account SET password_hash = $password_hash, failed_attempts = 0, locked_until = NONE WHERE user_id = $user_id AND account_type = 'credentials' RETURN NONE;"
+        }
+        None => {
+            "CREATE account SET account_type = 'credentials', provider = $provider, provider_account_id = type::string($user_id), user_id = $user_id, access_token = '', expires_at = 0, refresh_token = NONE, scope = '', token_type = '', password_hash = $password_hash, failed_attempts = 0, locked_until = NONE RETURN NONE;"
+        }
+    };
+
+    client
+        .query(query)
+        .bind(("password_hash", password_hash))
+        .bind(("provider", CREDENTIALS_PROVIDER))
+        .bind(("user_id", user_id))
+        .await?
+        .check()?;
+
+    Ok(())
+}
+
+/// Counts a failed sign-in, locking the account for `LOCKOUT_MINUTES` on the
+/// `MAX_FAILED_ATTEMPTS`th. The counter is incremented in the database so concurrent attempts
+/// are all counted.
+#[cfg(feature = "ssr")]
+async fn record_failed_attempt(account: &AdapterAccount) -> Result<(), AppError> {
+    let client = db_init().await?;
+
+    let attempts: Option<u32> = client
+        .query("UPDATE ONLY $id SET failed_attempts += 1 RETURN VALUE failed_attempts;")
+        .bind(("id", account.id.clone()))
+        .await?
+        .take(0)?;
+
+    if attempts.unwrap_or_default() < MAX_FAILED_ATTEMPTS {
+        return Ok(());
+    }
+
+    tracing::warn!(account = %account.id, "Password sign-in locked after repeated failures");
+
+    client
+        .query(format!(
+            "UPDATE $id SET failed_attempts = 0, locked_until = time::now() + {}m RETURN NONE;",
+            LOCKOUT_MINUTES
+        ))
+        .bind(("id", account.id.clone()))
+        .await?
+        .check()?;
+
+    Ok(())
+}
+
+#[cfg(feature = "ssr")]
+async fn clear_failed_attempts(account: &AdapterAccount) -> Result<(), AppError> {
+    let client = db_init().await?;
+
+    client
+        .query("UPDATE $id SET failed_attempts = 0, locked_until = NONE RETURN NONE;")
+        .bind(("id", account.id.clone()))
+        .await?
+        .check()?;
+
+    Ok(())
+}
+
+/// Seconds until the lockout of `account` ends, `None` when it isn't locked.
+#[cfg(feature = "ssr")]
+fn lockout_remaining(account: &AdapterAccount) -> Option<u64> {
+    let locked_until = account.locked_until.as_ref()?;
+    let locked_until = crate::date_utils::parse_surrealdb_datetime_to_chrono(locked_until)?;
+    let remaining = (locked_until - chrono::Utc::now()).num_seconds();
+
+    (remaining > 0).then_some(remaining as u64)
+}
+
+/// The user with `email` and `password`, counting failures towards the lockout.
+#[cfg(feature = "ssr")]
+pub async fn authenticate_password(email: &str, password: &str) -> Result<AdapterUser, AppError> {
+    use std::sync::OnceLock;
+
+    // verified against when there is no account, so unknown emails take as long as wrong passwords
+    static DUMMY_HASH: OnceLock<Option<String>> = OnceLock::new();
+
+    let invalid = || AppError::AuthError("Invalid email or password".into());
+
+    let user = AdapterUser::get_by_email(email.to_string()).await.ok();
+    let account = match &user {
+        Some(user) => get_credentials(user.id.clone()).await?,
+        None => None,
+    };
+
+    let (Some(user), Some(account)) = (user, account) else {
+        if let Some(hash) = DUMMY_HASH.get_or_init(|| hash_password("tinkr-dummy-password").ok()) {
+            verify_password(password, hash);
+        }
+        return Err(invalid());
+    };
+
+    if let Some(retry_after) = lockout_remaining(&account) {
+        return Err(AppError::RateLimited(retry_after));
+    }
+
+    let hash = account.password_hash.as_deref().unwrap_or_default();
+
+    if !verify_password(password, hash) {
+        record_failed_attempt(&account).await?;
+        return Err(invalid());
+    }
+
+    if account.failed_attempts > 0 {
+        clear_failed_attempts(&account).await?;
+    }
+
+    Ok(user)
+}
+
+/// Signs in with email and password, returns the URL to continue to.
+#[server]
+pub async fn signin_password(
+    email: String,
+    password: String,
+    callback_url: Option<String>,
+) -> Result<String, AppError> {
+    use crate::rate_limit::{PASSWORD_PER_EMAIL, PASSWORD_PER_IP, check_rate_limits};
+
+    let ip = crate::session::SessionClient::from_context().ip;
+    check_rate_limits(&[
+        (&PASSWORD_PER_IP, ip.as_deref()),
+        (&PASSWORD_PER_EMAIL, Some(&email)),
+    ])
+    .await?;
+
+    let user = authenticate_password(&email, &password).await?;
+    let session = user.new_session().await?;
+    session.set_cookie();
+
+    let callback_url = callback_url
+        .as_deref()
+        .and_then(crate::auth::protected::safe_callback_url)
+        .unwrap_or_else(|| "/".to_string());
+
+    if session.two_factor_pending {
+        return Ok(crate::two_factor::two_factor_challenge_path(&callback_url));
+    }
+
+    Ok(callback_url)
+}
+
+/// Emails a link to set a new password. Succeeds whether or not the address has an account, so
+/// it can't be used to find out who is registered.
+#[server]
+pub async fn request_password_reset(email: String) -> Result<(), AppError> {
+    use crate::email::send_email;
+    use crate::rate_limit::{VERIFICATION_PER_EMAIL, VERIFICATION_PER_IP, check_rate_limits};
+
+    let ip = crate::session::SessionClient::from_context().ip;
+    check_rate_limits(&[
+        (&VERIFICATION_PER_IP, ip.as_deref()),
+        (&VERIFICATION_PER_EMAIL, Some(&email)),
+    ])
+    .await?;
+
+    let Ok(user) = AdapterUser::get_by_email(email).await else {
+        return Ok(());
+    };
+
+    let token = user.new_verification_token().await?;
+
+    let base_url =
+        std::env::var("TINKR_AUTH_URL").unwrap_or_else(|_| "http://localhost:3000".to_string());
+    let reset_url = format!("{}{}?token={}", base_url, RESET_PASSWORD_PATH, token.token);
+
+    let email_body = format!(
+        r#"<html>
+        <body>
+            <h2>Reset Your Password</h2>
+            <p>Hello {},</p>
+            <p>Click the link below to choose a new password:</p>
+            <p><a href="{}">Reset Password</a></p>
+            <p>If you didn't ask for this, you can ignore this email.</p>
+            <p>This link will expire in 30 minutes.</p>
+        </body>
+        </html>"#,
+        user.name, reset_url
+    );
+
+    send_email(user.email.clone(), "Reset Your Password", &email_body).await?;
+
+    Ok(())
+}
+
+/// Sets a new password with a token from `request_password_reset`, signs out every other device,
+/// revokes the user's API keys and signs in here.
+#[server]
+pub async fn reset_password(token: String, password: String) -> Result<(), AppError> {
+    use crate::session::AdapterSession;
+    use crate::token::VerificationToken;
+
+    // before using up the token, so a rejected password can be retried with the same link
+    validate_new_password(&password).await?;
+
+    let verified = VerificationToken::use_verification_token(token).await?;
+    let user = AdapterUser::get_user(verified.user_id).await?;
+
+    if verified.email != user.email {
+        return Err(AppError::AuthError(
+            "Token email does not match user email".into(),
+        ));
+    }
+
+    set_user_password(user.id.clone(), &password).await?;
+
+    // the link proves the address is theirs
+    if user.email_verified.is_none() {
+        user.set_verified_email().await?;
+    }
+
+    // recovering the account, whoever had it may have created keys
+    AdapterSession::revoke_all(user.id.clone(), None).await?;
+    crate::api_key::ApiKey::revoke_all(user.id.clone()).await?;
+    user.new_session().await?.set_cookie();
+
+    Ok(())
+}
+
+/// Changes the signed in user's password. Users that already have one must confirm it, adding a
+/// first one needs a recent passkey reverification, otherwise use `request_password_reset`. Signs
+/// out every other device.
+#[server]
+pub async fn change_password(
+    current_password: Option<String>,
+    new_password: String,
+) -> Result<(), AppError> {
+    use crate::session::AdapterSession;
+
+    let user = crate::auth::extract::session_user().await?;
+
+    match get_credentials(user.id.clone()).await? {
+        Some(account) => {
+            let hash = account.password_hash.as_deref().unwrap_or_default();
+            let confirmed = current_password.is_some_and(|current| verify_password(&current, hash));
+
+            if !confirmed {
+                return Err(AppError::AuthError("Current password is incorrect".into()));
+            }
+        }
+        // a session alone doesn't prove the user owns the address, see `request_password_reset`
+        None => crate::auth::passkey::require_recent_reverification().await?,
+    }
+
+    validate_new_password(&new_password).await?;
+    set_user_password(user.id.clone(), &new_password).await?;
+
+    // other devices signed in with the old password
+    let current = AdapterSession::rotate_after_privilege_change(user.id.clone()).await?;
+    AdapterSession::revoke_all(user.id, current.map(|s| s.session_token)).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_policy_and_hash() {
+        assert!(check_password_policy("short").is_err());
+        assert!(check_password_policy(&"a".repeat(PASSWORD_MAX_LENGTH + 1)).is_err());
+        assert!(check_password_policy("correct horse battery").is_ok());
+
+        let hash = hash_password("correct horse battery").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse battery", &hash));
+        assert!(!verify_password("correct horse battery!", &hash));
+        assert!(!verify_password("correct horse battery", "not a hash"));
+    }
+
+    #[tokio::test]
+    async fn test_password_lockout() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            let email = user.email.to_string();
+            set_user_password(user.id.clone(), "correct horse battery").await?;
+
+            // a record written before the counter existed starts from zero
+            db_init()
+                .await?
+                .query("UPDATE account SET failed_attempts = NONE WHERE user_id = $user_id;")
+                .bind(("user_id", user.id.clone()))
+                .await?
+                .check()?;
+
+            for _ in 1..MAX_FAILED_ATTEMPTS {
+                assert!(matches!(
+                    authenticate_password(&email, "wrong password!").await,
+                    Err(AppError::AuthError(_))
+                ));
+            }
+            let account = get_credentials(user.id.clone()).await?.unwrap();
+            assert_eq!(account.failed_attempts, MAX_FAILED_ATTEMPTS - 1);
+
+            assert!(
+                authenticate_password(&email, "wrong password!")
+                    .await
+                    .is_err()
+            );
+            assert!(matches!(
+                authenticate_password(&email, "correct horse battery").await,
+                Err(AppError::RateLimited(secs)) if secs > 0
+            ));
+
+            // setting a new password clears the lockout
+            set_user_password(user.id.clone(), "another correct horse").await?;
+            assert_eq!(
+                authenticate_password(&email, "another correct horse")
+                    .await?
+                    .id,
+                user.id
+            );
+
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,231 @@
+//! Route guards that check access on the server. The check is a blocking resource, so during SSR
+//! the redirect happens before the response starts and is sent as a `302`. On client-side
+//! navigation the same check runs through the `check_route_access` server function.
+
+use leptos::prelude::*;
+use leptos_router::{
+    MatchNestedRoutes, PossibleRouteMatch, SsrMode,
+    components::{Redirect, Route, RouteProps},
+    hooks::{use_location, use_params_map},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::components::loading::LoadingIndicator;
+use crate::organization::organization::OrganizationRole;
+use crate::two_factor::{TWO_FACTOR_SETTINGS_PATH, two_factor_challenge_path};
+
+#[cfg(feature = "ssr")]
+use surrealdb::RecordId;
+
+#[cfg(not(feature = "ssr"))]
+use crate::RecordId;
+
+/// Where signed out users are sent, with the page they wanted as `callbackUrl`.
+pub const LOGIN_PATH: &str = "/login";
+
+/// What a user needs to open a guarded route.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum RouteRequirement {
+    #[default]
+    SignedIn,
+    Admin,
+    SuperAdmin,
+    /// At least this role in the organization named by the route's `organization_param`.
+    OrganizationRole(OrganizationRole),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RouteAccess {
+    Allowed,
+    SignedOut,
+    Forbidden,
+    /// Signed in with the first factor, the second is still to be entered.
+    TwoFactorPending,
+    /// An admin without two-factor while `TINKR_REQUIRE_ADMIN_2FA` is set.
+    TwoFactorSetupRequired,
+}
+
+/// The login page URL that returns to `callback` after signing in.
+pub fn login_redirect_path(callback: &str) -> String {
+    format!("{}?callbackUrl={}", LOGIN_PATH, urlencoding::encode(callback))
+}
+
+/// `url` if it is a path on this site, so `callbackUrl` can't redirect elsewhere.
+pub fn safe_callback_url(url: &str) -> Option<String> {
+    let is_local = url.starts_with('/') && !url.starts_with("//") && !url.starts_with("/\\");
+    is_local.then(|| url.to_string())
+}
+
+#[server]
+pub async fn check_route_access(
+    requirement: RouteRequirement,
+    organization_id: Option<RecordId>,
+) -> Result<RouteAccess, ServerFnError> {
+    use crate::organization::organization::OrganizationMember;
+    use crate::two_factor::{check_admin_two_factor, pending_session};
+
+    // invalid sessions count as signed out, like get_user_option
+    let Some(user) = crate::auth::extract::request_user().await.unwrap_or(None) else {
+        if pending_session().await?.is_some() {
+            return Ok(RouteAccess::TwoFactorPending);
+        }
+        return Ok(RouteAccess::SignedOut);
+    };
+
+    if matches!(
+        requirement,
+        RouteRequirement::Admin | RouteRequirement::SuperAdmin
+    ) && check_admin_two_factor(&user).await.is_err()
+    {
+        return Ok(RouteAccess::TwoFactorSetupRequired);
+    }
+
+    let allowed = match requirement {
+        RouteRequirement::SignedIn => true,
+        RouteRequirement::Admin => user.is_admin == Some(true) || user.superadmin == Some(true),
+        RouteRequirement::SuperAdmin => user.superadmin == Some(true),
+        RouteRequirement::OrganizationRole(required) => match organization_id {
+            Some(organization_id) => OrganizationMember::role_of(organization_id, user.id)
+                .await?
+                .is_some_and(|role| role.includes(&required)),
+            None => false,
+        },
+    };
+
+    Ok(if allowed {
+        RouteAccess::Allowed
+    } else {
+        RouteAccess::Forbidden
+    })
+}
+
+/// Renders `children` if the user meets `require`. Otherwise redirects signed out users to the
+/// login page, sign-ins waiting for their second factor to the challenge and everyone else to
+/// `forbidden_path`.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     <Route path=path!("/org/:org_id/billing") view=|| view! {
+///         <RouteGuard
+///             require=RouteRequirement::OrganizationRole(OrganizationRole::Admin)
+///             organization_param="org_id"
+///         >
+///             <Billing />
+///         </RouteGuard>
+///     } />
+/// ```
+#[component]
+pub fn RouteGuard(
+    #[prop(optional)] require: RouteRequirement,
+    /// Route parameter holding the organization key, for `RouteRequirement::OrganizationRole`.
+    #[prop(optional)]
+    organization_param: Option<&'static str>,
+    #[prop(into, default = "/".to_string())] forbidden_path: String,
+    children: ChildrenFn,
+) -> impl IntoView {
+    let location = use_location();
+    let params = use_params_map();
+
+    let organization_id = move || {
+        organization_param
+            .and_then(|param| params.read().get(param))
+            .map(|key| RecordId::from_table_key("organization", &key))
+    };
+
+    let access = Resource::new_blocking(
+        move || (require.clone(), organization_id()),
+        |(require, organization_id)| check_route_access(require, organization_id),
+    );
+
+    let callback = move || {
+        let path = location.pathname.get_untracked();
+        let search = location.search.get_untracked();
+        let search = search.trim_start_matches('?');
+
+        if search.is_empty() {
+            path
+        } else {
+            format!("{path}?{search}")
+        }
+    };
+
+    view! {
+        <Transition fallback=|| view! { <LoadingIndicator /> }>
+            {move || {
+                let children = children.clone();
+                let forbidden_path = forbidden_path.clone();
+                Suspend::new(async move {
+                    match access.await {
+                        Ok(RouteAccess::Allowed) => children().into_any(),
+                        Ok(RouteAccess::Forbidden) => {
+                            view! { <Redirect path=forbidden_path /> }.into_any()
+                        }
+                        Ok(RouteAccess::TwoFactorPending) => {
+                            view! { <Redirect path=two_factor_challenge_path(&callback()) /> }
+                                .into_any()
+                        }
+                        Ok(RouteAccess::TwoFactorSetupRequired) => {
+                            view! { <Redirect path=TWO_FACTOR_SETTINGS_PATH /> }.into_any()
+                        }
+                        Ok(RouteAccess::SignedOut) | Err(_) => {
+                            view! { <Redirect path=login_redirect_path(&callback()) /> }.into_any()
+                        }
+                    }
+                })
+            }}
+        </Transition>
+    }
+}
+
+/// A `<Route/>` whose view is wrapped in a `RouteGuard`.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     <Routes fallback=|| "Page not found.".into_view()>
+///         <ProtectedRoute path=path!("/settings") view=SettingsHome />
+///         <ProtectedRoute path=path!("/admin") view=AdminPage require=RouteRequirement::Admin />
+///     </Routes>
+/// ```
+#[component(transparent)]
+pub fn ProtectedRoute<Segments, ViewFn, V>(
+    path: Segments,
+    view: ViewFn,
+    #[prop(optional)] require: RouteRequirement,
+    #[prop(optional)] organization_param: Option<&'static str>,
+    #[prop(into, default = "/".to_string())] forbidden_path: String,
+    #[prop(optional)] ssr: SsrMode,
+) -> impl MatchNestedRoutes + Clone
+where
+    Segments: PossibleRouteMatch + Clone + Send + 'static,
+    ViewFn: Fn() -> V + Send + Sync + Clone + 'static,
+    V: IntoView + 'static,
+{
+    let guarded = move || {
+        let view = view.clone();
+        RouteGuard(RouteGuardProps {
+            require: require.clone(),
+            organization_param,
+            forbidden_path: forbidden_path.clone(),
+            children: std::sync::Arc::new(move || view().into_any()),
+        })
+        .into_any()
+    };
+
+    Route(RouteProps::builder().path(path).view(guarded).ssr(ssr).build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redirect_paths() {
+        assert_eq!(
+            login_redirect_path("/settings/keys?tab=new"),
+            "/login?callbackUrl=%2Fsettings%2Fkeys%3Ftab%3Dnew"
+        );
+        assert_eq!(safe_callback_url("/settings"), Some("/settings".into()));
+        assert_eq!(safe_callback_url("//evil.example"), None);
+        assert_eq!(safe_callback_url("https://evil.example"), None);
+    }
+}
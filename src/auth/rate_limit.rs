@@ -0,0 +1,328 @@
+//! Fixed-window rate limits for the endpoints that send email or start sign-ins. Each rule counts
+//! hits per subject (an IP address, an email address or `GLOBAL`) and rejects the hit that goes
+//! over its limit with `AppError::RateLimited`, holding the seconds until the window resets.
+//!
+//! Counters are kept in memory by default. Deployments running more than one instance should
+//! share them through SurrealDB:
+//!
+//! ```rs
+//!     set_rate_limiter(
+//!         RateLimiter::surrealdb().with_rule(SIGNIN_PER_EMAIL.with_limit(3)),
+//!     )?;
+//! ```
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{AppError, db_init};
+
+/// Subject of the rules that count every caller together.
+pub const GLOBAL: &str = "global";
+
+const MINUTE: Duration = Duration::from_secs(60);
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Memory counters are swept of expired windows once there are this many.
+const MEMORY_SWEEP_LEN: usize = 10_000;
+
+pub const SIGNIN_PER_IP: RateLimitRule =
+    RateLimitRule::new("signin_ip", 10, MINUTE.saturating_mul(15));
+pub const SIGNIN_PER_EMAIL: RateLimitRule = RateLimitRule::new("signin_email", 5, HOUR);
+pub const SIGNIN_GLOBAL: RateLimitRule = RateLimitRule::new("signin_global", 500, HOUR);
+
+pub const OAUTH_PER_IP: RateLimitRule =
+    RateLimitRule::new("oauth_ip", 20, MINUTE.saturating_mul(15));
+pub const OAUTH_GLOBAL: RateLimitRule = RateLimitRule::new("oauth_global", 2000, HOUR);
+
+pub const PASSWORD_PER_IP: RateLimitRule =
+    RateLimitRule::new("password_ip", 30, MINUTE.saturating_mul(15));
+pub const PASSWORD_PER_EMAIL: RateLimitRule =
+    RateLimitRule::new("password_email", 10, MINUTE.saturating_mul(15));
+
+pub const TWO_FACTOR_PER_IP: RateLimitRule =
+    RateLimitRule::new("two_factor_ip", 30, MINUTE.saturating_mul(15));
+pub const TWO_FACTOR_PER_USER: RateLimitRule =
+    RateLimitRule::new("two_factor_user", 10, MINUTE.saturating_mul(15));
+
+pub const PASSKEY_PER_IP: RateLimitRule =
+    RateLimitRule::new("passkey_ip", 30, MINUTE.saturating_mul(15));
+
+pub const VERIFICATION_PER_IP: RateLimitRule = RateLimitRule::new("verification_ip", 10, HOUR);
+pub const VERIFICATION_PER_EMAIL: RateLimitRule = RateLimitRule::new("verification_email", 3, HOUR);
+
+pub const INVITATION_PER_INVITER: RateLimitRule =
+    RateLimitRule::new("invitation_inviter", 20, HOUR);
+pub const INVITATION_PER_EMAIL: RateLimitRule = RateLimitRule::new("invitation_email", 5, DAY);
+pub const INVITATION_GLOBAL: RateLimitRule = RateLimitRule::new("invitation_global", 500, HOUR);
+
+/// At most `limit` hits per subject in each `window`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitRule {
+    /// Identifies the rule in counters and in `RateLimiter::with_rule` overrides.
+    pub name: &'static str,
+    pub limit: u32,
+    pub window: Duration,
+}
+
+impl RateLimitRule {
+    pub const fn new(name: &'static str, limit: u32, window: Duration) -> Self {
+        Self {
+            name,
+            limit,
+            window,
+        }
+    }
+
+    pub const fn with_limit(mut self, limit: u32) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub const fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+}
+
+/// Where the counters live.
+#[derive(Debug)]
+pub enum RateLimitStore {
+    /// Per process, lost on restart.
+    Memory(Mutex<HashMap<String, MemoryWindow>>),
+    /// In the `rate_limit` table, shared by every instance using the database.
+    SurrealDb,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryWindow {
+    started: Instant,
+    count: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredWindow {
+    count: u32,
+    retry_after: i64,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    store: RateLimitStore,
+    overrides: HashMap<&'static str, RateLimitRule>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::memory()
+    }
+}
+
+impl RateLimiter {
+    pub fn memory() -> Self {
+        Self {
+            store: RateLimitStore::Memory(Mutex::new(HashMap::new())),
+            overrides: HashMap::new(),
+        }
+    }
+
+    pub fn surrealdb() -> Self {
+        Self {
+            store: RateLimitStore::SurrealDb,
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Replaces the built-in rule with the same name.
+    pub fn with_rule(mut self, rule: RateLimitRule) -> Self {
+        self.overrides.insert(rule.name, rule);
+        self
+    }
+
+    /// Counts a hit of `subject` against `rule`, or returns `AppError::RateLimited` when the
+    /// subject has used up the window.
+    pub async fn hit(&self, rule: &RateLimitRule, subject: &str) -> Result<(), AppError> {
+        let rule = self.overrides.get(rule.name).unwrap_or(rule);
+        let key = format!("{}:{}", rule.name, subject.trim().to_lowercase());
+
+        let (count, retry_after) = match &self.store {
+            RateLimitStore::Memory(windows) => memory_hit(windows, rule, key),
+            RateLimitStore::SurrealDb => surrealdb_hit(rule, &key).await?,
+        };
+
+        if count > rule.limit {
+            tracing::warn!(
+                rule = rule.name,
+                retry_after = retry_after,
+                "Rate limit exceeded"
+            );
+            return Err(AppError::RateLimited(retry_after));
+        }
+
+        Ok(())
+    }
+}
+
+/// The hit count in the current window and the seconds until it ends.
+fn memory_hit(
+    windows: &Mutex<HashMap<String, MemoryWindow>>,
+    rule: &RateLimitRule,
+    key: String,
+) -> (u32, u64) {
+    let now = Instant::now();
+    let mut windows = windows.lock().unwrap_or_else(|e| e.into_inner());
+
+    if windows.len() >= MEMORY_SWEEP_LEN {
+        // windows of other rules may be longer, keep anything younger than a day
+        windows.retain(|_, window| now.duration_since(window.started) < DAY);
+    }
+
+    let window = windows.entry(key).or_insert(MemoryWindow {
+        started: now,
+        count: 0,
+    });
+
+    if now.duration_since(window.started) >= rule.window {
+        *window = MemoryWindow {
+            started: now,
+            count: 0,
+        };
+    }
+
+    window.count = window.count.saturating_add(1);
+
+    let remaining = rule
+        .window
+        .saturating_sub(now.duration_since(window.started));
+    (window.count, remaining.as_secs().max(1))
+}
+
+async fn surrealdb_hit(rule: &RateLimitRule, key: &str) -> Result<(u32, u64), AppError> {
+    let client = db_init().await?;
+
+    // the key holds email addresses, only store its hash
+    let key = hex::encode(Sha256::digest(key.as_bytes()));
+
+    let mut result = client
+        .query(format!(
+            "LET $window = UPSERT ONLY type::thing('rate_limit', $key) SET
+                count = IF window_end > time::now() THEN count + 1 ELSE 1 END,
+                window_end = IF window_end > time::now() THEN window_end ELSE time::now() + {}s END
+            RETURN AFTER;
+            RETURN {{ count: $window.count, retry_after: duration::secs($window.window_end - time::now()) }};",
+            rule.window.as_secs()
+        ))
+        .bind(("key", key))
+        .await?
+        .check()?;
+
+    let window: Option<StoredWindow> = result.take(1)?;
+    let window =
+        window.ok_or_else(|| AppError::DatabaseError("Could not update rate limit".into()))?;
+
+    Ok((window.count, window.retry_after.max(1) as u64))
+}
+
+static RATE_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+/// Sets the limiter used by `check_rate_limits`. Call it once at startup, before the first
+/// request, otherwise the in-memory default is used.
+pub fn set_rate_limiter(limiter: RateLimiter) -> Result<(), AppError> {
+    RATE_LIMITER
+        .set(limiter)
+        .map_err(|_| AppError::Config("Rate limiter is already set".into()))
+}
+
+pub fn rate_limiter() -> &'static RateLimiter {
+    RATE_LIMITER.get_or_init(RateLimiter::default)
+}
+
+/// Counts a hit for each `(rule, subject)`, skipping missing subjects, and stops at the first
+/// rule that is over its limit.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     let ip = SessionClient::from_context().ip;
+///     check_rate_limits(&[
+///         (&SIGNIN_PER_IP, ip.as_deref()),
+///         (&SIGNIN_PER_EMAIL, Some(&email)),
+///         (&SIGNIN_GLOBAL, Some(GLOBAL)),
+///     ])
+///     .await?;
+/// ```
+pub async fn check_rate_limits(checks: &[(&RateLimitRule, Option<&str>)]) -> Result<(), AppError> {
+    let limiter = rate_limiter();
+
+    for (rule, subject) in checks {
+        if let Some(subject) = subject {
+            limiter.hit(rule, subject).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_rate_limit() {
+        let rule = RateLimitRule::new("test", 2, HOUR);
+        let limiter = RateLimiter::memory();
+
+        assert!(limiter.hit(&rule, "a@example.com").await.is_ok());
+        assert!(limiter.hit(&rule, "A@example.com ").await.is_ok());
+        assert!(matches!(
+            limiter.hit(&rule, "a@example.com").await,
+            Err(AppError::RateLimited(secs)) if secs > 0 && secs <= 3600
+        ));
+        assert!(limiter.hit(&rule, "b@example.com").await.is_ok());
+
+        let limiter = RateLimiter::memory().with_rule(rule.with_window(Duration::ZERO));
+        for _ in 0..5 {
+            assert!(limiter.hit(&rule, "a@example.com").await.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_surrealdb_rate_limit() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+
+        let rule = RateLimitRule::new("test", 2, HOUR);
+        let limiter = RateLimiter::surrealdb();
+
+        with_db(db_memory().await?, async {
+            limiter.hit(&rule, "a@example.com").await?;
+            limiter.hit(&rule, "A@example.com ").await?;
+            assert!(matches!(
+                limiter.hit(&rule, "a@example.com").await,
+                Err(AppError::RateLimited(secs)) if secs > 0 && secs <= 3600
+            ));
+            limiter.hit(&rule, "b@example.com").await?;
+
+            // only the hash of the subject is stored
+            let db = db_init().await?;
+            let keys: Vec<String> = db
+                .query("SELECT VALUE record::id(id) FROM rate_limit;")
+                .await?
+                .take(0)?;
+            assert_eq!(keys.len(), 2);
+            assert!(keys.iter().all(|key| !key.contains('@')));
+
+            // an ended window starts again at 1
+            db.query("UPDATE rate_limit SET window_end = time::now() - 1s;")
+                .await?;
+            limiter.hit(&rule, "a@example.com").await?;
+            limiter.hit(&rule, "a@example.com").await?;
+            assert!(limiter.hit(&rule, "a@example.com").await.is_err());
+
+            Ok(())
+        })
+        .await
+    }
+}
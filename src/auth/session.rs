@@ -3,14 +3,15 @@ use leptos::prelude::*;
 use partial_struct::Partial;
 use serde::{Deserialize, Serialize};
 
-#[cfg(feature = "ssr")]
-use crate::user::AdapterUser;
-
 #[cfg(feature = "ssr")]
 use crate::db_init;
 
 #[cfg(feature = "ssr")]
-use crate::AppError;
+use crate::{
+    AppError,
+    date_utils::parse_surrealdb_datetime_to_chrono,
+    token_hash::{hash_token, verify_token},
+};
 
 #[cfg(feature = "ssr")]
 use surrealdb::{Datetime, RecordId};
@@ -28,34 +29,194 @@ pub struct AdapterSession {
     pub session_token: String,
     pub user_id: RecordId,
     pub expires: Datetime,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub created_at: Option<Datetime>,
+    /// Updated at most every `LAST_SEEN_INTERVAL_MINUTES` while the session is used.
+    #[serde(default)]
+    pub last_seen: Option<Datetime>,
+    /// Signed in with the first factor only, see `auth::two_factor`.
+    #[serde(default)]
+    pub two_factor_pending: bool,
+    /// Last passkey verification of the session, see `auth::passkey`.
+    #[serde(default)]
+    pub reverified_at: Option<Datetime>,
+}
+
+/// A session as shown to its user, without the token.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SessionInfo {
+    pub id: RecordId,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: Option<Datetime>,
+    pub last_seen: Option<Datetime>,
+    pub expires: Datetime,
+    /// The session of the device making the request.
+    pub current: bool,
+}
+
+/// How often `last_seen` is written while a session is in use.
+pub const LAST_SEEN_INTERVAL_MINUTES: i64 = 5;
+
+/// How long a new or renewed session stays valid.
+pub const SESSION_LIFETIME_DAYS: i64 = 60;
+
+/// Sessions used with less than this left are renewed for another `SESSION_LIFETIME_DAYS`.
+pub const SESSION_RENEW_WITHIN_DAYS: i64 = 15;
+
+/// The device a session is created from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionClient {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[cfg(feature = "ssr")]
+impl SessionClient {
+    /// Reads the user agent and address of the request being handled, empty outside of one.
+    pub fn from_context() -> Self {
+        let Some(parts) = use_context::<http::request::Parts>() else {
+            return Self::default();
+        };
+
+        Self {
+            user_agent: parts
+                .headers
+                .get(http::header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.chars().take(512).collect()),
+            ip: crate::middleware::client_ip(&parts.headers, &parts.extensions),
+        }
+    }
+}
+
+/// The `session_token` cookie of the request being handled.
+#[cfg(feature = "ssr")]
+pub async fn current_session_token() -> Result<String, ServerFnError> {
+    crate::auth::extract::request_session_token().ok_or(ServerFnError::new("Not logged in."))
 }
 
 #[cfg(feature = "ssr")]
 impl AdapterSession {
+    /// The unexpired session with `session_token`.
     pub async fn from_string(session_token: String) -> Result<AdapterSession, AppError> {
         let client = db_init().await?;
 
         let mut result = client
-            .query("SELECT * FROM ONLY session WHERE session_token = $session_token LIMIT 1;")
-            .bind(("session_token", session_token))
+            .query("SELECT * FROM ONLY session WHERE session_token = $session_token AND expires > time::now() LIMIT 1;")
+            .bind(("session_token", hash_token(&session_token)?))
             .await?;
 
         let token: Option<AdapterSession> = result.take(0)?;
 
-        match token {
-            Some(session) => Ok(session),
-            None => Err(AppError::AuthError("Session not found".into())),
+        token
+            .and_then(|session| session.with_token(session_token))
+            .ok_or_else(|| AppError::AuthError("Session not found".into()))
+    }
+
+    /// The unexpired session with `session_token` that isn't waiting for its second factor.
+    /// Everything a signed in user does goes through this, `from_string` also finds sessions
+    /// that are only allowed to finish the two-factor challenge.
+    pub async fn signed_in(session_token: String) -> Result<AdapterSession, AppError> {
+        let session = Self::from_string(session_token).await?;
+
+        if session.two_factor_pending {
+            return Err(AppError::AuthError("Session not found".into()));
+        }
+
+        Ok(session)
+    }
+
+    /// Puts the plaintext `session_token` back in place of the stored hash, `None` if they
+    /// don't match.
+    pub fn with_token(mut self, session_token: String) -> Option<Self> {
+        if !verify_token(&session_token, &self.session_token) {
+            return None;
         }
+        self.session_token = session_token;
+        Some(self)
     }
 
+    /// Stores the session with its token hashed, the returned session holds the plaintext token.
     pub async fn create_session(
-        session_data: CreateSessionData,
+        mut session_data: CreateSessionData,
     ) -> Result<AdapterSession, AppError> {
         let client = db_init().await?;
+
+        let session_token = session_data.session_token.clone();
+        session_data.session_token = hash_token(&session_token)?;
+
         let result: Option<AdapterSession> = client.create("session").content(session_data).await?;
-        let session: AdapterSession =
-            result.ok_or_else(|| AppError::AuthError("Could not create session".into()))?;
-        Ok(session)
+        result
+            .and_then(|session| session.with_token(session_token))
+            .ok_or_else(|| AppError::AuthError("Could not create session".into()))
+    }
+
+    /// Active sessions of `user_id`, most recently used first. Their `session_token` is the
+    /// stored hash.
+    pub async fn get_by_user(user_id: RecordId) -> Result<Vec<AdapterSession>, AppError> {
+        let client = db_init().await?;
+
+        let mut result = client
+            .query("SELECT * FROM session WHERE user_id = $user_id AND expires > time::now() ORDER BY last_seen DESC;")
+            .bind(("user_id", user_id))
+            .await?;
+
+        let sessions: Vec<AdapterSession> = result.take(0)?;
+        Ok(sessions)
+    }
+
+    /// Deletes the session `id` if it belongs to `user_id`, returns whether it existed.
+    pub async fn revoke(user_id: RecordId, id: RecordId) -> Result<bool, AppError> {
+        let client = db_init().await?;
+
+        let mut result = client
+            .query("DELETE session WHERE id = $id AND user_id = $user_id RETURN BEFORE;")
+            .bind(("id", id))
+            .bind(("user_id", user_id))
+            .await?;
+
+        let removed: Vec<AdapterSession> = result.take(0)?;
+        Ok(!removed.is_empty())
+    }
+
+    /// Deletes every session of `user_id` except the one with `keep_token`, returns how many.
+    ///
+    /// ### EXAMPLE:
+    /// ```rs
+    ///     // log out everywhere else
+    ///     AdapterSession::revoke_all(user.id, Some(current_token)).await?;
+    /// ```
+    pub async fn revoke_all(
+        user_id: RecordId,
+        keep_token: Option<String>,
+    ) -> Result<u64, AppError> {
+        let client = db_init().await?;
+
+        let mut result = client
+            .query("RETURN count((DELETE session WHERE user_id = $user_id AND session_token != $keep_token RETURN BEFORE));")
+            .bind(("user_id", user_id))
+            .bind(("keep_token", keep_token.as_deref().map(hash_token).transpose()?))
+            .await?;
+
+        let removed: Option<u64> = result.take(0)?;
+        Ok(removed.unwrap_or(0))
+    }
+
+    pub fn to_info(&self, current_token: &str) -> SessionInfo {
+        SessionInfo {
+            id: self.id.clone(),
+            user_agent: self.user_agent.clone(),
+            ip: self.ip.clone(),
+            created_at: self.created_at.clone(),
+            last_seen: self.last_seen.clone(),
+            expires: self.expires.clone(),
+            current: verify_token(current_token, &self.session_token),
+        }
     }
 
     pub fn build_session_cookie(&self) -> axum_extra::extract::cookie::Cookie<'_> {
@@ -69,7 +230,7 @@ impl AdapterSession {
                 .secure(true) // use only over HTTPS
                 .http_only(true) // JS can't read the cookie
                 .same_site(leptos_use::SameSite::Strict)
-                .max_age(Duration::days(60))
+                .max_age(Duration::days(SESSION_LIFETIME_DAYS))
                 .build()
         } else {
             // debug mode
@@ -78,7 +239,7 @@ impl AdapterSession {
                 .secure(false) // use only over HTTPS
                 .http_only(true) // JS can't read the cookie
                 .same_site(leptos_use::SameSite::Lax)
-                .max_age(Duration::days(365))
+                .max_age(Duration::days(SESSION_LIFETIME_DAYS))
                 .build()
         };
 
@@ -102,20 +263,158 @@ impl AdapterSession {
     // }
 
     pub async fn update_session(
-        // &self,
         data: UpdateSessionData,
     ) -> Result<Option<AdapterSession>, AppError> {
         let client = db_init().await?;
 
-        let result = client
-            .query("UPDATE session SET expires = $expires WHERE session_token = $session_token;")
+        let mut result = client
+            .query("UPDATE session SET expires = $expires WHERE session_token = $session_token RETURN AFTER;")
             .bind(("expires", data.expires))
-            .bind(("session_token", data.session_token))
+            .bind(("session_token", hash_token(&data.session_token)?))
             .await?;
 
-        println!("update_session: {:?}", result);
+        let updated: Vec<AdapterSession> = result.take(0)?;
+        Ok(updated
+            .into_iter()
+            .next()
+            .and_then(|session| session.with_token(data.session_token)))
+    }
 
-        Ok(None)
+    /// Whether the session is close enough to expiry to be renewed when used.
+    pub fn needs_renewal(&self) -> bool {
+        parse_surrealdb_datetime_to_chrono(&self.expires).is_some_and(|expires| {
+            expires < chrono::Utc::now() + chrono::Duration::days(SESSION_RENEW_WITHIN_DAYS)
+        })
+    }
+
+    /// Extends the session for another `SESSION_LIFETIME_DAYS` and re-issues its cookie through
+    /// `ResponseOptions`, call it only where they are in context.
+    pub async fn renew(&self) -> Result<AdapterSession, AppError> {
+        let expires = chrono::Utc::now() + chrono::Duration::days(SESSION_LIFETIME_DAYS);
+
+        let session = Self::update_session(UpdateSessionData {
+            session_token: self.session_token.clone(),
+            expires: Datetime::from(expires),
+            user_agent: self.user_agent.clone(),
+            ip: self.ip.clone(),
+            created_at: self.created_at.clone(),
+            last_seen: self.last_seen.clone(),
+            two_factor_pending: self.two_factor_pending,
+            reverified_at: self.reverified_at.clone(),
+        })
+        .await?
+        .ok_or_else(|| AppError::AuthError("Session not found".into()))?;
+
+        session.set_cookie();
+        Ok(session)
+    }
+
+    /// Replaces the token of the session, the old token stops working immediately.
+    pub async fn rotate(session_token: String) -> Result<AdapterSession, AppError> {
+        let client = db_init().await?;
+        let expires = chrono::Utc::now() + chrono::Duration::days(SESSION_LIFETIME_DAYS);
+
+        let new_token = uuid::Uuid::new_v4().to_string();
+
+        let mut result = client
+            .query("UPDATE session SET session_token = $new_token, expires = $expires WHERE session_token = $session_token AND expires > time::now() RETURN AFTER;")
+            .bind(("session_token", hash_token(&session_token)?))
+            .bind(("new_token", hash_token(&new_token)?))
+            .bind(("expires", Datetime::from(expires)))
+            .await?;
+
+        let rotated: Vec<AdapterSession> = result.take(0)?;
+        let session = rotated
+            .into_iter()
+            .next()
+            .and_then(|session| session.with_token(new_token))
+            .ok_or_else(|| AppError::AuthError("Session not found".into()))?;
+
+        session.set_cookie();
+        Ok(session)
+    }
+
+    /// Marks a pending session as having passed its second factor, and rotates its token so the
+    /// token seen before the second factor can't be used.
+    pub async fn complete_two_factor(&self) -> Result<AdapterSession, AppError> {
+        let client = db_init().await?;
+
+        client
+            .query("UPDATE session SET two_factor_pending = false WHERE session_token = $session_token RETURN NONE;")
+            .bind(("session_token", hash_token(&self.session_token)?))
+            .await?
+            .check()?;
+
+        Self::rotate(self.session_token.clone()).await
+    }
+
+    /// Records a passkey verification, for operations that call
+    /// `passkey::require_recent_reverification`.
+    pub async fn mark_reverified(&self) -> Result<(), AppError> {
+        let client = db_init().await?;
+
+        client
+            .query("UPDATE session SET reverified_at = time::now() WHERE session_token = $session_token RETURN NONE;")
+            .bind(("session_token", hash_token(&self.session_token)?))
+            .await?
+            .check()?;
+
+        Ok(())
+    }
+
+    /// Whether the session was verified with a passkey in the last `minutes`.
+    pub fn reverified_within(&self, minutes: i64) -> bool {
+        self.reverified_at
+            .as_ref()
+            .and_then(parse_surrealdb_datetime_to_chrono)
+            .is_some_and(|at| at > chrono::Utc::now() - chrono::Duration::minutes(minutes))
+    }
+
+    /// Whether the session was signed in within the last `minutes`.
+    pub fn signed_in_within(&self, minutes: i64) -> bool {
+        self.created_at
+            .as_ref()
+            .and_then(parse_surrealdb_datetime_to_chrono)
+            .is_some_and(|at| at > chrono::Utc::now() - chrono::Duration::minutes(minutes))
+    }
+
+    /// Call after the privileges of `user_id` change. Rotates the token of the current request's
+    /// session if it belongs to the user, returns the rotated session. Other sessions stay signed
+    /// in, revoke them separately after a security downgrade.
+    ///
+    /// ### EXAMPLE:
+    /// ```rs
+    ///     TwoFactor::disable(user.id.clone()).await?;
+    ///     let current = AdapterSession::rotate_after_privilege_change(user.id.clone()).await?;
+    ///     AdapterSession::revoke_all(user.id, current.map(|s| s.session_token)).await?;
+    /// ```
+    pub async fn rotate_after_privilege_change(
+        user_id: RecordId,
+    ) -> Result<Option<AdapterSession>, AppError> {
+        let current = match current_session_token().await {
+            Ok(token) => Self::signed_in(token)
+                .await
+                .ok()
+                .filter(|session| session.user_id == user_id),
+            Err(_) => None,
+        };
+
+        match current {
+            Some(session) => Ok(Some(Self::rotate(session.session_token).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets the session cookie on the response being built, does nothing outside a server function.
+    pub fn set_cookie(&self) {
+        use http::header::HeaderValue;
+        use leptos_axum::ResponseOptions;
+
+        if let Some(resp) = use_context::<ResponseOptions>()
+            && let Ok(value) = HeaderValue::from_str(&self.build_session_cookie().to_string())
+        {
+            resp.append_header(http::header::SET_COOKIE, value);
+        }
     }
 
     pub async fn delete_session(session_token: String) -> Result<Option<AdapterSession>, AppError> {
@@ -123,23 +422,104 @@ impl AdapterSession {
 
         let _ = client
             .query("DELETE ONLY session WHERE session_token = $session_token RETURN BEFORE;")
-            .bind(("session_token", session_token))
+            .bind(("session_token", hash_token(&session_token)?))
             .await?;
 
         Ok(None)
     }
 }
 
+/// Deletes expired `session`, `verificationToken`, `oauth_state`, `rate_limit` and
+/// `webauthn_challenge` rows, returns how many.
+#[cfg(feature = "ssr")]
+pub async fn purge_expired_auth_rows() -> Result<u64, AppError> {
+    let client = db_init().await?;
+
+    let mut result = client
+        .query(
+            "RETURN count((DELETE session WHERE expires < time::now() RETURN BEFORE));
+            RETURN count((DELETE verificationToken WHERE expires < time::now() RETURN BEFORE));
+            RETURN count((DELETE oauth_state WHERE expires < time::now() RETURN BEFORE));
+            RETURN count((DELETE rate_limit WHERE window_end < time::now() RETURN BEFORE));
+            RETURN count((DELETE webauthn_challenge WHERE expires < time::now() RETURN BEFORE));",
+        )
+        .await?
+        .check()?;
+
+    let mut removed = 0;
+    for index in 0..5 {
+        removed += result.take::<Option<u64>>(index)?.unwrap_or(0);
+    }
+
+    Ok(removed)
+}
+
+/// Runs `purge_expired_auth_rows` every `interval` until the task is aborted.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     // in main, after the database is configured
+///     spawn_auth_purge(Duration::from_secs(3600));
+/// ```
+#[cfg(feature = "ssr")]
+pub fn spawn_auth_purge(interval: std::time::Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            match purge_expired_auth_rows().await {
+                Ok(0) => {}
+                Ok(removed) => tracing::debug!("Purged {} expired auth rows", removed),
+                Err(e) => tracing::warn!("Purge of expired auth rows failed: {}", e),
+            }
+        }
+    })
+}
+
+/// Lists the signed in user's active sessions.
+#[server]
+pub async fn list_sessions() -> Result<Vec<SessionInfo>, ServerFnError> {
+    let token = current_session_token().await?;
+    let user = get_user().await?;
+
+    let sessions = AdapterSession::get_by_user(user.id).await?;
+    Ok(sessions.iter().map(|s| s.to_info(&token)).collect())
+}
+
+/// Revokes one of the signed in user's other sessions, the current one ends with `logout`.
+#[server]
+pub async fn revoke_session(id: RecordId) -> Result<(), ServerFnError> {
+    let token = current_session_token().await?;
+    let session = AdapterSession::signed_in(token).await?;
+
+    if session.id == id {
+        return Err(ServerFnError::new(
+            "Use log out to end the current session.",
+        ));
+    }
+
+    if !AdapterSession::revoke(session.user_id, id).await? {
+        return Err(AppError::NotFound("Session not found".into()).into());
+    }
+
+    Ok(())
+}
+
+/// Revokes every session of the signed in user except the current one.
+#[server]
+pub async fn revoke_other_sessions() -> Result<u64, ServerFnError> {
+    let token = current_session_token().await?;
+    let session = AdapterSession::signed_in(token.clone()).await?;
+
+    Ok(AdapterSession::revoke_all(session.user_id, Some(token)).await?)
+}
+
 #[server]
 pub async fn get_session() -> Result<String, ServerFnError> {
-    use crate::user::AdapterUser;
-    let cookie_jar = leptos_axum::extract::<axum_extra::extract::CookieJar>().await?;
-    let csrf_cookie = cookie_jar
-        .iter()
-        .filter(|cookie| cookie.name().contains("session_token"))
-        .next()
-        .ok_or(ServerFnError::new("Not logged in."))?;
-    let user = AdapterUser::get_user_from_session(csrf_cookie.value().to_string()).await?;
+    let user = get_user().await?;
     Ok(user.name)
 }
 
@@ -155,27 +535,8 @@ pub async fn get_user() -> Result<crate::user::AdapterUser, ServerFnError> {
 /// Same as `get_user()` but wont error if no user
 #[server]
 pub async fn get_user_option() -> Result<Option<crate::user::AdapterUser>, ServerFnError> {
-    let cookie_jar = leptos_axum::extract::<axum_extra::extract::CookieJar>().await?;
-
-    let csrf_cookie = cookie_jar
-        .iter()
-        .filter(|cookie| cookie.name().contains("session_token"))
-        .next();
-
-    match csrf_cookie {
-        Some(cookie) => {
-            let user_from_cooki =
-                AdapterUser::get_user_from_session(cookie.value().to_string()).await;
-
-            let user = match user_from_cooki {
-                Ok(user) => user,
-                Err(_) => return Ok(None), // treat invalid session as no user
-            };
-
-            Ok(Some(user))
-        }
-        None => Ok(None),
-    }
+    // treat invalid sessions and keys as no user
+    Ok(crate::auth::extract::request_user().await.unwrap_or(None))
 }
 
 #[server]
@@ -185,14 +546,9 @@ pub async fn logout() -> Result<(), ServerFnError> {
     use leptos_axum::ResponseOptions;
     use time::Duration;
 
-    let cookie_jar = leptos_axum::extract::<axum_extra::extract::CookieJar>().await?;
-
     // Find and delete the session from database
-    if let Some(session_cookie) = cookie_jar
-        .iter()
-        .find(|cookie| cookie.name().contains("session_token"))
-    {
-        let _ = AdapterSession::delete_session(session_cookie.value().to_string()).await;
+    if let Some(session_token) = crate::auth::extract::request_session_token() {
+        let _ = AdapterSession::delete_session(session_token).await;
     }
 
     // Create the cookie to overwrite the existing session token
@@ -225,6 +581,9 @@ pub struct OAuthState {
     pub pkce_verifier: String,
     pub callback_url: String,
     pub provider: OAuthProvider,
+    /// Sent to OpenID Connect providers and expected back in the `id_token`.
+    #[serde(default)]
+    pub nonce: Option<String>,
 }
 
 #[cfg(feature = "ssr")]
@@ -241,11 +600,12 @@ pub async fn store_oauth_state(state: OAuthState) -> Result<(), ServerFnError> {
     tracing::info!("Storing OAuth state with expires at {:?}", expires);
 
     let result = client
-        .query("CREATE oauth_state SET csrf_token = $csrf_token, pkce_verifier = $pkce_verifier, callback_url = $callback_url, provider = $provider, expires = $expires;")
+        .query("CREATE oauth_state SET csrf_token = $csrf_token, pkce_verifier = $pkce_verifier, callback_url = $callback_url, provider = $provider, nonce = $nonce, expires = $expires;")
         .bind(("csrf_token", state.csrf_token))
         .bind(("pkce_verifier", state.pkce_verifier))
         .bind(("callback_url", state.callback_url))
         .bind(("provider", state.provider.as_str().to_string()))
+        .bind(("nonce", state.nonce))
         .bind(("expires", surrealdb::Datetime::from(expires)))
         .await
         .map_err(|e| ServerFnError::new(format!("Failed to store OAuth state: {}", e)))?;
@@ -337,3 +697,288 @@ pub fn LogoutPage() -> impl IntoView {
         </div>
     }
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    fn session_expiring_in(days: i64) -> AdapterSession {
+        AdapterSession {
+            id: RecordId::from_table_key("session", "test"),
+            session_token: "token".into(),
+            user_id: RecordId::from_table_key("user", "test"),
+            expires: Datetime::from(chrono::Utc::now() + chrono::Duration::days(days)),
+            user_agent: None,
+            ip: None,
+            created_at: None,
+            last_seen: None,
+            two_factor_pending: false,
+            reverified_at: None,
+        }
+    }
+
+    #[test]
+    fn test_needs_renewal() {
+        assert!(session_expiring_in(1).needs_renewal());
+        assert!(!session_expiring_in(SESSION_LIFETIME_DAYS).needs_renewal());
+    }
+
+    #[test]
+    fn test_reverified_within() {
+        let mut session = session_expiring_in(1);
+        assert!(!session.reverified_within(5));
+
+        session.reverified_at = Some(Datetime::from(
+            chrono::Utc::now() - chrono::Duration::minutes(1),
+        ));
+        assert!(session.reverified_within(5));
+
+        session.reverified_at = Some(Datetime::from(
+            chrono::Utc::now() - chrono::Duration::minutes(10),
+        ));
+        assert!(!session.reverified_within(5));
+    }
+
+    #[tokio::test]
+    async fn test_revoke_other_sessions() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+        use crate::user::AdapterUser;
+
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            let current = user.new_session().await?;
+            let other = user.new_session().await?;
+            assert_eq!(AdapterSession::get_by_user(user.id.clone()).await?.len(), 2);
+            let (_, key) =
+                crate::api_key::ApiKey::create(user.id.clone(), "CI".into(), None).await?;
+
+            // log out everywhere else
+            let removed =
+                AdapterSession::revoke_all(user.id.clone(), Some(current.session_token.clone()))
+                    .await?;
+            assert_eq!(removed, 1);
+            assert!(
+                AdapterSession::from_string(other.session_token)
+                    .await
+                    .is_err()
+            );
+            assert!(
+                AdapterSession::from_string(current.session_token)
+                    .await
+                    .is_ok()
+            );
+            // API keys are only revoked on their own
+            assert!(crate::api_key::ApiKey::authenticate(&key).await.is_ok());
+            assert_eq!(
+                crate::api_key::ApiKey::revoke_all(user.id.clone()).await?,
+                1
+            );
+            assert!(crate::api_key::ApiKey::authenticate(&key).await.is_err());
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_session_rotation_and_expiry() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+        use crate::user::AdapterUser;
+
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            let session = user.new_session().await?;
+
+            // rotating invalidates the old token
+            let rotated = AdapterSession::rotate(session.session_token.clone()).await?;
+            assert!(
+                AdapterSession::from_string(session.session_token)
+                    .await
+                    .is_err()
+            );
+            assert!(
+                AdapterSession::from_string(rotated.session_token.clone())
+                    .await
+                    .is_ok()
+            );
+
+            // expired sessions are rejected and purged
+            db_init()
+                .await?
+                .query("UPDATE $id SET expires = time::now() - 1m;")
+                .bind(("id", rotated.id.clone()))
+                .await?
+                .check()?;
+            assert!(
+                AdapterSession::from_string(rotated.session_token)
+                    .await
+                    .is_err()
+            );
+            assert_eq!(purge_expired_auth_rows().await?, 1);
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_session_token_hashed() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+        use crate::user::AdapterUser;
+
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            let session = user.new_session().await?;
+
+            // only the hash is stored
+            let found = AdapterSession::from_string(session.session_token.clone()).await?;
+            assert_eq!(found.id, session.id);
+            let stored = AdapterSession::get_by_user(user.id.clone()).await?;
+            assert_eq!(stored.len(), 1);
+            assert!(
+                stored
+                    .iter()
+                    .all(|s| s.session_token != session.session_token)
+            );
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_pending_session_is_not_signed_in() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+        use crate::user::AdapterUser;
+
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            let session = user.new_session().await?;
+            let token = session.session_token.clone();
+            assert!(AdapterSession::signed_in(token.clone()).await.is_ok());
+
+            db_init()
+                .await?
+                .query("UPDATE $id SET two_factor_pending = true;")
+                .bind(("id", session.id.clone()))
+                .await?
+                .check()?;
+
+            // found for the two-factor challenge, but not allowed to act as the user
+            let pending = AdapterSession::from_string(token.clone()).await?;
+            assert!(pending.two_factor_pending);
+            assert!(matches!(
+                AdapterSession::signed_in(token.clone()).await,
+                Err(AppError::AuthError(_))
+            ));
+            assert!(AdapterUser::get_user_from_session(token).await.is_err());
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_renewal_needs_response_options() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+        use crate::user::AdapterUser;
+        use leptos::reactive::computed::ScopedFuture;
+        use leptos_axum::ResponseOptions;
+
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            let session = user.new_session().await?;
+            let token = session.session_token.clone();
+            db_init()
+                .await?
+                .query("UPDATE $id SET expires = time::now() + 1d;")
+                .bind(("id", session.id.clone()))
+                .await?
+                .check()?;
+
+            // outside a server fn the cookie can't be re-issued, so the session isn't renewed
+            AdapterUser::get_user_from_session(token.clone()).await?;
+            assert!(
+                AdapterSession::from_string(token.clone())
+                    .await?
+                    .needs_renewal()
+            );
+
+            let options = ResponseOptions::default();
+            let owner = Owner::new();
+            owner.with(|| provide_context(options.clone()));
+            owner
+                .with(|| ScopedFuture::new(AdapterUser::get_user_from_session(token.clone())))
+                .await?;
+
+            assert!(!AdapterSession::from_string(token).await?.needs_renewal());
+            assert!(
+                options
+                    .0
+                    .read()
+                    .headers
+                    .contains_key(http::header::SET_COOKIE)
+            );
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_privilege_change_rotates_only_the_current_session() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+        use crate::user::AdapterUser;
+        use leptos::reactive::computed::ScopedFuture;
+
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            let current = user.new_session().await?;
+            let other = user.new_session().await?;
+
+            let (parts, _) = http::Request::builder()
+                .header(
+                    http::header::COOKIE,
+                    format!(
+                        "{}={}",
+                        crate::auth::extract::SESSION_COOKIE,
+                        current.session_token
+                    ),
+                )
+                .body(())
+                .unwrap()
+                .into_parts();
+            let owner = Owner::new();
+            owner.with(|| provide_context(parts));
+
+            let rotated = owner
+                .with(|| {
+                    ScopedFuture::new(AdapterSession::rotate_after_privilege_change(
+                        user.id.clone(),
+                    ))
+                })
+                .await?
+                .expect("current session rotated");
+
+            assert_eq!(rotated.id, current.id);
+            assert!(
+                AdapterSession::from_string(current.session_token)
+                    .await
+                    .is_err()
+            );
+            assert!(
+                AdapterSession::from_string(rotated.session_token)
+                    .await
+                    .is_ok()
+            );
+            assert!(
+                AdapterSession::from_string(other.session_token)
+                    .await
+                    .is_ok()
+            );
+
+            Ok(())
+        })
+        .await
+    }
+}
@@ -11,6 +11,9 @@ use crate::{AppError, date_utils::parse_surrealdb_datetime_to_chrono};
 #[cfg(feature = "ssr")]
 use uuid::Uuid;
 
+#[cfg(feature = "ssr")]
+use crate::token_hash::{hash_token, verify_token};
+
 #[cfg(feature = "ssr")]
 use surrealdb::{Datetime, RecordId};
 
@@ -34,6 +37,7 @@ pub struct VerificationToken {
 }
 
 impl VerificationToken {
+    /// Stores a new token hashed, the returned token holds the plaintext for the email link.
     pub async fn create_verification_token(
         content: CreateVerificationToken,
     ) -> Result<VerificationToken, AppError> {
@@ -43,14 +47,17 @@ impl VerificationToken {
 
         let mut query = client.query("CREATE verificationToken SET email = $email, expires = time::now() + 30m, token = $tokenstring, user_id = $user_id;")
             .bind(("email", content.email))
-            .bind(("tokenstring", newtoken_string))
+            .bind(("tokenstring", hash_token(&newtoken_string)?))
             .bind(("user_id", content.user_id))
             .await?;
 
         let result: Option<Self> = query.take(0)?;
 
         match result {
-            Some(token) => Ok(token),
+            Some(token) => Ok(Self {
+                token: newtoken_string,
+                ..token
+            }),
             None => Err(AppError::AuthError(
                 "Could not create verification token".into(),
             )),
@@ -62,15 +69,15 @@ impl VerificationToken {
 
         let mut result = client
             .query("DELETE verificationToken WHERE token = $tokenstring RETURN BEFORE;")
-            .bind(("tokenstring", token))
+            .bind(("tokenstring", hash_token(&token)?))
             .await?;
 
-        let token: Option<Self> = result.take(0)?;
+        let stored: Option<Self> = result.take(0)?;
 
-        tracing::info!("use_verification_token result: {:#?}", token);
+        match stored {
+            Some(stored) if verify_token(&token, &stored.token) => {
+                let token = Self { token, ..stored };
 
-        match token {
-            Some(token) => {
                 let expired = parse_surrealdb_datetime_to_chrono(&token.expires)
                     .ok_or_else(|| AppError::AuthError("Invalid token date".into()))?;
 
@@ -79,7 +86,7 @@ impl VerificationToken {
                 }
                 Ok(token)
             }
-            None => return Err(AppError::AuthError("Token not found".into())),
+            _ => Err(AppError::AuthError("Token not found".into())),
         }
     }
 }
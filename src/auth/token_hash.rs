@@ -0,0 +1,169 @@
+//! Keyed hashing of the bearer tokens stored in the database (`session.session_token`,
+//! `verificationToken.token` and `team_invitation.token`). Only `hash_token(token)` is written,
+//! the plaintext token lives in the cookie or link given to the user.
+//!
+//! The key is read from `TINKR_TOKEN_SECRET`. Debug builds fall back to a fixed development key.
+//!
+//! Changing the key, including going from the development key to a real one, invalidates every
+//! stored hash: sessions, verification links, invitations, API keys and two-factor recovery codes.
+//! TOTP secrets are stored separately and are not affected.
+
+use std::sync::OnceLock;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use surrealdb::RecordId;
+
+use crate::{AppError, db_init};
+
+/// Environment variable holding the HMAC key.
+pub const TOKEN_SECRET_ENV: &str = "TINKR_TOKEN_SECRET";
+
+/// Marks a stored value as hashed, rows without it are plaintext from before hashing.
+pub const TOKEN_HASH_PREFIX: &str = "hmac1_";
+
+/// Columns holding hashed tokens, as `(table, field)`.
+pub const HASHED_TOKEN_FIELDS: [(&str, &str); 3] = [
+    ("session", "session_token"),
+    ("verificationToken", "token"),
+    ("team_invitation", "token"),
+];
+
+const DEV_TOKEN_SECRET: &str = "tinkr-development-token-secret";
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn secret() -> Result<&'static [u8], AppError> {
+    static SECRET: OnceLock<Option<String>> = OnceLock::new();
+
+    let secret = SECRET.get_or_init(|| match std::env::var(TOKEN_SECRET_ENV) {
+        Ok(secret) if !secret.is_empty() => Some(secret),
+        _ if cfg!(debug_assertions) => {
+            tracing::warn!(
+                "{} is not set, using the development token secret",
+                TOKEN_SECRET_ENV
+            );
+            Some(DEV_TOKEN_SECRET.to_string())
+        }
+        _ => None,
+    });
+
+    secret
+        .as_deref()
+        .map(str::as_bytes)
+        .ok_or_else(|| AppError::Config(format!("{} must be set", TOKEN_SECRET_ENV)))
+}
+
+fn mac() -> Result<HmacSha256, AppError> {
+    HmacSha256::new_from_slice(secret()?)
+        .map_err(|e| AppError::Config(format!("Invalid token secret: {}", e)))
+}
+
+/// The value stored in place of `token`.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     let mut result = client
+///         .query("SELECT * FROM ONLY session WHERE session_token = $hash LIMIT 1;")
+///         .bind(("hash", hash_token(&token)?))
+///         .await?;
+/// ```
+pub fn hash_token(token: &str) -> Result<String, AppError> {
+    let mut mac = mac()?;
+    mac.update(token.as_bytes());
+    Ok(format!(
+        "{}{}",
+        TOKEN_HASH_PREFIX,
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
+
+/// Checks `token` against a stored hash in constant time.
+pub fn verify_token(token: &str, stored: &str) -> bool {
+    let Some(Ok(expected)) = stored.strip_prefix(TOKEN_HASH_PREFIX).map(hex::decode) else {
+        return false;
+    };
+    let Ok(mut mac) = mac() else {
+        return false;
+    };
+
+    mac.update(token.as_bytes());
+    mac.verify_slice(&expected).is_ok()
+}
+
+#[derive(Debug, Deserialize)]
+struct PlaintextRow {
+    id: RecordId,
+    token: String,
+}
+
+#[derive(Debug, Serialize)]
+struct HashedRow {
+    id: RecordId,
+    hash: String,
+}
+
+/// Hashes the tokens of rows written before tokens were hashed, returns how many. Until it has
+/// run those sessions, links and invitations are not accepted. `MigrationRunner::tinkr()` runs
+/// it once as `hash_plaintext_tokens`, it is also safe to call again.
+pub async fn migrate_plaintext_tokens() -> Result<u64, AppError> {
+    let client = db_init().await?;
+    let mut migrated = 0;
+
+    for (table, field) in HASHED_TOKEN_FIELDS {
+        let mut result = client
+            .query(format!(
+                "SELECT id, {field} AS token FROM type::table($table) WHERE {field} != NONE AND !string::starts_with({field}, $prefix);"
+            ))
+            .bind(("table", table))
+            .bind(("prefix", TOKEN_HASH_PREFIX))
+            .await?;
+
+        let rows: Vec<PlaintextRow> = result.take(0)?;
+        if rows.is_empty() {
+            continue;
+        }
+
+        let hashed = rows
+            .into_iter()
+            .map(|row| {
+                Ok(HashedRow {
+                    id: row.id,
+                    hash: hash_token(&row.token)?,
+                })
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
+
+        let count = hashed.len() as u64;
+
+        client
+            .query(format!(
+                "FOR $row IN $rows {{ UPDATE $row.id SET {field} = $row.hash RETURN NONE; }};"
+            ))
+            .bind(("rows", hashed))
+            .await?
+            .check()?;
+
+        tracing::info!("Hashed {} plaintext tokens in {}", count, table);
+        migrated += count;
+    }
+
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_and_verify_token() {
+        let hash = hash_token("session-token").unwrap();
+
+        assert!(hash.starts_with(TOKEN_HASH_PREFIX));
+        assert_eq!(hash, hash_token("session-token").unwrap());
+        assert!(verify_token("session-token", &hash));
+        assert!(!verify_token("other-token", &hash));
+        assert!(!verify_token("session-token", "session-token"));
+    }
+}
@@ -0,0 +1,630 @@
+//! RFC 6238 TOTP as a second factor, with single-use recovery codes.
+//!
+//! Once a user has enrolled, every new session starts with `two_factor_pending` set, whatever the
+//! first factor was (magic link, OAuth, wallet or password). Pending sessions don't sign the user
+//! in until `verify_two_factor` accepts a code from `TwoFactorChallenge`.
+//!
+//! The TOTP secret is random and kept in the `two_factor` row, so it survives a rotation of
+//! `TINKR_TOKEN_SECRET`. Recovery codes are stored as `hash_token` hashes.
+
+use leptos::{prelude::*, reactive::spawn_local};
+use leptos_router::hooks::use_query_map;
+use serde::{Deserialize, Serialize};
+
+use crate::AppError;
+use crate::components::input::{FormField, Input, InputType};
+
+#[cfg(feature = "ssr")]
+use surrealdb::{Datetime, RecordId};
+
+#[cfg(feature = "ssr")]
+use crate::{
+    db_init,
+    session::AdapterSession,
+    token_hash::{hash_token, verify_token},
+    user::AdapterUser,
+};
+
+/// Where pending sessions are sent to enter their code, render `TwoFactorChallenge` here.
+pub const TWO_FACTOR_PATH: &str = "/two-factor";
+
+/// Where admins are sent to enroll when `REQUIRE_ADMIN_TWO_FACTOR_ENV` is set.
+pub const TWO_FACTOR_SETTINGS_PATH: &str = "/settings/security";
+
+/// Set to `true` to deny admin access to admins without two-factor authentication.
+pub const REQUIRE_ADMIN_TWO_FACTOR_ENV: &str = "TINKR_REQUIRE_ADMIN_2FA";
+
+/// Issuer shown in authenticator apps, `Tinkr` when unset.
+pub const TOTP_ISSUER_ENV: &str = "TINKR_TOTP_ISSUER";
+
+pub const TOTP_PERIOD_SECONDS: u64 = 30;
+pub const TOTP_DIGITS: u32 = 6;
+
+/// Codes of this many periods before and after now are accepted, for clock drift.
+pub const TOTP_ALLOWED_DRIFT: u64 = 1;
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Length of the secret, 160 bits as recommended for HMAC-SHA1.
+const TOTP_SECRET_BYTES: usize = 20;
+
+/// The two-factor state of the signed in user.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    pub recovery_codes_left: usize,
+    /// Whether the user's admin access depends on having two-factor enabled.
+    pub required: bool,
+}
+
+/// A started enrollment, shown once so the user can add it to an authenticator app.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TwoFactorEnrollment {
+    /// Base32 secret for manual entry.
+    pub secret: String,
+    /// `otpauth://` URI, open it on a phone or render it as a QR code.
+    pub otpauth_uri: String,
+}
+
+/// The challenge page URL that continues to `callback` after the code is accepted.
+pub fn two_factor_challenge_path(callback: &str) -> String {
+    format!(
+        "{}?callbackUrl={}",
+        TWO_FACTOR_PATH,
+        urlencoding::encode(callback)
+    )
+}
+
+/// The `TOTP_DIGITS` digit code of `secret` for time step `step` (RFC 4226 dynamic truncation).
+#[cfg(feature = "ssr")]
+pub fn totp_code(secret: &[u8], step: u64) -> u32 {
+    use hmac::{Hmac, Mac};
+
+    let mut mac =
+        Hmac::<sha1::Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[19] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// The time step `code` is valid for around `now` (seconds since the epoch), if any.
+#[cfg(feature = "ssr")]
+pub fn matching_totp_step(secret: &[u8], code: &str, now: u64) -> Option<u64> {
+    let code = code.trim().replace(' ', "");
+    if code.len() != TOTP_DIGITS as usize {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+
+    let current = now / TOTP_PERIOD_SECONDS;
+    (current.saturating_sub(TOTP_ALLOWED_DRIFT)..=current + TOTP_ALLOWED_DRIFT)
+        .find(|step| totp_code(secret, *step) == code)
+}
+
+/// `otpauth://` URI for authenticator apps.
+#[cfg(feature = "ssr")]
+pub fn otpauth_uri(secret_base32: &str, account: &str, issuer: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        urlencoding::encode(issuer),
+        urlencoding::encode(account),
+        secret_base32,
+        urlencoding::encode(issuer),
+        TOTP_DIGITS,
+        TOTP_PERIOD_SECONDS
+    )
+}
+
+/// Recovery codes are compared without case, spaces or dashes.
+#[cfg(feature = "ssr")]
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// New recovery codes as `(plaintext, hash)` pairs, formatted `xxxxx-xxxxx`.
+#[cfg(feature = "ssr")]
+fn generate_recovery_codes() -> Result<Vec<(String, String)>, AppError> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let raw = uuid::Uuid::new_v4().simple().to_string();
+            let code = format!("{}-{}", &raw[..5], &raw[5..10]);
+            let hash = hash_token(&normalize_recovery_code(&code))?;
+            Ok((code, hash))
+        })
+        .collect()
+}
+
+#[cfg(feature = "ssr")]
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Whether admins must have two-factor enabled, from `REQUIRE_ADMIN_TWO_FACTOR_ENV`.
+#[cfg(feature = "ssr")]
+pub fn admin_two_factor_required() -> bool {
+    std::env::var(REQUIRE_ADMIN_TWO_FACTOR_ENV).is_ok_and(|value| value == "true" || value == "1")
+}
+
+/// Rejects admins without two-factor authentication when it is required.
+#[cfg(feature = "ssr")]
+pub async fn check_admin_two_factor(user: &AdapterUser) -> Result<(), AppError> {
+    if admin_two_factor_required() && !TwoFactor::is_enabled(user.id.clone()).await? {
+        return Err(AppError::Forbidden(
+            "Two-factor authentication is required for admins".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// The session of the current request if it is waiting for its second factor.
+#[cfg(feature = "ssr")]
+pub async fn pending_session() -> Result<Option<AdapterSession>, AppError> {
+    let Some(token) = crate::auth::extract::request_session_token() else {
+        return Ok(None);
+    };
+
+    Ok(AdapterSession::from_string(token)
+        .await
+        .ok()
+        .filter(|session| session.two_factor_pending))
+}
+
+/// A user's TOTP enrollment, enabled once the first code has been confirmed.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Deserialize)]
+pub struct TwoFactor {
+    pub id: RecordId,
+    pub user_id: RecordId,
+    /// Hex of the TOTP secret.
+    secret: String,
+    pub enabled_at: Option<Datetime>,
+    #[serde(default)]
+    recovery_codes: Vec<String>,
+    /// Last accepted time step, a code is never accepted twice.
+    #[serde(default)]
+    last_used_step: Option<u64>,
+}
+
+#[cfg(feature = "ssr")]
+impl TwoFactor {
+    pub async fn get(user_id: RecordId) -> Result<Option<TwoFactor>, AppError> {
+        let client = db_init().await?;
+
+        let mut result = client
+            .query("SELECT * FROM ONLY two_factor WHERE user_id = $user_id LIMIT 1;")
+            .bind(("user_id", user_id))
+            .await?;
+
+        let two_factor: Option<TwoFactor> = result.take(0)?;
+        Ok(two_factor)
+    }
+
+    pub async fn is_enabled(user_id: RecordId) -> Result<bool, AppError> {
+        Ok(Self::get(user_id)
+            .await?
+            .is_some_and(|two_factor| two_factor.enabled_at.is_some()))
+    }
+
+    pub fn recovery_codes_left(&self) -> usize {
+        self.recovery_codes.len()
+    }
+
+    fn secret(&self) -> Result<Vec<u8>, AppError> {
+        Ok(hex::decode(&self.secret)?)
+    }
+
+    /// Starts over with a new secret, unless two-factor is already enabled.
+    pub async fn start(user: &AdapterUser) -> Result<TwoFactorEnrollment, AppError> {
+        use rand::Rng;
+
+        let secret = hex::encode(rand::rng().random::<[u8; TOTP_SECRET_BYTES]>());
+        let client = db_init().await?;
+
+        if Self::is_enabled(user.id.clone()).await? {
+            return Err(AppError::Conflict(
+                "Two-factor authentication is already enabled".into(),
+            ));
+        }
+
+        let mut result = client
+            .query(
+                "DELETE two_factor WHERE user_id = $user_id AND enabled_at = NONE;
+                CREATE ONLY two_factor SET user_id = $user_id, secret = $secret, enabled_at = NONE, recovery_codes = [], last_used_step = NONE, created_at = time::now();",
+            )
+            .bind(("user_id", user.id.clone()))
+            .bind(("secret", secret))
+            .await?;
+
+        let two_factor: Option<TwoFactor> = result.take(1)?;
+        let two_factor = two_factor
+            .ok_or_else(|| AppError::DatabaseError("Could not start enrollment".into()))?;
+
+        let secret = data_encoding::BASE32_NOPAD.encode(&two_factor.secret()?);
+        let issuer = std::env::var(TOTP_ISSUER_ENV).unwrap_or_else(|_| "Tinkr".to_string());
+
+        Ok(TwoFactorEnrollment {
+            otpauth_uri: otpauth_uri(&secret, &user.email.0, &issuer),
+            secret,
+        })
+    }
+
+    /// Accepts a TOTP code once, a code is single use even within its period.
+    async fn use_totp_code(&self, code: &str) -> Result<bool, AppError> {
+        let secret = self.secret()?;
+        let Some(step) = matching_totp_step(&secret, code, unix_now()) else {
+            return Ok(false);
+        };
+
+        if self.last_used_step.is_some_and(|last| step <= last) {
+            return Ok(false);
+        }
+
+        let client = db_init().await?;
+
+        // conditional update, so two requests racing with the same code can't both pass
+        let mut result = client
+            .query("UPDATE $id SET last_used_step = $step WHERE last_used_step = NONE OR last_used_step < $step RETURN AFTER;")
+            .bind(("id", self.id.clone()))
+            .bind(("step", step))
+            .await?;
+
+        let updated: Vec<TwoFactor> = result.take(0)?;
+        Ok(!updated.is_empty())
+    }
+
+    /// Accepts and removes a recovery code.
+    async fn use_recovery_code(&self, code: &str) -> Result<bool, AppError> {
+        let normalized = normalize_recovery_code(code);
+
+        let Some(hash) = self
+            .recovery_codes
+            .iter()
+            .find(|hash| verify_token(&normalized, hash))
+        else {
+            return Ok(false);
+        };
+
+        let client = db_init().await?;
+
+        let mut result = client
+            .query("UPDATE $id SET recovery_codes -= $hash WHERE recovery_codes CONTAINS $hash RETURN AFTER;")
+            .bind(("id", self.id.clone()))
+            .bind(("hash", hash.clone()))
+            .await?;
+
+        let updated: Vec<TwoFactor> = result.take(0)?;
+        Ok(!updated.is_empty())
+    }
+
+    /// Accepts a TOTP code or, once enabled, a recovery code.
+    pub async fn verify(&self, code: &str) -> Result<bool, AppError> {
+        if self.use_totp_code(code).await? {
+            return Ok(true);
+        }
+
+        if self.enabled_at.is_some() {
+            return self.use_recovery_code(code).await;
+        }
+
+        Ok(false)
+    }
+
+    /// Replaces the recovery codes, returns the new plaintext codes.
+    pub async fn regenerate_recovery_codes(&self) -> Result<Vec<String>, AppError> {
+        let client = db_init().await?;
+        let (codes, hashes): (Vec<String>, Vec<String>) =
+            generate_recovery_codes()?.into_iter().unzip();
+
+        client
+            .query("UPDATE $id SET recovery_codes = $hashes RETURN NONE;")
+            .bind(("id", self.id.clone()))
+            .bind(("hashes", hashes))
+            .await?
+            .check()?;
+
+        Ok(codes)
+    }
+
+    /// Enables two-factor after the first code is confirmed, returns the recovery codes.
+    pub async fn enable(&self) -> Result<Vec<String>, AppError> {
+        let client = db_init().await?;
+
+        client
+            .query("UPDATE $id SET enabled_at = time::now() RETURN NONE;")
+            .bind(("id", self.id.clone()))
+            .await?
+            .check()?;
+
+        self.regenerate_recovery_codes().await
+    }
+
+    pub async fn disable(user_id: RecordId) -> Result<(), AppError> {
+        let client = db_init().await?;
+
+        client
+            .query("DELETE two_factor WHERE user_id = $user_id;")
+            .bind(("user_id", user_id))
+            .await?
+            .check()?;
+
+        Ok(())
+    }
+}
+
+/// The enabled enrollment of the signed in user, checking `code` against it.
+#[cfg(feature = "ssr")]
+pub(crate) async fn verify_signed_in_code(
+    user: &AdapterUser,
+    code: &str,
+) -> Result<TwoFactor, AppError> {
+    let two_factor = TwoFactor::get(user.id.clone())
+        .await?
+        .filter(|two_factor| two_factor.enabled_at.is_some())
+        .ok_or_else(|| AppError::NotFound("Two-factor authentication is not enabled".into()))?;
+
+    if !two_factor.verify(code).await? {
+        return Err(AppError::AuthError("Invalid code".into()));
+    }
+
+    Ok(two_factor)
+}
+
+#[cfg(feature = "ssr")]
+async fn signed_in_user() -> Result<AdapterUser, AppError> {
+    crate::auth::extract::session_user().await
+}
+
+#[server]
+pub async fn get_two_factor_status() -> Result<TwoFactorStatus, AppError> {
+    let user = signed_in_user().await?;
+    let two_factor = TwoFactor::get(user.id.clone())
+        .await?
+        .filter(|two_factor| two_factor.enabled_at.is_some());
+
+    Ok(TwoFactorStatus {
+        enabled: two_factor.is_some(),
+        recovery_codes_left: two_factor.map_or(0, |t| t.recovery_codes_left()),
+        required: admin_two_factor_required()
+            && (user.is_admin == Some(true) || user.superadmin == Some(true)),
+    })
+}
+
+/// Starts enrollment, confirm it with `confirm_two_factor_enrollment`.
+#[server]
+pub async fn start_two_factor_enrollment() -> Result<TwoFactorEnrollment, AppError> {
+    let user = signed_in_user().await?;
+    TwoFactor::start(&user).await
+}
+
+/// Enables two-factor with the first code from the authenticator app, returns the recovery
+/// codes. Signs out every other session, they were started without the second factor.
+#[server]
+pub async fn confirm_two_factor_enrollment(code: String) -> Result<Vec<String>, AppError> {
+    let user = signed_in_user().await?;
+
+    let two_factor = TwoFactor::get(user.id.clone())
+        .await?
+        .filter(|two_factor| two_factor.enabled_at.is_none())
+        .ok_or_else(|| AppError::NotFound("No enrollment in progress".into()))?;
+
+    if !two_factor.verify(&code).await? {
+        return Err(AppError::AuthError("Invalid code".into()));
+    }
+
+    let codes = two_factor.enable().await?;
+    AdapterSession::rotate_after_privilege_change(user.id).await?;
+
+    Ok(codes)
+}
+
+/// Replaces the recovery codes, needs a current code.
+#[server]
+pub async fn regenerate_recovery_codes(code: String) -> Result<Vec<String>, AppError> {
+    let user = signed_in_user().await?;
+    verify_signed_in_code(&user, &code)
+        .await?
+        .regenerate_recovery_codes()
+        .await
+}
+
+/// Turns two-factor off, needs a current code or a recovery code. Signs out every other device.
+#[server]
+pub async fn disable_two_factor(code: String) -> Result<(), AppError> {
+    let user = signed_in_user().await?;
+    verify_signed_in_code(&user, &code).await?;
+    TwoFactor::disable(user.id.clone()).await?;
+
+    let current = AdapterSession::rotate_after_privilege_change(user.id.clone()).await?;
+    AdapterSession::revoke_all(user.id, current.map(|s| s.session_token)).await?;
+    Ok(())
+}
+
+/// Whether the current session is waiting for its second factor.
+#[server]
+pub async fn two_factor_pending() -> Result<bool, AppError> {
+    Ok(pending_session().await?.is_some())
+}
+
+/// Completes the sign-in of a pending session with a TOTP or recovery code.
+#[server]
+pub async fn verify_two_factor(code: String) -> Result<(), AppError> {
+    use crate::rate_limit::{TWO_FACTOR_PER_IP, TWO_FACTOR_PER_USER, check_rate_limits};
+
+    let session = pending_session()
+        .await?
+        .ok_or_else(|| AppError::AuthError("No sign-in is waiting for a code".into()))?;
+
+    let ip = crate::session::SessionClient::from_context().ip;
+    check_rate_limits(&[
+        (&TWO_FACTOR_PER_IP, ip.as_deref()),
+        (&TWO_FACTOR_PER_USER, Some(&session.user_id.to_string())),
+    ])
+    .await?;
+
+    let two_factor = TwoFactor::get(session.user_id.clone())
+        .await?
+        .filter(|two_factor| two_factor.enabled_at.is_some())
+        .ok_or_else(|| AppError::AuthError("Two-factor authentication is not enabled".into()))?;
+
+    if !two_factor.verify(&code).await? {
+        tracing::warn!(user = %session.user_id, "Rejected two-factor code");
+        return Err(AppError::AuthError("Invalid code".into()));
+    }
+
+    session.complete_two_factor().await?;
+    Ok(())
+}
+
+/// Asks for the second factor of a pending sign-in, then continues to `callbackUrl`.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     <Route path=path!("/two-factor") view=TwoFactorChallenge />
+/// ```
+#[component]
+pub fn TwoFactorChallenge() -> impl IntoView {
+    let query = use_query_map();
+    let code = RwSignal::new(String::new());
+    let (is_loading, set_is_loading) = signal(false);
+    let (error_message, set_error_message) = signal(Option::<String>::None);
+
+    let on_submit = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+
+        let callback = query
+            .read_untracked()
+            .get("callbackUrl")
+            .and_then(|url| crate::auth::protected::safe_callback_url(&url))
+            .unwrap_or_else(|| "/".to_string());
+
+        set_is_loading.set(true);
+        set_error_message.set(None);
+
+        spawn_local(async move {
+            match verify_two_factor(code.get_untracked()).await {
+                Ok(()) => {
+                    window().location().set_href(&callback).unwrap();
+                }
+                Err(e) => {
+                    set_error_message.set(Some(e.to_string()));
+                    set_is_loading.set(false);
+                }
+            }
+        });
+    };
+
+    view! {
+        <div class="py-20 pb-[300px]">
+            <div class="bg-white dark:bg-black rounded-lg shadow-2xl p-8 mx-8 w-full max-w-md mx-auto">
+                <div class="text-center mb-8">
+                    <h1 class="text-3xl font-bold text-neutral-800 dark:text-neutral-100 mb-2">
+                        "Two-factor authentication"
+                    </h1>
+                    <small class="text-neutral-500 dark:text-neutral-400">
+                        "Enter the code from your authenticator app, or one of your recovery codes."
+                    </small>
+                </div>
+
+                <form on:submit=on_submit class="flex flex-col">
+                    <FormField label="Code" label_for="code" class="mb-6">
+                        <Input
+                            id="code"
+                            name="code"
+                            r#type=InputType::Text
+                            placeholder="123456"
+                            value=code
+                            autocomplete="one-time-code"
+                            autofocus=true
+                            disabled=is_loading.get()
+                        />
+                    </FormField>
+
+                    <button
+                        type="submit"
+                        class="w-full text-neutral-100 bg-sky-600 hover:bg-sky-700 dark:bg-sky-600 dark:hover:bg-sky-500 px-4 py-3 rounded-md font-semibold duration-150 disabled:opacity-50 disabled:cursor-not-allowed"
+                        disabled=move || is_loading.get() || code.get().trim().is_empty()
+                    >
+                        "VERIFY"
+                    </button>
+
+                    {move || {
+                        error_message
+                            .get()
+                            .map(|error| {
+                                view! {
+                                    <div class="text-center mt-4 text-red-600 dark:text-red-400">
+                                        {error}
+                                    </div>
+                                }
+                            })
+                    }}
+                </form>
+            </div>
+        </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 appendix B, SHA1, truncated to six digits
+    #[test]
+    fn test_totp_code() {
+        let secret = b"12345678901234567890";
+
+        assert_eq!(totp_code(secret, 59 / TOTP_PERIOD_SECONDS), 287082);
+        assert_eq!(totp_code(secret, 1111111109 / TOTP_PERIOD_SECONDS), 81804);
+        assert_eq!(totp_code(secret, 1234567890 / TOTP_PERIOD_SECONDS), 5924);
+
+        assert_eq!(
+            matching_totp_step(secret, "005924", 1234567890 + 30),
+            Some(1234567890 / TOTP_PERIOD_SECONDS)
+        );
+        assert_eq!(matching_totp_step(secret, "005924", 1234567890 + 90), None);
+        assert_eq!(matching_totp_step(secret, "5924", 1234567890), None);
+
+        assert_eq!(normalize_recovery_code(" AB12C-d34eF "), "ab12cd34ef");
+        assert_eq!(
+            two_factor_challenge_path("/admin"),
+            "/two-factor?callbackUrl=%2Fadmin"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_enrollment_secret_is_stored() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            let enrollment = TwoFactor::start(&user).await?;
+            let secret = data_encoding::BASE32_NOPAD
+                .decode(enrollment.secret.as_bytes())
+                .unwrap();
+
+            let two_factor = TwoFactor::get(user.id.clone()).await?.unwrap();
+            assert_eq!(two_factor.secret, hex::encode(&secret));
+            let code = totp_code(&secret, unix_now() / TOTP_PERIOD_SECONDS);
+            assert!(two_factor.verify(&format!("{:06}", code)).await?);
+
+            Ok(())
+        })
+        .await
+    }
+}
@@ -9,6 +9,11 @@ use leptos::{prelude::*, reactive::spawn_local};
 use serde::{Deserialize, Serialize};
 
 use crate::auth::oauth::OAuthProvider;
+use crate::auth::oidc::OidcSignInButtons;
+use crate::auth::passkey::PasskeySignInButton;
+use crate::auth::password::{
+    PASSWORD_MIN_LENGTH, request_password_reset, reset_password, signin_password,
+};
 use crate::{metamask::WalletConnectButton, user::AdapterUser};
 
 #[cfg(feature = "ssr")]
@@ -44,6 +49,19 @@ pub async fn get_users() -> Result<Vec<AdapterUser>, ServerFnError> {
 
 #[server]
 pub async fn signin(input: SignInForm) -> Result<String, ServerFnError> {
+    use crate::rate_limit::{
+        GLOBAL, SIGNIN_GLOBAL, SIGNIN_PER_EMAIL, SIGNIN_PER_IP, check_rate_limits,
+    };
+
+    // every call may create a user and send an email
+    let ip = crate::session::SessionClient::from_context().ip;
+    check_rate_limits(&[
+        (&SIGNIN_PER_IP, ip.as_deref()),
+        (&SIGNIN_PER_EMAIL, Some(&input.email.0)),
+        (&SIGNIN_GLOBAL, Some(GLOBAL)),
+    ])
+    .await?;
+
     // validate email
 
     let user: AdapterUser =
@@ -123,7 +141,13 @@ pub async fn signin(input: SignInForm) -> Result<String, ServerFnError> {
         url,
         token.token,
         urlencoding::encode(user.email.to_string().as_str()),
-        urlencoding::encode(input.callback_url.unwrap_or_default().as_str())
+        urlencoding::encode(
+            input
+                .callback_url
+                .and_then(|url| crate::auth::protected::safe_callback_url(&url))
+                .unwrap_or_default()
+                .as_str()
+        )
     );
 
     let name = user.name;
@@ -151,12 +175,24 @@ async fn oauth_signin_internal(
         callback_url,
     );
     use crate::auth::oauth::OAuthConfig;
+    use crate::rate_limit::{GLOBAL, OAUTH_GLOBAL, OAUTH_PER_IP, check_rate_limits};
     use oauth2::{CsrfToken, PkceCodeChallenge, Scope};
 
-    let config = match provider {
+    let ip = crate::session::SessionClient::from_context().ip;
+    check_rate_limits(&[
+        (&OAUTH_PER_IP, ip.as_deref()),
+        (&OAUTH_GLOBAL, Some(GLOBAL)),
+    ])
+    .await?;
+
+    let config = match &provider {
         OAuthProvider::Github => OAuthConfig::github(),
         OAuthProvider::Google => OAuthConfig::google(),
         OAuthProvider::Discord => OAuthConfig::discord(),
+        OAuthProvider::Oidc(name) => crate::auth::oidc::oidc_provider(name)?
+            .oauth_config()
+            .await
+            .map_err(|e| e.to_string()),
     }
     .map_err(|e| ServerFnError::new(e))?;
 
@@ -173,8 +209,11 @@ async fn oauth_signin_internal(
         .authorize_url(|| csrf_token.clone())
         .set_pkce_challenge(pkce_challenge);
 
+    // OpenID Connect providers echo the nonce in their id_token
+    let mut nonce = None;
+
     // Add provider-specific scopes
-    auth_request = match provider {
+    auth_request = match &provider {
         OAuthProvider::Github => auth_request
             .add_scope(Scope::new("user:email".to_string()))
             .add_scope(Scope::new("read:user".to_string())),
@@ -184,6 +223,18 @@ async fn oauth_signin_internal(
         OAuthProvider::Discord => auth_request
             .add_scope(Scope::new("identify".to_string()))
             .add_scope(Scope::new("email".to_string())),
+        OAuthProvider::Oidc(name) => {
+            let oidc = crate::auth::oidc::oidc_provider(name)?;
+            let value = CsrfToken::new_random().secret().to_string();
+            let mut request = auth_request
+                .add_scope(Scope::new("openid".to_string()))
+                .add_extra_param("nonce", value.clone());
+            for scope in &oidc.config.scopes {
+                request = request.add_scope(Scope::new(scope.clone()));
+            }
+            nonce = Some(value);
+            request
+        }
     };
 
     let (authorize_url, _csrf_state) = auth_request.url();
@@ -194,8 +245,11 @@ async fn oauth_signin_internal(
     let state = OAuthState {
         csrf_token: csrf_token.secret().to_string(),
         pkce_verifier: pkce_verifier.secret().to_string(),
-        callback_url: callback_url.unwrap_or_else(|| "/".to_string()),
+        callback_url: callback_url
+            .and_then(|url| crate::auth::protected::safe_callback_url(&url))
+            .unwrap_or_else(|| "/".to_string()),
         provider: provider.clone(),
+        nonce,
     };
 
     crate::auth::session::store_oauth_state(state).await?;
@@ -236,6 +290,21 @@ pub fn LoginForm() -> impl IntoView {
     // if the user should check their email now
     let (check_email, set_check_email) = signal(false);
 
+    // password sign-in instead of an emailed link
+    let (use_password, set_use_password) = signal(false);
+    let password = RwSignal::new(String::new());
+    let (notice, set_notice) = signal(Option::<String>::None);
+
+    // where to return after signing in, set by ProtectedRoute redirects
+    let query = leptos_router::hooks::use_query_map();
+    let callback_url = move || {
+        query
+            .read_untracked()
+            .get("callbackUrl")
+            .and_then(|url| crate::auth::protected::safe_callback_url(&url))
+            .unwrap_or_else(|| "/".to_string())
+    };
+
     // Update email and validate
     let on_email_input = move |value: String| {
         email_str.set(value.clone());
@@ -266,10 +335,26 @@ pub fn LoginForm() -> impl IntoView {
         set_is_loading.set(true);
         set_error_message.set(None);
 
+        if use_password.get() {
+            let password_value = password.get();
+            spawn_local(async move {
+                match signin_password(email_value.0, password_value, Some(callback_url())).await {
+                    Ok(url) => {
+                        window().location().set_href(&url).unwrap();
+                    }
+                    Err(e) => {
+                        set_error_message.set(Some(e.to_string()));
+                        set_is_loading.set(false);
+                    }
+                }
+            });
+            return;
+        }
+
         spawn_local(async move {
             let _ = signin(SignInForm {
                 email: email_value,
-                callback_url: Some("/".to_string()),
+                callback_url: Some(callback_url()),
             })
             .await;
 
@@ -278,10 +363,28 @@ pub fn LoginForm() -> impl IntoView {
         });
     };
 
+    let on_forgot_password = move |_| {
+        let email_value = email.get();
+
+        if !email_value.validate_email() {
+            set_error_message.set(Some("Please enter a valid email address".to_string()));
+            return;
+        }
+
+        spawn_local(async move {
+            match request_password_reset(email_value.0).await {
+                Ok(()) => set_notice.set(Some(
+                    "If an account exists for this email, a reset link is on its way.".to_string(),
+                )),
+                Err(e) => set_error_message.set(Some(e.to_string())),
+            }
+        });
+    };
+
     // OAuth signin handlers
     let on_github_signin = move |_| {
         spawn_local(async move {
-            match oauth_signin(OAuthProvider::Github, Some("/".to_string())).await {
+            match oauth_signin(OAuthProvider::Github, Some(callback_url())).await {
                 Ok(url) => {
                     window().location().set_href(&url).unwrap();
                 }
@@ -295,7 +398,7 @@ pub fn LoginForm() -> impl IntoView {
 
     let on_google_signin = move |_| {
         spawn_local(async move {
-            match oauth_signin(OAuthProvider::Google, Some("/".to_string())).await {
+            match oauth_signin(OAuthProvider::Google, Some(callback_url())).await {
                 Ok(url) => {
                     window().location().set_href(&url).unwrap();
                 }
@@ -309,7 +412,7 @@ pub fn LoginForm() -> impl IntoView {
 
     let on_discord_signin = move |_| {
         spawn_local(async move {
-            match oauth_signin(OAuthProvider::Discord, Some("/".to_string())).await {
+            match oauth_signin(OAuthProvider::Discord, Some(callback_url())).await {
                 Ok(url) => {
                     window().location().set_href(&url).unwrap();
                 }
@@ -387,6 +490,10 @@ pub fn LoginForm() -> impl IntoView {
                         </svg>
                         "Continue with Discord"
                     </button>
+
+                    <OidcSignInButtons callback_url=Signal::derive(callback_url) />
+
+                    <PasskeySignInButton />
                 </div>
 
                 <Seperator />
@@ -409,20 +516,196 @@ pub fn LoginForm() -> impl IntoView {
                         />
                     </FormField>
 
+                    <Show when=move || use_password.get()>
+                        <FormField label="Password" label_for="password" class="mb-6">
+                            <Input
+                                id="password"
+                                name="password"
+                                r#type=InputType::Password
+                                placeholder="Enter your password"
+                                value=password
+                                autocomplete="current-password"
+                                disabled=is_loading.get()
+                            />
+                        </FormField>
+                    </Show>
+
                     <button
                         type="submit"
                         class="w-full text-neutral-100 bg-sky-600 hover:bg-sky-700 dark:bg-sky-600 dark:hover:bg-sky-500 px-4 py-3 rounded-md font-semibold duration-150 disabled:opacity-50 disabled:cursor-not-allowed"
-                        disabled=move || is_loading.get() || !is_valid_email.get()
+                        disabled=move || {
+                            is_loading.get() || !is_valid_email.get()
+                                || (use_password.get() && password.get().is_empty())
+                        }
                     >
                         "LOGIN"
                     </button>
 
-                    <Show when=move || check_email.get()>
+                    <div class="flex justify-between mt-4 text-sm">
+                        <button
+                            type="button"
+                            class="text-sky-600 hover:text-sky-700 dark:text-sky-400"
+                            on:click=move |_| {
+                                set_use_password.update(|value| *value = !*value);
+                                set_error_message.set(None);
+                                set_notice.set(None);
+                            }
+                        >
+                            {move || {
+                                if use_password.get() {
+                                    "Email me a login link instead"
+                                } else {
+                                    "Use a password instead"
+                                }
+                            }}
+                        </button>
+                        <Show when=move || use_password.get()>
+                            <button
+                                type="button"
+                                class="text-neutral-500 hover:text-neutral-700 dark:text-neutral-400"
+                                on:click=on_forgot_password
+                            >
+                                "Forgot password?"
+                            </button>
+                        </Show>
+                    </div>
+
+                    <Show when=move || check_email.get() && !use_password.get()>
                         <div class="text-center mt-4 text-neutral-600 dark:text-neutral-400">
                             "Check your email for the login link!"
                         </div>
                     </Show>
 
+                    {move || {
+                        notice
+                            .get()
+                            .map(|notice| {
+                                view! {
+                                    <div class="text-center mt-4 text-neutral-600 dark:text-neutral-400">
+                                        {notice}
+                                    </div>
+                                }
+                            })
+                    }}
+
+                    {move || {
+                        error_message
+                            .get()
+                            .filter(|_| use_password.get())
+                            .map(|error| {
+                                view! {
+                                    <div class="text-center mt-4 text-red-600 dark:text-red-400">
+                                        {error}
+                                    </div>
+                                }
+                            })
+                    }}
+
+                </form>
+            </div>
+        </div>
+    }
+}
+
+/// Form behind the link sent by `request_password_reset`, mount it on `RESET_PASSWORD_PATH`.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     <Route path=path!("/reset-password") view=ResetPasswordForm />
+/// ```
+#[component]
+pub fn ResetPasswordForm() -> impl IntoView {
+    let query = leptos_router::hooks::use_query_map();
+    let password = RwSignal::new(String::new());
+    let confirm = RwSignal::new(String::new());
+    let (is_loading, set_is_loading) = signal(false);
+    let (error_message, set_error_message) = signal(Option::<String>::None);
+
+    let on_submit = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+
+        let Some(token) = query.read_untracked().get("token") else {
+            set_error_message.set(Some("The reset link is missing its token".to_string()));
+            return;
+        };
+
+        let password_value = password.get();
+
+        if password_value != confirm.get() {
+            set_error_message.set(Some("Passwords do not match".to_string()));
+            return;
+        }
+
+        set_is_loading.set(true);
+        set_error_message.set(None);
+
+        spawn_local(async move {
+            match reset_password(token, password_value).await {
+                Ok(()) => {
+                    window().location().set_href("/").unwrap();
+                }
+                Err(e) => {
+                    set_error_message.set(Some(e.to_string()));
+                    set_is_loading.set(false);
+                }
+            }
+        });
+    };
+
+    view! {
+        <div class="py-20 pb-[300px]">
+            <div class="bg-white dark:bg-black rounded-lg shadow-2xl p-8 mx-8 w-full max-w-md mx-auto">
+                <div class="text-center mb-8">
+                    <h1 class="text-3xl font-bold text-neutral-800 dark:text-neutral-100 mb-2">
+                        "Choose a new password"
+                    </h1>
+                    <small class="text-neutral-500 dark:text-neutral-400">
+                        {format!("At least {} characters.", PASSWORD_MIN_LENGTH)}
+                    </small>
+                </div>
+
+                <form on:submit=on_submit class="flex flex-col">
+                    <FormField label="New password" label_for="password" class="mb-6">
+                        <Input
+                            id="password"
+                            name="password"
+                            r#type=InputType::Password
+                            value=password
+                            autocomplete="new-password"
+                            disabled=is_loading.get()
+                        />
+                    </FormField>
+
+                    <FormField label="Confirm password" label_for="confirm" class="mb-6">
+                        <Input
+                            id="confirm"
+                            name="confirm"
+                            r#type=InputType::Password
+                            value=confirm
+                            autocomplete="new-password"
+                            disabled=is_loading.get()
+                        />
+                    </FormField>
+
+                    <button
+                        type="submit"
+                        class="w-full text-neutral-100 bg-sky-600 hover:bg-sky-700 dark:bg-sky-600 dark:hover:bg-sky-500 px-4 py-3 rounded-md font-semibold duration-150 disabled:opacity-50 disabled:cursor-not-allowed"
+                        disabled=move || is_loading.get() || password.get().is_empty()
+                    >
+                        "SET PASSWORD"
+                    </button>
+
+                    {move || {
+                        error_message
+                            .get()
+                            .map(|error| {
+                                view! {
+                                    <div class="text-center mt-4 text-red-600 dark:text-red-400">
+                                        {error}
+                                    </div>
+                                }
+                            })
+                    }}
                 </form>
             </div>
         </div>
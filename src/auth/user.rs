@@ -111,21 +111,25 @@ use crate::db_init;
 #[cfg(feature = "ssr")]
 impl AdapterUser {
     pub async fn create_user(user_data: CreateUserData) -> Result<Self, AppError> {
+        use crate::db::Transaction;
         use tracing::debug;
 
-        let client = db_init().await?;
-
         debug!("Creating user with data: {:#?}", user_data);
-        // if !user_data.email.is_empty() {
-        //     let user = AdapterUser::get_user_by_email(user_data.email.clone()).await;
-        //     if let Ok(_) = user {
-        //         return Err(AppError::AuthError("Email already in use".into()));
-        //     }
-        // }
+        let mut tx = Transaction::new();
+        tx.bind("user_data", user_data);
+        // guests have no email, everyone else gets one account per address
+        tx.guard(
+            r#"$user_data.email = "" OR count((SELECT VALUE id FROM user WHERE email = $user_data.email LIMIT 1)) = 0"#,
+            AppError::AuthError("Email already in use".into()),
+        );
+        let created = tx.statement::<Option<Self>>("CREATE ONLY user CONTENT $user_data");
+
         debug!("Saving user to db");
-        let create_result: Option<Self> = client.create("user").content(user_data).await?;
-        let created: Self =
-            create_result.ok_or_else(|| AppError::AuthError("Could not create user".into()))?;
+        let created: Self = tx
+            .commit()
+            .await?
+            .take(created)?
+            .ok_or_else(|| AppError::AuthError("Could not create user".into()))?;
         Ok(created)
     }
 
@@ -169,6 +173,32 @@ impl AdapterUser {
         Ok(user)
     }
 
+    /// Grants or removes admin, rotating the user's session tokens.
+    pub async fn set_admin(&self, is_admin: bool) -> Result<Self, AppError> {
+        self.set_privilege("UPDATE $userid SET is_admin = $value RETURN AFTER;", is_admin).await
+    }
+
+    /// Grants or removes super admin, rotating the user's session tokens.
+    pub async fn set_superadmin(&self, superadmin: bool) -> Result<Self, AppError> {
+        self.set_privilege("UPDATE $userid SET superadmin = $value RETURN AFTER;", superadmin).await
+    }
+
+    async fn set_privilege(&self, query: &'static str, value: bool) -> Result<Self, AppError> {
+        let client = db_init().await?;
+
+        let mut query = client
+            .query(query)
+            .bind(("userid", self.id.clone()))
+            .bind(("value", value))
+            .await?;
+
+        let user: Option<Self> = query.take(0)?;
+        let user = user.ok_or_else(|| AppError::AuthError("User not found".into()))?;
+
+        AdapterSession::rotate_after_privilege_change(user.id.clone()).await?;
+        Ok(user)
+    }
+
     pub fn is_super_admin(&self) -> Result<bool, AppError> {
         if let Some(superadmin) = self.superadmin {
             Ok(superadmin)
@@ -232,32 +262,49 @@ impl AdapterUser {
         Ok(user)
     }
 
+    /// The user of an unexpired session that isn't waiting for its second factor, renewing the
+    /// session when it is close to expiry. Renewal needs `ResponseOptions` in context to re-issue
+    /// the cookie, so requests without them (plain axum routes) leave the session as is.
     pub async fn get_user_from_session(session_token: String) -> Result<Self, AppError> {
         use crate::db_seperate_connection;
 
         let client = db_seperate_connection().await?;
 
         let mut result = client
-            .query("(SELECT user_id from ONLY session where session_token = $session_token LIMIT 1 FETCH user_id).user_id;")
-            .bind(("session_token", session_token))
+            .query(format!(
+                "UPDATE session SET last_seen = time::now() WHERE session_token = $session_token AND expires > time::now() AND two_factor_pending != true AND (last_seen = NONE OR last_seen < time::now() - {}m) RETURN NONE;
+                SELECT * FROM ONLY session WHERE session_token = $session_token AND expires > time::now() AND two_factor_pending != true LIMIT 1;
+                (SELECT user_id from ONLY session where session_token = $session_token AND expires > time::now() AND two_factor_pending != true LIMIT 1 FETCH user_id).user_id;",
+                crate::session::LAST_SEEN_INTERVAL_MINUTES
+            ))
+            .bind(("session_token", crate::token_hash::hash_token(&session_token)?))
             .await?;
 
-        let user: Option<Self> = result.take(0)?;
+        let session: Option<AdapterSession> = result.take(1)?;
+        let user: Option<Self> = result.take(2)?;
+        let session = session.and_then(|session| session.with_token(session_token));
 
-        if let Some(user) = user {
-            Ok(user)
-        } else {
-            Err(AppError::AuthError(
+        let (Some(session), Some(user)) = (session, user) else {
+            return Err(AppError::AuthError(
                 "User not found for session_token".into(),
-            ))
+            ));
+        };
+
+        if session.needs_renewal()
+            && use_context::<leptos_axum::ResponseOptions>().is_some()
+            && let Err(e) = session.renew().await
+        {
+            tracing::warn!("Could not renew session {}: {}", session.id, e);
         }
+
+        Ok(user)
     }
 
     pub async fn set_verified_email(&self) -> Result<Self, AppError> {
         let client = db_init().await?;
 
         let mut user_update = client
-            .query("UPDATE $userid SET email_verified = time::now() RETURN AFTER;")
+            .query("UPDATE $userid SET emailVerified = time::now() RETURN AFTER;")
             .bind(("userid", self.id.clone()))
             .await?;
 
@@ -310,7 +357,11 @@ impl AdapterUser {
         Ok(user)
     }
 
+    /// Deletes the user and revokes all of their sessions and API keys.
     pub async fn delete_user(&self) -> Result<(), AppError> {
+        AdapterSession::revoke_all(self.id.clone(), None).await?;
+        crate::api_key::ApiKey::revoke_all(self.id.clone()).await?;
+
         let client = db_init().await?;
         let _: Option<AdapterUser> = client.delete(&self.id).await?;
         // delete all related data?
@@ -328,11 +379,25 @@ impl AdapterUser {
         Ok(token)
     }
 
+    /// Creates a session for the device of the request being handled, pending its second factor
+    /// when the user has enabled two-factor.
     pub async fn new_session(&self) -> Result<AdapterSession, AppError> {
+        let client = crate::session::SessionClient::from_context();
+        let now = Utc::now();
+        let two_factor_pending = crate::two_factor::TwoFactor::is_enabled(self.id.clone()).await?;
+
         let session_data = CreateSessionData {
             user_id: self.id.clone(),
             session_token: uuid::Uuid::new_v4().to_string(),
-            expires: Datetime::from(Utc::now() + chrono::Duration::days(365)),
+            expires: Datetime::from(
+                now + chrono::Duration::days(crate::session::SESSION_LIFETIME_DAYS),
+            ),
+            user_agent: client.user_agent,
+            ip: client.ip,
+            created_at: Some(Datetime::from(now)),
+            last_seen: Some(Datetime::from(now)),
+            two_factor_pending,
+            reverified_at: None,
         };
 
         AdapterSession::create_session(session_data).await
@@ -525,6 +590,15 @@ pub async fn update_user_profile(
     // Update user
     let updated_user = AdapterUser::update_user(update_data).await?;
 
+    // A changed email (this includes a guest adding one) signs out every other device and
+    // rotates the current session token
+    if email_changed {
+        let current =
+            AdapterSession::rotate_after_privilege_change(updated_user.id.clone()).await?;
+        AdapterSession::revoke_all(updated_user.id.clone(), current.map(|s| s.session_token))
+            .await?;
+    }
+
     // If email changed, send verification email
     if email_changed {
         // Send verification email (non-blocking, ignore errors)
@@ -537,6 +611,7 @@ pub async fn update_user_profile(
 #[server]
 pub async fn send_verification_email() -> Result<(), ServerFnError> {
     use crate::email::send_email;
+    use crate::rate_limit::{VERIFICATION_PER_EMAIL, VERIFICATION_PER_IP, check_rate_limits};
 
     let user = get_user().await?;
 
@@ -547,6 +622,13 @@ pub async fn send_verification_email() -> Result<(), ServerFnError> {
         ));
     }
 
+    let ip = crate::session::SessionClient::from_context().ip;
+    check_rate_limits(&[
+        (&VERIFICATION_PER_IP, ip.as_deref()),
+        (&VERIFICATION_PER_EMAIL, Some(&user.email.0)),
+    ])
+    .await?;
+
     // Create verification token
     let token = user.new_verification_token().await?;
 
@@ -578,3 +660,91 @@ pub async fn send_verification_email() -> Result<(), ServerFnError> {
 
     Ok(())
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::db::connection::{db_memory, with_db};
+
+    #[tokio::test]
+    async fn test_set_verified_email() -> Result<(), AppError> {
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            assert!(user.email_verified.is_none());
+
+            let verified = user.set_verified_email().await?;
+            assert!(verified.email_verified.is_some());
+            assert!(
+                AdapterUser::get_user(user.id.clone())
+                    .await?
+                    .email_verified
+                    .is_some()
+            );
+
+            // users verified before the fix have the date under the wrong name
+            let legacy = AdapterUser::create_test_user().await?;
+            let db = db_init().await?;
+            db.query("UPDATE $userid SET email_verified = time::now();")
+                .bind(("userid", legacy.id.clone()))
+                .await?
+                .check()?;
+            let migration = crate::db::migrations::tinkr_migrations()
+                .into_iter()
+                .find(|m| m.name == "user_email_verified")
+                .unwrap();
+            db.query(migration.statements).await?.check()?;
+            assert!(
+                AdapterUser::get_user(legacy.id)
+                    .await?
+                    .email_verified
+                    .is_some()
+            );
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_create_user_rejects_taken_email() -> Result<(), AppError> {
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            let data = |email: EmailAddress| CreateUserData {
+                email,
+                email_verified: None,
+                name: "Second".to_string(),
+                image: None,
+                theme: Theme::System,
+                address1: None,
+                address2: None,
+                address3: None,
+                postcode: None,
+                phone: None,
+                telephone: None,
+                first_name: None,
+                last_name: None,
+            };
+
+            assert!(matches!(
+                AdapterUser::create_user(data(user.email.clone())).await,
+                Err(AppError::AuthError(_))
+            ));
+            assert_eq!(
+                db_init()
+                    .await?
+                    .query("RETURN count(SELECT id FROM user WHERE email = $email);")
+                    .bind(("email", user.email.clone()))
+                    .await?
+                    .take::<Option<i64>>(0)?,
+                Some(1)
+            );
+
+            // guests share the blank email
+            AdapterUser::new_guest().await?;
+            AdapterUser::new_guest().await?;
+
+            Ok(())
+        })
+        .await
+    }
+}
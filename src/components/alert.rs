@@ -130,3 +130,35 @@ pub fn Alert(
         </div>
     }
 }
+
+/// Warning shown when a save failed because someone else changed the record in the meantime.
+/// `on_reload` should refetch the record so the form shows the latest version.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     {move || match update_action.value().get() {
+///         Some(Err(e)) if e.is_conflict() => view! {
+///             <ConflictAlert on_reload=Callback::new(move |_| resource.refetch()) />
+///         }.into_any(),
+///         _ => view! {}.into_any(),
+///     }}
+/// ```
+#[component]
+pub fn ConflictAlert(
+    on_reload: Callback<()>,
+    #[prop(default = "")] class: &'static str,
+) -> impl IntoView {
+    view! {
+        <Alert severity=AlertSeverity::Warning class=class>
+            "Someone else changed this record while you were editing it. "
+            <button
+                type="button"
+                class="underline font-semibold"
+                on:click=move |_| on_reload.run(())
+            >
+                "Reload the latest version"
+            </button>
+            " and apply your changes again."
+        </Alert>
+    }
+}
@@ -203,6 +203,11 @@ fn upload_file(
     // Prepare and send request
     match xhr.open("POST", endpoint) {
         Ok(_) => {
+            // CsrfLayer rejects posts without the token, CsrfScript only adds it to fetch calls
+            if let Some(token) = crate::auth::csrf::browser_csrf_token() {
+                let _ = xhr.set_request_header(crate::auth::csrf::CSRF_HEADER, &token);
+            }
+
             let form_data = FormData::new().expect("Failed to create FormData");
             match form_data.append_with_blob("file", &file) {
                 Ok(_) => match xhr.send_with_opt_form_data(Some(&form_data)) {
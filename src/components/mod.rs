@@ -38,7 +38,7 @@ pub mod user_navbutton;
 pub mod version;
 //////////////////////////////
 
-pub use alert::{Alert, AlertSeverity};
+pub use alert::{Alert, AlertSeverity, ConflictAlert};
 pub use animated_demo::{
     AIChatDemo, AnimatedDemo, CompressionDemo, FileUploadDemo, RealtimeDataDemo, WebGLDemo,
 };
@@ -0,0 +1,197 @@
+//! Per-table metrics of `AsyncSurrealCache`, recorded both to the Prometheus recorder installed
+//! by `middleware::create_metrics_setup` and to the global OpenTelemetry meter set by
+//! `telemetry::init_meter_provider`.
+
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use axum_prometheus::metrics;
+use opentelemetry::{
+    KeyValue, global,
+    metrics::{Counter, Histogram, Unit},
+};
+
+/// Number of cache operations by `table`, `operation` and `result`.
+pub const CACHE_REQUESTS_METRIC: &str = "tinkr_cache_requests_total";
+/// Duration of cache operations in seconds by `table` and `operation`.
+pub const CACHE_DURATION_METRIC: &str = "tinkr_cache_operation_duration_seconds";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheOperation {
+    Get,
+    Set,
+    Remove,
+}
+
+impl CacheOperation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheOperation::Get => "get",
+            CacheOperation::Set => "set",
+            CacheOperation::Remove => "remove",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheResult {
+    /// Found in the in-memory layer.
+    MemoryHit,
+    /// Found in SurrealDB.
+    Hit,
+    Miss,
+    /// Found in SurrealDB but expired, counts as a miss for the caller.
+    Expired,
+    /// `set` or `remove` succeeded.
+    Ok,
+    Error,
+}
+
+impl CacheResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheResult::MemoryHit => "memory_hit",
+            CacheResult::Hit => "hit",
+            CacheResult::Miss => "miss",
+            CacheResult::Expired => "expired",
+            CacheResult::Ok => "ok",
+            CacheResult::Error => "error",
+        }
+    }
+}
+
+struct OtelInstruments {
+    requests: Counter<u64>,
+    duration: Histogram<f64>,
+}
+
+static INSTRUMENTS: RwLock<Option<Arc<OtelInstruments>>> = RwLock::new(None);
+
+// built from the global meter provider on first use and dropped by `reset_otel_instruments` when
+// `init_meter_provider` installs a new one, so cache use before that doesn't pin the noop meter
+fn otel() -> Arc<OtelInstruments> {
+    if let Some(instruments) = INSTRUMENTS.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        return instruments.clone();
+    }
+
+    INSTRUMENTS
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert_with(|| {
+            let meter = global::meter("tinkr");
+
+            Arc::new(OtelInstruments {
+                requests: meter
+                    .u64_counter("tinkr.cache.requests")
+                    .with_description("Cache operations by table, operation and result")
+                    .init(),
+                duration: meter
+                    .f64_histogram("tinkr.cache.duration")
+                    .with_description("Duration of cache operations")
+                    .with_unit(Unit::new("s"))
+                    .init(),
+            })
+        })
+        .clone()
+}
+
+/// Drops the OpenTelemetry instruments so the next operation builds them from the current global
+/// meter provider, called by `init_meter_provider`.
+pub fn reset_otel_instruments() {
+    *INSTRUMENTS.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Registers the descriptions of the Prometheus metrics, called by `create_metrics_setup`.
+pub fn describe_cache_metrics() {
+    metrics::describe_counter!(
+        CACHE_REQUESTS_METRIC,
+        "Cache operations by table, operation and result"
+    );
+    metrics::describe_histogram!(
+        CACHE_DURATION_METRIC,
+        metrics::Unit::Seconds,
+        "Duration of cache operations"
+    );
+}
+
+/// Records one cache operation on `table`.
+pub fn record_cache_operation(
+    table: &str,
+    operation: CacheOperation,
+    result: CacheResult,
+    elapsed: Duration,
+) {
+    let seconds = elapsed.as_secs_f64();
+
+    metrics::counter!(
+        CACHE_REQUESTS_METRIC,
+        "table" => table.to_string(),
+        "operation" => operation.as_str(),
+        "result" => result.as_str(),
+    )
+    .increment(1);
+    metrics::histogram!(
+        CACHE_DURATION_METRIC,
+        "table" => table.to_string(),
+        "operation" => operation.as_str(),
+    )
+    .record(seconds);
+
+    let instruments = otel();
+    instruments.requests.add(
+        1,
+        &[
+            KeyValue::new("table", table.to_string()),
+            KeyValue::new("operation", operation.as_str()),
+            KeyValue::new("result", result.as_str()),
+        ],
+    );
+    instruments.duration.record(
+        seconds,
+        &[
+            KeyValue::new("table", table.to_string()),
+            KeyValue::new("operation", operation.as_str()),
+        ],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum_prometheus::metrics_exporter_prometheus::PrometheusBuilder;
+
+    #[test]
+    fn test_record_cache_operation() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let handle = recorder.handle();
+
+        metrics::with_local_recorder(&recorder, || {
+            for result in [CacheResult::Hit, CacheResult::Hit, CacheResult::Miss] {
+                record_cache_operation(
+                    "cache_table",
+                    CacheOperation::Get,
+                    result,
+                    Duration::from_millis(5),
+                );
+            }
+        });
+
+        let rendered = handle.render();
+        assert!(rendered.contains(
+            r#"tinkr_cache_requests_total{table="cache_table",operation="get",result="hit"} 2"#
+        ));
+        assert!(rendered.contains(
+            r#"tinkr_cache_requests_total{table="cache_table",operation="get",result="miss"} 1"#
+        ));
+    }
+
+    #[test]
+    fn test_otel_instruments_follow_the_meter_provider() {
+        let before = otel();
+        assert!(Arc::ptr_eq(&before, &otel()));
+
+        // a new provider gets new instruments instead of the ones from the noop meter
+        reset_otel_instruments();
+        assert!(!Arc::ptr_eq(&before, &otel()));
+    }
+}
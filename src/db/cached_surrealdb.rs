@@ -5,10 +5,12 @@ use std::time::Duration;
 #[cfg(feature = "ssr")]
 use async_trait::async_trait;
 #[cfg(feature = "ssr")]
-use cached::IOCachedAsync;
+use cached::{Cached, IOCachedAsync, SizedCache};
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "ssr")]
 use serde_json;
+#[cfg(feature = "ssr")]
+use sha2::{Digest, Sha256};
 use surrealdb::Datetime;
 
 #[cfg(feature = "ssr")]
@@ -20,11 +22,56 @@ use thiserror::Error;
 #[cfg(feature = "ssr")]
 use crate::db_init;
 
+#[cfg(feature = "ssr")]
+use super::cache_metrics::{CacheOperation, CacheResult, record_cache_operation};
+
+#[cfg(feature = "ssr")]
+use std::collections::HashMap;
+#[cfg(feature = "ssr")]
+use std::sync::Mutex;
+#[cfg(feature = "ssr")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "ssr")]
+use std::time::Instant;
+#[cfg(feature = "ssr")]
+use tokio::sync::{Notify, futures::OwnedNotified};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CacheEntry<V> {
     pub id: RecordId,
     pub value: V,
-    pub expires_at: Datetime, // Unix timestamp
+    /// `None` for entries that never expire.
+    #[serde(default)]
+    pub expires_at: Option<Datetime>,
+    /// Last time the entry was set or read from SurrealDB, the least recent ones are evicted first.
+    #[serde(default)]
+    pub accessed_at: Option<Datetime>,
+    #[serde(default)]
+    pub namespace: String,
+    /// The unhashed key, matched by `invalidate_prefix`.
+    #[serde(default)]
+    pub key: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Bumped whenever the record key format changes, so rows written in an older format are never
+/// read back. They expire and can be dropped with `clear_table`.
+#[cfg(feature = "ssr")]
+pub const CACHE_KEY_VERSION: u32 = 1;
+
+#[cfg(feature = "ssr")]
+const DEFAULT_NAMESPACE: &str = "default";
+
+/// Record key of a cache entry: the key format version, the namespace and a SHA-256 of the
+/// namespaced key, which unlike `DefaultHasher` stays the same across Rust releases.
+#[cfg(feature = "ssr")]
+fn cache_record_key(namespace: &str, key_json: &str) -> String {
+    let digest = Sha256::digest(format!("{namespace}\0{key_json}").as_bytes());
+    format!(
+        "v{CACHE_KEY_VERSION}_{namespace}_{}",
+        hex::encode(&digest[..16])
+    )
 }
 
 #[cfg(feature = "ssr")]
@@ -40,9 +87,16 @@ pub enum SurrealCacheError {
 
 #[cfg(feature = "ssr")]
 pub struct AsyncSurrealCacheBuilder<K, V> {
+    db: Option<Arc<Surreal<Any>>>,
     table_name: String,
     ttl: Option<Duration>,
     refresh: bool,
+    memory_capacity: Option<usize>,
+    flight_timeout: Option<Duration>,
+    namespace: String,
+    tags: Vec<String>,
+    max_entries: Option<usize>,
+    sweep_interval: Option<Duration>,
     _phantom: PhantomData<(K, V)>,
 }
 
@@ -54,9 +108,16 @@ where
 {
     pub fn new(table_name: impl Into<String>, ttl: Duration) -> Self {
         Self {
+            db: None,
             table_name: table_name.into(),
             ttl: Some(ttl),
             refresh: false,
+            memory_capacity: None,
+            flight_timeout: None,
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            tags: Vec::new(),
+            max_entries: None,
+            sweep_interval: None,
             _phantom: PhantomData,
         }
     }
@@ -71,27 +132,317 @@ where
         self
     }
 
+    /// Entries never expire, they are only replaced, invalidated or evicted.
+    pub fn without_lifespan(mut self) -> Self {
+        self.ttl = None;
+        self
+    }
+
+    /// Keeps about `max_entries` entries in this cache's namespace, evicting the least recently
+    /// used. Eviction runs every `max_entries / 10` sets rather than on each one, so the namespace
+    /// can briefly hold up to 10% more. With `with_memory_cache`, reads served from memory don't
+    /// count as use.
+    pub fn set_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Deletes the expired entries of the table every `interval` in a background task, instead of
+    /// only when they are read.
+    pub fn with_sweeper(mut self, interval: Duration) -> Self {
+        self.sweep_interval = Some(interval);
+        self
+    }
+
+    /// Use `db` instead of the connection from `db_init()`.
+    pub fn with_db(mut self, db: Arc<Surreal<Any>>) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// Keeps up to `capacity` entries in process memory (L1) in front of SurrealDB (L2), evicting
+    /// the least recently used. L1 entries live as long as the cache lifespan and are not shared
+    /// between processes, so another process's writes show up once they expire.
+    pub fn with_memory_cache(mut self, capacity: usize) -> Self {
+        self.memory_capacity = Some(capacity.max(1));
+        self
+    }
+
+    /// Coalesces concurrent misses on the same key: the first caller computes the value while the
+    /// others wait up to 10 seconds for it to be set. `#[io_cached]` doesn't set anything when
+    /// the function fails, so waiters wait out the timeout then, unless the function holds a
+    /// `FlightGuard`. `get_or_set_with` releases the lease itself.
+    pub fn with_single_flight(mut self) -> Self {
+        self.flight_timeout = Some(DEFAULT_FLIGHT_TIMEOUT);
+        self
+    }
+
+    /// Like `with_single_flight`, with waiters giving up after `timeout` instead.
+    pub fn set_flight_timeout(mut self, timeout: Duration) -> Self {
+        self.flight_timeout = Some(timeout);
+        self
+    }
+
+    /// Keeps these keys apart from equal keys of other caches sharing the table.
+    ///
+    /// ### EXAMPLE:
+    /// ```rs
+    ///     AsyncSurrealCache::new("cache_table", Duration::from_secs(60)).set_namespace("chain")
+    /// ```
+    pub fn set_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    /// Tags every entry of this cache, see `AsyncSurrealCache::invalidate_tag`.
+    pub fn with_tags(mut self, tags: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.tags.extend(tags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Every caller that misses computes the value, without waiting for each other. The default.
+    pub fn without_single_flight(mut self) -> Self {
+        self.flight_timeout = None;
+        self
+    }
+
     pub async fn build(self) -> Result<AsyncSurrealCache<K, V>, SurrealCacheError> {
-        let db = db_init()
-            .await
-            .map_err(|_| SurrealCacheError::ConnectionError)?;
+        let db = match self.db {
+            Some(db) => db,
+            None => db_init()
+                .await
+                .map_err(|_| SurrealCacheError::ConnectionError)?,
+        };
+
+        if let Some(interval) = self.sweep_interval {
+            spawn_cache_sweeper(db.clone(), self.table_name.clone(), interval);
+        }
+
+        if self.max_entries.is_some() {
+            define_lru_index(&db, &self.table_name).await?;
+        }
 
         Ok(AsyncSurrealCache {
             db,
             table_name: self.table_name,
-            ttl: self.ttl.unwrap_or(Duration::from_secs(3600)),
+            ttl: self.ttl,
             refresh: self.refresh,
+            memory: self
+                .memory_capacity
+                .map(|capacity| Mutex::new(SizedCache::with_size(capacity))),
+            flights: Flights::default(),
+            flight_timeout: self.flight_timeout,
+            namespace: self.namespace,
+            tags: self.tags,
+            max_entries: self.max_entries,
+            sets_since_eviction: AtomicUsize::new(0),
             _phantom: PhantomData,
         })
     }
 }
 
+/// Index for the eviction query of `set_max_entries`, which orders a namespace by last use.
+#[cfg(feature = "ssr")]
+async fn define_lru_index(db: &Surreal<Any>, table_name: &str) -> Result<(), SurrealCacheError> {
+    // DEFINE INDEX takes no parameters, only plain identifiers are interpolated
+    if !table_name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        tracing::warn!("Not indexing cache table {}, unusual name", table_name);
+        return Ok(());
+    }
+
+    db.query(format!(
+        "DEFINE INDEX IF NOT EXISTS {table_name}_lru ON TABLE {table_name} COLUMNS namespace, accessed_at;"
+    ))
+    .await?
+    .check()?;
+
+    Ok(())
+}
+
+/// Deletes the expired entries of `table_name` and returns how many were removed.
+#[cfg(feature = "ssr")]
+pub async fn sweep_expired(db: &Surreal<Any>, table_name: &str) -> Result<u64, SurrealCacheError> {
+    let mut result = db
+        .query(
+            "RETURN count((DELETE type::table($table) WHERE expires_at != NONE AND expires_at < time::now() RETURN BEFORE));",
+        )
+        .bind(("table", table_name.to_string()))
+        .await?
+        .check()?;
+
+    Ok(result.take::<Option<u64>>(0)?.unwrap_or(0))
+}
+
+/// Runs `sweep_expired` on `table_name` every `interval` until the task is aborted.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     let sweeper = spawn_cache_sweeper(db_init().await?, "cache_table".into(), Duration::from_secs(300));
+/// ```
+#[cfg(feature = "ssr")]
+pub fn spawn_cache_sweeper(
+    db: Arc<Surreal<Any>>,
+    table_name: String,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            match sweep_expired(&db, &table_name).await {
+                Ok(0) => {}
+                Ok(removed) => {
+                    tracing::debug!("Swept {} expired entries from {}", removed, table_name)
+                }
+                Err(e) => tracing::warn!("Cache sweep of {} failed: {}", table_name, e),
+            }
+        }
+    })
+}
+
+#[cfg(feature = "ssr")]
+const DEFAULT_FLIGHT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of a read, `result` is what gets recorded in the cache metrics.
+#[cfg(feature = "ssr")]
+struct Lookup<V> {
+    value: Option<V>,
+    result: CacheResult,
+}
+
+#[cfg(feature = "ssr")]
+impl<V> Lookup<V> {
+    fn memory(value: V) -> Self {
+        Lookup {
+            value: Some(value),
+            result: CacheResult::MemoryHit,
+        }
+    }
+}
+
+/// A value in the in-memory layer, `expires` is `None` when it never expires.
+#[cfg(feature = "ssr")]
+struct MemoryEntry<V> {
+    value: V,
+    expires: Option<Instant>,
+}
+
+/// Keys currently being computed. The first caller to miss a key becomes its leader, later
+/// callers wait until the leader sets the value or its lease runs out.
+#[cfg(feature = "ssr")]
+#[derive(Default)]
+struct Flights {
+    leases: Mutex<HashMap<String, (Arc<Notify>, Instant)>>,
+}
+
+#[cfg(feature = "ssr")]
+enum Flight {
+    Leader,
+    Follower(OwnedNotified),
+}
+
+#[cfg(feature = "ssr")]
+impl Flights {
+    fn join(&self, slot: &str, timeout: Duration) -> Flight {
+        let mut leases = self.leases.lock().unwrap_or_else(|e| e.into_inner());
+
+        match leases.get(slot) {
+            Some((notify, started)) if started.elapsed() < timeout => {
+                // registered before the lock is released, so a release can't be missed
+                Flight::Follower(notify.clone().notified_owned())
+            }
+            _ => {
+                leases.insert(slot.to_string(), (Arc::new(Notify::new()), Instant::now()));
+                Flight::Leader
+            }
+        }
+    }
+
+    fn release(&self, slot: &str) {
+        let mut leases = self.leases.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some((notify, _)) = leases.remove(slot) {
+            notify.notify_waiters();
+        }
+    }
+
+    fn started(&self, slot: &str) -> Option<Instant> {
+        let leases = self.leases.lock().unwrap_or_else(|e| e.into_inner());
+        leases.get(slot).map(|(_, started)| *started)
+    }
+
+    /// Like `release`, but leaves a lease someone else took over in the meantime alone.
+    fn release_started(&self, slot: &str, started: Instant) {
+        let mut leases = self.leases.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some((notify, current)) = leases.get(slot)
+            && *current == started
+        {
+            notify.notify_waiters();
+            leases.remove(slot);
+        }
+    }
+}
+
+/// Releases the lease on a key when dropped, unless `keep` was called. `#[io_cached]` sets
+/// nothing when the function fails, so create one at the top of the cached function to wake
+/// the callers waiting on the key right away on errors and panics.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     let flight = CACHE.get().and_then(|cache| cache.flight_guard(&key));
+///     let value = compute().await?;
+///     if let Some(flight) = flight {
+///         flight.keep();
+///     }
+///     Ok(value)
+/// ```
+#[cfg(feature = "ssr")]
+pub struct FlightGuard<'a> {
+    flights: &'a Flights,
+    slot: String,
+    started: Option<Instant>,
+}
+
+#[cfg(feature = "ssr")]
+impl FlightGuard<'_> {
+    /// The value is about to be set, `cache_set` releases the lease then.
+    pub fn keep(mut self) {
+        self.started = None;
+    }
+}
+
+#[cfg(feature = "ssr")]
+impl Drop for FlightGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(started) = self.started {
+            self.flights.release_started(&self.slot, started);
+        }
+    }
+}
+
+/// SurrealDB backed cache for `#[io_cached]`, optionally with an in-memory L1 layer in front.
+/// With `with_single_flight`, concurrent misses on the same key are coalesced: one caller
+/// computes the value while the others wait for it to be set.
 #[cfg(feature = "ssr")]
 pub struct AsyncSurrealCache<K, V> {
     db: Arc<Surreal<Any>>,
     table_name: String,
-    ttl: Duration,
+    ttl: Option<Duration>,
     refresh: bool,
+    memory: Option<Mutex<SizedCache<String, MemoryEntry<V>>>>,
+    flights: Flights,
+    flight_timeout: Option<Duration>,
+    namespace: String,
+    tags: Vec<String>,
+    max_entries: Option<usize>,
+    sets_since_eviction: AtomicUsize,
     _phantom: PhantomData<(K, V)>,
 }
 
@@ -108,13 +459,18 @@ where
 
     fn generate_key(&self, key: &K) -> Result<RecordId, SurrealCacheError> {
         let key_json = serde_json::to_string(key)?;
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-        let mut hasher = DefaultHasher::new();
-        key_json.hash(&mut hasher);
-        let hash = hasher.finish();
-        let record_id: RecordId = format!("{}:cache_{}", self.table_name, hash).parse()?;
-        Ok(record_id)
+        Ok(RecordId::from_table_key(
+            &self.table_name,
+            cache_record_key(&self.namespace, &key_json),
+        ))
+    }
+
+    /// The key as stored for `invalidate_prefix`, strings without their JSON quotes.
+    fn key_text(key: &K) -> serde_json::Result<String> {
+        Ok(match serde_json::to_value(key)? {
+            serde_json::Value::String(text) => text,
+            other => other.to_string(),
+        })
     }
 
     fn get_current_timestamp() -> Datetime {
@@ -122,78 +478,189 @@ where
     }
 
     fn is_expired(entry: &CacheEntry<V>) -> bool {
-        Self::get_current_timestamp().gt(&entry.expires_at)
+        entry
+            .expires_at
+            .as_ref()
+            .is_some_and(|expires_at| Self::get_current_timestamp().gt(expires_at))
+    }
+
+    fn next_expiry(&self) -> Option<Datetime> {
+        self.ttl
+            .map(|ttl| Datetime::from(chrono::offset::Utc::now() + ttl))
     }
 
     pub fn table_name(&self) -> &str {
         &self.table_name
     }
-}
 
-#[cfg(feature = "ssr")]
-#[async_trait]
-impl<K, V> IOCachedAsync<K, V> for AsyncSurrealCache<K, V>
-where
-    K: Send + std::fmt::Debug + Sync + Clone + Serialize + for<'de> Deserialize<'de> + 'static,
-    V: Send + Sync + Clone + Serialize + for<'de> Deserialize<'de> + 'static,
-{
-    type Error = SurrealCacheError;
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
 
-    async fn cache_get(&self, key: &K) -> Result<Option<V>, Self::Error> {
-        let key_id = self.generate_key(key)?;
+    fn memory_get(&self, slot: &str) -> Option<V> {
+        let memory = self.memory.as_ref()?;
+        let mut memory = memory.lock().unwrap_or_else(|e| e.into_inner());
 
-        // Try to select the specific record
-        let entry: Option<CacheEntry<V>> = self
+        let entry = memory.cache_get_mut(slot)?;
+        if entry
+            .expires
+            .is_some_and(|expires| expires <= Instant::now())
+        {
+            memory.cache_remove(slot);
+            return None;
+        }
+        if self.refresh {
+            entry.expires = self.ttl.map(|ttl| Instant::now() + ttl);
+        }
+
+        Some(entry.value.clone())
+    }
+
+    fn memory_set(&self, slot: String, value: V) {
+        if let Some(memory) = &self.memory {
+            let mut memory = memory.lock().unwrap_or_else(|e| e.into_inner());
+            memory.cache_set(
+                slot,
+                MemoryEntry {
+                    value,
+                    expires: self.ttl.map(|ttl| Instant::now() + ttl),
+                },
+            );
+        }
+    }
+
+    fn memory_remove(&self, slot: &str) {
+        if let Some(memory) = &self.memory {
+            let mut memory = memory.lock().unwrap_or_else(|e| e.into_inner());
+            memory.cache_remove(slot);
+        }
+    }
+
+    fn memory_clear(&self) {
+        if let Some(memory) = &self.memory {
+            let mut memory = memory.lock().unwrap_or_else(|e| e.into_inner());
+            memory.cache_clear();
+        }
+    }
+
+    /// Deletes the entries of the table matching `condition` and returns how many were removed.
+    /// The whole in-memory layer is dropped, it doesn't know the tags of its entries.
+    async fn invalidate_where(
+        &self,
+        condition: &str,
+        bindings: impl Serialize + 'static,
+    ) -> Result<u64, SurrealCacheError> {
+        self.memory_clear();
+
+        let mut result = self
             .db
-            .select(key_id.clone())
+            .query(format!(
+                "RETURN count((DELETE type::table($table) WHERE {condition} RETURN BEFORE));"
+            ))
+            .bind(("table", self.table_name.clone()))
+            .bind(bindings)
+            .await?
+            .check()?;
+
+        Ok(result.take::<Option<u64>>(0)?.unwrap_or(0))
+    }
+
+    /// Removes every entry tagged `tag`, in all namespaces of the table.
+    ///
+    /// ### EXAMPLE:
+    /// ```rs
+    ///     cache.invalidate_tag("chain").await?;
+    /// ```
+    pub async fn invalidate_tag(&self, tag: &str) -> Result<u64, SurrealCacheError> {
+        self.invalidate_where("$tag IN tags", ("tag", tag.to_string()))
             .await
-            .map_err(SurrealCacheError::DatabaseError)?;
+    }
 
-        if let Some(entry) = entry {
-            if !Self::is_expired(&entry) {
-                // Refresh TTL if enabled
-                if self.refresh {
-                    let expires_at: Datetime =
-                        Datetime::from(chrono::offset::Utc::now() + self.ttl);
-
-                    let updated_entry = CacheEntry {
-                        id: entry.id.clone(),
-                        value: entry.value.clone(),
-                        expires_at,
-                    };
-
-                    self.db
-                        .query("UPSERT $cacheidtorefresh CONTENT $content RETURN NONE;")
-                        .bind(("cacheidtorefresh", key_id.clone()))
-                        .bind(("content", updated_entry))
-                        .await?;
-                }
-                return Ok(Some(entry.value));
-            } else {
-                // Entry expired, remove it
-                let _: Option<CacheEntry<V>> = self
-                    .db
-                    .delete(key_id)
-                    .await
-                    .map_err(SurrealCacheError::DatabaseError)?;
-
-                return Ok(None);
-            }
-        }
+    /// Removes the entries of this cache's namespace whose key starts with `prefix`.
+    pub async fn invalidate_prefix(&self, prefix: &str) -> Result<u64, SurrealCacheError> {
+        self.invalidate_where(
+            "namespace = $namespace AND string::starts_with(key, $prefix)",
+            serde_json::json!({ "namespace": self.namespace, "prefix": prefix }),
+        )
+        .await
+    }
 
-        Ok(None)
+    /// Removes every entry of the table, including other namespaces and older key formats.
+    pub async fn clear_table(&self) -> Result<u64, SurrealCacheError> {
+        self.invalidate_where("true", serde_json::json!({})).await
     }
 
-    // asynchronously inserts a key-value pair into a cache and
-    // returns the previously stored value associated with that key, or None if no value was present.
-    async fn cache_set(&self, key: K, value: V) -> Result<Option<V>, Self::Error> {
+    /// Deletes the expired entries of the table, see `AsyncSurrealCacheBuilder::with_sweeper`.
+    pub async fn sweep_expired(&self) -> Result<u64, SurrealCacheError> {
+        sweep_expired(&self.db, &self.table_name).await
+    }
+
+    /// Deletes the least recently used entries of this namespace beyond `max_entries`.
+    async fn evict_overflow(&self, max_entries: usize) -> Result<(), SurrealCacheError> {
+        self.db
+            .query(
+                "LET $evicted = (SELECT id, accessed_at FROM type::table($table) WHERE namespace = $namespace ORDER BY accessed_at DESC START $max);
+                FOR $entry IN $evicted { DELETE $entry.id; };",
+            )
+            .bind(("table", self.table_name.clone()))
+            .bind(("namespace", self.namespace.clone()))
+            .bind(("max", max_entries))
+            .await?
+            .check()?;
+
+        Ok(())
+    }
+
+    fn record<T>(
+        &self,
+        operation: CacheOperation,
+        started: Instant,
+        outcome: &Result<T, SurrealCacheError>,
+        result: impl FnOnce(&T) -> CacheResult,
+    ) {
+        let result = match outcome {
+            Ok(value) => result(value),
+            Err(_) => CacheResult::Error,
+        };
+
+        record_cache_operation(&self.table_name, operation, result, started.elapsed());
+    }
+
+    /// Like `cache_set`, with `tags` added to the cache's own tags.
+    pub async fn cache_set_tagged(
+        &self,
+        key: K,
+        value: V,
+        tags: &[&str],
+    ) -> Result<Option<V>, SurrealCacheError>
+    where
+        V: 'static,
+    {
+        let started = Instant::now();
+        let outcome = self.store(key, value, tags).await;
+        self.record(CacheOperation::Set, started, &outcome, |_| CacheResult::Ok);
+        outcome
+    }
+
+    async fn store(&self, key: K, value: V, tags: &[&str]) -> Result<Option<V>, SurrealCacheError>
+    where
+        V: 'static,
+    {
         let key_id = self.generate_key(&key)?;
-        let expires_at: Datetime = Datetime::from(chrono::offset::Utc::now() + self.ttl);
+
+        let mut entry_tags = self.tags.clone();
+        entry_tags.extend(tags.iter().map(|tag| tag.to_string()));
+        entry_tags.sort();
+        entry_tags.dedup();
 
         let new_entry = CacheEntry {
             id: key_id.clone(),
             value: value.clone(),
-            expires_at,
+            expires_at: self.next_expiry(),
+            accessed_at: Some(Self::get_current_timestamp()),
+            namespace: self.namespace.clone(),
+            key: Self::key_text(&key)?,
+            tags: entry_tags,
         };
         let mut query_res = self
             .db
@@ -203,19 +670,211 @@ where
             .await?;
         let existing = query_res.take::<Option<CacheEntry<V>>>(0)?;
 
+        let slot = key_id.to_string();
+        self.memory_set(slot.clone(), value);
+        self.flights.release(&slot);
+
+        if let Some(max_entries) = self.max_entries {
+            // the eviction query reads the whole namespace, so it runs once per batch of sets
+            let batch = (max_entries / 10).max(1);
+            if self.sets_since_eviction.fetch_add(1, Ordering::Relaxed) + 1 >= batch {
+                self.sets_since_eviction.store(0, Ordering::Relaxed);
+                self.evict_overflow(max_entries).await?;
+            }
+        }
+
         Ok(existing.map(|e| e.value))
     }
 
-    async fn cache_remove(&self, key: &K) -> Result<Option<V>, Self::Error> {
-        let key_id = self.generate_key(key)?;
-
-        let removed: Option<CacheEntry<V>> = self
+    /// Reads the entry from SurrealDB, removing it when expired.
+    async fn stored_get(&self, key_id: &RecordId) -> Result<Lookup<V>, SurrealCacheError>
+    where
+        V: 'static,
+    {
+        let entry: Option<CacheEntry<V>> = self
             .db
-            .delete(key_id)
+            .select(key_id.clone())
             .await
             .map_err(SurrealCacheError::DatabaseError)?;
 
-        Ok(removed.map(|entry| entry.value))
+        let Some(entry) = entry else {
+            return Ok(Lookup {
+                value: None,
+                result: CacheResult::Miss,
+            });
+        };
+
+        if Self::is_expired(&entry) {
+            let _: Option<CacheEntry<V>> = self
+                .db
+                .delete(key_id.clone())
+                .await
+                .map_err(SurrealCacheError::DatabaseError)?;
+
+            return Ok(Lookup {
+                value: None,
+                result: CacheResult::Expired,
+            });
+        }
+
+        // Refresh TTL if enabled, and track use for eviction
+        if self.refresh || self.max_entries.is_some() {
+            let expires_at = match self.refresh {
+                true => self.next_expiry(),
+                false => entry.expires_at.clone(),
+            };
+
+            self.db
+                .query(
+                    "UPDATE $cacheidtorefresh SET expires_at = $expires_at, accessed_at = time::now() RETURN NONE;",
+                )
+                .bind(("cacheidtorefresh", key_id.clone()))
+                .bind(("expires_at", expires_at))
+                .await?;
+        }
+
+        self.memory_set(key_id.to_string(), entry.value.clone());
+
+        Ok(Lookup {
+            value: Some(entry.value),
+            result: CacheResult::Hit,
+        })
+    }
+
+    async fn lookup(&self, key: &K) -> Result<Lookup<V>, SurrealCacheError>
+    where
+        V: 'static,
+    {
+        let key_id = self.generate_key(key)?;
+        let slot = key_id.to_string();
+
+        let Some(flight_timeout) = self.flight_timeout else {
+            if let Some(value) = self.memory_get(&slot) {
+                return Ok(Lookup::memory(value));
+            }
+            return self.stored_get(&key_id).await;
+        };
+
+        loop {
+            if let Some(value) = self.memory_get(&slot) {
+                return Ok(Lookup::memory(value));
+            }
+
+            match self.flights.join(&slot, flight_timeout) {
+                Flight::Leader => {
+                    let lookup = self.stored_get(&key_id).await;
+
+                    // on a miss the lease is kept until `cache_set`, the caller computes the value
+                    if !matches!(&lookup, Ok(Lookup { value: None, .. })) {
+                        self.flights.release(&slot);
+                    }
+
+                    return lookup;
+                }
+                Flight::Follower(notified) => {
+                    let _ = tokio::time::timeout(flight_timeout, notified).await;
+
+                    if let Some(value) = self.memory_get(&slot) {
+                        return Ok(Lookup::memory(value));
+                    }
+                    let lookup = self.stored_get(&key_id).await?;
+                    if lookup.value.is_some() {
+                        return Ok(lookup);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Guard for the lease `cache_get` took on a miss of `key`, see `FlightGuard`. `None` when
+    /// there is no lease, e.g. without single flight.
+    pub fn flight_guard(&self, key: &K) -> Option<FlightGuard<'_>> {
+        let slot = self.generate_key(key).ok()?.to_string();
+        let started = self.flights.started(&slot)?;
+
+        Some(FlightGuard {
+            flights: &self.flights,
+            slot,
+            started: Some(started),
+        })
+    }
+
+    /// Returns the cached value for `key`, or computes it with `f` and caches it. With
+    /// `with_single_flight`, concurrent callers for the same key wait for the first one instead
+    /// of running `f` again.
+    ///
+    /// ### EXAMPLE:
+    /// ```rs
+    ///     let chains = cache
+    ///         .get_or_set_with("chainlist".to_string(), || download_chainlist())
+    ///         .await?;
+    /// ```
+    pub async fn get_or_set_with<F, Fut, E>(&self, key: K, f: F) -> Result<V, E>
+    where
+        K: std::fmt::Debug + 'static,
+        V: 'static,
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<V, E>>,
+        E: From<SurrealCacheError>,
+    {
+        if let Some(value) = self.cache_get(&key).await? {
+            return Ok(value);
+        }
+
+        // lets the next caller try without waiting out the lease when `f` or `cache_set` fails
+        let _flight = self.flight_guard(&key);
+
+        let value = f().await?;
+        self.cache_set(key, value.clone()).await?;
+        Ok(value)
+    }
+}
+
+#[cfg(feature = "ssr")]
+#[async_trait]
+impl<K, V> IOCachedAsync<K, V> for AsyncSurrealCache<K, V>
+where
+    K: Send + std::fmt::Debug + Sync + Clone + Serialize + for<'de> Deserialize<'de> + 'static,
+    V: Send + Sync + Clone + Serialize + for<'de> Deserialize<'de> + 'static,
+{
+    type Error = SurrealCacheError;
+
+    async fn cache_get(&self, key: &K) -> Result<Option<V>, Self::Error> {
+        let started = Instant::now();
+        let outcome = self.lookup(key).await;
+        self.record(CacheOperation::Get, started, &outcome, |lookup| {
+            lookup.result
+        });
+
+        outcome.map(|lookup| lookup.value)
+    }
+
+    // asynchronously inserts a key-value pair into a cache and
+    // returns the previously stored value associated with that key, or None if no value was present.
+    async fn cache_set(&self, key: K, value: V) -> Result<Option<V>, Self::Error> {
+        self.cache_set_tagged(key, value, &[]).await
+    }
+
+    async fn cache_remove(&self, key: &K) -> Result<Option<V>, Self::Error> {
+        let started = Instant::now();
+        let outcome = async {
+            let key_id = self.generate_key(key)?;
+            self.memory_remove(&key_id.to_string());
+
+            let removed: Option<CacheEntry<V>> = self
+                .db
+                .delete(key_id)
+                .await
+                .map_err(SurrealCacheError::DatabaseError)?;
+
+            Ok(removed.map(|entry| entry.value))
+        }
+        .await;
+        self.record(CacheOperation::Remove, started, &outcome, |_| {
+            CacheResult::Ok
+        });
+
+        outcome
     }
 
     fn cache_set_refresh(&mut self, refresh: bool) -> bool {
@@ -225,19 +884,16 @@ where
     }
 
     fn cache_lifespan(&self) -> Option<Duration> {
-        Some(self.ttl)
+        self.ttl
     }
 
     fn cache_set_lifespan(&mut self, ttl: Duration) -> Option<Duration> {
-        let old_ttl = self.ttl;
-        self.ttl = ttl;
-        Some(old_ttl)
+        self.ttl.replace(ttl)
     }
 
+    // entries set from now on never expire
     fn cache_unset_lifespan(&mut self) -> Option<Duration> {
-        let old_ttl = self.ttl;
-        self.ttl = Duration::from_secs(0); // Never expire
-        Some(old_ttl)
+        self.ttl.take()
     }
 }
 
@@ -309,6 +965,14 @@ async fn test_structured_data(a: u32, b: f64) -> Result<StructuredData, String>
 #[cfg(feature = "ssr")]
 #[tokio::test]
 async fn test_slow_result() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::db::connection::{db_memory, with_db};
+
+    with_db(db_memory().await?, slow_results()).await;
+    Ok(())
+}
+
+#[cfg(all(test, feature = "ssr"))]
+async fn slow_results() {
     async fn some_test(input: u32) {
         let start = std::time::Instant::now();
         let first = slow_result(2, input).await;
@@ -341,13 +1005,205 @@ async fn test_slow_result() -> Result<(), Box<dyn std::error::Error>> {
     some_test_structured_data(1.0001).await;
     some_test_structured_data(2.0002).await;
     some_test_structured_data(3.00003).await;
+}
+
+#[cfg(feature = "ssr")]
+#[test]
+fn test_cache_record_key_is_stable() {
+    // a changed hash orphans every cached row, bump CACHE_KEY_VERSION instead
+    assert_eq!(
+        cache_record_key("chain", "\"chainlist_get_rpcs\""),
+        "v1_chain_fa88727f0468573969fa00260a0a0ece"
+    );
+    assert_ne!(
+        cache_record_key("chain", "\"a\""),
+        cache_record_key("wallet", "\"a\"")
+    );
+}
+
+#[cfg(feature = "ssr")]
+#[tokio::test]
+async fn test_memory_lifespan() -> Result<(), Box<dyn std::error::Error>> {
+    use cached::IOCachedAsync;
+
+    // never connected, only the in-memory layer is used
+    let db = Arc::new(Surreal::<Any>::init());
+
+    let mut cache = AsyncSurrealCache::<String, u32>::new("test_cache", Duration::from_millis(20))
+        .with_db(db)
+        .with_memory_cache(2)
+        .build()
+        .await?;
+
+    assert_eq!(
+        cache.cache_unset_lifespan(),
+        Some(Duration::from_millis(20))
+    );
+    assert_eq!(cache.cache_lifespan(), None);
+
+    cache.memory_set("forever".into(), 1);
+    cache.cache_set_lifespan(Duration::from_millis(20));
+    cache.memory_set("short".into(), 2);
+    tokio::time::sleep(Duration::from_millis(40)).await;
+
+    assert_eq!(cache.memory_get("forever"), Some(1));
+    assert_eq!(cache.memory_get("short"), None);
 
     Ok(())
 }
 
+#[cfg(feature = "ssr")]
+#[tokio::test]
+async fn test_flights_coalesce() {
+    let flights = Arc::new(Flights::default());
+    let timeout = Duration::from_secs(5);
+
+    assert!(matches!(flights.join("cache:a", timeout), Flight::Leader));
+    assert!(matches!(flights.join("cache:b", timeout), Flight::Leader));
+
+    let Flight::Follower(notified) = flights.join("cache:a", timeout) else {
+        panic!("second caller should wait for the leader");
+    };
+
+    let leader = flights.clone();
+    tokio::spawn(async move { leader.release("cache:a") });
+    tokio::time::timeout(timeout, notified)
+        .await
+        .expect("follower is woken when the leader releases");
+
+    assert!(matches!(flights.join("cache:a", timeout), Flight::Leader));
+
+    // a lease older than the timeout is taken over
+    assert!(matches!(
+        flights.join("cache:b", Duration::ZERO),
+        Flight::Leader
+    ));
+}
+
+#[cfg(feature = "ssr")]
+#[io_cached(
+    name = "FAILING_RESULT",
+    map_error = r##"|e| format!("Cache error: {:?}", e)"##,
+    ty = "AsyncSurrealCache<String, u32>",
+    create = r##" {
+        AsyncSurrealCache::new("cache_table", Duration::from_secs(60))
+            .set_namespace("failing_result")
+            .set_flight_timeout(Duration::from_secs(30))
+            .build()
+            .await
+            .expect("Failed to build SurrealDB cache")
+    } "##,
+    convert = r#"{ a.to_string() }"#
+)]
+async fn failing_result(a: u32) -> Result<u32, String> {
+    let _flight = FAILING_RESULT
+        .get()
+        .and_then(|cache| cache.flight_guard(&a.to_string()));
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    Err(format!("{a} failed"))
+}
+
+#[cfg(feature = "ssr")]
+#[tokio::test]
+async fn test_failed_leader_releases_flight() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::db::connection::{db_memory, with_db};
+
+    with_db(db_memory().await?, async {
+        // the follower would wait out the 30 second lease if the failed leader kept it
+        let (leader, follower) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(failing_result(1), failing_result(1))
+        })
+        .await
+        .expect("follower is woken when the leader fails");
+
+        assert!(leader.is_err());
+        assert!(follower.is_err());
+    })
+    .await;
+    Ok(())
+}
+
+#[cfg(feature = "ssr")]
+#[tokio::test]
+async fn test_max_entries_evicts_in_batches() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::db::connection::{db_memory, with_db};
+    use cached::IOCachedAsync;
+
+    with_db(db_memory().await?, async {
+        let cache = AsyncSurrealCache::<String, u32>::new("cache_table", Duration::from_secs(60))
+            .set_namespace("evicted")
+            .set_max_entries(20)
+            .build()
+            .await?;
+
+        // evicts every second set, an odd set can leave one entry over
+        for i in 0..21 {
+            cache.cache_set(format!("key{i}"), i).await?;
+        }
+        let count = |db: Arc<Surreal<Any>>| async move {
+            let mut result = db
+                .query("RETURN count(SELECT id FROM cache_table WHERE namespace = 'evicted');")
+                .await?;
+            Ok::<_, SurrealCacheError>(result.take::<Option<u64>>(0)?.unwrap_or(0))
+        };
+        assert_eq!(count(cache.db.clone()).await?, 21);
+
+        cache.cache_set("key21".to_string(), 21).await?;
+        assert_eq!(count(cache.db.clone()).await?, 20);
+        assert_eq!(cache.cache_get(&"key0".to_string()).await?, None);
+        assert_eq!(cache.cache_get(&"key21".to_string()).await?, Some(21));
+
+        let info: surrealdb::Value = cache
+            .db
+            .query("INFO FOR TABLE cache_table;")
+            .await?
+            .take(0)?;
+        assert!(info.to_string().contains("cache_table_lru"));
+
+        Ok::<(), Box<dyn std::error::Error>>(())
+    })
+    .await
+}
+
+#[cfg(feature = "ssr")]
+#[tokio::test]
+async fn test_single_flight_is_opt_in() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::db::connection::{db_memory, with_db};
+    use cached::IOCachedAsync;
+
+    with_db(db_memory().await?, async {
+        let key = "a".to_string();
+
+        // no lease is taken on a miss, a failing `#[io_cached]` function leaves nobody waiting
+        let cache = AsyncSurrealCache::<String, u32>::new("cache_table", Duration::from_secs(60))
+            .build()
+            .await?;
+        assert_eq!(cache.cache_get(&key).await?, None);
+        assert!(cache.flight_guard(&key).is_none());
+
+        let cache = AsyncSurrealCache::<String, u32>::new("cache_table", Duration::from_secs(60))
+            .with_single_flight()
+            .build()
+            .await?;
+        assert_eq!(cache.cache_get(&key).await?, None);
+        assert!(cache.flight_guard(&key).is_some());
+
+        Ok::<(), Box<dyn std::error::Error>>(())
+    })
+    .await
+}
+
 #[cfg(feature = "ssr")]
 #[tokio::test]
 async fn test_async_surreal_cache() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::db::connection::{db_memory, with_db};
+
+    with_db(db_memory().await?, async_surreal_cache()).await
+}
+
+#[cfg(all(test, feature = "ssr"))]
+async fn async_surreal_cache() -> Result<(), Box<dyn std::error::Error>> {
     use cached::IOCachedAsync;
 
     // Build the cache
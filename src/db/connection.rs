@@ -1,6 +1,8 @@
 #[cfg(feature = "ssr")]
 use crate::AppError;
 #[cfg(feature = "ssr")]
+use crate::db::migrations::MigrationRunner;
+#[cfg(feature = "ssr")]
 use crate::db::settings;
 
 #[cfg(feature = "ssr")]
@@ -8,7 +10,13 @@ use std::sync::Arc;
 #[cfg(feature = "ssr")]
 use std::time::Duration;
 #[cfg(feature = "ssr")]
-use surrealdb::{Surreal, engine::any::Any, opt::auth::Root};
+use crate::db::settings::{DbAuth, Settings};
+#[cfg(feature = "ssr")]
+use surrealdb::{
+    Surreal,
+    engine::any::Any,
+    opt::auth::{Database, Namespace, Root},
+};
 
 #[cfg(feature = "ssr")]
 use tokio::sync::OnceCell;
@@ -19,11 +27,100 @@ use tokio::time::{sleep, timeout};
 #[cfg(feature = "ssr")]
 static DB: OnceCell<Arc<Surreal<Any>>> = OnceCell::const_new();
 
+#[cfg(feature = "ssr")]
+tokio::task_local! {
+    static DB_OVERRIDE: Arc<Surreal<Any>>;
+}
+
+/// Runs `f` with `db` returned from every `db_init()` call made inside it, so `Storage`,
+/// `StorageAuthed`, the auth adapters and caches built in scope all use that handle.
+/// The override is task-local: work moved onto `tokio::spawn` falls back to the global connection.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     let db = db_memory().await?;
+///     with_db(db, async {
+///         let chain = Chain::create(chain).await?;
+///         Ok::<_, AppError>(())
+///     })
+///     .await?;
+/// ```
+#[cfg(feature = "ssr")]
+pub async fn with_db<F: std::future::Future>(db: Arc<Surreal<Any>>, f: F) -> F::Output {
+    DB_OVERRIDE.scope(db, f).await
+}
+
+/// Sets the process-wide connection, for apps that connect themselves (e.g. an embedded engine).
+/// Fails if `db_init()` has already connected.
+#[cfg(feature = "ssr")]
+pub fn db_set(db: Arc<Surreal<Any>>) -> Result<(), AppError> {
+    DB.set(db)
+        .map_err(|_| AppError::DatabaseError("Database connection already initialised".into()))
+}
+
+/// Connects to a fresh, isolated in-memory database with the tinkr schema applied.
+/// Requires the `kv-mem` feature of `surrealdb` to be enabled by the app.
+#[cfg(feature = "ssr")]
+pub async fn db_memory() -> Result<Arc<Surreal<Any>>, AppError> {
+    let db = surrealdb::engine::any::connect("mem://").await?;
+    db.use_ns("tinkr").await?;
+    db.use_db("tinkr").await?;
+
+    let db = Arc::new(db);
+    with_db(db.clone(), MigrationRunner::tinkr().run()).await?;
+
+    Ok(db)
+}
+
+/// Opens a new connection with `settings`, signs in and selects the namespace and database.
+#[cfg(feature = "ssr")]
+pub async fn connect(settings: &Settings) -> Result<Surreal<Any>, AppError> {
+    tracing::trace!("Connecting to SurrealDB at {}", &settings.surrealdb_host);
+
+    let db = surrealdb::engine::any::connect(&settings.surrealdb_host).await?;
+
+    match &settings.auth {
+        DbAuth::None => {}
+        DbAuth::Root { username, password } => {
+            db.signin(Root { username, password }).await?;
+        }
+        DbAuth::Namespace { username, password } => {
+            db.signin(Namespace {
+                namespace: &settings.surrealdb_ns,
+                username,
+                password,
+            })
+            .await?;
+        }
+        DbAuth::Database { username, password } => {
+            db.signin(Database {
+                namespace: &settings.surrealdb_ns,
+                database: &settings.surrealdb_db,
+                username,
+                password,
+            })
+            .await?;
+        }
+        DbAuth::Token(token) => {
+            db.authenticate(token.as_str()).await?;
+        }
+    }
+
+    db.use_ns(&settings.surrealdb_ns).await?;
+    db.use_db(&settings.surrealdb_db).await?;
+
+    Ok(db)
+}
+
 #[cfg(feature = "ssr")]
 pub async fn db_init() -> Result<Arc<Surreal<Any>>, AppError> {
+    if let Ok(db) = DB_OVERRIDE.try_with(|db| db.clone()) {
+        return Ok(db);
+    }
+
     let mut db = DB
         .get_or_try_init(|| async {
-            let settings = settings::get_settings();
+            let settings = settings::try_get_settings()?;
 
             const MAX_RETRIES: u32 = 5;
             const INITIAL_DELAY: Duration = Duration::from_millis(500);
@@ -31,16 +128,7 @@ pub async fn db_init() -> Result<Arc<Surreal<Any>>, AppError> {
 
             for attempt in 1..=MAX_RETRIES {
                 let connect_result = timeout(CONNECTION_TIMEOUT, async {
-                    let db = surrealdb::engine::any::connect(&settings.surrealdb_host).await?;
-
-                    db.signin(Root {
-                        username: &settings.surrealdb_user,
-                        password: &settings.surrealdb_pass,
-                    })
-                    .await?;
-                    db.use_ns(&settings.surrealdb_ns).await?;
-                    db.use_db(&settings.surrealdb_db).await?;
-                    Ok::<_, AppError>(Arc::new(db))
+                    Ok::<_, AppError>(Arc::new(connect(&settings).await?))
                 })
                 .await;
 
@@ -72,7 +160,7 @@ pub async fn db_init() -> Result<Arc<Surreal<Any>>, AppError> {
 
     db = match db {
         Ok(db) => {
-            let settings = settings::get_settings();
+            let settings = settings::try_get_settings()?;
             db.use_ns(&settings.surrealdb_ns).await?;
             db.use_db(&settings.surrealdb_db).await?;
 
@@ -92,55 +180,29 @@ pub async fn db_init() -> Result<Arc<Surreal<Any>>, AppError> {
 // used for background tests to have a separate connection
 #[cfg(feature = "ssr")]
 pub async fn db_seperate_connection() -> Result<Surreal<Any>, AppError> {
-    let settings = settings::get_settings();
+    if let Ok(db) = DB_OVERRIDE.try_with(|db| db.clone()) {
+        return Ok((*db).clone());
+    }
 
-    tracing::trace!("Connecting to SurrealDB at {}", &settings.surrealdb_host);
-
-    let db = surrealdb::engine::any::connect(&settings.surrealdb_host).await?;
+    let settings = settings::try_get_settings()?;
 
-    db.signin(Root {
-        username: &settings.surrealdb_user,
-        password: &settings.surrealdb_pass,
-    })
-    .await?;
-
-    db.use_ns(&settings.surrealdb_ns).await?;
-    db.use_db(&settings.surrealdb_db).await?;
-
-    Ok(db)
+    connect(&settings).await
 }
 
+/// Applies tinkr's pending schema migrations. Apps with their own migrations should build a
+/// `MigrationRunner::tinkr()`, add theirs and call `run()` instead.
 #[cfg(feature = "ssr")]
 pub async fn db_schema() -> Result<(), AppError> {
-    let db = db_init().await?;
-
-    let schema = r#"
-        remove field if exists email on table user;
-        REMOVE INDEX if exists user_email_index ON TABLE user;
-        update token set mc = 0 where mc > 1_000_000_000_000_000;
-        update token set last_trade = time::from::unix(last_trade_unix_time/1000);
-        
-        -- Log events table for tracing
-        DEFINE TABLE log_events SCHEMAFULL;
-        DEFINE FIELD id ON TABLE log_events TYPE string;
-        DEFINE FIELD timestamp ON TABLE log_events TYPE datetime;
-        DEFINE FIELD level ON TABLE log_events TYPE string;
-        DEFINE FIELD target ON TABLE log_events TYPE string;
-        DEFINE FIELD message ON TABLE log_events TYPE string;
-        DEFINE FIELD module_path ON TABLE log_events TYPE option<string>;
-        DEFINE FIELD file ON TABLE log_events TYPE option<string>;
-        DEFINE FIELD line ON TABLE log_events TYPE option<number>;
-        DEFINE FIELD fields ON TABLE log_events TYPE object;
-        
-        -- Index for querying logs by timestamp and level
-        DEFINE INDEX log_events_timestamp ON TABLE log_events COLUMNS timestamp;
-        DEFINE INDEX log_events_level ON TABLE log_events COLUMNS level;
-        DEFINE INDEX log_events_target ON TABLE log_events COLUMNS target;
-
-       
-    "#;
-
-    let _ = db.query(schema).await;
+    let applied = MigrationRunner::tinkr().run().await?;
+
+    for migration in applied {
+        tracing::info!(
+            namespace = %migration.namespace,
+            version = migration.version,
+            name = %migration.name,
+            "Applied migration"
+        );
+    }
 
     Ok(())
 }
@@ -151,3 +213,21 @@ pub async fn db_health() -> Result<bool, AppError> {
     let _ = db.version().await?;
     Ok(true)
 }
+
+#[cfg(feature = "ssr")]
+#[tokio::test]
+async fn test_with_db_override() -> Result<(), AppError> {
+    let db = Arc::new(Surreal::<Any>::init());
+
+    let scoped = with_db(db.clone(), db_init()).await?;
+    assert!(Arc::ptr_eq(&db, &scoped));
+
+    let nested = with_db(db.clone(), async {
+        tokio::task::yield_now().await;
+        db_init().await
+    })
+    .await?;
+    assert!(Arc::ptr_eq(&db, &nested));
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use crate::AppError;
+use crate::db::query::Filter;
+use crate::live::{Change, ChangeAction};
+use futures::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use std::pin::Pin;
+use surrealdb::{Action, Notification};
+
+pub type LiveStream<T> = Pin<Box<dyn Stream<Item = Result<Change<T>, AppError>> + Send>>;
+
+/// Starts a `LIVE SELECT` on `table` and streams every matching create/update/delete.
+/// The live query is killed when the stream is dropped.
+pub async fn watch_table<T>(table: &str, filter: Option<Filter>) -> Result<LiveStream<T>, AppError>
+where
+    T: DeserializeOwned + Unpin + Send + 'static,
+{
+    let where_clause = match filter {
+        Some(filter) => crate::db::Query::new()
+            .filter(filter)
+            .inline_where_clause()?,
+        None => String::new(),
+    };
+
+    let db = crate::db_init().await?;
+
+    let stream = db
+        .query(format!("LIVE SELECT * FROM {table}{where_clause};"))
+        .await?
+        .stream::<Notification<T>>(0)?;
+
+    Ok(stream
+        .filter_map(|notification| async move {
+            match notification {
+                Ok(notification) => {
+                    let action = match notification.action {
+                        Action::Create => ChangeAction::Create,
+                        Action::Update => ChangeAction::Update,
+                        Action::Delete => ChangeAction::Delete,
+                        _ => return None,
+                    };
+
+                    Some(Ok(Change {
+                        action,
+                        data: notification.data,
+                    }))
+                }
+                Err(e) => Some(Err(AppError::from(e))),
+            }
+        })
+        .boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_watch_table_filter() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+        use std::time::Duration;
+
+        #[derive(Debug, serde::Deserialize)]
+        struct Thing {
+            owner: Option<String>,
+        }
+
+        with_db(db_memory().await?, async {
+            // the owner is written into the query after `$p0`, and must not be rewritten by it
+            let filter = Filter::ne("x", 2).and(Filter::eq("owner", "o'wner $p0"));
+            let mut changes = watch_table::<Thing>("thing", Some(filter)).await?;
+
+            let db = crate::db_init().await?;
+            db.query("CREATE thing:1 SET x = 1; CREATE thing:2 SET owner = 'other';")
+                .await?
+                .check()?;
+            db.query("UPDATE thing:1 SET owner = \"o'wner $p0\";")
+                .await?
+                .check()?;
+
+            // only the record once it belongs to the owner
+            let change = tokio::time::timeout(Duration::from_secs(5), changes.next())
+                .await
+                .expect("a change")
+                .expect("an open stream")?;
+            assert_eq!(change.action, ChangeAction::Update);
+            assert_eq!(change.data.owner.as_deref(), Some("o'wner $p0"));
+
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,644 @@
+use crate::AppError;
+use serde::{Deserialize, Serialize};
+use std::{future::Future, pin::Pin};
+use sha2::{Digest, Sha256};
+use surrealdb::{Datetime, RecordId};
+
+/// Table that records every migration applied against a database.
+pub const MIGRATIONS_TABLE: &str = "_migrations";
+
+/// Namespace used for the migrations shipped with tinkr itself.
+pub const TINKR_NAMESPACE: &str = "tinkr";
+
+/// Rust code run as part of a migration, for data changes SurrealQL can't express.
+pub type MigrationHook = fn() -> Pin<Box<dyn Future<Output = Result<(), AppError>> + Send>>;
+
+/// A single named, versioned schema change.
+///
+/// Migrations are grouped by `namespace` (tinkr uses `"tinkr"`, apps should use their own name)
+/// and applied in ascending `version` order within that namespace. Once a migration has been
+/// applied its statements must not change, the stored checksum is compared on every run.
+#[derive(Debug, Clone)]
+pub struct Migration {
+    pub namespace: String,
+    pub version: u32,
+    pub name: String,
+    pub statements: String,
+    pub hook: Option<MigrationHook>,
+}
+
+impl Migration {
+    pub fn new(
+        namespace: impl Into<String>,
+        version: u32,
+        name: impl Into<String>,
+        statements: impl Into<String>,
+    ) -> Self {
+        Self {
+            namespace: namespace.into(),
+            version,
+            name: name.into(),
+            statements: statements.into(),
+            hook: None,
+        }
+    }
+
+    /// Runs `hook` before the statements. The migration is only recorded once both succeeded, a
+    /// failed run repeats the hook, so it has to be safe to run twice.
+    ///
+    /// ### EXAMPLE:
+    /// ```rs
+    ///     Migration::new("myapp", 2, "encrypt_notes", "")
+    ///         .with_hook(|| Box::pin(encrypt_plaintext_notes()))
+    /// ```
+    pub fn with_hook(mut self, hook: MigrationHook) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    /// Hex encoded sha256 of the migration statements.
+    pub fn checksum(&self) -> String {
+        hex::encode(Sha256::digest(self.statements.trim().as_bytes()))
+    }
+
+    pub fn record_id(&self) -> RecordId {
+        RecordId::from_table_key(
+            MIGRATIONS_TABLE,
+            format!("{}_{:05}", self.namespace, self.version),
+        )
+    }
+
+    /// Wraps the statements in a transaction together with the `_migrations` bookkeeping record,
+    /// so a migration is either applied and recorded, or not applied at all.
+    fn transaction_query(&self) -> String {
+        let statements = self.statements.trim().trim_end_matches(';');
+        let statements = match statements.is_empty() {
+            true => String::new(),
+            false => format!("{statements};\n"),
+        };
+
+        format!(
+            "BEGIN TRANSACTION;\n{statements}CREATE $migration_id CONTENT $migration_record RETURN NONE;\nCOMMIT TRANSACTION;"
+        )
+    }
+}
+
+/// Row stored in the `_migrations` table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppliedMigration {
+    pub id: RecordId,
+    pub namespace: String,
+    pub version: u32,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: Datetime,
+}
+
+#[derive(Debug, Serialize)]
+struct AppliedMigrationContent {
+    namespace: String,
+    version: u32,
+    name: String,
+    checksum: String,
+    applied_at: Datetime,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// The migration was applied, but its statements have since been edited.
+    ChecksumMismatch { applied_checksum: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationStatus {
+    pub namespace: String,
+    pub version: u32,
+    pub name: String,
+    pub checksum: String,
+    pub state: MigrationState,
+    pub applied_at: Option<Datetime>,
+}
+
+/// Collects migrations from tinkr and downstream apps and applies the pending ones in order.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     MigrationRunner::tinkr()
+///         .register(Migration::new("myapp", 1, "token_indexes", "DEFINE INDEX token_mint ON TABLE token COLUMNS mint;"))
+///         .run()
+///         .await?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MigrationRunner {
+    migrations: Vec<Migration>,
+}
+
+impl MigrationRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A runner preloaded with the migrations tinkr needs for its own tables.
+    pub fn tinkr() -> Self {
+        Self::new().register_many(tinkr_migrations())
+    }
+
+    pub fn register(mut self, migration: Migration) -> Self {
+        self.migrations.push(migration);
+        self
+    }
+
+    pub fn register_many(mut self, migrations: impl IntoIterator<Item = Migration>) -> Self {
+        self.migrations.extend(migrations);
+        self
+    }
+
+    /// Namespaces keep the order they were first registered in, migrations within a namespace
+    /// are sorted by version. Duplicate versions are rejected.
+    pub fn ordered(&self) -> Result<Vec<&Migration>, AppError> {
+        let mut namespaces: Vec<&str> = Vec::new();
+        for migration in &self.migrations {
+            if !namespaces.contains(&migration.namespace.as_str()) {
+                namespaces.push(migration.namespace.as_str());
+            }
+        }
+
+        let mut ordered = Vec::with_capacity(self.migrations.len());
+        for namespace in namespaces {
+            let mut group: Vec<&Migration> = self
+                .migrations
+                .iter()
+                .filter(|m| m.namespace == namespace)
+                .collect();
+            group.sort_by_key(|m| m.version);
+
+            if let Some(pair) = group.windows(2).find(|w| w[0].version == w[1].version) {
+                return Err(AppError::MigrationError(format!(
+                    "duplicate migration version {}:{} ({} and {})",
+                    namespace, pair[0].version, pair[0].name, pair[1].name
+                )));
+            }
+
+            ordered.extend(group);
+        }
+
+        Ok(ordered)
+    }
+
+    /// Reads `_migrations` without defining it, a database without the table has applied nothing.
+    async fn applied(&self) -> Result<Vec<AppliedMigration>, AppError> {
+        #[derive(Deserialize)]
+        struct DbInfo {
+            tables: std::collections::BTreeMap<String, String>,
+        }
+
+        let db = crate::db_init().await?;
+
+        let info: Option<DbInfo> = db.query("INFO FOR DB;").await?.take(0)?;
+        if !info.is_some_and(|info| info.tables.contains_key(MIGRATIONS_TABLE)) {
+            return Ok(Vec::new());
+        }
+
+        let mut result = db
+            .query(format!("SELECT * FROM {MIGRATIONS_TABLE};"))
+            .await?;
+
+        let applied: Vec<AppliedMigration> = result.take(0)?;
+        Ok(applied)
+    }
+
+    /// Reports every registered migration and whether it has been applied to the current database.
+    pub async fn status(&self) -> Result<Vec<MigrationStatus>, AppError> {
+        let ordered = self.ordered()?;
+        let applied = self.applied().await?;
+
+        let status = ordered
+            .into_iter()
+            .map(|migration| {
+                let checksum = migration.checksum();
+                let existing = applied.iter().find(|a| {
+                    a.namespace == migration.namespace && a.version == migration.version
+                });
+
+                let (state, applied_at) = match existing {
+                    Some(a) if a.checksum == checksum => {
+                        (MigrationState::Applied, Some(a.applied_at.clone()))
+                    }
+                    Some(a) => (
+                        MigrationState::ChecksumMismatch {
+                            applied_checksum: a.checksum.clone(),
+                        },
+                        Some(a.applied_at.clone()),
+                    ),
+                    None => (MigrationState::Pending, None),
+                };
+
+                MigrationStatus {
+                    namespace: migration.namespace.clone(),
+                    version: migration.version,
+                    name: migration.name.clone(),
+                    checksum,
+                    state,
+                    applied_at,
+                }
+            })
+            .collect();
+
+        Ok(status)
+    }
+
+    /// Returns the migrations `run` would apply, without touching the schema.
+    pub async fn dry_run(&self) -> Result<Vec<MigrationStatus>, AppError> {
+        let status = self.status().await?;
+        check_no_mismatch(&status)?;
+
+        Ok(status
+            .into_iter()
+            .filter(|s| s.state == MigrationState::Pending)
+            .collect())
+    }
+
+    /// Applies all pending migrations in order, each in its own transaction.
+    /// Stops at the first failure and returns the statement error.
+    pub async fn run(&self) -> Result<Vec<AppliedMigration>, AppError> {
+        let pending = self.dry_run().await?;
+        let ordered = self.ordered()?;
+        let db = crate::db_init().await?;
+
+        if !pending.is_empty() {
+            db.query(format!(
+                "DEFINE TABLE IF NOT EXISTS {MIGRATIONS_TABLE} SCHEMALESS;"
+            ))
+            .await?
+            .check()?;
+        }
+
+        let mut applied = Vec::with_capacity(pending.len());
+
+        for status in pending {
+            let Some(migration) = ordered
+                .iter()
+                .find(|m| m.namespace == status.namespace && m.version == status.version)
+            else {
+                continue;
+            };
+
+            tracing::info!(
+                namespace = %migration.namespace,
+                version = migration.version,
+                name = %migration.name,
+                "Applying migration"
+            );
+
+            let record = AppliedMigration {
+                id: migration.record_id(),
+                namespace: migration.namespace.clone(),
+                version: migration.version,
+                name: migration.name.clone(),
+                checksum: migration.checksum(),
+                applied_at: Datetime::from(chrono::Utc::now()),
+            };
+
+            let content = AppliedMigrationContent {
+                namespace: record.namespace.clone(),
+                version: record.version,
+                name: record.name.clone(),
+                checksum: record.checksum.clone(),
+                applied_at: record.applied_at.clone(),
+            };
+
+            if let Some(hook) = migration.hook {
+                hook().await.map_err(|e| {
+                    AppError::MigrationError(format!(
+                        "{}:{} {} failed: {}",
+                        migration.namespace, migration.version, migration.name, e
+                    ))
+                })?;
+            }
+
+            let mut response = db
+                .query(migration.transaction_query())
+                .bind(("migration_id", record.id.clone()))
+                .bind(("migration_record", content))
+                .await?;
+
+            let mut errors: Vec<(usize, surrealdb::Error)> =
+                response.take_errors().into_iter().collect();
+            errors.sort_by_key(|(index, _)| *index);
+
+            // every statement after a failure reports the cancelled transaction, the first
+            // other error is the one that actually broke the migration
+            let failure = errors
+                .iter()
+                .find(|(_, e)| !e.to_string().contains("failed transaction"))
+                .or(errors.first());
+
+            if let Some((index, error)) = failure {
+                return Err(AppError::MigrationError(format!(
+                    "{}:{} {} failed at statement {}: {}",
+                    migration.namespace, migration.version, migration.name, index, error
+                )));
+            }
+
+            applied.push(record);
+        }
+
+        Ok(applied)
+    }
+}
+
+fn check_no_mismatch(status: &[MigrationStatus]) -> Result<(), AppError> {
+    let mismatched: Vec<String> = status
+        .iter()
+        .filter(|s| matches!(s.state, MigrationState::ChecksumMismatch { .. }))
+        .map(|s| format!("{}:{} {}", s.namespace, s.version, s.name))
+        .collect();
+
+    if mismatched.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::MigrationError(format!(
+            "applied migrations were modified: {}",
+            mismatched.join(", ")
+        )))
+    }
+}
+
+/// Schema changes for the tables owned by tinkr.
+pub fn tinkr_migrations() -> Vec<Migration> {
+    vec![
+        Migration::new(
+            TINKR_NAMESPACE,
+            1,
+            "remove_user_email_definitions",
+            r#"
+            REMOVE FIELD IF EXISTS email ON TABLE user;
+            REMOVE INDEX IF EXISTS user_email_index ON TABLE user;
+            "#,
+        ),
+        // IF NOT EXISTS so databases set up before migrations existed pick this up cleanly
+        Migration::new(
+            TINKR_NAMESPACE,
+            2,
+            "log_events",
+            r#"
+            DEFINE TABLE IF NOT EXISTS log_events SCHEMAFULL;
+            DEFINE FIELD IF NOT EXISTS id ON TABLE log_events TYPE string;
+            DEFINE FIELD IF NOT EXISTS timestamp ON TABLE log_events TYPE datetime;
+            DEFINE FIELD IF NOT EXISTS level ON TABLE log_events TYPE string;
+            DEFINE FIELD IF NOT EXISTS target ON TABLE log_events TYPE string;
+            DEFINE FIELD IF NOT EXISTS message ON TABLE log_events TYPE string;
+            DEFINE FIELD IF NOT EXISTS module_path ON TABLE log_events TYPE option<string>;
+            DEFINE FIELD IF NOT EXISTS file ON TABLE log_events TYPE option<string>;
+            DEFINE FIELD IF NOT EXISTS line ON TABLE log_events TYPE option<number>;
+            DEFINE FIELD IF NOT EXISTS fields ON TABLE log_events TYPE object;
+
+            -- Index for querying logs by timestamp and level
+            DEFINE INDEX IF NOT EXISTS log_events_timestamp ON TABLE log_events COLUMNS timestamp;
+            DEFINE INDEX IF NOT EXISTS log_events_level ON TABLE log_events COLUMNS level;
+            DEFINE INDEX IF NOT EXISTS log_events_target ON TABLE log_events COLUMNS target;
+            "#,
+        ),
+        // history written by StorageAuthed, append-only for record users
+        Migration::new(
+            TINKR_NAMESPACE,
+            3,
+            "record_history",
+            r#"
+            DEFINE TABLE IF NOT EXISTS key_history SCHEMALESS PERMISSIONS FOR select, create FULL FOR update, delete NONE;
+            DEFINE INDEX IF NOT EXISTS key_history_record ON TABLE key_history COLUMNS record;
+
+            DEFINE TABLE IF NOT EXISTS wallet_history SCHEMALESS PERMISSIONS FOR select, create FULL FOR update, delete NONE;
+            DEFINE INDEX IF NOT EXISTS wallet_history_record ON TABLE wallet_history COLUMNS record;
+
+            DEFINE TABLE IF NOT EXISTS organization_history SCHEMALESS PERMISSIONS FOR select, create FULL FOR update, delete NONE;
+            DEFINE INDEX IF NOT EXISTS organization_history_record ON TABLE organization_history COLUMNS record;
+
+            DEFINE TABLE IF NOT EXISTS organization_member_history SCHEMALESS PERMISSIONS FOR select, create FULL FOR update, delete NONE;
+            DEFINE INDEX IF NOT EXISTS organization_member_history_record ON TABLE organization_member_history COLUMNS record;
+            "#,
+        ),
+        // bearer keys of auth::api_key, looked up by hash on every API request
+        Migration::new(
+            TINKR_NAMESPACE,
+            4,
+            "api_keys",
+            r#"
+            DEFINE TABLE IF NOT EXISTS api_key SCHEMALESS;
+            DEFINE INDEX IF NOT EXISTS api_key_hash ON TABLE api_key COLUMNS key_hash UNIQUE;
+            DEFINE INDEX IF NOT EXISTS api_key_user ON TABLE api_key COLUMNS user_id;
+            "#,
+        ),
+        // TOTP enrollments of auth::two_factor, one per user
+        Migration::new(
+            TINKR_NAMESPACE,
+            5,
+            "two_factor",
+            r#"
+            DEFINE TABLE IF NOT EXISTS two_factor SCHEMALESS;
+            DEFINE INDEX IF NOT EXISTS two_factor_user ON TABLE two_factor COLUMNS user_id UNIQUE;
+            "#,
+        ),
+        // WebAuthn credentials and pending ceremonies of auth::passkey
+        Migration::new(
+            TINKR_NAMESPACE,
+            6,
+            "passkey",
+            r#"
+            DEFINE TABLE IF NOT EXISTS passkey SCHEMALESS;
+            DEFINE INDEX IF NOT EXISTS passkey_credential ON TABLE passkey COLUMNS credential_id UNIQUE;
+            DEFINE INDEX IF NOT EXISTS passkey_user ON TABLE passkey COLUMNS user_id;
+            DEFINE TABLE IF NOT EXISTS webauthn_challenge SCHEMALESS;
+            DEFINE INDEX IF NOT EXISTS webauthn_challenge_hash ON TABLE webauthn_challenge COLUMNS challenge UNIQUE;
+            "#,
+        ),
+        // set_verified_email wrote `email_verified`, users are read from `emailVerified`
+        Migration::new(
+            TINKR_NAMESPACE,
+            7,
+            "user_email_verified",
+            r#"
+            UPDATE user SET emailVerified = emailVerified ?? email_verified, email_verified = NONE WHERE email_verified != NONE;
+            "#,
+        ),
+        // log_events fields as derived from LogEvent, OVERWRITE since v2 already defined them
+        Migration::new(
+            TINKR_NAMESPACE,
+            8,
+            "log_events_derived_fields",
+            r#"
+            DEFINE FIELD OVERWRITE line ON TABLE log_events TYPE option<int>;
+            DEFINE FIELD OVERWRITE fields ON TABLE log_events FLEXIBLE TYPE object;
+            "#,
+        ),
+        // sessions, links and invitations from before tokens were hashed, see auth::token_hash
+        Migration::new(TINKR_NAMESPACE, 9, "hash_plaintext_tokens", "").with_hook(|| {
+            Box::pin(async { crate::token_hash::migrate_plaintext_tokens().await.map(|_| ()) })
+        }),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migration_order() {
+        let runner = MigrationRunner::new()
+            .register(Migration::new("tinkr", 2, "b", "SELECT 2;"))
+            .register(Migration::new("app", 1, "c", "SELECT 3;"))
+            .register(Migration::new("tinkr", 1, "a", "SELECT 1;"));
+
+        let ordered: Vec<(String, u32)> = runner
+            .ordered()
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.namespace.clone(), m.version))
+            .collect();
+
+        assert_eq!(
+            ordered,
+            vec![
+                ("tinkr".to_string(), 1),
+                ("tinkr".to_string(), 2),
+                ("app".to_string(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_version_rejected() {
+        let runner = MigrationRunner::new()
+            .register(Migration::new("app", 1, "a", "SELECT 1;"))
+            .register(Migration::new("app", 1, "b", "SELECT 2;"));
+
+        assert!(matches!(
+            runner.ordered(),
+            Err(AppError::MigrationError(_))
+        ));
+    }
+
+    #[test]
+    fn test_checksum_is_stable() {
+        let a = Migration::new("app", 1, "a", "DEFINE TABLE x;");
+        let b = Migration::new("app", 1, "a", "\n  DEFINE TABLE x;  \n");
+        let c = Migration::new("app", 1, "a", "DEFINE TABLE y;");
+
+        assert_eq!(a.checksum(), b.checksum());
+        assert_ne!(a.checksum(), c.checksum());
+        assert_eq!(
+            a.checksum(),
+            "c79a2d0ac532db7db18eeadfb57e678deeb7151bd88814a3cf708b62875e13ad"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tinkr_migrations_apply() -> Result<(), AppError> {
+        use crate::db::TinkrModel;
+        use crate::db::connection::{db_memory, with_db};
+        use crate::logs::tracing_layer::LogEvent;
+
+        // applied migrations are checked against their checksum, so shipped ones never change
+        let log_events = &tinkr_migrations()[1];
+        assert_eq!(
+            log_events.checksum(),
+            "2d0684f53077472c4904b133f9f8d6e72b7148bee8bf7566f6b1f27f35a0da36"
+        );
+        assert!(LogEvent::SCHEMA.contains("line ON TABLE log_events TYPE option<int>;"));
+
+        // db_memory applies the tinkr migrations
+        with_db(db_memory().await?, async {
+            assert!(MigrationRunner::tinkr().dry_run().await?.is_empty());
+
+            let db = crate::db_init().await?;
+            let info: surrealdb::Value = db.query("INFO FOR TABLE log_events;").await?.take(0)?;
+            assert!(info.to_string().contains("TYPE option<int>"));
+            assert!(info.to_string().contains("FLEXIBLE TYPE object"));
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_leaves_schema() -> Result<(), AppError> {
+        use crate::db::connection::with_db;
+        use std::sync::Arc;
+
+        let db = surrealdb::engine::any::connect("mem://").await?;
+        db.use_ns("tinkr").await?;
+        db.use_db("tinkr").await?;
+
+        with_db(Arc::new(db), async {
+            let runner = MigrationRunner::new().register(Migration::new(
+                "app",
+                1,
+                "things",
+                "DEFINE TABLE thing SCHEMALESS;",
+            ));
+
+            assert_eq!(runner.dry_run().await?.len(), 1);
+            let db = crate::db_init().await?;
+            let info: surrealdb::Value = db.query("INFO FOR DB;").await?.take(0)?;
+            assert!(!info.to_string().contains(MIGRATIONS_TABLE));
+
+            assert_eq!(runner.run().await?.len(), 1);
+            assert!(runner.dry_run().await?.is_empty());
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_tokens_are_migrated() -> Result<(), AppError> {
+        use crate::db::connection::with_db;
+        use crate::token_hash::{TOKEN_HASH_PREFIX, verify_token};
+        use std::sync::Arc;
+
+        let db = surrealdb::engine::any::connect("mem://").await?;
+        db.use_ns("tinkr").await?;
+        db.use_db("tinkr").await?;
+
+        with_db(Arc::new(db), async {
+            // a session written before tokens were hashed
+            let db = crate::db_init().await?;
+            db.query("CREATE session:old SET session_token = 'plaintext-token';")
+                .await?
+                .check()?;
+
+            MigrationRunner::tinkr().run().await?;
+
+            let stored: Option<String> = db
+                .query("RETURN session:old.session_token;")
+                .await?
+                .take(0)?;
+            let stored = stored.unwrap_or_default();
+            assert!(stored.starts_with(TOKEN_HASH_PREFIX));
+            assert!(verify_token("plaintext-token", &stored));
+
+            let status = MigrationRunner::tinkr().status().await?;
+            assert!(status.iter().all(|s| s.state == MigrationState::Applied));
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[test]
+    fn test_transaction_query() {
+        let m = Migration::new("app", 1, "a", "DEFINE TABLE x;;\n");
+        let query = m.transaction_query();
+
+        assert!(query.starts_with("BEGIN TRANSACTION;\nDEFINE TABLE x;\n"));
+        assert!(query.ends_with("COMMIT TRANSACTION;"));
+
+        let m = Migration::new("app", 1, "a", "");
+        assert!(
+            m.transaction_query()
+                .starts_with("BEGIN TRANSACTION;\nCREATE $migration_id")
+        );
+    }
+}
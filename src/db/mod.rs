@@ -2,30 +2,81 @@ pub mod connection;
 pub mod settings;
 
 #[cfg(feature = "ssr")]
-pub use connection::{db_init, db_schema, db_seperate_connection};
+pub use connection::{
+    connect, db_init, db_memory, db_schema, db_seperate_connection, db_set, with_db,
+};
 
 #[cfg(feature = "ssr")]
-pub use settings::{Settings, get_settings};
+pub use settings::{DbAuth, Profile, Settings, SettingsBuilder, try_get_settings};
+#[cfg(feature = "ssr")]
+#[allow(deprecated)]
+pub use settings::get_settings;
 
 pub mod storage_trait;
 
+#[cfg(feature = "ssr")]
+pub mod query;
+
+#[cfg(feature = "ssr")]
+pub use query::{Filter, FilterOp, Page, Query, SortDirection};
+
 #[cfg(feature = "ssr")]
 pub use storage_trait::Storage;
 
 #[cfg(feature = "ssr")]
 pub mod cached_surrealdb;
 
+#[cfg(feature = "ssr")]
+pub mod cache_metrics;
+
+#[cfg(feature = "ssr")]
+pub mod migrations;
+
+#[cfg(feature = "ssr")]
+pub mod model;
+
+#[cfg(feature = "ssr")]
+pub use model::TinkrModel;
+
+#[cfg(feature = "ssr")]
+pub mod transaction;
+
+#[cfg(feature = "ssr")]
+pub mod live;
+
+#[cfg(feature = "ssr")]
+pub use transaction::{Statement, Transaction, TransactionResult};
+
+#[cfg(feature = "ssr")]
+pub use migrations::{Migration, MigrationRunner};
+
 #[cfg(feature = "ssr")]
 #[tokio::test]
 async fn test_database() -> Result<(), Box<dyn std::error::Error>> {
-    use crate::db::connection::db_init;
-    use crate::db::settings::get_settings;
+    use crate::db::connection::{db_init, db_memory, with_db};
+
+    let db = db_memory().await?;
+
+    with_db(db.clone(), async {
+        // db_init() returns the scoped connection, with the tinkr schema applied
+        let status = MigrationRunner::tinkr().status().await?;
+        assert!(!status.is_empty());
+        assert!(status.iter().all(|migration| migration.applied_at.is_some()));
+
+        let mut result = db_init().await?.query("RETURN 1 + 1;").await?;
+        let sum: Option<i64> = result.take(0)?;
+        assert_eq!(sum, Some(2));
 
-    let settings = get_settings();
-    println!("Settings: {:?}", settings);
+        Ok::<_, Box<dyn std::error::Error>>(())
+    })
+    .await?;
 
-    let db = db_init().await?;
-    println!("Database connection successful: {:?}", db);
+    // every db_memory() is a separate database
+    let other = db_memory().await?;
+    db.query("CREATE isolation_check SET n = 1;").await?;
+    let mut result = other.query("SELECT VALUE n FROM isolation_check;").await?;
+    let rows: Vec<i64> = result.take(0)?;
+    assert!(rows.is_empty());
 
     Ok(())
 }
@@ -0,0 +1,82 @@
+use crate::db::migrations::Migration;
+
+/// Table metadata of a model, implemented by `#[derive(TinkrModel)]`.
+pub trait TinkrModel {
+    const TABLE_NAME: &'static str;
+
+    /// `DEFINE TABLE/FIELD/INDEX` statements derived from the struct's fields and attributes.
+    const SCHEMA: &'static str;
+
+    /// The schema as a migration for `MigrationRunner`. Statements use `IF NOT EXISTS`, so later
+    /// field changes need a migration of their own.
+    ///
+    /// ### EXAMPLE:
+    /// ```rs
+    ///     MigrationRunner::tinkr()
+    ///         .register(Project::migration("myapp", 1))
+    ///         .run()
+    ///         .await?;
+    /// ```
+    fn migration(namespace: impl Into<String>, version: u32) -> Migration {
+        Migration::new(
+            namespace,
+            version,
+            format!("{}_schema", Self::TABLE_NAME),
+            Self::SCHEMA,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::{Key, KeyCreate};
+    use crate::logs::tracing_layer::LogEvent;
+
+    #[test]
+    fn test_derived_schema() {
+        assert_eq!(<LogEvent as TinkrModel>::TABLE_NAME, "log_events");
+        assert_eq!(
+            LogEvent::SCHEMA,
+            "DEFINE TABLE IF NOT EXISTS log_events SCHEMAFULL;
+DEFINE FIELD IF NOT EXISTS timestamp ON TABLE log_events TYPE datetime;
+DEFINE FIELD IF NOT EXISTS level ON TABLE log_events TYPE string;
+DEFINE FIELD IF NOT EXISTS target ON TABLE log_events TYPE string;
+DEFINE FIELD IF NOT EXISTS message ON TABLE log_events TYPE string;
+DEFINE FIELD IF NOT EXISTS module_path ON TABLE log_events TYPE option<string>;
+DEFINE FIELD IF NOT EXISTS file ON TABLE log_events TYPE option<string>;
+DEFINE FIELD IF NOT EXISTS line ON TABLE log_events TYPE option<int>;
+DEFINE FIELD IF NOT EXISTS fields ON TABLE log_events FLEXIBLE TYPE object;
+DEFINE INDEX IF NOT EXISTS log_events_timestamp ON TABLE log_events COLUMNS timestamp;
+DEFINE INDEX IF NOT EXISTS log_events_level ON TABLE log_events COLUMNS level;
+DEFINE INDEX IF NOT EXISTS log_events_target ON TABLE log_events COLUMNS target;"
+        );
+
+        let migration = Key::migration("app", 1);
+        assert_eq!(migration.name, "key_schema");
+        assert!(Key::SCHEMA.contains(
+            "DEFINE FIELD IF NOT EXISTS key_for ON TABLE key TYPE option<record>;"
+        ));
+        assert!(Key::SCHEMA.contains(
+            "DEFINE FIELD IF NOT EXISTS deleted_at ON TABLE key TYPE option<datetime>;"
+        ));
+
+        // managed and skip_create fields are left out of the Create partial
+        let create = serde_json::to_value(KeyCreate {
+            name: "n".into(),
+            key_for: None,
+            key_public: None,
+            key_private: None,
+            key_apikey: None,
+            key_token: None,
+            description: "d".into(),
+            expires_at: None,
+        })
+        .unwrap();
+        assert_eq!(create.as_object().unwrap().len(), 8);
+        assert_eq!(
+            <Key as crate::StorageAuthed<_, _>>::HISTORY_REDACTED,
+            ["key_private", "key_token"]
+        );
+    }
+}
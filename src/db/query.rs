@@ -0,0 +1,403 @@
+use crate::AppError;
+use serde::Serialize;
+use surrealdb::Value;
+
+/// Comparison used by a single filter condition.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    /// Array field contains the value
+    Contains,
+    /// Field is one of the values in an array
+    In,
+    /// Case insensitive substring match on a string field
+    Like,
+}
+
+/// A typed `WHERE` clause. Values are sent as query parameters, never formatted into the query
+/// string, except by `Query::inline_where_clause` which writes each one as an escaped literal.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     let filter = Filter::eq("chainSlug", "ethereum").or(Filter::eq("chainId", 1));
+/// ```
+#[derive(Debug, Clone)]
+pub enum Filter {
+    Condition {
+        field: String,
+        op: FilterOp,
+        value: Result<Value, String>,
+    },
+    IsNone(String),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn new(field: impl Into<String>, op: FilterOp, value: impl Serialize + 'static) -> Self {
+        Filter::Condition {
+            field: field.into(),
+            op,
+            value: surrealdb::value::to_value(value).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn eq(field: impl Into<String>, value: impl Serialize + 'static) -> Self {
+        Self::new(field, FilterOp::Eq, value)
+    }
+
+    pub fn ne(field: impl Into<String>, value: impl Serialize + 'static) -> Self {
+        Self::new(field, FilterOp::Ne, value)
+    }
+
+    pub fn gt(field: impl Into<String>, value: impl Serialize + 'static) -> Self {
+        Self::new(field, FilterOp::Gt, value)
+    }
+
+    pub fn gte(field: impl Into<String>, value: impl Serialize + 'static) -> Self {
+        Self::new(field, FilterOp::Gte, value)
+    }
+
+    pub fn lt(field: impl Into<String>, value: impl Serialize + 'static) -> Self {
+        Self::new(field, FilterOp::Lt, value)
+    }
+
+    pub fn lte(field: impl Into<String>, value: impl Serialize + 'static) -> Self {
+        Self::new(field, FilterOp::Lte, value)
+    }
+
+    pub fn contains(field: impl Into<String>, value: impl Serialize + 'static) -> Self {
+        Self::new(field, FilterOp::Contains, value)
+    }
+
+    pub fn is_in(field: impl Into<String>, values: impl Serialize + 'static) -> Self {
+        Self::new(field, FilterOp::In, values)
+    }
+
+    pub fn like(field: impl Into<String>, text: impl Into<String>) -> Self {
+        Self::new(field, FilterOp::Like, text.into())
+    }
+
+    pub fn is_none(field: impl Into<String>) -> Self {
+        Filter::IsNone(field.into())
+    }
+
+    pub fn and(self, other: Filter) -> Self {
+        match self {
+            Filter::And(mut filters) => {
+                filters.push(other);
+                Filter::And(filters)
+            }
+            filter => Filter::And(vec![filter, other]),
+        }
+    }
+
+    pub fn or(self, other: Filter) -> Self {
+        match self {
+            Filter::Or(mut filters) => {
+                filters.push(other);
+                Filter::Or(filters)
+            }
+            filter => Filter::Or(vec![filter, other]),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Self {
+        Filter::Not(Box::new(self))
+    }
+
+    /// `bind` turns each value into the text used for it in the query, a parameter or a literal.
+    fn build(&self, bind: &mut dyn FnMut(Value) -> String) -> Result<String, AppError> {
+        match self {
+            Filter::Condition { field, op, value } => {
+                check_field(field)?;
+
+                let value = value.clone().map_err(|e| {
+                    AppError::DeserializationError(format!("Invalid value for {field}: {e}"))
+                })?;
+
+                let value = bind(value);
+
+                Ok(match op {
+                    FilterOp::Eq => format!("{field} = {value}"),
+                    FilterOp::Ne => format!("{field} != {value}"),
+                    FilterOp::Gt => format!("{field} > {value}"),
+                    FilterOp::Gte => format!("{field} >= {value}"),
+                    FilterOp::Lt => format!("{field} < {value}"),
+                    FilterOp::Lte => format!("{field} <= {value}"),
+                    FilterOp::Contains => format!("{field} CONTAINS {value}"),
+                    FilterOp::In => format!("{field} IN {value}"),
+                    FilterOp::Like => format!(
+                        "string::contains(string::lowercase(<string> {field}), string::lowercase({value}))"
+                    ),
+                })
+            }
+            Filter::IsNone(field) => {
+                check_field(field)?;
+                Ok(format!("{field} = NONE"))
+            }
+            Filter::And(filters) => join(filters, " AND ", "true", bind),
+            Filter::Or(filters) => join(filters, " OR ", "false", bind),
+            Filter::Not(filter) => Ok(format!("!({})", filter.build(bind)?)),
+        }
+    }
+}
+
+/// `empty` is what no filters mean: an empty `And` matches everything, an empty `Or` nothing.
+fn join(
+    filters: &[Filter],
+    separator: &str,
+    empty: &str,
+    bind: &mut dyn FnMut(Value) -> String,
+) -> Result<String, AppError> {
+    if filters.is_empty() {
+        return Ok(empty.to_string());
+    }
+
+    let parts = filters
+        .iter()
+        .map(|f| f.build(bind))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(format!("({})", parts.join(separator)))
+}
+
+/// Field names can't be parameterised, so only plain (optionally dotted) identifiers are accepted.
+fn check_field(field: &str) -> Result<(), AppError> {
+    let valid = !field.is_empty()
+        && field.split('.').all(|part| {
+            let mut chars = part.chars();
+            matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::ErrorReason(format!(
+            "Invalid field name: {field}"
+        )))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+/// Filter, sort and paginate builder used by `Storage::find`.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     let chains = Chain::find(
+///         Query::new()
+///             .filter(Filter::like("name", "ether"))
+///             .order_by("chainId", SortDirection::Asc)
+///             .page(0, 20),
+///     )
+///     .await?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    filter: Option<Filter>,
+    sort: Vec<(String, SortDirection)>,
+    limit: Option<usize>,
+    start: Option<usize>,
+}
+
+/// The SurrealQL text and the parameters to bind for a built `Query`.
+#[derive(Debug, Clone)]
+pub struct BuiltQuery {
+    pub query: String,
+    pub params: Vec<(String, Value)>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a filter, combined with `AND` when one is already set.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = Some(match self.filter {
+            Some(existing) => existing.and(filter),
+            None => filter,
+        });
+        self
+    }
+
+    pub fn order_by(mut self, field: impl Into<String>, direction: SortDirection) -> Self {
+        self.sort.push((field.into(), direction));
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn start(mut self, start: usize) -> Self {
+        self.start = Some(start);
+        self
+    }
+
+    /// Zero based page of `per_page` items.
+    pub fn page(self, page: usize, per_page: usize) -> Self {
+        self.limit(per_page).start(page * per_page)
+    }
+
+    pub fn where_clause(&self, params: &mut Vec<(String, Value)>) -> Result<String, AppError> {
+        match &self.filter {
+            Some(filter) => {
+                let clause = filter.build(&mut |value| {
+                    let param = format!("p{}", params.len());
+                    params.push((param.clone(), value));
+                    format!("${param}")
+                })?;
+                Ok(format!(" WHERE {clause}"))
+            }
+            None => Ok(String::new()),
+        }
+    }
+
+    /// The `WHERE` clause with every value written as a SurrealQL literal, for `LIVE SELECT`
+    /// whose bound parameters are not applied when notifications are matched.
+    pub fn inline_where_clause(&self) -> Result<String, AppError> {
+        match &self.filter {
+            Some(filter) => Ok(format!(
+                " WHERE {}",
+                filter.build(&mut |value| value.to_string())?
+            )),
+            None => Ok(String::new()),
+        }
+    }
+
+    pub fn build_select(&self, table: &str) -> Result<BuiltQuery, AppError> {
+        let mut params = Vec::new();
+        let mut query = format!("SELECT * FROM {table}{}", self.where_clause(&mut params)?);
+
+        if !self.sort.is_empty() {
+            let sort = self
+                .sort
+                .iter()
+                .map(|(field, direction)| {
+                    check_field(field)?;
+                    Ok(match direction {
+                        SortDirection::Asc => format!("{field} ASC"),
+                        SortDirection::Desc => format!("{field} DESC"),
+                    })
+                })
+                .collect::<Result<Vec<_>, AppError>>()?;
+            query.push_str(&format!(" ORDER BY {}", sort.join(", ")));
+        }
+
+        if let Some(limit) = self.limit {
+            query.push_str(&format!(" LIMIT {limit}"));
+        }
+
+        if let Some(start) = self.start {
+            query.push_str(&format!(" START {start}"));
+        }
+
+        query.push(';');
+
+        Ok(BuiltQuery { query, params })
+    }
+
+    pub fn build_count(&self, table: &str) -> Result<BuiltQuery, AppError> {
+        let mut params = Vec::new();
+        let query = format!(
+            "SELECT count() AS count FROM {table}{} GROUP ALL;",
+            self.where_clause(&mut params)?
+        );
+
+        Ok(BuiltQuery { query, params })
+    }
+}
+
+/// A page of results together with the total number of matching records.
+#[derive(Debug, Clone, Serialize, serde::Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_select() {
+        let built = Query::new()
+            .filter(Filter::eq("chainSlug", "ethereum").or(Filter::gt("chainId", 10)))
+            .filter(Filter::is_none("parent").not())
+            .order_by("name", SortDirection::Desc)
+            .page(2, 20)
+            .build_select("chain")
+            .unwrap();
+
+        assert_eq!(
+            built.query,
+            "SELECT * FROM chain WHERE ((chainSlug = $p0 OR chainId > $p1) AND !(parent = NONE)) ORDER BY name DESC LIMIT 20 START 40;"
+        );
+        assert_eq!(built.params.len(), 2);
+        assert_eq!(built.params[0].0, "p0");
+    }
+
+    #[test]
+    fn test_build_count() {
+        let built = Query::new()
+            .filter(Filter::like("name", "Eth"))
+            .build_count("chain")
+            .unwrap();
+
+        assert_eq!(
+            built.query,
+            "SELECT count() AS count FROM chain WHERE string::contains(string::lowercase(<string> name), string::lowercase($p0)) GROUP ALL;"
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_field() {
+        let injected = Query::new()
+            .filter(Filter::eq("name = 1; DELETE chain; --", 1))
+            .build_select("chain");
+        assert!(injected.is_err());
+
+        let sorted = Query::new()
+            .order_by("name; DELETE chain", SortDirection::Asc)
+            .build_select("chain");
+        assert!(sorted.is_err());
+
+        assert!(check_field("nativeCurrency.symbol").is_ok());
+        assert!(check_field("1abc").is_err());
+    }
+
+    #[test]
+    fn test_empty_filters() {
+        let mut bind = |value: Value| value.to_string();
+
+        assert_eq!(Filter::And(Vec::new()).build(&mut bind).unwrap(), "true");
+        // e.g. `search` across no fields matches nothing
+        assert_eq!(Filter::Or(Vec::new()).build(&mut bind).unwrap(), "false");
+    }
+
+    #[test]
+    fn test_inline_where_clause() {
+        let clause = Query::new()
+            .filter(Filter::eq("owner", "o'wner $p1").and(Filter::eq("kind", "$p0")))
+            .inline_where_clause()
+            .unwrap();
+
+        // each value is written once, as its own escaped literal
+        assert_eq!(clause, r#" WHERE (owner = "o'wner $p1" AND kind = '$p0')"#);
+    }
+}
@@ -2,16 +2,436 @@
 use crate::AppError;
 #[cfg(feature = "ssr")]
 use dotenvy::dotenv;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
 
-#[allow(non_snake_case)]
-#[derive(Debug)]
+/// Config file read when no other file is given, relative to the working directory.
+pub const DEFAULT_CONFIG_FILE: &str = "tinkr.toml";
+
+/// Which `[profile.<name>]` section of the config file applies on top of the base settings.
+/// A `test` profile on `mem://` needs the `embedded-mem` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    Dev,
+    Test,
+    Prod,
+}
+
+impl Profile {
+    /// `TINKR_PROFILE` if set, otherwise `Dev` for debug builds and `Prod` for release builds.
+    pub fn from_env() -> Result<Self, AppError> {
+        match env::var("TINKR_PROFILE") {
+            Ok(profile) => profile.parse(),
+            Err(_) if cfg!(debug_assertions) => Ok(Profile::Dev),
+            Err(_) => Ok(Profile::Prod),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Profile::Dev => "dev",
+            Profile::Test => "test",
+            Profile::Prod => "prod",
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dev" | "development" => Ok(Profile::Dev),
+            "test" => Ok(Profile::Test),
+            "prod" | "production" => Ok(Profile::Prod),
+            other => Err(AppError::Config(format!("Unknown profile: {other}"))),
+        }
+    }
+}
+
+/// How the connection signs in to SurrealDB.
+#[derive(Clone, PartialEq)]
+pub enum DbAuth {
+    /// No sign in, for embedded engines (`embedded-mem`, `embedded-rocksdb` features)
+    None,
+    Root { username: String, password: String },
+    /// A user defined with `DEFINE USER ... ON NAMESPACE`
+    Namespace { username: String, password: String },
+    /// A user defined with `DEFINE USER ... ON DATABASE`
+    Database { username: String, password: String },
+    /// A JWT issued for the connection
+    Token(String),
+}
+
+impl std::fmt::Debug for DbAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbAuth::None => write!(f, "None"),
+            DbAuth::Root { username, .. } => write!(f, "Root({username})"),
+            DbAuth::Namespace { username, .. } => write!(f, "Namespace({username})"),
+            DbAuth::Database { username, .. } => write!(f, "Database({username})"),
+            DbAuth::Token(_) => write!(f, "Token(..)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AuthKind {
+    None,
+    Root,
+    Namespace,
+    Database,
+    Token,
+}
+
+impl FromStr for AuthKind {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(AuthKind::None),
+            "root" => Ok(AuthKind::Root),
+            "namespace" | "ns" => Ok(AuthKind::Namespace),
+            "database" | "db" => Ok(AuthKind::Database),
+            "token" => Ok(AuthKind::Token),
+            other => Err(AppError::Config(format!("Unknown database auth: {other}"))),
+        }
+    }
+}
+
+/// One layer of database settings, every value optional so layers can be merged.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DatabaseLayer {
+    host: Option<String>,
+    namespace: Option<String>,
+    database: Option<String>,
+    auth: Option<AuthKind>,
+    username: Option<String>,
+    password: Option<String>,
+    token: Option<String>,
+}
+
+impl DatabaseLayer {
+    fn merge(&mut self, other: DatabaseLayer) {
+        self.host = other.host.or(self.host.take());
+        self.namespace = other.namespace.or(self.namespace.take());
+        self.database = other.database.or(self.database.take());
+        self.auth = other.auth.or(self.auth.take());
+        self.username = other.username.or(self.username.take());
+        self.password = other.password.or(self.password.take());
+        self.token = other.token.or(self.token.take());
+    }
+
+    fn from_env() -> Result<Self, AppError> {
+        let var = |key: &str| env::var(key).ok().filter(|v| !v.is_empty());
+
+        Ok(DatabaseLayer {
+            // SURREALDB_HOST_NEW is the name older apps used
+            host: var("SURREALDB_HOST").or(var("SURREALDB_HOST_NEW")),
+            namespace: var("SURREALDB_NS"),
+            database: var("SURREALDB_DB"),
+            auth: var("SURREALDB_AUTH").map(|a| a.parse()).transpose()?,
+            username: var("SURREALDB_USER"),
+            password: var("SURREALDB_PASS"),
+            token: var("SURREALDB_TOKEN"),
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    database: DatabaseLayer,
+    #[serde(default)]
+    profile: HashMap<String, ProfileSection>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileSection {
+    #[serde(default)]
+    database: DatabaseLayer,
+}
+
+#[derive(Debug, Clone)]
 pub struct Settings {
+    pub profile: Profile,
     pub surrealdb_host: String,
-    pub surrealdb_db: String,
     pub surrealdb_ns: String,
-    pub surrealdb_user: String,
-    pub surrealdb_pass: String,
+    pub surrealdb_db: String,
+    pub auth: DbAuth,
+}
+
+impl Settings {
+    /// ### EXAMPLE:
+    /// ```rs
+    ///     let settings = Settings::builder()
+    ///         .file("config/tinkr.toml")
+    ///         .profile(Profile::Test)
+    ///         .build()?;
+    /// ```
+    pub fn builder() -> SettingsBuilder {
+        SettingsBuilder::default()
+    }
+
+    /// True for engines running inside the process (`mem://`, `rocksdb://`, ...) rather than a
+    /// SurrealDB server. They need the `embedded-mem` or `embedded-rocksdb` cargo feature.
+    pub fn is_embedded(&self) -> bool {
+        is_embedded(&self.surrealdb_host)
+    }
+
+    /// Username of `auth`, empty when it has none.
+    #[deprecated(note = "match on `Settings::auth` instead")]
+    pub fn surrealdb_user(&self) -> &str {
+        match &self.auth {
+            DbAuth::Root { username, .. }
+            | DbAuth::Namespace { username, .. }
+            | DbAuth::Database { username, .. } => username,
+            DbAuth::None | DbAuth::Token(_) => "",
+        }
+    }
+
+    /// Password of `auth`, empty when it has none.
+    #[deprecated(note = "match on `Settings::auth` instead")]
+    pub fn surrealdb_pass(&self) -> &str {
+        match &self.auth {
+            DbAuth::Root { password, .. }
+            | DbAuth::Namespace { password, .. }
+            | DbAuth::Database { password, .. } => password,
+            DbAuth::None | DbAuth::Token(_) => "",
+        }
+    }
+}
+
+fn is_embedded(host: &str) -> bool {
+    !["ws://", "wss://", "http://", "https://"]
+        .iter()
+        .any(|scheme| host.starts_with(scheme))
+}
+
+/// Builds `Settings` from layers, later ones winning: built-in defaults, the TOML config file,
+/// its `[profile.<name>]` section, `SURREALDB_*` environment variables and finally values set on
+/// the builder.
+///
+/// ```toml
+/// [database]
+/// host = "ws://localhost:8000"
+/// namespace = "myapp"
+/// database = "myapp"
+///
+/// [profile.test.database]
+/// host = "mem://"
+///
+/// [profile.prod.database]
+/// host = "wss://db.example.com"
+/// auth = "database"          # none | root | namespace | database | token
+/// username = "myapp"
+/// ```
+#[derive(Debug, Clone)]
+pub struct SettingsBuilder {
+    file: Option<PathBuf>,
+    profile: Option<Profile>,
+    env: bool,
+    overrides: DatabaseLayer,
+}
+
+impl Default for SettingsBuilder {
+    fn default() -> Self {
+        Self {
+            file: None,
+            profile: None,
+            env: true,
+            overrides: DatabaseLayer::default(),
+        }
+    }
+}
+
+impl SettingsBuilder {
+    /// Config file to read instead of `TINKR_CONFIG` or `tinkr.toml`. Unlike those it must exist.
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// Profile to apply instead of `Profile::from_env()`.
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Ignore the `TINKR_*` and `SURREALDB_*` environment variables, e.g. in tests.
+    pub fn without_env(mut self) -> Self {
+        self.env = false;
+        self
+    }
+
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.overrides.host = Some(host.into());
+        self
+    }
+
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.overrides.namespace = Some(namespace.into());
+        self
+    }
+
+    pub fn database(mut self, database: impl Into<String>) -> Self {
+        self.overrides.database = Some(database.into());
+        self
+    }
+
+    pub fn auth(mut self, auth: DbAuth) -> Self {
+        let (kind, username, password, token) = match auth {
+            DbAuth::None => (AuthKind::None, None, None, None),
+            DbAuth::Root { username, password } => {
+                (AuthKind::Root, Some(username), Some(password), None)
+            }
+            DbAuth::Namespace { username, password } => {
+                (AuthKind::Namespace, Some(username), Some(password), None)
+            }
+            DbAuth::Database { username, password } => {
+                (AuthKind::Database, Some(username), Some(password), None)
+            }
+            DbAuth::Token(token) => (AuthKind::Token, None, None, Some(token)),
+        };
+
+        self.overrides.auth = Some(kind);
+        self.overrides.username = username;
+        self.overrides.password = password;
+        self.overrides.token = token;
+        self
+    }
+
+    fn read_file(&self) -> Result<ConfigFile, AppError> {
+        let env_file = env::var("TINKR_CONFIG").ok().filter(|_| self.env);
+        let (path, required) = match (&self.file, env_file) {
+            (Some(path), _) => (path.clone(), true),
+            (None, Some(path)) => (PathBuf::from(path), true),
+            (None, None) => (PathBuf::from(DEFAULT_CONFIG_FILE), false),
+        };
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(ConfigFile::default());
+            }
+            Err(e) => {
+                return Err(AppError::Config(format!(
+                    "Could not read {}: {e}",
+                    path.display()
+                )));
+            }
+        };
+
+        toml::from_str(&contents)
+            .map_err(|e| AppError::Config(format!("Invalid {}: {e}", path.display())))
+    }
+
+    pub fn build(self) -> Result<Settings, AppError> {
+        #[cfg(feature = "ssr")]
+        if self.env {
+            dotenv().ok();
+        }
+
+        let profile = match self.profile {
+            Some(profile) => profile,
+            None if self.env => Profile::from_env()?,
+            None if cfg!(debug_assertions) => Profile::Dev,
+            None => Profile::Prod,
+        };
+
+        let mut file = self.read_file()?;
+        let mut layer = std::mem::take(&mut file.database);
+        if let Some(section) = file.profile.remove(profile.name()) {
+            layer.merge(section.database);
+        }
+        if self.env {
+            layer.merge(DatabaseLayer::from_env()?);
+        }
+        layer.merge(self.overrides);
+
+        let settings = resolve(profile, layer)?;
+        check_credentials(&settings, !cfg!(debug_assertions))?;
+
+        Ok(settings)
+    }
+}
+
+fn resolve(profile: Profile, layer: DatabaseLayer) -> Result<Settings, AppError> {
+    let host = layer.host.unwrap_or("ws://localhost:8000".to_string());
+
+    let kind = match layer.auth {
+        Some(kind) => kind,
+        None if layer.token.is_some() => AuthKind::Token,
+        None if is_embedded(&host) => AuthKind::None,
+        None => AuthKind::Root,
+    };
+
+    // root/root only when nothing is configured, `check_credentials` refuses it in release builds
+    let credentials = || match (layer.username.clone(), layer.password.clone()) {
+        (Some(username), Some(password)) => Ok((username, password)),
+        (None, None) if layer.auth.is_none() => Ok(("root".to_string(), "root".to_string())),
+        _ => Err(AppError::Config(
+            "Database auth needs both SURREALDB_USER and SURREALDB_PASS".into(),
+        )),
+    };
+
+    let auth = match kind {
+        AuthKind::None => DbAuth::None,
+        AuthKind::Root => {
+            let (username, password) = credentials()?;
+            DbAuth::Root { username, password }
+        }
+        AuthKind::Namespace => {
+            let (username, password) = credentials()?;
+            DbAuth::Namespace { username, password }
+        }
+        AuthKind::Database => {
+            let (username, password) = credentials()?;
+            DbAuth::Database { username, password }
+        }
+        AuthKind::Token => DbAuth::Token(layer.token.clone().ok_or(AppError::Config(
+            "Database auth is token but no token is set".into(),
+        ))?),
+    };
+
+    Ok(Settings {
+        profile,
+        surrealdb_host: host,
+        surrealdb_ns: layer.namespace.unwrap_or("default".to_string()),
+        surrealdb_db: layer.database.unwrap_or("default".to_string()),
+        auth,
+    })
+}
+
+/// Release builds must not talk to a server with the built-in `root` password or an empty one.
+fn check_credentials(settings: &Settings, release: bool) -> Result<(), AppError> {
+    let default_credentials = match &settings.auth {
+        DbAuth::Root { password, .. }
+        | DbAuth::Namespace { password, .. }
+        | DbAuth::Database { password, .. } => password == "root" || password.is_empty(),
+        DbAuth::None | DbAuth::Token(_) => false,
+    };
+
+    if default_credentials && !settings.is_embedded() {
+        if release {
+            return Err(AppError::Config(
+                "Refusing to connect with a default or empty database password, set SURREALDB_USER and SURREALDB_PASS".into(),
+            ));
+        }
+
+        #[cfg(feature = "ssr")]
+        tracing::warn!("Connecting to SurrealDB with a default or empty password");
+    }
+
+    Ok(())
 }
 
 #[cfg(feature = "ssr")]
@@ -21,14 +441,144 @@ pub fn get_env(key: &str) -> Result<String, AppError> {
 }
 
 #[cfg(feature = "ssr")]
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// Settings from `Settings::builder()` with the defaults, loaded once per process.
+#[cfg(feature = "ssr")]
+pub fn try_get_settings() -> Result<Settings, AppError> {
+    if let Some(settings) = SETTINGS.get() {
+        return Ok(settings.clone());
+    }
+
+    let settings = Settings::builder().build()?;
+    Ok(SETTINGS.get_or_init(|| settings).clone())
+}
+
+/// Panics when the settings are invalid, e.g. default credentials in a release build.
+#[cfg(feature = "ssr")]
+#[deprecated(note = "use `try_get_settings`, which returns configuration errors")]
 pub fn get_settings() -> Settings {
-    dotenv().ok();
-
-    Settings {
-        surrealdb_host: get_env("SURREALDB_HOST_NEW").unwrap_or("ws://localhost:8000".to_string()),
-        surrealdb_db: get_env("SURREALDB_DB").unwrap_or("default".to_string()),
-        surrealdb_ns: get_env("SURREALDB_NS").unwrap_or("default".to_string()),
-        surrealdb_user: get_env("SURREALDB_USER").unwrap_or("root".to_string()),
-        surrealdb_pass: get_env("SURREALDB_PASS").unwrap_or("root".to_string()),
+    try_get_settings().unwrap_or_else(|e| panic!("{e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("tinkr_settings_{name}.toml"));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_settings_layers() {
+        let path = write_config(
+            "layers",
+            r#"
+            [database]
+            host = "ws://db:8000"
+            namespace = "app"
+            database = "app"
+            auth = "database"
+            username = "app"
+            password = "secret"
+
+            [profile.test.database]
+            host = "mem://"
+            auth = "none"
+            "#,
+        );
+
+        let dev = Settings::builder()
+            .file(&path)
+            .profile(Profile::Dev)
+            .without_env()
+            .build()
+            .unwrap();
+        assert_eq!(dev.surrealdb_host, "ws://db:8000");
+        assert_eq!(dev.surrealdb_ns, "app");
+        assert_eq!(
+            dev.auth,
+            DbAuth::Database {
+                username: "app".into(),
+                password: "secret".into()
+            }
+        );
+
+        let test = Settings::builder()
+            .file(&path)
+            .profile(Profile::Test)
+            .without_env()
+            .database("other")
+            .build()
+            .unwrap();
+        assert!(test.is_embedded());
+        assert_eq!(test.auth, DbAuth::None);
+        assert_eq!(test.surrealdb_db, "other");
+    }
+
+    #[test]
+    fn test_settings_rejects_default_credentials_in_release() {
+        let settings = resolve(Profile::Prod, DatabaseLayer::default()).unwrap();
+        assert_eq!(
+            settings.auth,
+            DbAuth::Root {
+                username: "root".into(),
+                password: "root".into()
+            }
+        );
+        assert!(check_credentials(&settings, true).is_err());
+        assert!(check_credentials(&settings, false).is_ok());
+
+        let admin = resolve(
+            Profile::Prod,
+            DatabaseLayer {
+                username: Some("admin".into()),
+                password: Some("root".into()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(check_credentials(&admin, true).is_err());
+
+        let embedded = resolve(
+            Profile::Prod,
+            DatabaseLayer {
+                host: Some("rocksdb://data".into()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(embedded.auth, DbAuth::None);
+        assert!(check_credentials(&embedded, true).is_ok());
+    }
+
+    #[test]
+    fn test_settings_invalid_file() {
+        let path = write_config("invalid", "[database]\nhots = \"ws://db\"\n");
+        let result = Settings::builder().file(&path).without_env().build();
+        assert!(matches!(result, Err(AppError::Config(_))));
+    }
+
+    #[test]
+    fn test_settings_incomplete_credentials() {
+        let username_only = resolve(
+            Profile::Dev,
+            DatabaseLayer {
+                username: Some("app".into()),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(username_only, Err(AppError::Config(_))));
+
+        let no_credentials = resolve(
+            Profile::Dev,
+            DatabaseLayer {
+                auth: Some(AuthKind::Database),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(no_credentials, Err(AppError::Config(_))));
     }
 }
@@ -1,7 +1,13 @@
 #[cfg(feature = "ssr")]
 use crate::AppError;
 #[cfg(feature = "ssr")]
+use crate::db::live::{LiveStream, watch_table};
+#[cfg(feature = "ssr")]
+use crate::db::query::{Filter, Page, Query};
+#[cfg(feature = "ssr")]
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "ssr")]
+use surrealdb::RecordId;
 
 #[cfg(feature = "ssr")]
 #[allow(async_fn_in_trait)]
@@ -71,48 +77,117 @@ where
         Ok(results)
     }
 
-    /*
-       /// Read/get an item by key
-       async fn from_id(&self, id: &RecordId) -> Result<Option<WithId>, E>;
+    async fn create_many(content: Vec<NoId>) -> Result<Vec<WithId>, AppError> {
+        let db = crate::db_init().await?;
+
+        let items: Vec<WithId> = db.insert(Self::TABLE_NAME).content(content).await?;
+
+        Ok(items)
+    }
+
+    /// Read/get an item by id, `None` if it does not exist or belongs to another table.
+    async fn from_id(id: RecordId) -> Result<Option<WithId>, AppError> {
+        if id.table() != Self::TABLE_NAME {
+            return Ok(None);
+        }
+
+        let db = crate::db_init().await?;
+
+        let item: Option<WithId> = db.select(id).await?;
+
+        Ok(item)
+    }
+
+    /// Delete an item by id, returns `true` if it existed.
+    async fn delete(id: RecordId) -> Result<bool, AppError> {
+        if id.table() != Self::TABLE_NAME {
+            return Ok(false);
+        }
+
+        let db = crate::db_init().await?;
+
+        let deleted: Option<WithId> = db.delete(id).await?;
+
+        Ok(deleted.is_some())
+    }
+
+    async fn exists(id: RecordId) -> Result<bool, AppError> {
+        Ok(Self::from_id(id).await?.is_some())
+    }
+
+    async fn count() -> Result<usize, AppError> {
+        Self::count_where(Query::new()).await
+    }
+
+    async fn count_where(query: Query) -> Result<usize, AppError> {
+        #[derive(Deserialize)]
+        struct Count {
+            count: usize,
+        }
+
+        let db = crate::db_init().await?;
+
+        let built = query.build_count(Self::TABLE_NAME)?;
+        let mut dbq = db.query(built.query);
+        for (name, value) in built.params {
+            dbq = dbq.bind((name, value));
+        }
+
+        let count: Option<Count> = dbq.await?.take(0)?;
+
+        Ok(count.map(|c| c.count).unwrap_or(0))
+    }
+
+    /// Select items using a filter/sort/paginate `Query`.
+    async fn find(query: Query) -> Result<Vec<WithId>, AppError> {
+        let db = crate::db_init().await?;
+
+        let built = query.build_select(Self::TABLE_NAME)?;
+        let mut dbq = db.query(built.query);
+        for (name, value) in built.params {
+            dbq = dbq.bind((name, value));
+        }
 
-       /// Update an existing item
-       async fn update(&mut self, key: &NoId, value: WithId) -> Result<bool, E>;
+        let items: Vec<WithId> = dbq.await?.take(0)?;
+
+        Ok(items)
+    }
 
-       /// Delete an item by key
-       async fn delete(&mut self, key: &NoId) -> Result<bool, E>;
+    async fn find_one(query: Query) -> Result<Option<WithId>, AppError> {
+        Ok(Self::find(query.limit(1)).await?.into_iter().next())
+    }
 
-       /// Check if a key exists
-       async fn exists(&self, key: &NoId) -> Result<bool, E>;
-       // fn exists(&self, key: &NoId) -> impl std::future::Future<Output = Result<bool, E>> + Send;
+    /// Returns the requested page along with the total number of matching items.
+    async fn find_page(query: Query) -> Result<Page<WithId>, AppError> {
+        let total = Self::count_where(query.clone()).await?;
+        let items = Self::find(query).await?;
 
-       /// Get all keys
-       async fn keys(&self) -> Result<Vec<NoId>, E>;
+        Ok(Page { items, total })
+    }
 
-       /// Get all values
-       async fn values(&self) -> Result<Vec<WithId>, E>;
+    /// Case insensitive text search across `fields`, nothing matches when `fields` is empty.
+    async fn search(fields: &[&str], text: &str) -> Result<Vec<WithId>, AppError> {
+        let filter = fields.iter().fold(Filter::Or(Vec::new()), |filter, field| {
+            filter.or(Filter::like(*field, text))
+        });
 
-       /// Get all key-value pairs
-       /// todo add filtering, sorting and pagination
-       async fn entries(&self) -> Result<Vec<WithId>, E>;
+        Self::find(Query::new().filter(filter)).await
+    }
 
-       /// Clear all entries
-       async fn clear(&mut self) -> Result<(), E>;
+    /// Streams creates, updates and deletes of items matching `filter` as they happen.
+    async fn watch(filter: Option<Filter>) -> Result<LiveStream<WithId>, AppError>
+    where
+        WithId: Unpin,
+    {
+        watch_table(Self::TABLE_NAME, filter).await
+    }
 
-       /// Get the count of items
-       async fn count(&self) -> Result<usize, E>;
+    /// Delete every item in the table.
+    async fn clear() -> Result<(), AppError> {
+        let db = crate::db_init().await?;
 
-       /// Batch create/insert multiple items
-       /// Perhaps better to implement this without many create calls.
-       async fn create_many(&mut self, items: Vec<NoId>) -> Result<Vec<WithId>, E> {
-           let mut results = Vec::new();
-           for value in items {
-               let result = self.create(value).await?;
-               results.push(result);
-           }
-           Ok(results)
-       }
+        let _: Vec<WithId> = db.delete(Self::TABLE_NAME).await?;
 
-       /// Search with a predicate function
-       async fn search<F>(&self, text: &str) -> Result<Vec<WithId>, E>;
-    */
+        Ok(())
+    }
 }
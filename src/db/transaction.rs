@@ -0,0 +1,176 @@
+use crate::AppError;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+use surrealdb::Value;
+use surrealdb::opt::QueryResult;
+
+/// Handle to the result of a statement added to a `Transaction`. `T` is the type it is taken as,
+/// e.g. `Option<Organization>` for a `CREATE` or `Vec<TeamMember>` for a `SELECT`.
+#[derive(Debug)]
+pub struct Statement<T> {
+    index: usize,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Statement<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Statement<T> {}
+
+/// Multi-statement unit of work sent as a single `BEGIN TRANSACTION; ... COMMIT TRANSACTION;` query.
+/// If any statement fails the whole transaction is cancelled and nothing is written.
+///
+/// Statements run in order and can refer to earlier results through `LET` parameters. Select a
+/// parameter with a bare `$name` statement rather than `RETURN`, which discards earlier results.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     let mut tx = Transaction::new();
+///     tx.bind("org_data", org_data);
+///     tx.statement::<Value>("LET $org = (CREATE ONLY organization CONTENT $org_data)");
+///     tx.statement::<Value>("CREATE organization_member SET organization_id = $org.id");
+///     let org = tx.statement::<Option<Organization>>("$org");
+///     let mut result = tx.commit().await?;
+///     let org = result.take(org)?;
+/// ```
+#[derive(Debug, Default)]
+pub struct Transaction {
+    statements: Vec<String>,
+    params: Vec<(String, Result<Value, String>)>,
+    guards: Vec<(usize, AppError)>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds a parameter available to every statement as `$name`.
+    pub fn bind(&mut self, name: impl Into<String>, value: impl Serialize + 'static) -> &mut Self {
+        self.params.push((
+            name.into(),
+            surrealdb::value::to_value(value).map_err(|e| e.to_string()),
+        ));
+        self
+    }
+
+    /// Adds a single SurrealQL statement and returns a handle to its result.
+    pub fn statement<T>(&mut self, statement: impl Into<String>) -> Statement<T> {
+        let statement = statement.into();
+        let statement = statement.trim().trim_end_matches(';');
+
+        self.statements.push(format!("{statement};"));
+
+        Statement {
+            index: self.statements.len() - 1,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Cancels the transaction with `error` unless `condition` (a SurrealQL expression) is true.
+    /// Use it to re-check state read before the transaction, e.g. that an invitation is unused.
+    pub fn guard(&mut self, condition: impl AsRef<str>, error: AppError) -> &mut Self {
+        let index = self.statements.len();
+        self.statements.push(format!(
+            "IF !({}) {{ THROW \"guard {index} failed\" }};",
+            condition.as_ref()
+        ));
+        self.guards.push((index, error));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+
+    pub fn to_query(&self) -> String {
+        format!(
+            "BEGIN TRANSACTION;\n{}\nCOMMIT TRANSACTION;",
+            self.statements.join("\n")
+        )
+    }
+
+    /// Discards the queued statements. Nothing is sent to the database before `commit`.
+    pub fn cancel(self) {
+        tracing::trace!(statements = self.statements.len(), "Transaction cancelled");
+    }
+
+    pub async fn commit(mut self) -> Result<TransactionResult, AppError> {
+        let db = crate::db_init().await?;
+
+        let mut query = db.query(self.to_query());
+        for (name, value) in std::mem::take(&mut self.params) {
+            let value = value.map_err(|e| {
+                AppError::DeserializationError(format!("Invalid transaction parameter {name}: {e}"))
+            })?;
+            query = query.bind((name, value));
+        }
+
+        let mut response = query.await?;
+
+        let mut errors: Vec<(usize, surrealdb::Error)> =
+            response.take_errors().into_iter().collect();
+        errors.sort_by_key(|(index, _)| *index);
+
+        // every statement other than the one that failed reports the cancelled transaction
+        let failure = errors
+            .iter()
+            .position(|(_, e)| !e.to_string().contains("failed transaction"))
+            .unwrap_or(0);
+
+        if !errors.is_empty() {
+            let (index, error) = errors.swap_remove(failure);
+
+            if let Some(position) = self.guards.iter().position(|(i, _)| *i == index) {
+                return Err(self.guards.swap_remove(position).1);
+            }
+
+            return Err(AppError::DatabaseError(format!(
+                "Transaction failed at statement {index}: {error}"
+            )));
+        }
+
+        Ok(TransactionResult { response })
+    }
+}
+
+/// Results of a committed `Transaction`.
+#[derive(Debug)]
+pub struct TransactionResult {
+    response: surrealdb::Response,
+}
+
+impl TransactionResult {
+    pub fn take<T>(&mut self, statement: Statement<T>) -> Result<T, AppError>
+    where
+        T: DeserializeOwned,
+        usize: QueryResult<T>,
+    {
+        Ok(self.response.take(statement.index)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transaction_query() {
+        let mut tx = Transaction::new();
+        tx.bind("data", "value");
+        let first = tx.statement::<Option<String>>("LET $a = (CREATE ONLY thing CONTENT $data);;");
+        tx.guard("$a.id != NONE", AppError::NotFound("missing".into()));
+        let last = tx.statement::<Vec<String>>("SELECT * FROM thing");
+
+        assert_eq!(first.index, 0);
+        assert_eq!(last.index, 2);
+        assert_eq!(
+            tx.to_query(),
+            "BEGIN TRANSACTION;\nLET $a = (CREATE ONLY thing CONTENT $data);\nIF !($a.id != NONE) { THROW \"guard 1 failed\" };\nSELECT * FROM thing;\nCOMMIT TRANSACTION;"
+        );
+    }
+}
@@ -10,7 +10,8 @@ use crate::components::{
     Button, Modal, ModalSize,
     button::{BtnColor, BtnVariant, ButtonIcon},
 };
-use partial_struct::Partial;
+use crate::TinkrModel;
+use crate::date_utils::FormatDatetime;
 use phosphor_leptos::KEY;
 use serde::{Deserialize, Serialize};
 
@@ -20,41 +21,69 @@ use surrealdb::{Datetime, RecordId};
 #[cfg(feature = "ssr")]
 use crate::user::AdapterUser;
 
+#[cfg(feature = "ssr")]
+use crate::db::live::LiveStream;
+
+use crate::live::{LIVE_KEYS_URL, use_live_refetch};
 use leptos::prelude::*;
 
 #[cfg(feature = "ssr")]
 use chrono::Utc;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Partial)]
-#[partial(
-    "KeyCreate",
-    derive(Debug, Serialize, Deserialize, Clone),
-    omit(id, created_by_user_id, created_at, updated_at, last_used)
+#[derive(Debug, Clone, Serialize, Deserialize, TinkrModel)]
+#[tinkr(
+    table = "key",
+    create = "KeyCreate",
+    authed,
+    soft_delete,
+    cfg(feature = "ssr")
 )]
-
 pub struct Key {
     pub id: RecordId,
     pub name: String,
     pub key_for: Option<RecordId>,
     pub key_public: Option<String>,
+    #[tinkr(redact_history)]
     pub key_private: Option<String>,
     pub key_apikey: Option<String>,
+    #[tinkr(redact_history)]
     pub key_token: Option<String>,
     pub description: String,
-    pub created_at: String,
-    pub updated_at: String,
+    pub created_at: Datetime,
+    pub updated_at: Datetime,
     pub created_by_user_id: RecordId,
     pub expires_at: Option<Datetime>,
+    #[tinkr(skip_create)]
     pub last_used: Option<Datetime>,
-}
-
-#[cfg(feature = "ssr")]
-impl StorageAuthed<KeyCreate, Key> for Key {
-    const TABLE_NAME: &'static str = "key";
+    /// Set when the key was soft deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<Datetime>,
 }
 
 #[cfg(feature = "ssr")]
 impl Key {
+    /// The key with `key_private` cleared, for anything sent to the browser. Private keys are
+    /// only sent by `reveal_wallet_private_key`, after a passkey reverification.
+    pub fn without_private_key(self) -> Self {
+        Key {
+            key_private: None,
+            ..self
+        }
+    }
+
+    /// `watch_by_user` with every key passed through `without_private_key`, for the live feed.
+    pub async fn watch_without_private_keys(
+        user: AdapterUser,
+    ) -> Result<LiveStream<Self>, AppError> {
+        use futures::StreamExt;
+
+        let changes = Self::watch_by_user(user).await?;
+
+        Ok(Box::pin(changes.map(|change| {
+            change.map(|change| change.map(Self::without_private_key))
+        })))
+    }
+
     pub async fn get_user_keys_for(
         user: AdapterUser,
         key_for: RecordId,
@@ -87,7 +116,8 @@ impl Key {
 pub async fn get_user_keys() -> Result<Vec<Key>, leptos::server_fn::ServerFnError> {
     let user = crate::session::get_user().await?;
     let keys = Key::get_by_user(user).await?;
-    Ok(keys)
+
+    Ok(keys.into_iter().map(Key::without_private_key).collect())
 }
 
 #[component]
@@ -125,7 +155,7 @@ pub fn KeyItem(key: Key) -> impl IntoView {
                     </p>
                     <div class="flex items-center gap-4 mt-2 text-xs text-neutral-600 dark:text-neutral-400">
                         <span class="font-mono">{key_public_preview}</span>
-                        <span>"Created: "{key.created_at.clone()}</span>
+                        <span>"Created: "{key.created_at.format_date()}</span>
                         {key
                             .last_used
                             .is_some()
@@ -147,6 +177,7 @@ pub fn KeyItem(key: Key) -> impl IntoView {
 #[component]
 pub fn KeyList() -> impl IntoView {
     let keys_resource = Resource::new(|| (), |_| get_user_keys());
+    use_live_refetch(LIVE_KEYS_URL, keys_resource);
 
     view! {
         <Suspense fallback=move || {
@@ -259,7 +290,7 @@ pub async fn create_user_key(
     key_data.key_for = key_for;
 
     let created_key = Key::create_by_user(user, key_data).await?;
-    Ok(created_key)
+    Ok(created_key.without_private_key())
 }
 
 #[component]
@@ -560,3 +591,45 @@ pub fn KeysAdd(
         </>
     }
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_live_keys_without_private_key() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+        use futures::StreamExt;
+
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            let mut changes = Key::watch_without_private_keys(user.clone()).await?;
+
+            let key = Key::create_by_user(
+                user,
+                KeyCreate {
+                    name: "Signer".into(),
+                    key_for: None,
+                    key_public: Some("public".into()),
+                    key_private: Some("secret".into()),
+                    key_apikey: None,
+                    key_token: None,
+                    description: String::new(),
+                    expires_at: None,
+                },
+            )
+            .await?;
+            assert_eq!(key.key_private.as_deref(), Some("secret"));
+
+            // the first notification is the complete CREATE
+            let change = changes.next().await.expect("a change")?;
+            assert!(matches!(change.action, crate::live::ChangeAction::Create));
+            assert_eq!(change.data.id, key.id);
+            assert_eq!(change.data.key_private, None);
+            assert_eq!(change.data.key_public.as_deref(), Some("public"));
+
+            Ok(())
+        })
+        .await
+    }
+}
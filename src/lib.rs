@@ -1,3 +1,6 @@
+// lets code generated by tinkr-macros refer to `::tinkr` inside this crate too
+extern crate self as tinkr;
+
 pub mod apperror;
 pub mod builds;
 pub mod date_utils;
@@ -42,12 +45,23 @@ pub use db::db_init;
 #[cfg(feature = "ssr")]
 pub use db::db_seperate_connection;
 
+#[cfg(feature = "ssr")]
+pub use db::{db_memory, with_db};
+
 #[cfg(feature = "ssr")]
 pub use db::storage_trait::Storage;
 
 #[cfg(feature = "ssr")]
 pub use db::connection::db_schema;
 
+#[cfg(feature = "ssr")]
+pub use db::model::TinkrModel;
+
+pub use tinkr_macros::TinkrModel;
+
+#[cfg(feature = "ssr")]
+pub use db::migrations::{Migration, MigrationRunner};
+
 pub mod email;
 pub use email::EmailAddress;
 
@@ -59,18 +73,35 @@ pub use auth::user;
 #[cfg(feature = "ssr")]
 pub use auth::adapter_rs_surreal;
 pub use auth::callback;
+pub use auth::oidc;
+pub use auth::passkey;
+pub use auth::password;
+pub use auth::two_factor;
 pub use auth::session;
 pub use auth::ui_auth;
 
 #[cfg(feature = "ssr")]
 pub use auth::token;
 
+#[cfg(feature = "ssr")]
+pub use auth::token_hash;
+
+#[cfg(feature = "ssr")]
+pub use auth::rate_limit;
+
+pub use auth::api_key;
+
+#[cfg(feature = "ssr")]
+pub use auth::extract::{AdminUser, CurrentUser, OptionalUser, SuperAdmin};
+
 #[cfg(feature = "ssr")]
 pub use auth::account;
 
 pub use auth::authcheck;
 
 pub use authcheck::AuthCheck;
+pub use auth::protected::{ProtectedRoute, RouteGuard, RouteRequirement};
+pub use auth::csrf::{CsrfField, CsrfScript};
 pub mod storage_authed_trait;
 
 #[cfg(feature = "ssr")]
@@ -84,6 +115,7 @@ pub mod users;
 pub mod wallet;
 pub use wallet::metamask;
 pub mod admin;
+pub mod live;
 pub mod logs;
 
 pub use apperror::AppError;
@@ -0,0 +1,224 @@
+use leptos::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssr")]
+use crate::AppError;
+#[cfg(feature = "ssr")]
+use crate::db::live::LiveStream;
+
+pub const LIVE_KEYS_URL: &str = "/api/live/keys";
+pub const LIVE_WALLETS_URL: &str = "/api/live/wallets";
+pub const LIVE_ORGANIZATIONS_URL: &str = "/api/live/organizations";
+pub const LIVE_LOGS_URL: &str = "/api/live/logs";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ChangeAction {
+    Create,
+    Update,
+    Delete,
+}
+
+/// A single live query notification, sent to the browser as a server-sent event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Change<T> {
+    pub action: ChangeAction,
+    pub data: T,
+}
+
+impl<T> Change<T> {
+    /// Applies the change to `items`, matching records with `id_of`.
+    pub fn apply<K: PartialEq>(self, items: &mut Vec<T>, id_of: impl Fn(&T) -> K) {
+        let id = id_of(&self.data);
+        let position = items.iter().position(|item| id_of(item) == id);
+
+        match (self.action, position) {
+            (ChangeAction::Delete, Some(index)) => {
+                items.remove(index);
+            }
+            (ChangeAction::Delete, None) => {}
+            (_, Some(index)) => items[index] = self.data,
+            (_, None) => items.push(self.data),
+        }
+    }
+
+    /// The same change with `f` applied to the record, e.g. to strip fields before sending it.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Change<U> {
+        Change {
+            action: self.action,
+            data: f(self.data),
+        }
+    }
+}
+
+/// Subscribes to the server-sent events at `url` and calls `on_change` for every change.
+/// The connection is closed when the calling component is unmounted. Does nothing during SSR.
+pub fn use_live_changes<T, F>(url: impl Into<String>, on_change: F)
+where
+    T: DeserializeOwned + 'static,
+    F: Fn(Change<T>) + 'static,
+{
+    #[cfg(not(feature = "ssr"))]
+    {
+        use wasm_bindgen::{JsCast, closure::Closure};
+        use web_sys::{EventSource, MessageEvent};
+
+        let Ok(source) = EventSource::new(&url.into()) else {
+            leptos::logging::warn!("Could not open live updates");
+            return;
+        };
+
+        let on_message = Closure::<dyn Fn(MessageEvent)>::new(move |event: MessageEvent| {
+            let Some(data) = event.data().as_string() else {
+                return;
+            };
+
+            match serde_json::from_str::<Change<T>>(&data) {
+                Ok(change) => on_change(change),
+                Err(e) => leptos::logging::warn!("Invalid live update: {e}"),
+            }
+        });
+        source.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        let live = StoredValue::new_local((source, on_message));
+        on_cleanup(move || live.with_value(|(source, _)| source.close()));
+    }
+
+    #[cfg(feature = "ssr")]
+    {
+        let _ = (url.into(), on_change);
+    }
+}
+
+/// Refetches `resource` whenever the live feed at `url` reports a change.
+pub fn use_live_refetch<T>(url: impl Into<String>, resource: Resource<T>)
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    use_live_changes::<serde::de::IgnoredAny, _>(url, move |_| resource.refetch());
+}
+
+/// Keeps a list in sync with the live feed at `url`, starting from `initial`.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     let keys = use_live_list(LIVE_KEYS_URL, keys, |key: &Key| key.id.clone());
+/// ```
+pub fn use_live_list<T, K, F>(url: impl Into<String>, initial: Vec<T>, id_of: F) -> RwSignal<Vec<T>>
+where
+    T: DeserializeOwned + Send + Sync + 'static,
+    K: PartialEq,
+    F: Fn(&T) -> K + 'static,
+{
+    let items = RwSignal::new(initial);
+
+    use_live_changes(url, move |change: Change<T>| {
+        items.update(|items| change.apply(items, &id_of));
+    });
+
+    items
+}
+
+/// Streams `changes` to the browser as server-sent events, one JSON `Change` per event.
+#[cfg(feature = "ssr")]
+pub fn live_sse<T>(changes: LiveStream<T>) -> impl axum::response::IntoResponse
+where
+    T: Serialize + Send + 'static,
+{
+    use axum::response::sse::{Event, KeepAlive, Sse};
+    use futures::StreamExt;
+
+    let events = changes.filter_map(|change| async move {
+        match change.and_then(|change| {
+            Event::default()
+                .json_data(&change)
+                .map_err(|e| AppError::GenericError(e.to_string()))
+        }) {
+            Ok(event) => Some(Ok::<_, std::convert::Infallible>(event)),
+            Err(e) => {
+                tracing::warn!("Dropped live update: {}", e);
+                None
+            }
+        }
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Router serving the live feeds used by `KeyList`, `WalletList`, `OrganizationList` and
+/// `LogsAdmin`. Each feed only carries records the signed in user may see.
+///
+/// # Example
+/// ```rs
+/// let app = Router::new().merge(tinkr::live::create_live_router());
+/// ```
+#[cfg(feature = "ssr")]
+pub fn create_live_router() -> axum::Router {
+    use crate::db::Filter;
+    use crate::db::live::watch_table;
+    use crate::auth::extract::{AdminUser, CurrentUser};
+    use axum::{response::IntoResponse, routing::get};
+
+    async fn keys(CurrentUser(user): CurrentUser) -> Result<impl IntoResponse, AppError> {
+        Ok(live_sse(
+            crate::keys::Key::watch_without_private_keys(user).await?,
+        ))
+    }
+
+    async fn wallets(CurrentUser(user): CurrentUser) -> Result<impl IntoResponse, AppError> {
+        Ok(live_sse(
+            watch_table::<crate::wallet::Wallet>(
+                "wallet",
+                Some(Filter::eq("created_by_user_id", user.id)),
+            )
+            .await?,
+        ))
+    }
+
+    async fn organizations(CurrentUser(user): CurrentUser) -> Result<impl IntoResponse, AppError> {
+        Ok(live_sse(
+            crate::organization::organization::Organization::watch_for_user(user.id).await?,
+        ))
+    }
+
+    async fn logs(_admin: AdminUser) -> Result<impl IntoResponse, AppError> {
+        Ok(live_sse(
+            watch_table::<crate::logs::tracing_layer::LogEvent>("log_events", None).await?,
+        ))
+    }
+
+    axum::Router::new()
+        .route(LIVE_KEYS_URL, get(keys))
+        .route(LIVE_WALLETS_URL, get(wallets))
+        .route(LIVE_ORGANIZATIONS_URL, get(organizations))
+        .route(LIVE_LOGS_URL, get(logs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_change_apply() {
+        let mut items = vec![(1, "a"), (2, "b")];
+        let id_of = |item: &(i32, &str)| item.0;
+
+        Change {
+            action: ChangeAction::Update,
+            data: (2, "c"),
+        }
+        .apply(&mut items, id_of);
+        Change {
+            action: ChangeAction::Create,
+            data: (3, "d"),
+        }
+        .apply(&mut items, id_of);
+        Change {
+            action: ChangeAction::Delete,
+            data: (1, "a"),
+        }
+        .apply(&mut items, id_of);
+
+        assert_eq!(items, vec![(2, "c"), (3, "d")]);
+    }
+}
@@ -5,6 +5,7 @@ use crate::{
         button::{BtnColor, BtnVariant, ButtonIcon},
     },
     date_utils::FormatDatetime,
+    live::{Change, ChangeAction, LIVE_LOGS_URL, use_live_changes},
 };
 use leptos::prelude::*;
 
@@ -174,7 +175,16 @@ pub fn LogsAdmin() -> impl IntoView {
         |filter| async move { fetch_logs(filter).await },
     );
 
+    // Count new events from the live feed instead of refetching on every log line
+    let new_logs = RwSignal::new(0usize);
+    use_live_changes(LIVE_LOGS_URL, move |change: Change<serde::de::IgnoredAny>| {
+        if change.action == ChangeAction::Create {
+            new_logs.update(|count| *count += 1);
+        }
+    });
+
     let refresh_logs = move || {
+        new_logs.set(0);
         logs_resource.refetch();
         stats_resource.refetch();
     };
@@ -270,6 +280,22 @@ pub fn LogsAdmin() -> impl IntoView {
                         }
                     }
                 }} <LogsFilter filter=filter set_filter=update_filter on_refresh=refresh_logs />
+                {move || {
+                    (new_logs.get() > 0)
+                        .then(|| {
+                            view! {
+                                <div class="text-center mb-4">
+                                    <Button
+                                        variant=BtnVariant::Default
+                                        color=BtnColor::Primary
+                                        on:click=move |_| refresh_logs()
+                                    >
+                                        {format!("Show {} new log events", new_logs.get())}
+                                    </Button>
+                                </div>
+                            }
+                        })
+                }}
                 {move || {
                     match logs_resource.get() {
                         Some(Ok(logs_response)) => {
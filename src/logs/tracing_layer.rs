@@ -18,13 +18,17 @@ use tracing_subscriber::registry::LookupSpan;
 #[cfg(feature = "ssr")]
 use uuid::Uuid;
 
-use crate::{Datetime, RecordId};
+use crate::{Datetime, RecordId, TinkrModel};
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, TinkrModel)]
+#[tinkr(table = "log_events", cfg(feature = "ssr"))]
 pub struct LogEvent {
     pub id: RecordId,
+    #[tinkr(index)]
     pub timestamp: Datetime,
+    #[tinkr(index)]
     pub level: String,
+    #[tinkr(index)]
     pub target: String,
     pub message: String,
     pub module_path: Option<String>,
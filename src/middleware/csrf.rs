@@ -0,0 +1,365 @@
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use axum::{
+    body::Body,
+    extract::Request,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar};
+use futures::future::BoxFuture;
+use http::{HeaderMap, HeaderValue, Method, header};
+use tower::{Layer, Service};
+
+use crate::{
+    AppError,
+    auth::csrf::{CSRF_COOKIE, CSRF_FORM_FIELD, CSRF_HEADER, CsrfToken, csrf_token_for_session},
+    auth::extract::{SESSION_COOKIE, bearer_token_from_headers, session_token_from_headers},
+};
+
+/// Largest urlencoded body searched for a `csrf_token` field.
+const MAX_FORM_BYTES: usize = 1024 * 1024;
+
+/// Rejects state-changing requests (anything but GET, HEAD, OPTIONS and TRACE) whose
+/// `x-csrf-token` header or `csrf_token` form field doesn't match the CSRF token, and issues the
+/// token cookie. Requests with an `Authorization: Bearer` key and no session cookie are not
+/// checked, browsers never send one on their own. With a session cookie the session is what
+/// authenticates the request, so the check applies whatever the bearer.
+///
+/// Add to your app:
+/// ```rs
+/// let app = Router::new()
+///     .route(PAYFAST_NOTIFY_PATH, post(payfast_notify_handler))
+///     // ... rest of your routes
+///     .layer(CsrfLayer::new().exempt(PAYFAST_NOTIFY_PATH));
+/// ```
+#[derive(Clone, Default)]
+pub struct CsrfLayer {
+    exempt: Arc<Vec<String>>,
+}
+
+impl CsrfLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Skips the check for paths starting with `path_prefix`, e.g. webhooks posted by other
+    /// servers.
+    pub fn exempt(mut self, path_prefix: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.exempt).push(path_prefix.into());
+        self
+    }
+}
+
+impl<S> Layer<S> for CsrfLayer {
+    type Service = CsrfService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CsrfService {
+            inner,
+            exempt: self.exempt.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CsrfService<S> {
+    inner: S,
+    exempt: Arc<Vec<String>>,
+}
+
+impl<S> Service<Request<Body>> for CsrfService<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        // the clone may not be ready, keep the instance poll_ready was called on
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        // a browser never sends the bearer on its own, but one next to the cookie proves nothing
+        let bearer_only = bearer_token_from_headers(req.headers()).is_some()
+            && session_token_from_headers(req.headers()).is_none();
+        let needs_check = !is_safe_method(req.method())
+            && !self
+                .exempt
+                .iter()
+                .any(|prefix| req.uri().path().starts_with(prefix.as_str()))
+            && !bearer_only;
+
+        let cookie_token = CookieJar::from_headers(req.headers())
+            .get(CSRF_COOKIE)
+            .map(|cookie| cookie.value().to_string());
+
+        Box::pin(async move {
+            let expected = match expected_token(req.headers(), cookie_token.as_deref()) {
+                Ok(expected) => expected,
+                Err(e) if needs_check => return Ok(e.into_response()),
+                // requests that aren't checked only refresh the cookie, keep the one they sent
+                Err(e) => {
+                    tracing::warn!("Could not derive the CSRF token of the session: {}", e);
+                    cookie_token.clone()
+                }
+            };
+
+            if needs_check {
+                let submitted;
+                (req, submitted) = submitted_token(req).await;
+
+                let valid = match (&expected, &submitted) {
+                    (Some(expected), Some(submitted)) => {
+                        constant_time_eq(expected.as_bytes(), submitted.as_bytes())
+                    }
+                    _ => false,
+                };
+
+                if !valid {
+                    tracing::warn!(
+                        path = %req.uri().path(),
+                        method = %req.method(),
+                        "Rejected request with missing or invalid CSRF token"
+                    );
+                    return Ok(AppError::Forbidden("Invalid CSRF token".into()).into_response());
+                }
+            }
+
+            let issued = expected.unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+            req.extensions_mut().insert(CsrfToken(issued.clone()));
+
+            let mut response = inner.call(req).await?;
+
+            // a session started or ended by this request needs a token of its own
+            let token = match session_cookie_set_by(response.headers()) {
+                Some(Some(session_token)) => csrf_token_for_session(&session_token).ok(),
+                Some(None) => Some(uuid::Uuid::new_v4().simple().to_string()),
+                None => Some(issued),
+            };
+
+            if let Some(token) = token
+                && cookie_token.as_deref() != Some(token.as_str())
+                && let Ok(value) = HeaderValue::from_str(&build_csrf_cookie(token).to_string())
+            {
+                response.headers_mut().append(header::SET_COOKIE, value);
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+fn is_safe_method(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// The token bound to the request's session, or the cookie token of signed out visitors.
+fn expected_token(
+    headers: &HeaderMap,
+    cookie_token: Option<&str>,
+) -> Result<Option<String>, AppError> {
+    match session_token_from_headers(headers) {
+        Some(session_token) => csrf_token_for_session(&session_token).map(Some),
+        None => Ok(cookie_token.map(str::to_string)),
+    }
+}
+
+/// The token from the header, or from the body of an urlencoded form post.
+async fn submitted_token(req: Request<Body>) -> (Request<Body>, Option<String>) {
+    if let Some(token) = req
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        let token = token.to_string();
+        return (req, Some(token));
+    }
+
+    let is_form = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+
+    if !is_form {
+        return (req, None);
+    }
+
+    let (parts, body) = req.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_FORM_BYTES).await else {
+        return (Request::from_parts(parts, Body::empty()), None);
+    };
+
+    let token = url::form_urlencoded::parse(&bytes)
+        .find(|(name, _)| name == CSRF_FORM_FIELD)
+        .map(|(_, value)| value.into_owned());
+
+    (Request::from_parts(parts, Body::from(bytes)), token)
+}
+
+/// `Some(Some(token))` when the response sets a session cookie, `Some(None)` when it clears it.
+fn session_cookie_set_by(headers: &HeaderMap) -> Option<Option<String>> {
+    headers
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| Cookie::parse(value.to_string()).ok())
+        .rfind(|cookie| cookie.name() == SESSION_COOKIE)
+        .map(|cookie| Some(cookie.value().to_string()).filter(|value| !value.is_empty()))
+}
+
+fn build_csrf_cookie(token: String) -> Cookie<'static> {
+    // readable by CsrfScript, so not http_only
+    Cookie::build((CSRF_COOKIE, token))
+        .path("/")
+        .secure(!cfg!(debug_assertions))
+        .same_site(leptos_use::SameSite::Lax)
+        .build()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, routing::post};
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/api/update", post(|| async { "ok" }))
+            .route("/api/payment/notify", post(|| async { "ok" }))
+            .layer(CsrfLayer::new().exempt("/api/payment/notify"))
+    }
+
+    fn post_request(path: &str, cookie: Option<&str>, header: Option<&str>) -> Request<Body> {
+        post_request_with_bearer(path, cookie, header, None)
+    }
+
+    fn post_request_with_bearer(
+        path: &str,
+        cookie: Option<&str>,
+        header: Option<&str>,
+        bearer: Option<&str>,
+    ) -> Request<Body> {
+        let mut builder = Request::post(path);
+        if let Some(bearer) = bearer {
+            builder = builder.header(header::AUTHORIZATION, format!("Bearer {bearer}"));
+        }
+        if let Some(cookie) = cookie {
+            builder = builder.header(header::COOKIE, cookie);
+        }
+        if let Some(token) = header {
+            builder = builder.header(CSRF_HEADER, token);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_csrf_layer() {
+        let response = app()
+            .oneshot(post_request("/api/update", None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+
+        let response = app()
+            .oneshot(post_request(
+                "/api/update",
+                Some("csrf_token=abc"),
+                Some("abd"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+
+        let response = app()
+            .oneshot(post_request(
+                "/api/update",
+                Some("csrf_token=abc"),
+                Some("abc"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+
+        let response = app()
+            .oneshot(post_request("/api/payment/notify", None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert!(response.headers().contains_key(header::SET_COOKIE));
+    }
+
+    #[tokio::test]
+    async fn test_csrf_layer_multipart_upload() {
+        use crate::auth::csrf::csrf_token_from_cookies;
+
+        let cookie = "theme=dark; csrf_token=abc";
+        assert_eq!(csrf_token_from_cookies(cookie).as_deref(), Some("abc"));
+        assert_eq!(csrf_token_from_cookies("theme=dark"), None);
+
+        // XmlHttpRequest uploads copy the cookie into the header, the body isn't searched
+        let upload = |token: Option<String>| {
+            let mut builder = Request::post("/api/update")
+                .header(header::COOKIE, cookie)
+                .header(header::CONTENT_TYPE, "multipart/form-data; boundary=x");
+            if let Some(token) = token {
+                builder = builder.header(CSRF_HEADER, token);
+            }
+            builder
+                .body(Body::from(
+                    "--x\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.png\"\r\n\r\npng\r\n--x--\r\n",
+                ))
+                .unwrap()
+        };
+
+        let response = app().oneshot(upload(None)).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+
+        let response = app()
+            .oneshot(upload(csrf_token_from_cookies(cookie)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_csrf_layer_bearer() {
+        // API clients without a session aren't checked
+        let response = app()
+            .oneshot(post_request_with_bearer(
+                "/api/update",
+                None,
+                None,
+                Some("tk_123"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+
+        // a bearer doesn't lift the check for a request the session cookie authenticates
+        let response = app()
+            .oneshot(post_request_with_bearer(
+                "/api/update",
+                Some("session_token=abc; csrf_token=abc"),
+                None,
+                Some("junk"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+    }
+}
@@ -5,6 +5,8 @@ use super::metrics_auth::metrics_auth_middleware;
 
 /// Creates a Prometheus metrics router with Basic Auth protection
 ///
+/// Besides the HTTP metrics the endpoint serves the `tinkr_cache_*` metrics of `AsyncSurrealCache`.
+///
 /// Returns a tuple of (prometheus_layer, metrics_router)
 /// The prometheus_layer should be added to your main app router
 /// The metrics_router should be merged into your main app router
@@ -18,6 +20,7 @@ use super::metrics_auth::metrics_auth_middleware;
 /// ```
 pub fn create_metrics_setup() -> (PrometheusMetricLayer<'static>, Router) {
     let (prometheus_layer, metric_handle) = PrometheusMetricLayer::pair();
+    crate::db::cache_metrics::describe_cache_metrics();
 
     let metrics_router = Router::new()
         .route(
@@ -1,4 +1,5 @@
 pub mod compression;
+pub mod csrf;
 pub mod health;
 pub mod logging;
 pub mod metrics;
@@ -8,8 +9,9 @@ pub mod tracing;
 
 // Re-exports for convenience
 pub use compression::create_compression_layer;
+pub use csrf::CsrfLayer;
 pub use health::health_check;
 pub use metrics::create_metrics_setup;
 pub use metrics_auth::metrics_auth_middleware;
 pub use server_fn_logging::ServerFnLoggingLayer;
-pub use tracing::create_trace_layer;
+pub use tracing::{client_ip, create_trace_layer};
@@ -1,12 +1,13 @@
 use axum::extract::{OriginalUri, Request};
+use http::{Extensions, HeaderMap};
+use std::net::{IpAddr, SocketAddr};
 use tower_http::trace::TraceLayer;
 use tracing::info_span;
 
 /// Creates a pre-configured TraceLayer for HTTP requests
 ///
 /// Features:
-/// - Extracts real IP from x-forwarded-for or x-real-ip headers
-/// - Falls back to ConnectInfo socket address
+/// - Extracts the client IP with `client_ip`, see `TRUSTED_PROXIES_ENV`
 /// - Logs method, path, and remote_addr in span
 pub fn create_trace_layer() -> TraceLayer<
     tower_http::classify::SharedClassifier<tower_http::classify::ServerErrorsAsFailures>,
@@ -19,25 +20,7 @@ pub fn create_trace_layer() -> TraceLayer<
             request.uri().path().to_owned()
         };
 
-        let remote_addr = request
-            .headers()
-            .get("x-forwarded-for")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|s| s.split(',').next())
-            .map(|s| s.trim().to_owned())
-            .or_else(|| {
-                request
-                    .headers()
-                    .get("x-real-ip")
-                    .and_then(|v| v.to_str().ok())
-                    .map(|s| s.to_owned())
-            })
-            .or_else(|| {
-                request
-                    .extensions()
-                    .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
-                    .map(|ci| ci.0.to_string())
-            });
+        let remote_addr = client_ip(request.headers(), request.extensions());
 
         info_span!(
             "http_request",
@@ -47,3 +30,119 @@ pub fn create_trace_layer() -> TraceLayer<
         )
     })
 }
+
+/// Env var with the number of reverse proxies in front of the app that append to
+/// `x-forwarded-for`. Unset or `0` ignores forwarding headers and uses the socket address.
+pub const TRUSTED_PROXIES_ENV: &str = "TINKR_TRUSTED_PROXIES";
+
+/// Number of trusted proxy hops, from `TRUSTED_PROXIES_ENV`.
+pub fn trusted_proxies() -> usize {
+    std::env::var(TRUSTED_PROXIES_ENV)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Client IP of a request, without the port. Behind `trusted_proxies()` proxies this is the
+/// right-most `x-forwarded-for` entry the proxies did not add themselves, entries further left
+/// are set by the client and can't be trusted. Otherwise, or when the header has fewer entries
+/// than there are proxies, it is the `ConnectInfo` socket address.
+pub fn client_ip(headers: &HeaderMap, extensions: &Extensions) -> Option<String> {
+    client_ip_behind(headers, extensions, trusted_proxies())
+}
+
+fn client_ip_behind(
+    headers: &HeaderMap,
+    extensions: &Extensions,
+    proxies: usize,
+) -> Option<String> {
+    forwarded_ip(headers, proxies)
+        .or_else(|| {
+            extensions
+                .get::<axum::extract::ConnectInfo<SocketAddr>>()
+                .map(|ci| ci.0.ip())
+        })
+        .map(|ip| ip.to_string())
+}
+
+/// The `x-forwarded-for` entry added by the outermost of `proxies` proxies, the nearest one
+/// being the socket peer. Repeated headers are read as one list.
+fn forwarded_ip(headers: &HeaderMap, proxies: usize) -> Option<IpAddr> {
+    if proxies == 0 {
+        return None;
+    }
+
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+
+    let hop = hops.len().checked_sub(proxies).map(|index| hops[index])?;
+
+    hop.parse::<IpAddr>()
+        .ok()
+        .or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::ConnectInfo;
+
+    fn request(forwarded: &[&str]) -> (HeaderMap, Extensions) {
+        let mut headers = HeaderMap::new();
+        for value in forwarded {
+            headers.append("x-forwarded-for", value.parse().unwrap());
+        }
+
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 51234))));
+
+        (headers, extensions)
+    }
+
+    #[test]
+    fn test_client_ip() {
+        // no proxies: the socket address without port, forwarding headers are ignored
+        let (headers, extensions) = request(&["1.1.1.1"]);
+        assert_eq!(
+            client_ip_behind(&headers, &extensions, 0).as_deref(),
+            Some("10.0.0.1")
+        );
+
+        // one proxy: the entry it appended, not the one the client sent
+        let (headers, extensions) = request(&["6.6.6.6, 203.0.113.7"]);
+        assert_eq!(
+            client_ip_behind(&headers, &extensions, 1).as_deref(),
+            Some("203.0.113.7")
+        );
+
+        // two proxies across repeated headers
+        let (headers, extensions) = request(&["6.6.6.6, 203.0.113.7", "192.168.1.2"]);
+        assert_eq!(
+            client_ip_behind(&headers, &extensions, 2).as_deref(),
+            Some("203.0.113.7")
+        );
+
+        // fewer entries than proxies or a malformed entry falls back to the socket
+        let (headers, extensions) = request(&["203.0.113.7"]);
+        assert_eq!(
+            client_ip_behind(&headers, &extensions, 2).as_deref(),
+            Some("10.0.0.1")
+        );
+        let (headers, extensions) = request(&["unknown"]);
+        assert_eq!(
+            client_ip_behind(&headers, &extensions, 1).as_deref(),
+            Some("10.0.0.1")
+        );
+
+        let (headers, extensions) = request(&["[2001:db8::1]:443"]);
+        assert_eq!(
+            client_ip_behind(&headers, &extensions, 1).as_deref(),
+            Some("2001:db8::1")
+        );
+    }
+}
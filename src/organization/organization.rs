@@ -1,11 +1,15 @@
 #[cfg(feature = "ssr")]
-use crate::{user::AdapterUser, StorageAuthed};
+use crate::{storage_authed_trait::Access, user::AdapterUser, StorageAuthed};
 
 #[cfg(feature = "ssr")]
 use crate::AppError;
 use partial_struct::Partial;
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "ssr")]
+use crate::db::Transaction;
+#[cfg(feature = "ssr")]
+use crate::db::live::LiveStream;
 #[cfg(feature = "ssr")]
 use crate::db_init;
 
@@ -23,7 +27,7 @@ use super::super::team::team::Team;
 #[partial(
     "CreateOrganization",
     derive(Debug, Serialize, Deserialize, Clone),
-    omit(id, created_by_user_id, created_at, updated_at)
+    omit(id, created_by_user_id, created_at, updated_at, version, deleted_at)
 )]
 pub struct Organization {
     pub id: RecordId,
@@ -34,11 +38,39 @@ pub struct Organization {
     pub created_by_user_id: RecordId,
     pub created_at: Datetime,
     pub updated_at: Datetime,
+    /// Incremented on every update, used to detect concurrent edits
+    #[serde(default)]
+    pub version: u64,
+    /// Set when the organization was soft deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<Datetime>,
 }
 
 #[cfg(feature = "ssr")]
 impl StorageAuthed<CreateOrganization, Organization> for Organization {
     const TABLE_NAME: &str = "organization";
+    const SOFT_DELETE: bool = true;
+
+    /// Owners and admins can edit an organization, only owners can delete it.
+    async fn authorize(user: &AdapterUser, id: &RecordId, access: Access) -> Result<(), AppError> {
+        let role = OrganizationMember::role_of(id.clone(), user.id.clone()).await?;
+
+        let allowed = match access {
+            Access::Update => matches!(
+                role,
+                Some(OrganizationRole::Owner) | Some(OrganizationRole::Admin)
+            ),
+            Access::Delete => role == Some(OrganizationRole::Owner),
+        };
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(format!(
+                "Not allowed to modify organization {id}"
+            )))
+        }
+    }
 }
 
 // ----------------------------------------------------------------------
@@ -51,6 +83,21 @@ pub enum OrganizationRole {
     Owner,
 }
 
+impl OrganizationRole {
+    /// Whether this role has at least the rights of `required`.
+    pub fn includes(&self, required: &OrganizationRole) -> bool {
+        fn rank(role: &OrganizationRole) -> u8 {
+            match role {
+                OrganizationRole::Member => 0,
+                OrganizationRole::Admin => 1,
+                OrganizationRole::Owner => 2,
+            }
+        }
+
+        rank(self) >= rank(required)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Partial)]
 #[partial(
     "CreateOrganizationMember",
@@ -73,17 +120,111 @@ pub struct OrganizationMember {
 #[cfg(feature = "ssr")]
 impl StorageAuthed<CreateOrganizationMember, OrganizationMember> for OrganizationMember {
     const TABLE_NAME: &str = "organization_member";
+
+    /// Owners manage every membership, admins every membership except owners'.
+    /// Members can always remove themselves.
+    async fn authorize(user: &AdapterUser, id: &RecordId, access: Access) -> Result<(), AppError> {
+        let member = Self::get_by_id(id.clone()).await?;
+
+        if access == Access::Delete && member.user_id == user.id {
+            return Ok(());
+        }
+
+        let role = Self::role_of(member.organization_id, user.id.clone()).await?;
+
+        let allowed = match role {
+            Some(OrganizationRole::Owner) => true,
+            Some(OrganizationRole::Admin) => member.role != OrganizationRole::Owner,
+            _ => false,
+        };
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden(format!(
+                "Not allowed to modify organization member {id}"
+            )))
+        }
+    }
+
+    /// Only owners change roles, so nobody else can make themselves or anyone an owner.
+    /// A membership always stays with its organization and user.
+    async fn authorize_update(
+        user: &AdapterUser,
+        id: &RecordId,
+        content: &OrganizationMember,
+    ) -> Result<(), AppError> {
+        Self::authorize(user, id, Access::Update).await?;
+
+        let member = Self::get_by_id(id.clone()).await?;
+
+        if content.organization_id != member.organization_id || content.user_id != member.user_id {
+            return Err(AppError::Forbidden(format!(
+                "Organization member {id} can't be moved"
+            )));
+        }
+
+        if content.role != member.role
+            && Self::role_of(member.organization_id, user.id.clone()).await?
+                != Some(OrganizationRole::Owner)
+        {
+            return Err(AppError::Forbidden(format!(
+                "Only owners can change the role of organization member {id}"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// A record of the organizations live feed, see `Organization::watch_for_user`.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum OrganizationChange {
+    Member(OrganizationMember),
+    Organization(Organization),
 }
 
 // Implementation for Organization
 #[cfg(feature = "ssr")]
 impl Organization {
+    /// Live changes to the memberships of `user_id` and to the organizations they belong to when
+    /// the feed is opened. Joining another organization arrives as a membership change.
+    pub async fn watch_for_user(
+        user_id: RecordId,
+    ) -> Result<LiveStream<OrganizationChange>, AppError> {
+        use crate::db::{Filter, live::watch_table};
+        use futures::StreamExt;
+
+        let organization_ids: Vec<RecordId> = db_init()
+            .await?
+            .query("SELECT VALUE organization_id FROM organization_member WHERE user_id = $user_id")
+            .bind(("user_id", user_id.clone()))
+            .await?
+            .take(0)?;
+
+        let members = watch_table::<OrganizationMember>(
+            "organization_member",
+            Some(Filter::eq("user_id", user_id)),
+        )
+        .await?
+        .map(|change| change.map(|change| change.map(OrganizationChange::Member)));
+
+        let organizations = watch_table::<Organization>(
+            "organization",
+            Some(Filter::is_in("id", organization_ids)),
+        )
+        .await?
+        .map(|change| change.map(|change| change.map(OrganizationChange::Organization)));
+
+        Ok(Box::pin(futures::stream::select(members, organizations)))
+    }
+
     pub async fn create(
         data: CreateOrganization,
         created_by_user_id: RecordId,
     ) -> Result<Organization, AppError> {
-        let db = db_init().await?;
-
         #[derive(Serialize)]
         struct CreateOrgData {
             name: String,
@@ -106,36 +247,23 @@ impl Organization {
             updated_at: now,
         };
 
-        let created: Organization =
-            db.create("organization")
-                .content(org_data)
-                .await?
-                .ok_or(AppError::DatabaseError(
-                    "Failed to create organization".to_string(),
-                ))?;
-
-        // Add creator as owner
-        #[derive(Serialize)]
-        struct CreateOrgMemberData {
-            organization_id: RecordId,
-            user_id: RecordId,
-            role: OrganizationRole,
-            joined_at: Datetime,
-        }
-
-        let member_data = CreateOrgMemberData {
-            organization_id: created.id.clone(),
-            user_id: created_by_user_id,
-            role: OrganizationRole::Owner,
-            joined_at: Datetime::default(),
-        };
-
-        let _: OrganizationMember = db
-            .create("organization_member")
-            .content(member_data)
+        // Create the organization and add the creator as owner in one transaction
+        let mut tx = Transaction::new();
+        tx.bind("org_data", org_data);
+        tx.bind("user_id", created_by_user_id);
+        tx.bind("role", OrganizationRole::Owner);
+        tx.statement::<surrealdb::Value>("LET $org = (CREATE ONLY organization CONTENT $org_data)");
+        tx.statement::<surrealdb::Value>(
+            "CREATE organization_member SET organization_id = $org.id, user_id = $user_id, role = $role, joined_at = time::now()",
+        );
+        let org = tx.statement::<Option<Organization>>("$org");
+
+        let created = tx
+            .commit()
             .await?
+            .take(org)?
             .ok_or(AppError::DatabaseError(
-                "Failed to create organization member".to_string(),
+                "Failed to create organization".to_string(),
             ))?;
 
         Ok(created)
@@ -172,7 +300,9 @@ impl Organization {
                 .select::<Option<Organization>>(member.organization_id)
                 .await
             {
-                orgs.push(org);
+                if org.deleted_at.is_none() {
+                    orgs.push(org);
+                }
             }
         }
 
@@ -233,6 +363,23 @@ impl Organization {
 // Implementation for OrganizationMember
 #[cfg(feature = "ssr")]
 impl OrganizationMember {
+    /// The role `user_id` has in `organization_id`, `None` if they are not a member.
+    pub async fn role_of(
+        organization_id: RecordId,
+        user_id: RecordId,
+    ) -> Result<Option<OrganizationRole>, AppError> {
+        let db = db_init().await?;
+
+        let roles: Vec<OrganizationRole> = db
+            .query("SELECT VALUE role FROM organization_member WHERE organization_id = $org_id AND user_id = $user_id")
+            .bind(("org_id", organization_id))
+            .bind(("user_id", user_id))
+            .await?
+            .take(0)?;
+
+        Ok(roles.into_iter().next())
+    }
+
     pub async fn add_member(
         organization_id: RecordId,
         user_id: RecordId,
@@ -334,3 +481,107 @@ impl OrganizationMember {
         Ok(members)
     }
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_watch_for_user() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+        use futures::StreamExt;
+        use std::time::Duration;
+
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            let other = AdapterUser::create_test_user().await?;
+            let new_organization = |name: &str| CreateOrganization {
+                name: name.into(),
+                description: None,
+                logo_url: None,
+                website: None,
+            };
+            let mine = Organization::create(new_organization("Mine"), user.id.clone()).await?;
+            let theirs = Organization::create(new_organization("Theirs"), other.id).await?;
+
+            let mut changes = Organization::watch_for_user(user.id).await?;
+
+            let db = db_init().await?;
+            db.query("UPDATE $theirs SET name = 'Renamed'; UPDATE $mine SET name = 'Renamed';")
+                .bind(("theirs", theirs.id))
+                .bind(("mine", mine.id.clone()))
+                .await?
+                .check()?;
+
+            // renaming an organization of the user reaches them, other organizations don't
+            let change = tokio::time::timeout(Duration::from_secs(5), changes.next())
+                .await
+                .expect("a change")
+                .expect("an open stream")?;
+            match change.data {
+                OrganizationChange::Organization(organization) => {
+                    assert_eq!(organization.id, mine.id);
+                    assert_eq!(organization.name, "Renamed");
+                }
+                OrganizationChange::Member(member) => panic!("unexpected {member:?}"),
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_only_owners_change_roles() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+
+        with_db(db_memory().await?, async {
+            let owner = AdapterUser::create_test_user().await?;
+            let admin = AdapterUser::create_test_user().await?;
+            let organization = Organization::create(
+                CreateOrganization {
+                    name: "Org".into(),
+                    description: None,
+                    logo_url: None,
+                    website: None,
+                },
+                owner.id.clone(),
+            )
+            .await?;
+            let membership = OrganizationMember::add_member(
+                organization.id.clone(),
+                admin.id.clone(),
+                OrganizationRole::Admin,
+            )
+            .await?;
+            let promoted = OrganizationMember {
+                role: OrganizationRole::Owner,
+                ..membership.clone()
+            };
+
+            // an admin can't promote themselves
+            let result =
+                OrganizationMember::update(admin.clone(), membership.id.clone(), promoted.clone())
+                    .await;
+            assert!(matches!(result, Err(AppError::Forbidden(_))));
+            assert_eq!(
+                OrganizationMember::role_of(organization.id.clone(), admin.id.clone()).await?,
+                Some(OrganizationRole::Admin)
+            );
+
+            // nor move their membership to another user
+            let moved = OrganizationMember {
+                user_id: owner.id.clone(),
+                ..membership.clone()
+            };
+            let result = OrganizationMember::update(admin, membership.id.clone(), moved).await;
+            assert!(matches!(result, Err(AppError::Forbidden(_))));
+
+            let updated = OrganizationMember::update(owner, membership.id, promoted).await?;
+            assert_eq!(updated.role, OrganizationRole::Owner);
+
+            Ok(())
+        })
+        .await
+    }
+}
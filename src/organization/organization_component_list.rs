@@ -17,6 +17,7 @@ use crate::components::Button;
 #[cfg(feature = "ssr")]
 use surrealdb::RecordId;
 
+use crate::live::{LIVE_ORGANIZATIONS_URL, use_live_refetch};
 use leptos::prelude::*;
 use leptos_router::hooks::{use_navigate, use_params};
 use leptos_router::params::Params;
@@ -275,6 +276,7 @@ fn OrganizationCard(organization: Organization) -> impl IntoView {
 pub fn OrganizationList() -> impl IntoView {
     let navigate = use_navigate();
     let orgs_resource = Resource::new(|| (), |_| async move { get_user_organizations().await });
+    use_live_refetch(LIVE_ORGANIZATIONS_URL, orgs_resource);
 
     view! {
         <div>
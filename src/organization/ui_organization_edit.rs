@@ -1,5 +1,7 @@
 use std::str::FromStr;
 
+use crate::AppError;
+use crate::components::ConflictAlert;
 use crate::organization::organization::Organization;
 
 use crate::organization::ui_organization_form::{
@@ -31,10 +33,12 @@ async fn get_organization_for_edit(org_id: RecordId) -> Result<Organization, Ser
 
 #[server]
 async fn update_organization(
-    _org_id: RecordId,
+    org_id: RecordId,
     update_data: Organization,
-) -> Result<Organization, ServerFnError> {
-    let updated = update_data.update_self().await?;
+) -> Result<Organization, AppError> {
+    let user = crate::session::get_user().await?;
+    let updated =
+        <Organization as StorageAuthed<_, _>>::update(user, org_id, update_data).await?;
     Ok(updated)
 }
 
@@ -135,6 +139,18 @@ pub fn OrganizationEdit() -> impl IntoView {
                                                 // Error display
                                                 {move || {
                                                     if let Some(Err(e)) = update_action.value().get() {
+                                                        if e.is_conflict() {
+                                                            return view! {
+                                                                <ConflictAlert
+                                                                    class="mt-4"
+                                                                    on_reload=Callback::new(move |_| {
+                                                                        update_action.value().set(None);
+                                                                        org_resource.refetch();
+                                                                    })
+                                                                />
+                                                            }
+                                                                .into_any();
+                                                        }
                                                         view! {
                                                             <div class="mt-4 bg-red-50 dark:bg-red-900/20 border border-red-200 dark:border-red-800 rounded-md p-4">
                                                                 <p class="text-red-800 dark:text-red-400">
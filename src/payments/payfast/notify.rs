@@ -2,6 +2,8 @@ use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "ssr")]
 use crate::AppError;
+#[cfg(feature = "ssr")]
+use crate::db::Transaction;
 
 use crate::RecordId;
 
@@ -91,12 +93,12 @@ pub async fn handle_payfast_notify_internal(
         .nth(1)
         .ok_or_else(|| AppError::GenericError("Invalid order UUID format".into()))?;
 
-    // Initialize database connection
-    let db = crate::db_init().await?;
+    let order = RecordId::from(("order", order_id));
+    let complete = notify.payment_status == "COMPLETE";
 
     // Create payment record
     let payment_record = PaymentRecord {
-        order: RecordId::from(("order", order_id)),
+        order: order.clone(),
         amount_fee: notify.amount_fee.clone(),
         amount_gross: notify.amount_gross.clone(),
         amount_net: notify.amount_net.clone(),
@@ -117,25 +119,31 @@ pub async fn handle_payfast_notify_internal(
         extra: notify.clone(),
     };
 
-    // Insert payment record into database
-    let payment = db.create("payments").content(payment_record).await?;
-
-    // If payment is complete, update order status
-    if notify.payment_status == "COMPLETE" {
+    // Insert the payment and, if complete, mark the order paid in one transaction
+    let mut tx = Transaction::new();
+    tx.bind("payment_record", payment_record);
+    tx.bind("order", order.clone());
+    let payment = tx.statement::<Option<PaymentRecord>>(
+        "CREATE ONLY payments CONTENT $payment_record",
+    );
+    if complete {
         // Update order paid status
-        let query = format!("UPDATE order:{} SET paid = true;", order_id);
-        let _result = db.query(query).await?;
+        tx.statement::<surrealdb::Value>("UPDATE $order SET paid = true RETURN NONE");
+    }
 
+    let payment = tx.commit().await?.take(payment)?;
+
+    if complete {
         return Ok(HandlePayfastResult {
             payment_status: PaymentStatus::Complete,
-            order_id: RecordId::from(("order", order_id)),
+            order_id: order,
             payment,
         });
     }
 
     Ok(HandlePayfastResult {
         payment_status: PaymentStatus::Failed,
-        order_id: RecordId::from(("order", order_id)),
+        order_id: order,
         payment,
     })
 }
@@ -147,6 +155,9 @@ pub async fn handle_payfast_notify(notify: PayFastNotify) -> Result<(), AppError
     Ok(())
 }
 
+/// Path `PayFastButton` sends as `notify_url`, exempt it from `CsrfLayer`.
+pub const PAYFAST_NOTIFY_PATH: &str = "/api/payment/notify";
+
 /// Axum handler for PayFast IPN webhook
 /// This receives POST form data from PayFast
 #[cfg(feature = "ssr")]
@@ -2,6 +2,7 @@ use leptos::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::components::{Button, button::BtnColor};
+use crate::payments::payfast::notify::PAYFAST_NOTIFY_PATH;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct PayFastOptions {
@@ -77,7 +78,7 @@ pub fn PayFastButton(
 
         let return_url = format!("{}/payment/{}/success", origin, payment_id);
         let cancel_url = format!("{}/cart", origin);
-        let notify_url = format!("{}/api/payment/notify", origin);
+        let notify_url = format!("{}{}", origin, PAYFAST_NOTIFY_PATH);
         let full_address = format!("{} , {}", address, province);
         let amount_formatted = format!("{:.2}", payment_confirm_amount);
 
@@ -1,12 +1,15 @@
 pub mod avatar_edit;
 pub mod home;
+pub mod passkeys;
 pub mod profile;
+pub mod sessions;
+pub mod two_factor;
 pub mod upload;
 
 use leptos::prelude::*;
 
 use leptos_router::{
-    components::{Route, Routes},
+    components::Routes,
     path,
 };
 
@@ -14,8 +17,12 @@ use leptos_router::{
 pub mod upload_ssr;
 
 pub use home::SettingsHome;
+pub use passkeys::PasskeySettings;
+pub use sessions::SessionsControl;
+pub use two_factor::TwoFactorSettings;
 
 use crate::{
+    ProtectedRoute,
     keys::KeysControl,
     organization::{
         organization_component_list::OrganizationList, ui_organization_new::NewOrganizationForm,
@@ -28,10 +35,13 @@ pub fn SettingsRouter() -> impl IntoView {
         <div>
 
             <Routes fallback=|| "Page not found.".into_view()>
-                <Route path=path!("/settings") view=SettingsHome />
-                <Route path=path!("/settings/keys") view=KeysControl />
-                <Route path=path!("/settings/organizations") view=OrganizationList />
-                <Route path=path!("/users/organizations/new") view=NewOrganizationForm />
+                <ProtectedRoute path=path!("/settings") view=SettingsHome />
+                <ProtectedRoute path=path!("/settings/keys") view=KeysControl />
+                <ProtectedRoute path=path!("/settings/sessions") view=SessionsControl />
+                <ProtectedRoute path=path!("/settings/security") view=TwoFactorSettings />
+                <ProtectedRoute path=path!("/settings/passkeys") view=PasskeySettings />
+                <ProtectedRoute path=path!("/settings/organizations") view=OrganizationList />
+                <ProtectedRoute path=path!("/users/organizations/new") view=NewOrganizationForm />
             </Routes>
         </div>
     }
@@ -0,0 +1,163 @@
+use crate::{
+    components::{
+        Button, FormField, Input, InputType,
+        alert::{Alert, AlertSeverity},
+        button::BtnColor,
+        heading::{Heading, SubHeading},
+    },
+    passkey::{REVERIFY_WINDOW_MINUTES, list_passkeys, register_passkey, remove_passkey},
+};
+use leptos::{prelude::*, reactive::spawn_local};
+
+/// Registers, lists and removes the passkeys of the signed in user.
+#[component]
+pub fn PasskeySettings() -> impl IntoView {
+    let version = RwSignal::new(0);
+    let passkeys = Resource::new(move || version.get(), |_| list_passkeys());
+
+    let name = RwSignal::new(String::new());
+    let code = RwSignal::new(String::new());
+    let has_passkeys = move || {
+        passkeys
+            .get()
+            .and_then(Result::ok)
+            .is_some_and(|passkeys| !passkeys.is_empty())
+    };
+    let (is_adding, set_is_adding) = signal(false);
+    let (error, set_error) = signal(Option::<String>::None);
+
+    let on_add = Callback::new(move |_| {
+        if is_adding.get_untracked() {
+            return;
+        }
+        set_error.set(None);
+        set_is_adding.set(true);
+        spawn_local(async move {
+            let two_factor_code = Some(code.get_untracked()).filter(|code| !code.is_empty());
+            match register_passkey(name.get_untracked(), two_factor_code).await {
+                Ok(_) => {
+                    name.set(String::new());
+                    code.set(String::new());
+                    version.update(|v| *v += 1);
+                }
+                Err(e) => set_error.set(Some(e)),
+            }
+            set_is_adding.set(false);
+        });
+    });
+
+    view! {
+        <div class="p-4 bg-white dark:bg-neutral-800 rounded-lg shadow flex flex-col gap-5">
+            <div>
+                <Heading>"Passkeys"</Heading>
+                <SubHeading>
+                    "Sign in with your fingerprint, face or device PIN, and confirm sensitive actions"
+                </SubHeading>
+            </div>
+
+            {move || {
+                error.get().map(|e| view! { <Alert severity=AlertSeverity::Error>{e}</Alert> })
+            }}
+
+            <Suspense fallback=|| ()>
+                {move || {
+                    passkeys
+                        .get()
+                        .map(|passkeys| match passkeys {
+                            Ok(passkeys) if passkeys.is_empty() => {
+                                view! {
+                                    <p class="text-sm text-neutral-600 dark:text-neutral-400">
+                                        "No passkeys yet."
+                                    </p>
+                                }
+                                    .into_any()
+                            }
+                            Ok(passkeys) => {
+                                view! {
+                                    <div class="divide-y divide-neutral-200 dark:divide-neutral-700">
+                                        {passkeys
+                                            .into_iter()
+                                            .map(|passkey| {
+                                                let id = passkey.id.clone();
+                                                let on_delete = Callback::new(move |_| {
+                                                    let id = id.clone();
+                                                    set_error.set(None);
+                                                    spawn_local(async move {
+                                                        match remove_passkey(id).await {
+                                                            Ok(()) => version.update(|v| *v += 1),
+                                                            Err(e) => set_error.set(Some(e)),
+                                                        }
+                                                    });
+                                                });
+
+                                                view! {
+                                                    <div class="flex items-center justify-between py-3">
+                                                        <div>
+                                                            <p class="text-sm font-medium text-neutral-900 dark:text-neutral-100">
+                                                                {passkey.name.clone()}
+                                                            </p>
+                                                            <p class="text-xs text-neutral-500 dark:text-neutral-400">
+                                                                {passkey
+                                                                    .last_used
+                                                                    .map(|at| format!("Last used {}", at))
+                                                                    .unwrap_or_else(|| "Never used".to_string())}
+                                                            </p>
+                                                        </div>
+                                                        <Button color=BtnColor::Error on_click=on_delete>
+                                                            "Remove"
+                                                        </Button>
+                                                    </div>
+                                                }
+                                            })
+                                            .collect_view()}
+                                    </div>
+                                }
+                                    .into_any()
+                            }
+                            Err(e) => {
+                                view! {
+                                    <Alert severity=AlertSeverity::Error>
+                                        "Error loading passkeys: " {e.to_string()}
+                                    </Alert>
+                                }
+                                    .into_any()
+                            }
+                        })
+                }}
+            </Suspense>
+
+            <div class="flex flex-col gap-4">
+                <FormField label="Name" label_for="passkey_name" help_text="To tell your devices apart">
+                    <Input
+                        id="passkey_name"
+                        name="passkey_name"
+                        r#type=InputType::Text
+                        placeholder="Work laptop"
+                        value=name
+                    />
+                </FormField>
+                <Show when=move || !has_passkeys()>
+                    <FormField
+                        label="Two-factor code"
+                        label_for="passkey_code"
+                        help_text=format!(
+                            "Needed if you use two-factor and signed in more than {} minutes ago",
+                            REVERIFY_WINDOW_MINUTES,
+                        )
+                    >
+                        <Input
+                            id="passkey_code"
+                            name="passkey_code"
+                            r#type=InputType::Text
+                            placeholder="123456"
+                            value=code
+                        />
+                    </FormField>
+                </Show>
+                <Button color=BtnColor::Primary on_click=on_add>
+                    "Add a passkey"
+                </Button>
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,252 @@
+use crate::{
+    api_key::RevokeAllApiKeys,
+    components::{
+        Button,
+        alert::{Alert, AlertSeverity},
+        button::BtnColor,
+        heading::{Heading, SubHeading},
+    },
+    date_utils::FormatDatetime,
+    session::{RevokeOtherSessions, RevokeSession, SessionInfo, list_sessions},
+};
+use leptos::prelude::*;
+
+/// Short "Browser on OS" label for a user agent string.
+pub fn describe_user_agent(user_agent: &str) -> String {
+    let browser = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+        ("curl/", "curl"),
+    ]
+    .into_iter()
+    .find(|(marker, _)| user_agent.contains(marker))
+    .map(|(_, name)| name);
+
+    let os = [
+        ("iPhone", "iOS"),
+        ("iPad", "iPadOS"),
+        ("Android", "Android"),
+        ("Windows", "Windows"),
+        ("Mac OS X", "macOS"),
+        ("Linux", "Linux"),
+    ]
+    .into_iter()
+    .find(|(marker, _)| user_agent.contains(marker))
+    .map(|(_, name)| name);
+
+    match (browser, os) {
+        (Some(browser), Some(os)) => format!("{browser} on {os}"),
+        (Some(name), None) | (None, Some(name)) => name.to_string(),
+        (None, None) => "Unknown device".to_string(),
+    }
+}
+
+#[component]
+pub fn SessionItem(session: SessionInfo, revoke: ServerAction<RevokeSession>) -> impl IntoView {
+    let device = session
+        .user_agent
+        .as_deref()
+        .map(describe_user_agent)
+        .unwrap_or_else(|| "Unknown device".to_string());
+    let id = session.id.clone();
+
+    view! {
+        <div class="px-4 py-3 hover:bg-neutral-50 dark:hover:bg-neutral-700">
+            <div class="flex items-center justify-between gap-4">
+                <div class="flex-1">
+                    <div class="flex items-center gap-2">
+                        <h3 class="text-sm font-medium text-neutral-900 dark:text-neutral-100">
+                            {device}
+                        </h3>
+                        {session
+                            .current
+                            .then(|| {
+                                view! {
+                                    <span class="text-xs text-emerald-600 dark:text-emerald-400">
+                                        "(this device)"
+                                    </span>
+                                }
+                            })}
+                    </div>
+                    <div class="flex flex-wrap items-center gap-4 mt-2 text-xs text-neutral-600 dark:text-neutral-400">
+                        <span class="font-mono">
+                            {session.ip.clone().unwrap_or_else(|| "Unknown address".to_string())}
+                        </span>
+                        {session
+                            .created_at
+                            .as_ref()
+                            .map(|created_at| {
+                                view! { <span>"Signed in: " {created_at.format_date()}</span> }
+                            })}
+                        {session
+                            .last_seen
+                            .as_ref()
+                            .map(|last_seen| {
+                                view! { <span>"Last active: " {last_seen.ago()}</span> }
+                            })}
+                    </div>
+                </div>
+                {(!session.current)
+                    .then(|| {
+                        view! {
+                            <Button
+                                color=BtnColor::ErrorSubtle
+                                on_click=Callback::new(move |_| {
+                                    revoke.dispatch(RevokeSession { id: id.clone() });
+                                })
+                            >
+                                "Revoke"
+                            </Button>
+                        }
+                    })}
+            </div>
+        </div>
+    }
+}
+
+/// Lists the signed in user's sessions with controls to revoke them.
+#[component]
+pub fn SessionsControl() -> impl IntoView {
+    let revoke = ServerAction::<RevokeSession>::new();
+    let revoke_others = ServerAction::<RevokeOtherSessions>::new();
+    let revoke_keys = ServerAction::<RevokeAllApiKeys>::new();
+
+    let sessions_resource = Resource::new(
+        move || (revoke.version().get(), revoke_others.version().get()),
+        |_| list_sessions(),
+    );
+
+    let error = move || {
+        let revoke_error = revoke.value().get().and_then(|r| r.err());
+        let others_error = revoke_others.value().get().and_then(|r| r.err());
+        let keys_error = revoke_keys.value().get().and_then(|r| r.err());
+        revoke_error.or(others_error).or(keys_error)
+    };
+
+    view! {
+        <div class="p-4 bg-white dark:bg-neutral-800 rounded-lg shadow">
+            <div class="flex items-start justify-between gap-4 mb-6">
+                <div>
+                    <Heading>"Sessions"</Heading>
+                    <SubHeading>"Devices signed in to your account"</SubHeading>
+                </div>
+                <div class="flex flex-wrap justify-end gap-2">
+                    <Button
+                        color=BtnColor::Error
+                        on_click=Callback::new(move |_| {
+                            revoke_others.dispatch(RevokeOtherSessions {});
+                        })
+                    >
+                        "Log out all other sessions"
+                    </Button>
+                    <Button
+                        color=BtnColor::Error
+                        on_click=Callback::new(move |_| {
+                            revoke_keys.dispatch(RevokeAllApiKeys {});
+                        })
+                    >
+                        "Revoke all API keys"
+                    </Button>
+                </div>
+            </div>
+
+            {move || {
+                error()
+                    .map(|e| {
+                        view! { <Alert severity=AlertSeverity::Error>{e.to_string()}</Alert> }
+                    })
+            }}
+
+            {move || {
+                revoke_others
+                    .value()
+                    .get()
+                    .and_then(|r| r.ok())
+                    .map(|count| {
+                        view! {
+                            <Alert severity=AlertSeverity::Success>
+                                {format!("Logged out of {count} other session(s).")}
+                            </Alert>
+                        }
+                    })
+            }}
+
+            {move || {
+                revoke_keys
+                    .value()
+                    .get()
+                    .and_then(|r| r.ok())
+                    .map(|count| {
+                        view! {
+                            <Alert severity=AlertSeverity::Success>
+                                {format!("Revoked {count} API key(s).")}
+                            </Alert>
+                        }
+                    })
+            }}
+
+            <Suspense fallback=move || {
+                view! {
+                    <div class="animate-pulse space-y-4 p-6">
+                        <div class="h-4 bg-neutral-200 dark:bg-neutral-700 rounded w-3/4"></div>
+                        <div class="h-4 bg-neutral-200 dark:bg-neutral-700 rounded w-1/2"></div>
+                    </div>
+                }
+            }>
+                {move || {
+                    sessions_resource
+                        .get()
+                        .map(|sessions| match sessions {
+                            Ok(sessions) => {
+                                view! {
+                                    <div class="divide-y divide-neutral-200 dark:divide-neutral-700">
+                                        {sessions
+                                            .into_iter()
+                                            .map(|session| {
+                                                view! { <SessionItem session=session revoke=revoke /> }
+                                            })
+                                            .collect_view()}
+                                    </div>
+                                }
+                                    .into_any()
+                            }
+                            Err(e) => {
+                                view! {
+                                    <Alert severity=AlertSeverity::Error>
+                                        "Error loading sessions: " {e.to_string()}
+                                    </Alert>
+                                }
+                                    .into_any()
+                            }
+                        })
+                }}
+            </Suspense>
+        </div>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_user_agent() {
+        assert_eq!(
+            describe_user_agent(
+                "Mozilla/5.0 (Macintosh; Intel Mac OS X 14_5) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Safari/605.1.15"
+            ),
+            "Safari on macOS"
+        );
+        assert_eq!(
+            describe_user_agent(
+                "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36 Edg/126.0.0.0"
+            ),
+            "Edge on Windows"
+        );
+        assert_eq!(describe_user_agent("curl/8.7.1"), "curl");
+        assert_eq!(describe_user_agent(""), "Unknown device");
+    }
+}
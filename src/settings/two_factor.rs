@@ -0,0 +1,216 @@
+use crate::{
+    components::{
+        Button, FormField, Input, InputType,
+        alert::{Alert, AlertSeverity},
+        button::BtnColor,
+        heading::{Heading, SubHeading},
+    },
+    two_factor::{
+        TwoFactorEnrollment, confirm_two_factor_enrollment, disable_two_factor,
+        get_two_factor_status, regenerate_recovery_codes, start_two_factor_enrollment,
+    },
+};
+use leptos::{prelude::*, reactive::spawn_local};
+
+/// Recovery codes, shown once after enabling or regenerating them.
+#[component]
+fn RecoveryCodes(codes: Vec<String>) -> impl IntoView {
+    view! {
+        <Alert severity=AlertSeverity::Warning>
+            "Save these recovery codes somewhere safe. Each one signs you in once if you lose your authenticator."
+        </Alert>
+        <div class="grid grid-cols-2 gap-2 font-mono text-sm p-4 bg-neutral-50 dark:bg-neutral-900 rounded">
+            {codes.into_iter().map(|code| view! { <span>{code}</span> }).collect_view()}
+        </div>
+    }
+}
+
+/// Enrolls, disables and manages TOTP two-factor authentication of the signed in user.
+#[component]
+pub fn TwoFactorSettings() -> impl IntoView {
+    let version = RwSignal::new(0);
+    let status = Resource::new(move || version.get(), |_| get_two_factor_status());
+
+    let enrollment = RwSignal::new(Option::<TwoFactorEnrollment>::None);
+    let recovery_codes = RwSignal::new(Vec::<String>::new());
+    let code = RwSignal::new(String::new());
+    let (error, set_error) = signal(Option::<String>::None);
+
+    let on_start = Callback::new(move |_| {
+        set_error.set(None);
+        spawn_local(async move {
+            match start_two_factor_enrollment().await {
+                Ok(started) => enrollment.set(Some(started)),
+                Err(e) => set_error.set(Some(e.to_string())),
+            }
+        });
+    });
+
+    let on_confirm = Callback::new(move |_| {
+        set_error.set(None);
+        spawn_local(async move {
+            match confirm_two_factor_enrollment(code.get_untracked()).await {
+                Ok(codes) => {
+                    enrollment.set(None);
+                    recovery_codes.set(codes);
+                    code.set(String::new());
+                    version.update(|v| *v += 1);
+                }
+                Err(e) => set_error.set(Some(e.to_string())),
+            }
+        });
+    });
+
+    let on_regenerate = Callback::new(move |_| {
+        set_error.set(None);
+        spawn_local(async move {
+            match regenerate_recovery_codes(code.get_untracked()).await {
+                Ok(codes) => {
+                    recovery_codes.set(codes);
+                    code.set(String::new());
+                    version.update(|v| *v += 1);
+                }
+                Err(e) => set_error.set(Some(e.to_string())),
+            }
+        });
+    });
+
+    let on_disable = Callback::new(move |_| {
+        set_error.set(None);
+        spawn_local(async move {
+            match disable_two_factor(code.get_untracked()).await {
+                Ok(()) => {
+                    recovery_codes.set(Vec::new());
+                    code.set(String::new());
+                    version.update(|v| *v += 1);
+                }
+                Err(e) => set_error.set(Some(e.to_string())),
+            }
+        });
+    });
+
+    let code_input = move || {
+        view! {
+            <FormField
+                label="Code"
+                label_for="two_factor_code"
+                help_text="From your authenticator app, or a recovery code"
+            >
+                <Input
+                    id="two_factor_code"
+                    name="two_factor_code"
+                    r#type=InputType::Text
+                    placeholder="123456"
+                    value=code
+                    autocomplete="one-time-code"
+                />
+            </FormField>
+        }
+    };
+
+    view! {
+        <div class="p-4 bg-white dark:bg-neutral-800 rounded-lg shadow flex flex-col gap-5">
+            <div>
+                <Heading>"Two-factor authentication"</Heading>
+                <SubHeading>"Ask for a code from an authenticator app after signing in"</SubHeading>
+            </div>
+
+            {move || {
+                error.get().map(|e| view! { <Alert severity=AlertSeverity::Error>{e}</Alert> })
+            }}
+
+            {move || {
+                let codes = recovery_codes.get();
+                (!codes.is_empty()).then(|| view! { <RecoveryCodes codes=codes /> })
+            }}
+
+            <Suspense fallback=|| ()>
+                {move || {
+                    status
+                        .get()
+                        .map(|status| match status {
+                            Ok(status) if status.enabled => {
+                                view! {
+                                    <div class="flex flex-col gap-4">
+                                        <Alert severity=AlertSeverity::Success>
+                                            {format!(
+                                                "Enabled, {} recovery codes left.",
+                                                status.recovery_codes_left,
+                                            )}
+                                        </Alert>
+                                        {code_input()}
+                                        <div class="flex gap-2">
+                                            <Button on_click=on_regenerate>
+                                                "New recovery codes"
+                                            </Button>
+                                            <Button color=BtnColor::Error on_click=on_disable>
+                                                "Disable"
+                                            </Button>
+                                        </div>
+                                    </div>
+                                }
+                                    .into_any()
+                            }
+                            Ok(status) => {
+                                view! {
+                                    <div class="flex flex-col gap-4">
+                                        {status
+                                            .required
+                                            .then(|| {
+                                                view! {
+                                                    <Alert severity=AlertSeverity::Warning>
+                                                        "Admin access needs two-factor authentication."
+                                                    </Alert>
+                                                }
+                                            })}
+                                        {move || match enrollment.get() {
+                                            Some(started) => {
+                                                view! {
+                                                    <div class="flex flex-col gap-4">
+                                                        <p class="text-sm text-neutral-600 dark:text-neutral-400">
+                                                            "Add this key to your authenticator app, then enter the code it shows."
+                                                        </p>
+                                                        <a
+                                                            href=started.otpauth_uri.clone()
+                                                            class="text-sm text-sky-600 dark:text-sky-400 break-all"
+                                                        >
+                                                            {started.otpauth_uri.clone()}
+                                                        </a>
+                                                        <code class="font-mono text-sm p-2 bg-neutral-50 dark:bg-neutral-900 rounded break-all">
+                                                            {started.secret.clone()}
+                                                        </code>
+                                                        {code_input()}
+                                                        <Button color=BtnColor::Primary on_click=on_confirm>
+                                                            "Enable"
+                                                        </Button>
+                                                    </div>
+                                                }
+                                                    .into_any()
+                                            }
+                                            None => {
+                                                view! {
+                                                    <Button color=BtnColor::Primary on_click=on_start>
+                                                        "Set up two-factor authentication"
+                                                    </Button>
+                                                }
+                                                    .into_any()
+                                            }
+                                        }}
+                                    </div>
+                                }
+                                    .into_any()
+                            }
+                            Err(e) => {
+                                view! {
+                                    <Alert severity=AlertSeverity::Error>
+                                        "Error loading two-factor status: " {e.to_string()}
+                                    </Alert>
+                                }
+                                    .into_any()
+                            }
+                        })
+                }}
+            </Suspense>
+        </div>
+    }
+}
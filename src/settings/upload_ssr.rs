@@ -2,7 +2,7 @@
 use axum::{extract::Multipart, Json};
 
 #[cfg(feature = "ssr")]
-use axum_extra::extract::cookie::CookieJar;
+use crate::auth::extract::CurrentUser;
 
 #[cfg(feature = "ssr")]
 use crate::AppError;
@@ -21,23 +21,12 @@ pub struct UploadResponse {
 
 #[cfg(feature = "ssr")]
 pub async fn upload_avatar_handler(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     mut multipart: Multipart,
 ) -> Result<Json<UploadResponse>, AppError> {
     use std::fs;
     use std::path::Path;
 
-    // Get the session_token cookie
-    let session_token = jar.get("session_token").map(|cookie| cookie.value());
-
-    let user = if let Some(token) = session_token {
-        use crate::user::AdapterUser;
-
-        AdapterUser::get_user_from_session(token.to_string()).await?
-    } else {
-        return Err(AppError::AuthError("No session token found".into()));
-    };
-
     // Create uploads directory if it doesn't exist
     let upload_dir = "uploads/avatars";
     fs::create_dir_all(upload_dir)?;
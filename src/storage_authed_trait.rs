@@ -10,6 +10,274 @@ use crate::user::AdapterUser;
 #[cfg(feature = "ssr")]
 use surrealdb::RecordId;
 
+#[cfg(feature = "ssr")]
+use std::collections::BTreeMap;
+
+#[cfg(feature = "ssr")]
+use crate::db::{Filter, Statement, Transaction};
+#[cfg(feature = "ssr")]
+use crate::db::live::{LiveStream, watch_table};
+
+/// The `id` of a stored item, read without a query.
+#[cfg(feature = "ssr")]
+#[derive(Deserialize)]
+struct RecordRef {
+    id: RecordId,
+}
+
+/// Operation checked by `StorageAuthed::authorize`.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Update,
+    Delete,
+}
+
+/// Returns the `created_by_user_id` of a record, `NotFound` if the record does not exist.
+#[cfg(feature = "ssr")]
+pub async fn record_creator(id: &RecordId) -> Result<Option<RecordId>, AppError> {
+    let db = crate::db_init().await?;
+
+    let mut result = db
+        .query("SELECT VALUE created_by_user_id FROM $id;")
+        .bind(("id", id.clone()))
+        .await?;
+
+    let creators: Vec<Option<RecordId>> = result.take(0)?;
+
+    creators
+        .into_iter()
+        .next()
+        .ok_or(AppError::NotFound("Item not found".into()))
+}
+
+/// What a `HistoryEntry` records.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum HistoryAction {
+    Update,
+    Delete,
+    Restore,
+}
+
+/// How one field changed, values rendered as SurrealQL (strings as they are) and `None` where
+/// the field was missing. Fields in `StorageAuthed::HISTORY_REDACTED` only record that they changed.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FieldChange {
+    #[serde(default)]
+    pub before: Option<String>,
+    #[serde(default)]
+    pub after: Option<String>,
+    #[serde(default)]
+    pub redacted: bool,
+}
+
+/// One change to a record, stored append-only in `<table>_history`. `changes` holds the fields
+/// that differ before and after the change, every field for a hard delete.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: RecordId,
+    pub record: RecordId,
+    pub action: HistoryAction,
+    /// `None` for changes made by trusted server code, e.g. `update_self`
+    #[serde(default)]
+    pub user_id: Option<RecordId>,
+    #[serde(default)]
+    pub changes: BTreeMap<String, FieldChange>,
+    pub created_at: surrealdb::Datetime,
+}
+
+/// Appends the fields that differ between `$before` and `$after` of the record `$id` to its
+/// history table, without the values of the fields in `$redacted`.
+#[cfg(feature = "ssr")]
+fn record_history(tx: &mut Transaction, action: HistoryAction) {
+    tx.statement::<surrealdb::Value>("LET $old = $before ?? {}");
+    tx.statement::<surrealdb::Value>("LET $new = $after ?? {}");
+    tx.statement::<surrealdb::Value>(
+        "LET $changes = object::from_entries(
+            SELECT VALUE [$this, IF $this IN $redacted THEN { redacted: true } ELSE {
+                before: IF $old[$this] != NONE THEN <string> $old[$this] END,
+                after: IF $new[$this] != NONE THEN <string> $new[$this] END
+            } END]
+            FROM array::union(object::keys($old), object::keys($new))
+            WHERE $old[$this] != $new[$this]
+        )",
+    );
+    tx.statement::<surrealdb::Value>(format!(
+        r#"CREATE type::table(record::tb($id) + "_history") CONTENT {{
+            record: $id,
+            action: "{action:?}",
+            user_id: $user_id,
+            changes: $changes,
+            created_at: time::now()
+        }} RETURN NONE"#
+    ));
+}
+
+/// Merges `content` into the record `id` (or `content.id` when `id` is `None`) in one transaction
+/// and appends the change, made by `user_id`, to the record's history. The values of the
+/// `redacted` fields are left out of the history.
+///
+/// The record's `version` is incremented and `updated_at` set, while `created_by_user_id`,
+/// `created_at` and `deleted_at` keep their stored values whatever `content` contains. When
+/// `content` has a `version` field it must match the stored version (missing counts as `0`),
+/// otherwise nothing is written and `AppError::Conflict` is returned. Soft deleted records are
+/// not found, `restore_recorded` them first.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     #[derive(Serialize)]
+///     struct Rename { label: String, version: u64 }
+///
+///     let wallet: Wallet =
+///         update_versioned(Some(wallet.id), Rename { label, version }, Some(user.id), &[]).await?;
+/// ```
+#[cfg(feature = "ssr")]
+pub async fn update_versioned<T>(
+    id: Option<RecordId>,
+    content: impl Serialize + 'static,
+    user_id: Option<RecordId>,
+    redacted: &'static [&'static str],
+) -> Result<T, AppError>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let mut tx = Transaction::new();
+    let updated = update_versioned_in::<T>(&mut tx, id, content, user_id, redacted);
+
+    let mut result = tx.commit().await?;
+
+    result
+        .take(updated)?
+        .ok_or(AppError::NotFound("Item not found".into()))
+}
+
+/// Adds the statements of `update_versioned` to `tx`, for writes that have to commit together
+/// with the update. Later statements can use `$id`, `$before` and `$after`.
+///
+/// ### EXAMPLE:
+/// ```rs
+///     let mut tx = Transaction::new();
+///     let updated = update_versioned_in::<Wallet>(&mut tx, Some(id), changes, Some(user.id), &[]);
+///     tx.statement::<Value>("UPDATE wallet SET is_primary = false WHERE created_by_user_id = $after.created_by_user_id AND id != $id");
+///     let wallet = tx.commit().await?.take(updated)?;
+/// ```
+#[cfg(feature = "ssr")]
+pub fn update_versioned_in<T>(
+    tx: &mut Transaction,
+    id: Option<RecordId>,
+    content: impl Serialize + 'static,
+    user_id: Option<RecordId>,
+    redacted: &'static [&'static str],
+) -> Statement<Option<T>> {
+    tx.bind("target", id);
+    tx.bind("content", content);
+    tx.bind("user_id", user_id);
+    tx.bind("redacted", redacted);
+
+    tx.statement::<surrealdb::Value>("LET $id = $target ?? $content.id");
+    tx.statement::<surrealdb::Value>("LET $before = (SELECT * FROM ONLY $id)");
+    tx.guard(
+        "$before != NONE AND $before.deleted_at = NONE",
+        AppError::NotFound("Item not found".into()),
+    );
+    tx.guard(
+        "$content.version = NONE OR $content.version = ($before.version ?? 0)",
+        AppError::Conflict("This record was changed by someone else, reload it and try again".into()),
+    );
+    tx.statement::<surrealdb::Value>("UPDATE $id MERGE $content RETURN NONE");
+    tx.statement::<surrealdb::Value>(
+        "LET $after = (UPDATE ONLY $id SET
+            version = ($before.version ?? 0) + 1,
+            created_by_user_id = $before.created_by_user_id,
+            created_at = $before.created_at,
+            deleted_at = $before.deleted_at,
+            updated_at = time::now())",
+    );
+    record_history(tx, HistoryAction::Update);
+    tx.statement::<Option<T>>("$after")
+}
+
+/// Deletes the record `id` and appends the deletion, made by `user_id`, to its history without
+/// the values of the `redacted` fields. A `soft` delete only sets `deleted_at` and
+/// `deleted_by_user_id` so the record can be restored.
+#[cfg(feature = "ssr")]
+pub async fn delete_recorded(
+    id: RecordId,
+    user_id: Option<RecordId>,
+    soft: bool,
+    redacted: &'static [&'static str],
+) -> Result<bool, AppError> {
+    let mut tx = Transaction::new();
+    tx.bind("id", id);
+    tx.bind("user_id", user_id);
+    tx.bind("redacted", redacted);
+
+    tx.statement::<surrealdb::Value>("LET $before = (SELECT * FROM ONLY $id)");
+    tx.guard(
+        "$before != NONE AND $before.deleted_at = NONE",
+        AppError::NotFound("Item not found".into()),
+    );
+
+    if soft {
+        tx.statement::<surrealdb::Value>(
+            "LET $after = (UPDATE ONLY $id SET
+                deleted_at = time::now(),
+                deleted_by_user_id = $user_id,
+                updated_at = time::now())",
+        );
+    } else {
+        tx.statement::<surrealdb::Value>("DELETE $id");
+        tx.statement::<surrealdb::Value>("LET $after = NONE");
+    }
+
+    record_history(&mut tx, HistoryAction::Delete);
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// Clears `deleted_at` on a soft deleted record and appends the restore to its history.
+#[cfg(feature = "ssr")]
+pub async fn restore_recorded<T>(
+    id: RecordId,
+    user_id: Option<RecordId>,
+    redacted: &'static [&'static str],
+) -> Result<T, AppError>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let mut tx = Transaction::new();
+    tx.bind("id", id);
+    tx.bind("user_id", user_id);
+    tx.bind("redacted", redacted);
+
+    tx.statement::<surrealdb::Value>("LET $before = (SELECT * FROM ONLY $id)");
+    tx.guard("$before != NONE", AppError::NotFound("Item not found".into()));
+    tx.guard(
+        "$before.deleted_at != NONE",
+        AppError::ErrorReason("Item is not deleted".into()),
+    );
+    tx.statement::<surrealdb::Value>(
+        "LET $after = (UPDATE ONLY $id SET
+            deleted_at = NONE,
+            deleted_by_user_id = NONE,
+            version = ($before.version ?? 0) + 1,
+            updated_at = time::now())",
+    );
+    record_history(&mut tx, HistoryAction::Restore);
+    let restored = tx.statement::<Option<T>>("$after");
+
+    let mut result = tx.commit().await?;
+
+    result
+        .take(restored)?
+        .ok_or(AppError::NotFound("Item not found".into()))
+}
+
 #[cfg(feature = "ssr")]
 #[allow(async_fn_in_trait)]
 pub trait StorageAuthed<NoId, WithId>
@@ -20,6 +288,14 @@ where
 {
     const TABLE_NAME: &str;
 
+    /// Opt in to soft delete: `delete` only sets `deleted_at`, deleted items are left out of
+    /// `get_many`/`get_by_user` and can be brought back with `restore`.
+    const SOFT_DELETE: bool = false;
+
+    /// Fields whose values are never written to the history, e.g. private keys. Changes to them
+    /// are still recorded, without the values.
+    const HISTORY_REDACTED: &'static [&'static str] = &[];
+
     /// Create or insert a new item
     // async fn create(content: NoId) -> Result<WithId, AppError>;
 
@@ -27,10 +303,15 @@ where
         let db = crate::db_init().await?;
 
         let table = Self::TABLE_NAME;
+        // one CREATE with every field set, live queries never see the record half written
         let query = format!(
             r#"
-            LET $record = CREATE {table} CONTENT $content;
-            UPDATE $record SET created_by_user_id = $user_id, created_at = time::now(), updated_at = time::now();
+            CREATE {table} CONTENT object::from_entries(array::concat(object::entries($content), [
+                ["created_by_user_id", $user_id],
+                ["created_at", time::now()],
+                ["updated_at", time::now()],
+                ["version", 1]
+            ]));
         "#
         );
 
@@ -40,7 +321,7 @@ where
             .bind(("user_id", user.id))
             .await?;
 
-        let created_item: Option<WithId> = result.take(1)?;
+        let created_item: Option<WithId> = result.take(0)?;
 
         match created_item {
             Some(token) => Ok(token),
@@ -48,9 +329,14 @@ where
         }
     }
 
+    /// The item `id`, `NotFound` when it does not exist or is soft deleted.
     async fn get_by_id(id: RecordId) -> Result<WithId, AppError> {
         let db = crate::db_init().await?;
-        let item: Option<WithId> = db.select(id).await?;
+        let item: Option<WithId> = db
+            .query("SELECT * FROM $id WHERE deleted_at = NONE;")
+            .bind(("id", id))
+            .await?
+            .take(0)?;
         match item {
             Some(item) => Ok(item),
             None => Err(AppError::NotFound("Item not found".into())),
@@ -60,7 +346,12 @@ where
     async fn get_many() -> Result<Vec<WithId>, AppError> {
         let db = crate::db_init().await?;
 
-        let items: Vec<WithId> = db.select(Self::TABLE_NAME).await?;
+        let tablename = Self::TABLE_NAME;
+        let mut result = db
+            .query(format!("SELECT * FROM {tablename} WHERE deleted_at = NONE;"))
+            .await?;
+
+        let items: Vec<WithId> = result.take(0)?;
 
         Ok(items)
     }
@@ -70,7 +361,7 @@ where
         let client = db_init().await?;
         let tablename = Self::TABLE_NAME;
         let query = format!(
-            "SELECT * FROM {tablename} WHERE created_by_user_id = $user_id ORDER BY created_at DESC;"
+            "SELECT * FROM {tablename} WHERE created_by_user_id = $user_id AND deleted_at = NONE ORDER BY created_at DESC;"
         );
 
         let mut result = client.query(query).bind(("user_id", user.id)).await?;
@@ -80,74 +371,310 @@ where
         Ok(items)
     }
 
-    async fn update(user: AdapterUser, _id: RecordId, content: WithId) -> Result<WithId, AppError> {
-        // todo look up user permissions;
-
+    /// Soft deleted items created by `user`, most recently deleted first.
+    async fn get_deleted_by_user(user: AdapterUser) -> Result<Vec<WithId>, AppError> {
         let db = crate::db_init().await?;
-
+        let tablename = Self::TABLE_NAME;
         let query = format!(
-            r#"
-            LET $record = UPDATE $content.id MERGE $content;
-            UPDATE $record SET created_by_user_id = $user_id, created_at = time::now(), updated_at = time::now();
-            SELECT * from $record;
-            "#
+            "SELECT * FROM {tablename} WHERE created_by_user_id = $user_id AND deleted_at != NONE ORDER BY deleted_at DESC;"
         );
 
-        let mut response = db
-            .query(query)
-            .bind(("content", content))
-            .bind(("user_id", user.id))
-            .await?;
+        let mut result = db.query(query).bind(("user_id", user.id)).await?;
 
-        let updated_item: Option<WithId> = response.take(2)?;
+        let items: Vec<WithId> = result.take(0)?;
 
-        match updated_item {
-            Some(item) => Ok(item),
-            None => Err(AppError::NotFound("Item not found".into())),
+        Ok(items)
+    }
+
+    /// Streams live changes to the items created by `user`.
+    async fn watch_by_user(user: AdapterUser) -> Result<LiveStream<WithId>, AppError>
+    where
+        WithId: Unpin,
+    {
+        watch_table(
+            Self::TABLE_NAME,
+            Some(Filter::eq("created_by_user_id", user.id)),
+        )
+        .await
+    }
+
+    /// Policy run before `update` and `delete`. Only the creator of a record may change it by
+    /// default; override it for types with shared ownership, e.g. organization roles.
+    async fn authorize(user: &AdapterUser, id: &RecordId, _access: Access) -> Result<(), AppError> {
+        if id.table() != Self::TABLE_NAME {
+            return Err(AppError::Forbidden(format!(
+                "{id} is not a {} record",
+                Self::TABLE_NAME
+            )));
+        }
+
+        match record_creator(id).await? {
+            Some(creator) if creator == user.id => Ok(()),
+            _ => Err(AppError::Forbidden(format!("Not allowed to modify {id}"))),
         }
     }
 
+    /// Policy run before `update` with the new content, `authorize` by default. Override it when
+    /// some fields need more rights than the record, e.g. organization roles.
+    async fn authorize_update(
+        user: &AdapterUser,
+        id: &RecordId,
+        _content: &WithId,
+    ) -> Result<(), AppError> {
+        Self::authorize(user, id, Access::Update).await
+    }
+
+    /// Updates the record `id` when `user` passes `authorize_update`. Fails with `Conflict` when
+    /// `content` has a `version` older than the stored one and `NotFound` for soft deleted
+    /// records, see `update_versioned`.
+    async fn update(user: AdapterUser, id: RecordId, content: WithId) -> Result<WithId, AppError> {
+        Self::authorize_update(&user, &id, &content).await?;
+
+        update_versioned(Some(id), content, Some(user.id), Self::HISTORY_REDACTED).await
+    }
+
+    /// Writes `self` back without a permission check, only for trusted server-side code.
+    /// Versioned like `update`.
     async fn update_self(&self) -> Result<WithId, AppError> {
+        update_versioned(None, self.clone(), None, Self::HISTORY_REDACTED).await
+    }
+
+    /// Deletes the record `id` when `user` passes `authorize`, soft deleting it for `SOFT_DELETE`
+    /// types. The deletion is recorded in the history.
+    async fn delete(user: AdapterUser, id: RecordId) -> Result<bool, AppError> {
+        Self::authorize(&user, &id, Access::Delete).await?;
+
+        delete_recorded(id, Some(user.id), Self::SOFT_DELETE, Self::HISTORY_REDACTED).await
+    }
+
+    /// Deletes `self` without a permission check, only for trusted server-side code.
+    async fn delete_self(&self) -> Result<bool, AppError> {
+        let RecordRef { id } =
+            surrealdb::value::from_value(surrealdb::value::to_value(self.clone())?)
+                .map_err(|_| AppError::NotFound("Item not found".into()))?;
+
+        delete_recorded(id, None, Self::SOFT_DELETE, Self::HISTORY_REDACTED).await
+    }
+
+    /// Brings back a soft deleted record, checked like `delete`.
+    async fn restore(user: AdapterUser, id: RecordId) -> Result<WithId, AppError> {
+        Self::authorize(&user, &id, Access::Delete).await?;
+
+        restore_recorded(id, Some(user.id), Self::HISTORY_REDACTED).await
+    }
+
+    /// Change history of the record `id`, newest first. Visible to users who may update it.
+    async fn history(user: AdapterUser, id: RecordId) -> Result<Vec<HistoryEntry>, AppError> {
+        Self::authorize(&user, &id, Access::Update).await?;
+
         let db = crate::db_init().await?;
+        let tablename = Self::TABLE_NAME;
 
-        let mut query = db
-            .query("UPDATE $content.id CONTENT $content")
-            .bind(("content", self.clone()))
+        let mut result = db
+            .query(format!(
+                "SELECT * FROM {tablename}_history WHERE record = $id ORDER BY created_at DESC;"
+            ))
+            .bind(("id", id))
             .await?;
 
-        let updated_item: Option<WithId> = query.take(0)?;
+        let entries: Vec<HistoryEntry> = result.take(0)?;
 
-        match updated_item {
-            Some(item) => Ok(item),
-            None => Err(AppError::NotFound("Item not found".into())),
+        Ok(entries)
+    }
+}
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use crate::wallet::wallet::{CreateWallet, Wallet};
+
+    #[test]
+    fn test_record_history() {
+        let mut tx = Transaction::new();
+        record_history(&mut tx, HistoryAction::Restore);
+        let query = tx.to_query();
+
+        assert!(query.contains(r#"CREATE type::table(record::tb($id) + "_history")"#));
+        assert!(query.contains(r#"action: "Restore","#));
+        assert!(query.contains("IF $this IN $redacted THEN { redacted: true }"));
+        assert!(query.ends_with("RETURN NONE;\nCOMMIT TRANSACTION;"));
+    }
+
+    fn new_wallet(user: &AdapterUser) -> CreateWallet {
+        let now = surrealdb::Datetime::from(chrono::Utc::now());
+
+        CreateWallet {
+            address: "0x0000000000000000000000000000000000000001".into(),
+            label: "Test".into(),
+            wallet_type: "generated".into(),
+            chain_type: Some("evm".into()),
+            chain_id: None,
+            created_by_user_id: user.id.clone(),
+            created_at: now.clone(),
+            updated_at: now,
+            is_primary: false,
         }
     }
 
-    async fn delete(_user: AdapterUser, id: RecordId) -> Result<bool, AppError> {
-        // todo look up user permissions;
+    #[tokio::test]
+    async fn test_versioned_update() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+
+        with_db(db_memory().await?, async {
+            let owner = AdapterUser::create_test_user().await?;
+            let other = AdapterUser::create_test_user().await?;
+
+            let wallet = Wallet::create_by_user(owner.clone(), new_wallet(&owner)).await?;
+            assert_eq!(wallet.version, 1);
+
+            let renamed = Wallet::update(
+                owner.clone(),
+                wallet.id.clone(),
+                Wallet {
+                    label: "Renamed".into(),
+                    ..wallet.clone()
+                },
+            )
+            .await?;
+            assert_eq!(renamed.version, 2);
+            assert_eq!(renamed.label, "Renamed");
+            assert_eq!(renamed.created_by_user_id, owner.id);
 
-        let db = crate::db_init().await?;
-        let deleted: Option<WithId> = db.delete(id).await?;
+            // an edit based on version 1 lost the race
+            let stale = Wallet::update(owner.clone(), wallet.id.clone(), wallet.clone()).await;
+            assert!(matches!(stale, Err(AppError::Conflict(_))));
+            assert_eq!(Wallet::get_by_id(wallet.id.clone()).await?.label, "Renamed");
 
-        match deleted {
-            Some(_) => Ok(true),
-            None => Err(AppError::NotFound("Item not found".into())),
-        }
+            let foreign = Wallet::update(other, wallet.id.clone(), renamed).await;
+            assert!(matches!(foreign, Err(AppError::Forbidden(_))));
+
+            Ok(())
+        })
+        .await
     }
 
-    async fn delete_self(&self) -> Result<bool, AppError> {
-        let db = crate::db_init().await?;
+    #[tokio::test]
+    async fn test_soft_delete_and_restore() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+
+        with_db(db_memory().await?, async {
+            let owner = AdapterUser::create_test_user().await?;
+            let wallet = Wallet::create_by_user(owner.clone(), new_wallet(&owner)).await?;
+
+            assert!(Wallet::delete(owner.clone(), wallet.id.clone()).await?);
+            assert!(
+                <Wallet as StorageAuthed<_, _>>::get_by_user(owner.clone())
+                    .await?
+                    .is_empty()
+            );
+            assert_eq!(Wallet::get_deleted_by_user(owner.clone()).await?.len(), 1);
+
+            // deleting twice is not found, restoring brings it back
+            assert!(matches!(
+                Wallet::delete(owner.clone(), wallet.id.clone()).await,
+                Err(AppError::NotFound(_))
+            ));
+            assert!(matches!(
+                Wallet::get_by_id(wallet.id.clone()).await,
+                Err(AppError::NotFound(_))
+            ));
+            assert!(matches!(
+                Wallet::update(owner.clone(), wallet.id.clone(), wallet.clone()).await,
+                Err(AppError::NotFound(_))
+            ));
+            let restored = Wallet::restore(owner.clone(), wallet.id.clone()).await?;
+            assert!(restored.deleted_at.is_none());
+            assert_eq!(restored.version, 2);
+            assert_eq!(Wallet::get_by_id(wallet.id.clone()).await?.version, 2);
+
+            assert!(restored.delete_self().await?);
+            assert!(Wallet::get_by_id(wallet.id.clone()).await.is_err());
+
+            let actions: Vec<HistoryAction> = Wallet::history(owner, wallet.id)
+                .await?
+                .into_iter()
+                .map(|entry| entry.action)
+                .collect();
+            assert_eq!(
+                actions,
+                vec![
+                    HistoryAction::Delete,
+                    HistoryAction::Restore,
+                    HistoryAction::Delete
+                ]
+            );
+
+            Ok(())
+        })
+        .await
+    }
 
-        let mut query = db
-            .query("DELETE $content.id;")
-            .bind(("content", self.clone()))
+    #[tokio::test]
+    async fn test_history_redacts_secrets() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+        use crate::keys::{Key, KeyCreate};
+
+        with_db(db_memory().await?, async {
+            let owner = AdapterUser::create_test_user().await?;
+            let key = Key::create_by_user(
+                owner.clone(),
+                KeyCreate {
+                    name: "Signer".into(),
+                    key_for: None,
+                    key_public: Some("public".into()),
+                    key_private: Some("first-secret".into()),
+                    key_apikey: None,
+                    key_token: Some("first-token".into()),
+                    description: String::new(),
+                    expires_at: None,
+                },
+            )
             .await?;
 
-        let deleted: Option<WithId> = query.take(0)?;
-
-        match deleted {
-            Some(_) => Ok(true),
-            None => Err(AppError::NotFound("Item not found".into())),
-        }
+            let rotated = Key::update(
+                owner.clone(),
+                key.id.clone(),
+                Key {
+                    name: "Rotated".into(),
+                    key_private: Some("second-secret".into()),
+                    ..key.clone()
+                },
+            )
+            .await?;
+            assert!(rotated.deleted_at.is_none());
+            assert!(Key::delete(owner.clone(), key.id.clone()).await?);
+
+            let history = Key::history(owner, key.id.clone()).await?;
+            assert_eq!(history.len(), 2);
+
+            // only the changed fields are recorded, secrets without their values
+            let update = &history[1].changes;
+            assert_eq!(update["name"].before.as_deref(), Some("Signer"));
+            assert_eq!(update["name"].after.as_deref(), Some("Rotated"));
+            assert_eq!(
+                update["key_private"],
+                FieldChange {
+                    redacted: true,
+                    ..FieldChange::default()
+                }
+            );
+            assert!(!update.contains_key("key_token"));
+            assert!(!update.contains_key("key_public"));
+
+            let db = crate::db_init().await?;
+            let rows: surrealdb::Value = db
+                .query("SELECT * FROM key_history WHERE record = $id;")
+                .bind(("id", key.id))
+                .await?
+                .take(0)?;
+            let stored = rows.to_string();
+            assert!(stored.contains("Rotated"));
+            for secret in ["first-secret", "second-secret", "first-token"] {
+                assert!(!stored.contains(secret), "{secret} leaked into {stored}");
+            }
+
+            Ok(())
+        })
+        .await
     }
 }
@@ -1,7 +1,11 @@
 #[cfg(feature = "ssr")]
 use crate::AppError;
 #[cfg(feature = "ssr")]
+use crate::db::Transaction;
+#[cfg(feature = "ssr")]
 use crate::db_init;
+#[cfg(feature = "ssr")]
+use crate::token_hash::{hash_token, verify_token};
 
 use partial_struct::Partial;
 use serde::{Deserialize, Serialize};
@@ -312,12 +316,25 @@ impl TeamMember {
 // Implementation for TeamInvitation
 #[cfg(feature = "ssr")]
 impl TeamInvitation {
+    /// Stores the invitation with its token hashed, the returned invitation holds the plaintext.
     pub async fn create(
         team_id: RecordId,
         email: String,
         role: TeamRole,
         invited_by_user_id: RecordId,
     ) -> Result<TeamInvitation, AppError> {
+        use crate::rate_limit::{
+            GLOBAL, INVITATION_GLOBAL, INVITATION_PER_EMAIL, INVITATION_PER_INVITER,
+            check_rate_limits,
+        };
+
+        check_rate_limits(&[
+            (&INVITATION_PER_INVITER, Some(&invited_by_user_id.to_string())),
+            (&INVITATION_PER_EMAIL, Some(&email)),
+            (&INVITATION_GLOBAL, Some(GLOBAL)),
+        ])
+        .await?;
+
         let db = db_init().await?;
 
         use rand::Rng;
@@ -346,7 +363,7 @@ impl TeamInvitation {
             email,
             role,
             invited_by_user_id,
-            token,
+            token: hash_token(&token)?,
             created_at: now,
             expires_at: Datetime::from(expires_at),
             accepted_at: None,
@@ -359,7 +376,7 @@ impl TeamInvitation {
             .ok_or(AppError::DatabaseError(
                 "Failed to create team invitation".to_string(),
             ))?;
-        Ok(created)
+        Ok(TeamInvitation { token, ..created })
     }
 
     pub async fn accept(token: String, user_id: RecordId) -> Result<TeamMember, AppError> {
@@ -370,13 +387,14 @@ impl TeamInvitation {
             .query(
                 "SELECT * FROM team_invitation WHERE token = $invite_token AND accepted_at = NONE",
             )
-            .bind(("invite_token", token))
+            .bind(("invite_token", hash_token(&token)?))
             .await?
             .take(0)?;
 
         let invitation = invitations
             .into_iter()
             .next()
+            .filter(|invitation| verify_token(&token, &invitation.token))
             .ok_or_else(|| AppError::NotFound("Invalid or already used invitation".into()))?;
 
         // Check if expired
@@ -385,15 +403,32 @@ impl TeamInvitation {
             return Err(AppError::ErrorReason("Invitation has expired".into()));
         }
 
-        // Mark invitation as accepted
-        let _: Option<TeamInvitation> = db
-            .update(invitation.id.clone())
-            .merge(serde_json::json!({
-                "accepted_at": Datetime::default()
-            }))
-            .await?;
-
-        // Add user to team
-        TeamMember::add_member(invitation.team_id, user_id, invitation.role).await
+        // Mark invitation as accepted and add user to team in one transaction
+        let mut tx = Transaction::new();
+        tx.bind("invitation_id", invitation.id);
+        tx.bind("team_id", invitation.team_id);
+        tx.bind("user_id", user_id);
+        tx.bind("role", invitation.role);
+        tx.guard(
+            "$invitation_id.accepted_at = NONE",
+            AppError::NotFound("Invalid or already used invitation".into()),
+        );
+        tx.guard(
+            "count(SELECT id FROM team_member WHERE team_id = $team_id AND user_id = $user_id) = 0",
+            AppError::ErrorReason("User is already a member of this team".into()),
+        );
+        tx.statement::<surrealdb::Value>(
+            "UPDATE $invitation_id SET accepted_at = time::now() RETURN NONE",
+        );
+        let member = tx.statement::<Option<TeamMember>>(
+            "CREATE ONLY team_member SET team_id = $team_id, user_id = $user_id, role = $role, joined_at = time::now()",
+        );
+
+        tx.commit()
+            .await?
+            .take(member)?
+            .ok_or(AppError::DatabaseError(
+                "Failed to create team member".to_string(),
+            ))
     }
 }
@@ -96,6 +96,7 @@ pub fn init_meter_provider(config: &TelemetryConfig) -> SdkMeterProvider {
         .build();
 
     global::set_meter_provider(meter_provider.clone());
+    crate::db::cache_metrics::reset_otel_instruments();
 
     meter_provider
 }
@@ -6,9 +6,6 @@ use crate::AppError;
 #[cfg(feature = "ssr")]
 use crate::cached_surrealdb::AsyncSurrealCache;
 
-#[cfg(feature = "ssr")]
-use crate::db_init;
-
 #[cfg(feature = "ssr")]
 use std::time::Duration;
 
@@ -104,20 +101,47 @@ impl crate::Storage<ChainCreate, Chain> for Chain {
     const TABLE_NAME: &'static str = "chain";
 }
 
+/// Tag of every cached `Chain` lookup, see `invalidate_chain_cache`.
+pub const CHAIN_CACHE_TAG: &str = "chain";
+
+#[cfg(feature = "ssr")]
+const RPCS_CACHE_KEY: &str = "chainlist_get_rpcs";
+
 #[cfg(feature = "ssr")]
 #[io_cached(
+    name = "CHAIN_CACHE",
     map_error = r##"|e| format!("Cache error: {:?}", e)"##,
     ty = "AsyncSurrealCache<String, Vec<Chain>>",
     create = r##" {
         AsyncSurrealCache::new("cache_table", Duration::from_secs(60))
             .set_refresh(true)
+            .set_namespace("chain")
+            .with_tags([CHAIN_CACHE_TAG])
+            .with_memory_cache(1)
+            .with_single_flight()
+            .with_sweeper(Duration::from_secs(300))
             .build()
             .await
             .expect("Failed to build SurrealDB cache")
     } "##,
-    convert = r#"{ "chainlist_get_rpcs".to_string() }"#
+    convert = r#"{ RPCS_CACHE_KEY.to_string() }"#
 )]
 async fn get_rpcs() -> Result<Vec<Chain>, AppError> {
+    // callers waiting for the chainlist retry right away when the download fails
+    let flight = CHAIN_CACHE
+        .get()
+        .and_then(|cache| cache.flight_guard(&RPCS_CACHE_KEY.to_string()));
+
+    let chains = fetch_rpcs().await?;
+    if let Some(flight) = flight {
+        flight.keep();
+    }
+
+    Ok(chains)
+}
+
+#[cfg(feature = "ssr")]
+async fn fetch_rpcs() -> Result<Vec<Chain>, AppError> {
     use crate::Storage;
 
     let from_db = Chain::get_many().await?;
@@ -149,6 +173,24 @@ async fn get_rpcs() -> Result<Vec<Chain>, AppError> {
     Ok(res)
 }
 
+/// Drops every cached chain lookup, call it after editing `Chain` records.
+#[cfg(feature = "ssr")]
+pub async fn invalidate_chain_cache() -> Result<u64, AppError> {
+    // the in-memory layer only lives in the instance `#[io_cached]` created
+    let removed = match CHAIN_CACHE.get() {
+        Some(cache) => cache.invalidate_tag(CHAIN_CACHE_TAG).await?,
+        None => {
+            AsyncSurrealCache::<String, Vec<Chain>>::new("cache_table", Duration::from_secs(60))
+                .build()
+                .await?
+                .invalidate_tag(CHAIN_CACHE_TAG)
+                .await?
+        }
+    };
+
+    Ok(removed)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RpcInfo {
     pub chain_id: u64,
@@ -180,16 +222,13 @@ impl Chain {
     pub async fn from_str(name: &str) -> Result<Self, AppError> {
         get_rpcs().await?;
 
-        let db = db_init().await?;
+        use crate::Storage;
+        use crate::db::{Filter, Query};
 
         let name = name.to_lowercase().to_string();
 
-        let mut dbq = db
-            .query("select * from chain where chainSlug = $name;")
-            .bind(("name", name.clone()))
-            .await?;
-
-        let chain = dbq.take(0)?;
+        let chain =
+            Chain::find_one(Query::new().filter(Filter::eq("chainSlug", name.clone()))).await?;
 
         match chain {
             Some(chain) => Ok(chain),
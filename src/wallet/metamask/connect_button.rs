@@ -169,13 +169,13 @@ pub fn WalletConnectButton() -> impl IntoView {
                                 .await;
 
                                 match veri_result {
-                                    Ok(_) => {
+                                    Ok(url) => {
                                         leptos::logging::log!(
                                             "Wallet verified and saved successfully"
                                         );
 
                                         if let Some(window) = window() {
-                                            let _ = window.location().set_href("/");
+                                            let _ = window.location().set_href(&url);
                                         }
                                     }
                                     Err(e) => {
@@ -32,13 +32,16 @@ use chrono::Utc;
 #[cfg(feature = "ssr")]
 use surrealdb::Datetime;
 
+/// Verifies that `signature` of `message` was made by `address`, then adds the wallet to the
+/// signed in user or signs in with it. Returns the URL to continue to, the two-factor challenge
+/// when the user has enrolled.
 #[server]
 pub async fn verify_and_save_wallet(
     address: String,
     chain_id: Option<String>,
     message: String,
     signature: String,
-) -> Result<(), AppError> {
+) -> Result<String, AppError> {
     info!("Verifying wallet for address: {}", address);
 
     // Verify the signature
@@ -181,13 +184,14 @@ pub async fn verify_and_save_wallet(
                 );
             }
 
-            // If the wallet exists, we can link it to a new user or handle it accordingly
-            // For now, we will just return an error
-            return Ok(());
+            // a wallet is a primary factor like any other, enrolled users still owe their code
+            if session.two_factor_pending {
+                return Ok(crate::two_factor::two_factor_challenge_path("/"));
+            }
         }
     }
 
-    Ok(())
+    Ok("/".to_string())
 }
 
 #[server]
@@ -211,3 +215,55 @@ pub async fn verify_wallet_belongs_to_user(wallet_address: String) -> Result<boo
 
     Ok(wallet_belongs_to_user)
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+    use alloy::signers::{SignerSync, local::PrivateKeySigner};
+
+    async fn sign_in_with(signer: &PrivateKeySigner) -> Result<String, AppError> {
+        let message = "Sign this message to verify you control this wallet address.".to_string();
+        let signature = signer
+            .sign_message_sync(message.as_bytes())
+            .map_err(|e| AppError::new(e.to_string()))?;
+
+        verify_and_save_wallet(
+            signer.address().to_string(),
+            None,
+            message,
+            hex::encode(signature.as_bytes()),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_wallet_signin_with_two_factor() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+        use crate::db_init;
+
+        with_db(db_memory().await?, async {
+            let signer = PrivateKeySigner::random();
+
+            // the first sign-in creates the user and its wallet
+            assert_eq!(sign_in_with(&signer).await?, "/");
+            let wallet = Wallet::get_by_address(signer.address().to_string().to_lowercase())
+                .await?
+                .expect("wallet saved");
+
+            db_init()
+                .await?
+                .query("CREATE two_factor SET user_id = $user_id, secret = '3132333435363738393031323334353637383930', enabled_at = time::now(), recovery_codes = [];")
+                .bind(("user_id", wallet.created_by_user_id.clone()))
+                .await?
+                .check()?;
+
+            assert_eq!(
+                sign_in_with(&signer).await?,
+                crate::two_factor::two_factor_challenge_path("/")
+            );
+
+            Ok(())
+        })
+        .await
+    }
+}
@@ -1,3 +1,5 @@
+use crate::AppError;
+use crate::components::ConflictAlert;
 use crate::wallet::Wallet;
 
 #[cfg(not(feature = "ssr"))]
@@ -38,6 +40,7 @@ pub fn WalletEdit() -> impl IntoView {
 
     let (label, set_label) = signal(String::new());
     let (is_primary, set_is_primary) = signal(false);
+    let (version, set_version) = signal(0u64);
     let (is_loading, set_is_loading) = signal(false);
 
     // Update form fields when wallet loads
@@ -45,6 +48,7 @@ pub fn WalletEdit() -> impl IntoView {
         if let Some(Ok(wallet)) = wallet_resource.get() {
             set_label.set(wallet.label.clone());
             set_is_primary.set(wallet.is_primary);
+            set_version.set(wallet.version);
         }
     });
 
@@ -52,14 +56,16 @@ pub fn WalletEdit() -> impl IntoView {
         let current_wallet_id = wallet_id();
         let current_label = label.get();
         let current_is_primary = is_primary.get();
+        let current_version = version.get();
 
         async move {
             set_is_loading.set(true);
             let result = match current_wallet_id {
-                Some(id) => update_wallet_data(id, current_label, current_is_primary).await,
-                None => Err(leptos::server_fn::ServerFnError::ServerError(
-                    "Invalid wallet ID".to_string(),
-                )),
+                Some(id) => {
+                    update_wallet_data(id, current_label, current_is_primary, current_version)
+                        .await
+                }
+                None => Err(AppError::ErrorReason("Invalid wallet ID".to_string())),
             };
             set_is_loading.set(false);
             result
@@ -196,6 +202,15 @@ pub fn WalletEdit() -> impl IntoView {
 
                                         {move || {
                                             if let Some(Err(e)) = update_wallet.value().get() {
+                                                if e.is_conflict() {
+                                                    return view! {
+                                                        <ConflictAlert on_reload=Callback::new(move |_| {
+                                                            update_wallet.value().set(None);
+                                                            wallet_resource.refetch();
+                                                        }) />
+                                                    }
+                                                        .into_any();
+                                                }
                                                 view! {
                                                     <div class="text-red-600 dark:text-red-400 text-sm">
                                                         "Error: " {format!("{:?}", e)}
@@ -284,8 +299,9 @@ pub async fn update_wallet_data(
     wallet_id: RecordId,
     label: String,
     is_primary: bool,
-) -> Result<(), leptos::server_fn::ServerFnError> {
+    version: u64,
+) -> Result<(), AppError> {
     let user = get_user().await?;
-    Wallet::update_by_id_and_user(wallet_id, user.id.into(), label, is_primary).await?;
+    Wallet::update_by_id_and_user(wallet_id, user.id, label, is_primary, version).await?;
     Ok(())
 }
@@ -1,4 +1,5 @@
 use crate::wallet::Wallet;
+use crate::live::{LIVE_WALLETS_URL, use_live_refetch};
 use leptos::prelude::*;
 use crate::{
     AppError,
@@ -76,6 +77,7 @@ pub fn WalletItem(wallet: Wallet) -> impl IntoView {
 #[component]
 pub fn WalletList() -> impl IntoView {
     let wallets_resource = Resource::new(|| (), |_| get_user_wallets());
+    use_live_refetch(LIVE_WALLETS_URL, wallets_resource);
 
     view! {
         <Suspense fallback=move || {
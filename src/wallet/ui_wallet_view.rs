@@ -1,8 +1,10 @@
 use std::str::FromStr;
 
+use crate::AppError;
+use crate::passkey::reverify_with_passkey;
 use crate::wallet::Wallet;
 
-use leptos::prelude::*;
+use leptos::{prelude::*, reactive::spawn_local};
 use leptos_router::hooks::use_params_map;
 use crate::components::{
     Button,
@@ -179,6 +181,11 @@ pub fn WalletView() -> impl IntoView {
                                                 </div>
                                             </div>
                                         </div>
+                                        {(wallet.wallet_type == "generated"
+                                            || wallet.wallet_type == "imported")
+                                            .then(|| {
+                                                view! { <RevealPrivateKey wallet_id=wallet.id.clone() /> }
+                                            })}
                                     </div>
                                 </div>
                             }
@@ -218,3 +225,89 @@ pub async fn get_wallet_by_id(
     let wallet = Wallet::get_by_id_and_user(wallet_id.into(), user.id.into()).await?;
     Ok(wallet)
 }
+
+/// Shows the private key of a generated or imported wallet, after a passkey reverification.
+#[component]
+fn RevealPrivateKey(wallet_id: RecordId) -> impl IntoView {
+    let wallet_id = StoredValue::new(wallet_id);
+    let (private_key, set_private_key) = signal(Option::<String>::None);
+    let (error, set_error) = signal(Option::<String>::None);
+
+    let on_reveal = Callback::new(move |_| {
+        set_error.set(None);
+        spawn_local(async move {
+            let id = wallet_id.get_value();
+            let result = match reveal_wallet_private_key(id.clone()).await {
+                Err(AppError::ReverificationRequired) => match reverify_with_passkey().await {
+                    Ok(()) => reveal_wallet_private_key(id).await,
+                    Err(e) => {
+                        set_error.set(Some(e));
+                        return;
+                    }
+                },
+                other => other,
+            };
+
+            match result {
+                Ok(key) => set_private_key.set(Some(key)),
+                Err(e) => set_error.set(Some(e.to_string())),
+            }
+        });
+    });
+
+    view! {
+        <div class="px-6 pb-6">
+            <h3 class="text-lg font-semibold text-gray-900 dark:text-gray-100 mb-3">
+                "Private Key"
+            </h3>
+            {move || match private_key.get() {
+                Some(key) => {
+                    view! {
+                        <div class="flex flex-col gap-2">
+                            <p class="text-sm text-red-600 dark:text-red-400">
+                                "Anyone with this key controls the wallet. Never share it."
+                            </p>
+                            <code class="font-mono text-sm p-2 bg-gray-50 dark:bg-gray-900 rounded break-all">
+                                {key}
+                            </code>
+                        </div>
+                    }
+                        .into_any()
+                }
+                None => {
+                    view! {
+                        <Button color=BtnColor::Error on_click=on_reveal>
+                            "Reveal private key"
+                        </Button>
+                    }
+                        .into_any()
+                }
+            }}
+            {move || {
+                error
+                    .get()
+                    .map(|e| view! { <p class="mt-2 text-sm text-red-600 dark:text-red-400">{e}</p> })
+            }}
+        </div>
+    }
+}
+
+/// The private key of a generated or imported wallet, needs a recent passkey reverification.
+#[server]
+pub async fn reveal_wallet_private_key(wallet_id: RecordId) -> Result<String, AppError> {
+    use crate::keys::Key;
+
+    crate::passkey::require_recent_reverification().await?;
+
+    let user = crate::auth::extract::request_user()
+        .await?
+        .ok_or_else(|| AppError::AuthError("Not logged in.".into()))?;
+    let wallet = Wallet::get_by_id_and_user(wallet_id, user.id.clone()).await?;
+
+    tracing::info!(user = %user.id, wallet = %wallet.id, "Revealed wallet private key");
+
+    Key::get_user_firstkey_for(user, wallet.id)
+        .await?
+        .key_private
+        .ok_or_else(|| AppError::NotFound("This wallet has no stored private key".into()))
+}
@@ -22,8 +22,18 @@ use crate::keys::{Key, KeyCreate};
 #[cfg(feature = "ssr")]
 use crate::StorageAuthed;
 
+#[cfg(feature = "ssr")]
+use crate::storage_authed_trait::{delete_recorded, update_versioned_in};
+
+#[cfg(feature = "ssr")]
+use crate::db::Transaction;
+
 #[derive(Debug, Clone, Serialize, Deserialize, Partial)]
-#[partial("CreateWallet", derive(Serialize, Deserialize, Clone), omit(id))]
+#[partial(
+    "CreateWallet",
+    derive(Serialize, Deserialize, Clone),
+    omit(id, version, deleted_at)
+)]
 #[partial(
     "UpdateWallet",
     derive(Debug, Serialize, Deserialize, Clone),
@@ -40,6 +50,12 @@ pub struct Wallet {
     pub created_at: Datetime,
     pub updated_at: Datetime,
     pub is_primary: bool,
+    /// Incremented on every update, used to detect concurrent edits
+    #[serde(default)]
+    pub version: u64,
+    /// Set when the wallet was soft deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<Datetime>,
 }
 
 impl Default for Wallet {
@@ -55,10 +71,18 @@ impl Default for Wallet {
             created_at: Datetime::from(chrono::Utc::now()),
             updated_at: Datetime::from(chrono::Utc::now()),
             is_primary: false,
+            version: 0,
+            deleted_at: None,
         }
     }
 }
 
+#[cfg(feature = "ssr")]
+impl StorageAuthed<CreateWallet, Wallet> for Wallet {
+    const TABLE_NAME: &str = "wallet";
+    const SOFT_DELETE: bool = true;
+}
+
 #[cfg(feature = "ssr")]
 impl Wallet {
     pub async fn new(data: CreateWallet) -> Result<Self, AppError> {
@@ -72,7 +96,7 @@ impl Wallet {
     pub async fn get_by_address(address: String) -> Result<Option<Self>, AppError> {
         let client = db_init().await?;
         let mut result = client
-            .query("SELECT * FROM wallet WHERE string::lowercase(address) = string::lowercase($address) ORDER BY is_primary DESC, label;")
+            .query("SELECT * FROM wallet WHERE string::lowercase(address) = string::lowercase($address) AND deleted_at = NONE ORDER BY is_primary DESC, label;")
             .bind(("address", address))
             .await?;
         let wallets: Vec<Self> = result.take(0)?;
@@ -90,7 +114,7 @@ impl Wallet {
     pub async fn get_by_user(user_id: RecordId) -> Result<Vec<Self>, AppError> {
         let client = db_init().await?;
         let mut result = client
-            .query("SELECT * FROM wallet WHERE created_by_user_id = $user_id ORDER BY is_primary DESC, label;")
+            .query("SELECT * FROM wallet WHERE created_by_user_id = $user_id AND deleted_at = NONE ORDER BY is_primary DESC, label;")
             .bind(("user_id", user_id))
             .await?;
         let wallets: Vec<Self> = result.take(0)?;
@@ -103,7 +127,7 @@ impl Wallet {
     ) -> Result<Self, AppError> {
         let client = db_init().await?;
         let mut result = client
-            .query("SELECT * FROM wallet WHERE id = $wallet_id AND created_by_user_id = $user_id LIMIT 1;")
+            .query("SELECT * FROM wallet WHERE id = $wallet_id AND created_by_user_id = $user_id AND deleted_at = NONE LIMIT 1;")
             .bind(("wallet_id", wallet_id))
             .bind(("user_id", user_id))
             .await?;
@@ -112,41 +136,57 @@ impl Wallet {
         wallet.ok_or_else(|| AppError::AuthError("Wallet not found".into()))
     }
 
+    /// Updates label and primary flag. `version` is the version the edit was based on, a
+    /// `Conflict` error is returned when the wallet was changed since.
     pub async fn update_by_id_and_user(
         id: RecordId,
         user_id: RecordId,
         label: String,
         is_primary: bool,
+        version: u64,
     ) -> Result<Self, AppError> {
-        let client = db_init().await?;
+        // checks ownership
+        Self::get_by_id_and_user(id.clone(), user_id.clone()).await?;
+
+        #[derive(Serialize)]
+        struct WalletChanges {
+            label: String,
+            is_primary: bool,
+            version: u64,
+        }
 
-        // If setting as primary, unset other wallets first
+        let mut tx = Transaction::new();
+        let updated = update_versioned_in::<Self>(
+            &mut tx,
+            Some(id),
+            WalletChanges {
+                label,
+                is_primary,
+                version,
+            },
+            Some(user_id),
+            Self::HISTORY_REDACTED,
+        );
+
+        // If setting as primary, unset other wallets in the same transaction
         if is_primary {
-            client
-                .query("UPDATE wallet SET is_primary = false WHERE created_by_user_id = $user_id;")
-                .bind(("user_id", user_id.clone()))
-                .await?;
+            tx.statement::<surrealdb::Value>(
+                "UPDATE wallet SET is_primary = false WHERE created_by_user_id = $after.created_by_user_id AND id != $id RETURN NONE",
+            );
         }
 
-        let mut result = client
-            .query("UPDATE wallet SET label = $label, is_primary = $is_primary, updated_at = $updated_at WHERE id = $id AND created_by_user_id = $user_id RETURN AFTER;")
-            .bind(("id", id))
-            .bind(("user_id", user_id))
-            .bind(("label", label))
-            .bind(("is_primary", is_primary))
-            .bind(("updated_at", Datetime::from(chrono::Utc::now())))
-            .await?;
-        let wallet: Option<Self> = result.take(0)?;
-        wallet.ok_or_else(|| AppError::AuthError("Wallet not found or update failed".into()))
+        tx.commit()
+            .await?
+            .take(updated)?
+            .ok_or_else(|| AppError::NotFound("Wallet not found".into()))
     }
 
+    /// Soft deletes the wallet, see `StorageAuthed::restore` to bring it back.
     pub async fn delete_by_id_and_user(id: RecordId, user_id: RecordId) -> Result<(), AppError> {
-        let client = db_init().await?;
-        client
-            .query("DELETE wallet WHERE id = $id AND created_by_user_id = $user_id;")
-            .bind(("id", id))
-            .bind(("user_id", user_id))
-            .await?;
+        // checks ownership
+        Self::get_by_id_and_user(id.clone(), user_id.clone()).await?;
+
+        delete_recorded(id, Some(user_id), Self::SOFT_DELETE, Self::HISTORY_REDACTED).await?;
         Ok(())
     }
 
@@ -204,3 +244,79 @@ impl Wallet {
         Ok(wallet)
     }
 }
+
+#[cfg(all(test, feature = "ssr"))]
+mod tests {
+    use super::*;
+
+    async fn new_wallet(user: &AdapterUser, label: &str) -> Result<Wallet, AppError> {
+        let now = Datetime::from(chrono::Utc::now());
+
+        Wallet::create_by_user(
+            user.clone(),
+            CreateWallet {
+                address: format!("0x{label}"),
+                label: label.into(),
+                wallet_type: "generated".into(),
+                chain_type: Some("evm".into()),
+                chain_id: None,
+                created_by_user_id: user.id.clone(),
+                created_at: now.clone(),
+                updated_at: now,
+                is_primary: false,
+            },
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_single_primary_wallet() -> Result<(), AppError> {
+        use crate::db::connection::{db_memory, with_db};
+
+        with_db(db_memory().await?, async {
+            let user = AdapterUser::create_test_user().await?;
+            let first = new_wallet(&user, "first").await?;
+            let second = new_wallet(&user, "second").await?;
+
+            Wallet::update_by_id_and_user(
+                first.id.clone(),
+                user.id.clone(),
+                "first".into(),
+                true,
+                1,
+            )
+            .await?;
+            Wallet::update_by_id_and_user(
+                second.id.clone(),
+                user.id.clone(),
+                "second".into(),
+                true,
+                1,
+            )
+            .await?;
+
+            let primary: Vec<RecordId> = Wallet::get_by_user(user.id.clone())
+                .await?
+                .into_iter()
+                .filter(|wallet| wallet.is_primary)
+                .map(|wallet| wallet.id)
+                .collect();
+            assert_eq!(primary, vec![second.id.clone()]);
+
+            // a conflicting update doesn't unset the other wallets either
+            let stale = Wallet::update_by_id_and_user(
+                first.id.clone(),
+                user.id.clone(),
+                "first".into(),
+                true,
+                1,
+            )
+            .await;
+            assert!(matches!(stale, Err(AppError::Conflict(_))));
+            assert!(Wallet::get_by_id(second.id).await?.is_primary);
+
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,467 @@
+//! Derive macros for tinkr, re-exported from the `tinkr` crate.
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Attribute, Data, DeriveInput, Fields, Ident, LitStr, parse_macro_input};
+
+mod schema;
+
+use schema::{FieldSchema, TableSchema};
+
+/// Fields written by `StorageAuthed` itself, never part of the generated Create partial.
+const MANAGED_FIELDS: &[&str] = &[
+    "id",
+    "created_by_user_id",
+    "created_at",
+    "updated_at",
+    "version",
+    "deleted_at",
+    "deleted_by_user_id",
+];
+
+/// Fields the generated Update partial leaves out for `authed` models. `version` stays in so
+/// updates can be checked for conflicts.
+const UPDATE_OMITTED_FIELDS: &[&str] = &[
+    "id",
+    "created_by_user_id",
+    "created_at",
+    "updated_at",
+    "deleted_at",
+    "deleted_by_user_id",
+];
+
+/// Generates the table name, the `Create<Name>`/`Update<Name>` partial structs, the `Storage`
+/// (or `StorageAuthed`) impl and the `DEFINE TABLE/FIELD/INDEX` schema of a model.
+///
+/// Struct attributes, all optional:
+/// - `table = "name"`: table name, defaults to the snake case struct name
+/// - `create = "Name"` / `update = "Name"`: names of the partial structs
+/// - `authed`: implement `StorageAuthed` instead of `Storage`
+/// - `soft_delete`: set `StorageAuthed::SOFT_DELETE`
+/// - `schemaless`: define the table `SCHEMALESS` instead of `SCHEMAFULL`
+/// - `cfg(feature = "ssr")`: emit the trait impls under this `cfg`, for models also compiled
+///   for the browser, where tinkr has no `Storage` traits
+///
+/// Field attributes:
+/// - `index` / `unique`: define a (unique) index on the field
+/// - `type = "option<record<user>>"`: override the SurrealQL type derived from the Rust type
+/// - `default = "time::now()"`: SurrealQL `DEFAULT` expression
+/// - `skip_create` / `skip_update`: leave the field out of a partial
+/// - `skip_schema`: don't define the field
+/// - `redact_history`: list the field in `StorageAuthed::HISTORY_REDACTED`, for secrets
+///
+/// ### EXAMPLE:
+/// ```rs
+///     #[derive(Debug, Clone, Serialize, Deserialize, TinkrModel)]
+///     #[tinkr(table = "project", authed, soft_delete, cfg(feature = "ssr"))]
+///     pub struct Project {
+///         pub id: RecordId,
+///         #[tinkr(unique)]
+///         pub slug: String,
+///         pub name: String,
+///         pub created_by_user_id: RecordId,
+///         pub created_at: Datetime,
+///         pub updated_at: Datetime,
+///     }
+///
+///     MigrationRunner::tinkr().register(Project::migration("myapp", 1)).run().await?;
+/// ```
+#[proc_macro_derive(TinkrModel, attributes(tinkr))]
+pub fn derive_tinkr_model(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+#[derive(Default)]
+struct ModelAttrs {
+    table: Option<String>,
+    create: Option<Ident>,
+    update: Option<Ident>,
+    authed: bool,
+    soft_delete: bool,
+    schemaless: bool,
+    cfg: Option<proc_macro2::TokenStream>,
+}
+
+impl ModelAttrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut model = ModelAttrs::default();
+
+        for attr in attrs.iter().filter(|a| a.path().is_ident("tinkr")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("table") {
+                    model.table = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("create") {
+                    model.create = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+                } else if meta.path.is_ident("update") {
+                    model.update = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+                } else if meta.path.is_ident("authed") {
+                    model.authed = true;
+                } else if meta.path.is_ident("soft_delete") {
+                    model.soft_delete = true;
+                } else if meta.path.is_ident("schemaless") {
+                    model.schemaless = true;
+                } else if meta.path.is_ident("cfg") {
+                    let predicate;
+                    syn::parenthesized!(predicate in meta.input);
+                    model.cfg = Some(predicate.parse()?);
+                } else {
+                    return Err(meta.error("unknown tinkr model attribute"));
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(model)
+    }
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    index: bool,
+    unique: bool,
+    ty: Option<String>,
+    default: Option<String>,
+    skip_create: bool,
+    skip_update: bool,
+    skip_schema: bool,
+    redact_history: bool,
+}
+
+impl FieldAttrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut field = FieldAttrs::default();
+
+        for attr in attrs.iter().filter(|a| a.path().is_ident("tinkr")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("index") {
+                    field.index = true;
+                } else if meta.path.is_ident("unique") {
+                    field.unique = true;
+                } else if meta.path.is_ident("type") {
+                    field.ty = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("default") {
+                    field.default = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("skip_create") {
+                    field.skip_create = true;
+                } else if meta.path.is_ident("skip_update") {
+                    field.skip_update = true;
+                } else if meta.path.is_ident("skip_schema") {
+                    field.skip_schema = true;
+                } else if meta.path.is_ident("redact_history") {
+                    field.redact_history = true;
+                } else {
+                    return Err(meta.error("unknown tinkr field attribute"));
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(field)
+    }
+}
+
+/// The name serde uses for a field, honouring `#[serde(rename = "...")]` and `#[serde(skip)]`.
+fn serde_name(attrs: &[Attribute], ident: &Ident) -> syn::Result<Option<String>> {
+    let mut name = ident.to_string().trim_start_matches("r#").to_string();
+    let mut skipped = false;
+
+    for attr in attrs.iter().filter(|a| a.path().is_ident("serde")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") && meta.input.peek(syn::Token![=]) {
+                name = meta.value()?.parse::<LitStr>()?.value();
+            } else if meta.path.is_ident("skip") {
+                skipped = true;
+            } else if meta.input.peek(syn::Token![=]) {
+                meta.value()?.parse::<syn::Expr>()?;
+            } else if meta.input.peek(syn::token::Paren) {
+                meta.parse_nested_meta(|nested| {
+                    if nested.input.peek(syn::Token![=]) {
+                        nested.value()?.parse::<syn::Expr>()?;
+                    }
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok((!skipped).then_some(name))
+}
+
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Attributes copied onto the generated partial structs and their fields.
+fn forwarded(attrs: &[Attribute]) -> Vec<&Attribute> {
+    attrs
+        .iter()
+        .filter(|a| a.path().is_ident("serde") || a.path().is_ident("doc"))
+        .collect()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "TinkrModel does not support generic structs",
+        ));
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "TinkrModel needs a struct with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "TinkrModel can only be derived for structs",
+            ));
+        }
+    };
+
+    let model = ModelAttrs::parse(&input.attrs)?;
+    let ident = &input.ident;
+    let vis = &input.vis;
+    let table = model
+        .table
+        .clone()
+        .unwrap_or_else(|| snake_case(&ident.to_string()));
+    let create_ident = model
+        .create
+        .clone()
+        .unwrap_or_else(|| format_ident!("Create{}", ident));
+    let update_ident = model
+        .update
+        .clone()
+        .unwrap_or_else(|| format_ident!("Update{}", ident));
+
+    let mut create_fields = Vec::new();
+    let mut update_fields = Vec::new();
+    let mut redacted = Vec::new();
+    let mut schema = TableSchema::new(&table, !model.schemaless);
+
+    for field in fields {
+        let field_ident = field.ident.as_ref().expect("named field");
+        let attrs = FieldAttrs::parse(&field.attrs)?;
+        let rust_name = field_ident.to_string();
+        let field_vis = &field.vis;
+        let ty = &field.ty;
+        let copied = forwarded(&field.attrs);
+        let tokens = quote! { #(#copied)* #field_vis #field_ident: #ty };
+
+        let omitted_on_create = if model.authed {
+            MANAGED_FIELDS.contains(&rust_name.as_str())
+        } else {
+            rust_name == "id"
+        };
+        if !omitted_on_create && !attrs.skip_create {
+            create_fields.push(tokens.clone());
+        }
+
+        let omitted_on_update = if model.authed {
+            UPDATE_OMITTED_FIELDS.contains(&rust_name.as_str())
+        } else {
+            rust_name == "id"
+        };
+        if !omitted_on_update && !attrs.skip_update {
+            update_fields.push(tokens);
+        }
+
+        let Some(name) = serde_name(&field.attrs, field_ident)? else {
+            continue;
+        };
+        if attrs.redact_history {
+            redacted.push(name.clone());
+        }
+        let managed = model.authed && MANAGED_FIELDS.contains(&name.as_str());
+        if name == "id" || managed || attrs.skip_schema {
+            continue;
+        }
+
+        schema.field(FieldSchema {
+            ty: attrs.ty.unwrap_or_else(|| schema::surreal_type(&field.ty)),
+            name,
+            default: attrs.default,
+            index: attrs.index,
+            unique: attrs.unique,
+        });
+    }
+
+    if model.authed {
+        schema.managed_fields(model.soft_delete);
+    }
+
+    let schema = schema.to_statements();
+    let struct_attrs = forwarded(&input.attrs)
+        .into_iter()
+        .filter(|a| a.path().is_ident("serde"))
+        .collect::<Vec<_>>();
+    let create_doc = format!(" Fields for creating a [`{ident}`], generated by `TinkrModel`.");
+    let update_doc = format!(" Fields for updating a [`{ident}`], generated by `TinkrModel`.");
+    let cfg = model
+        .cfg
+        .as_ref()
+        .map(|predicate| quote! { #[cfg(#predicate)] });
+
+    let storage_impl = if model.authed {
+        let soft_delete = model.soft_delete;
+        quote! {
+            #cfg
+            impl ::tinkr::StorageAuthed<#create_ident, #ident> for #ident {
+                const TABLE_NAME: &'static str = #table;
+                const SOFT_DELETE: bool = #soft_delete;
+                const HISTORY_REDACTED: &'static [&'static str] = &[#(#redacted),*];
+            }
+        }
+    } else {
+        if model.soft_delete {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "soft_delete is only supported together with authed",
+            ));
+        }
+        if !redacted.is_empty() {
+            return Err(syn::Error::new_spanned(
+                ident,
+                "redact_history is only supported together with authed",
+            ));
+        }
+        quote! {
+            #cfg
+            impl ::tinkr::Storage<#create_ident, #ident> for #ident {
+                const TABLE_NAME: &'static str = #table;
+            }
+        }
+    };
+
+    Ok(quote! {
+        #[doc = #create_doc]
+        #[derive(Debug, Clone, ::serde::Serialize, ::serde::Deserialize)]
+        #(#struct_attrs)*
+        #vis struct #create_ident {
+            #(#create_fields,)*
+        }
+
+        #[doc = #update_doc]
+        #[derive(Debug, Clone, ::serde::Serialize, ::serde::Deserialize)]
+        #(#struct_attrs)*
+        #vis struct #update_ident {
+            #(#update_fields,)*
+        }
+
+        #cfg
+        impl ::tinkr::db::TinkrModel for #ident {
+            const TABLE_NAME: &'static str = #table;
+            const SCHEMA: &'static str = #schema;
+        }
+
+        #storage_impl
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expanded(input: DeriveInput) -> String {
+        expand(input).expect("expands").to_string()
+    }
+
+    #[test]
+    fn test_impls_are_unconditional_by_default() {
+        let out = expanded(syn::parse_quote! {
+            #[tinkr(table = "note")]
+            pub struct Note {
+                pub id: RecordId,
+                pub title: String,
+            }
+        });
+
+        assert!(!out.contains("cfg"));
+        assert!(out.contains(":: tinkr :: Storage < CreateNote , Note > for Note"));
+        assert!(out.contains(":: tinkr :: db :: TinkrModel for Note"));
+    }
+
+    #[test]
+    fn test_cfg_attribute_gates_the_impls() {
+        let out = expanded(syn::parse_quote! {
+            #[tinkr(table = "note", authed, cfg(feature = "ssr"))]
+            pub struct Note {
+                pub id: RecordId,
+                pub title: String,
+            }
+        });
+
+        // the partial structs stay available everywhere, only the two impls are gated
+        assert_eq!(out.matches("# [cfg (feature = \"ssr\")]").count(), 2);
+        assert!(out.contains(":: tinkr :: StorageAuthed < CreateNote , Note > for Note"));
+    }
+
+    #[test]
+    fn test_schema_output() {
+        let out = expanded(syn::parse_quote! {
+            #[tinkr(table = "note", authed)]
+            pub struct Note {
+                pub id: RecordId,
+                #[tinkr(unique)]
+                pub title: String,
+                pub done_at: Option<Datetime>,
+                #[serde(rename = "tagList")]
+                pub tags: Vec<String>,
+                pub created_by_user_id: RecordId,
+                pub created_at: Datetime,
+                pub updated_at: Datetime,
+            }
+        });
+
+        let expected = [
+            "DEFINE TABLE IF NOT EXISTS note SCHEMAFULL;",
+            "DEFINE FIELD IF NOT EXISTS title ON TABLE note TYPE string;",
+            "DEFINE FIELD IF NOT EXISTS done_at ON TABLE note TYPE option<datetime>;",
+            "DEFINE FIELD IF NOT EXISTS tagList ON TABLE note TYPE array<string>;",
+            "DEFINE FIELD IF NOT EXISTS created_at ON TABLE note TYPE datetime DEFAULT time::now();",
+            "DEFINE INDEX IF NOT EXISTS note_title ON TABLE note COLUMNS title UNIQUE;",
+        ];
+        for statement in expected {
+            assert!(out.contains(statement), "missing `{statement}` in {out}");
+        }
+        // managed fields are only defined once, by `managed_fields`
+        assert_eq!(out.matches("DEFINE FIELD IF NOT EXISTS created_at").count(), 1);
+    }
+
+    #[test]
+    fn test_rejects_unknown_attribute() {
+        let err = expand(syn::parse_quote! {
+            #[tinkr(tabel = "note")]
+            pub struct Note {
+                pub id: RecordId,
+            }
+        })
+        .unwrap_err();
+
+        assert_eq!(err.to_string(), "unknown tinkr model attribute");
+    }
+}
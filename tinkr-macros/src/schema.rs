@@ -0,0 +1,217 @@
+use syn::{GenericArgument, PathArguments, Type};
+
+pub struct FieldSchema {
+    pub name: String,
+    pub ty: String,
+    pub default: Option<String>,
+    pub index: bool,
+    pub unique: bool,
+}
+
+/// `DEFINE` statements for one table, built up field by field.
+pub struct TableSchema {
+    table: String,
+    schemafull: bool,
+    fields: Vec<FieldSchema>,
+}
+
+impl TableSchema {
+    pub fn new(table: &str, schemafull: bool) -> Self {
+        Self {
+            table: table.to_string(),
+            schemafull,
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn field(&mut self, field: FieldSchema) {
+        self.fields.push(field);
+    }
+
+    /// Fields `StorageAuthed` writes. `create_by_user` sets them all in its `CREATE`, they are
+    /// optional or defaulted so records written through plain `Storage::create` still validate.
+    pub fn managed_fields(&mut self, soft_delete: bool) {
+        let mut managed = vec![
+            ("created_by_user_id", "option<record>", None),
+            ("created_at", "datetime", Some("time::now()")),
+            ("updated_at", "datetime", Some("time::now()")),
+            ("version", "int", Some("0")),
+        ];
+
+        if soft_delete {
+            managed.push(("deleted_at", "option<datetime>", None));
+            managed.push(("deleted_by_user_id", "option<record>", None));
+        }
+
+        for (name, ty, default) in managed {
+            self.field(FieldSchema {
+                name: name.to_string(),
+                ty: ty.to_string(),
+                default: default.map(str::to_string),
+                index: name == "created_by_user_id",
+                unique: false,
+            });
+        }
+    }
+
+    pub fn to_statements(&self) -> String {
+        let table = &self.table;
+        let mut statements = vec![format!(
+            "DEFINE TABLE IF NOT EXISTS {table} {};",
+            if self.schemafull { "SCHEMAFULL" } else { "SCHEMALESS" }
+        )];
+
+        for field in &self.fields {
+            let name = &field.name;
+            // nested keys of objects are dropped on SCHEMAFULL tables unless the field is FLEXIBLE
+            let flexible = if field.ty.contains("object") {
+                " FLEXIBLE"
+            } else {
+                ""
+            };
+            let default = field
+                .default
+                .as_ref()
+                .map(|d| format!(" DEFAULT {d}"))
+                .unwrap_or_default();
+
+            statements.push(format!(
+                "DEFINE FIELD IF NOT EXISTS {name} ON TABLE {table}{flexible} TYPE {}{default};",
+                field.ty
+            ));
+        }
+
+        for field in self.fields.iter().filter(|f| f.index || f.unique) {
+            let name = &field.name;
+            let index = format!("{table}_{}", name.replace('.', "_"));
+            let unique = if field.unique { " UNIQUE" } else { "" };
+
+            statements.push(format!(
+                "DEFINE INDEX IF NOT EXISTS {index} ON TABLE {table} COLUMNS {name}{unique};"
+            ));
+        }
+
+        statements.join("\n")
+    }
+}
+
+fn first_type_argument(arguments: &PathArguments) -> Option<&Type> {
+    match arguments {
+        PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+/// SurrealQL type for a Rust field type. Types it doesn't know map to `any`, use
+/// `#[tinkr(type = "...")]` to be precise.
+pub fn surreal_type(ty: &Type) -> String {
+    match ty {
+        Type::Reference(reference) => surreal_type(&reference.elem),
+        Type::Paren(paren) => surreal_type(&paren.elem),
+        Type::Group(group) => surreal_type(&group.elem),
+        Type::Array(array) => array_of(&array.elem),
+        Type::Slice(slice) => array_of(&slice.elem),
+        Type::Path(path) => {
+            let Some(segment) = path.path.segments.last() else {
+                return "any".to_string();
+            };
+            let inner = first_type_argument(&segment.arguments);
+
+            match segment.ident.to_string().as_str() {
+                "Option" => match inner.map(surreal_type) {
+                    Some(inner) if inner != "any" => format!("option<{inner}>"),
+                    _ => "any".to_string(),
+                },
+                "Vec" | "VecDeque" | "HashSet" | "BTreeSet" => match inner {
+                    Some(inner) => array_of(inner),
+                    None => "array".to_string(),
+                },
+                "Box" | "Arc" | "Rc" => inner.map(surreal_type).unwrap_or("any".to_string()),
+                "String" | "str" | "char" | "Cow" | "EmailAddress" => "string".to_string(),
+                "bool" => "bool".to_string(),
+                "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64"
+                | "u128" | "usize" => "int".to_string(),
+                "f32" | "f64" => "float".to_string(),
+                "Decimal" => "decimal".to_string(),
+                "Datetime" | "DateTime" | "NaiveDateTime" => "datetime".to_string(),
+                "Duration" => "duration".to_string(),
+                "Uuid" => "uuid".to_string(),
+                "RecordId" | "Thing" => "record".to_string(),
+                "Value" | "Map" | "HashMap" | "BTreeMap" => "object".to_string(),
+                _ => "any".to_string(),
+            }
+        }
+        _ => "any".to_string(),
+    }
+}
+
+fn array_of(elem: &Type) -> String {
+    match surreal_type(elem) {
+        inner if inner == "any" => "array".to_string(),
+        inner => format!("array<{inner}>"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_surreal_type() {
+        let cases: [(Type, &str); 8] = [
+            (syn::parse_quote!(String), "string"),
+            (syn::parse_quote!(Option<u32>), "option<int>"),
+            (syn::parse_quote!(Vec<RecordId>), "array<record>"),
+            (syn::parse_quote!(Vec<Unknown>), "array"),
+            (syn::parse_quote!(Option<Unknown>), "any"),
+            (syn::parse_quote!(surrealdb::Datetime), "datetime"),
+            (syn::parse_quote!(Option<Datetime>), "option<datetime>"),
+            (syn::parse_quote!(&'static str), "string"),
+        ];
+
+        for (ty, expected) in cases {
+            assert_eq!(surreal_type(&ty), expected);
+        }
+    }
+
+    #[test]
+    fn test_to_statements() {
+        let mut schema = TableSchema::new("note", true);
+        schema.field(FieldSchema {
+            name: "title".to_string(),
+            ty: "string".to_string(),
+            default: None,
+            index: false,
+            unique: true,
+        });
+        schema.field(FieldSchema {
+            name: "meta".to_string(),
+            ty: "option<object>".to_string(),
+            default: None,
+            index: false,
+            unique: false,
+        });
+        schema.managed_fields(true);
+
+        assert_eq!(
+            schema.to_statements(),
+            [
+                "DEFINE TABLE IF NOT EXISTS note SCHEMAFULL;",
+                "DEFINE FIELD IF NOT EXISTS title ON TABLE note TYPE string;",
+                "DEFINE FIELD IF NOT EXISTS meta ON TABLE note FLEXIBLE TYPE option<object>;",
+                "DEFINE FIELD IF NOT EXISTS created_by_user_id ON TABLE note TYPE option<record>;",
+                "DEFINE FIELD IF NOT EXISTS created_at ON TABLE note TYPE datetime DEFAULT time::now();",
+                "DEFINE FIELD IF NOT EXISTS updated_at ON TABLE note TYPE datetime DEFAULT time::now();",
+                "DEFINE FIELD IF NOT EXISTS version ON TABLE note TYPE int DEFAULT 0;",
+                "DEFINE FIELD IF NOT EXISTS deleted_at ON TABLE note TYPE option<datetime>;",
+                "DEFINE FIELD IF NOT EXISTS deleted_by_user_id ON TABLE note TYPE option<record>;",
+                "DEFINE INDEX IF NOT EXISTS note_title ON TABLE note COLUMNS title UNIQUE;",
+                "DEFINE INDEX IF NOT EXISTS note_created_by_user_id ON TABLE note COLUMNS created_by_user_id;",
+            ]
+            .join("\n")
+        );
+    }
+}